@@ -0,0 +1,698 @@
+//! Multi-part append only file, laid out like Redis 7 in the `appenddirname` directory:
+//! - a base file, an RDB snapshot (`appendonly.aof.1.base.rdb`) or a plain AOF
+//!   (`appendonly.aof.1.base.aof`) when it was upgraded from a single file AOF
+//! - incremental files (`appendonly.aof.1.incr.aof`) holding the commands logged after the base
+//! - a manifest (`appendonly.aof.manifest`) listing them in order, one file per line:
+//!   `file appendonly.aof.1.base.rdb seq 1 type b`
+//!
+//! A rewrite opens a new incremental file, then writes a new base from another thread.
+//! The old base and incremental files are dropped once the manifest points to the new
+//! ones, so the live log is never copied
+
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::AppendFsync;
+use crate::frame::{check as check_frame, Frame, FrameError};
+use crate::rdb::{self, SnapshotEntry};
+
+/// with `appendfsync everysec`, how often the file is flushed to disk
+const FSYNC_PERIOD: Duration = Duration::from_secs(1);
+
+/// The append only file: every write command is appended as RESP to the current
+/// incremental file once it has run
+#[derive(Debug)]
+pub(crate) struct Aof {
+    dir: PathBuf,
+    manifest: Manifest,
+    file: Arc<File>,
+    fsync: AppendFsync,
+    /// something was written since the last fsync
+    unsynced: bool,
+    last_fsync: Instant,
+    /// commands not written yet because the last write failed, written before the next ones
+    pending: Vec<u8>,
+    /// why the last write or fsync failed. Clients can't write until one succeeds again
+    write_error: Option<String>,
+    /// seq of the incremental file opened when the running rewrite started
+    rewrite_incr: Option<u64>,
+    /// set by the rewrite thread when the new base is written
+    rewrite_result: Arc<Mutex<Option<io::Result<PathBuf>>>>,
+}
+
+/// what the files of an append only file hold: the keys of the base snapshot, if any,
+/// and the commands logged after it
+#[derive(Debug, Default)]
+pub(crate) struct AofContents {
+    pub(crate) preamble: Vec<SnapshotEntry>,
+    pub(crate) commands: Vec<Frame>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct AofFile {
+    name: String,
+    seq: u64,
+}
+
+/// the files an append only file is made of, in replay order
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Manifest {
+    /// `appendfilename`, the prefix of every file name
+    prefix: String,
+    base: Option<AofFile>,
+    incrs: Vec<AofFile>,
+}
+
+/// The outcome of checking an append only file, what `aof_check` prints
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    /// what is wrong with the manifest, like files it lists that do not exist
+    pub problems: Vec<String>,
+    /// every file that was read, in replay order
+    pub files: Vec<FileReport>,
+}
+
+#[derive(Debug)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// keys of the RDB part, if the file has one
+    pub keys: usize,
+    /// complete commands
+    pub commands: usize,
+    /// `Some((valid, total))` if the file ends with an incomplete command after `valid` bytes
+    pub truncated: Option<(u64, u64)>,
+}
+
+impl CheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty() && self.files.iter().all(|file| file.truncated.is_none())
+    }
+}
+
+impl Manifest {
+    fn new(prefix: &str) -> Self {
+        Self { prefix: prefix.to_string(), base: None, incrs: vec![] }
+    }
+
+    fn path(dir: &Path, prefix: &str) -> PathBuf {
+        dir.join(format!("{}.manifest", prefix))
+    }
+
+    /// parse a manifest. Lines it cannot make sense of are reported as problems and skipped
+    fn parse(prefix: &str, text: &str, problems: &mut Vec<String>) -> Self {
+        let mut manifest = Self::new(prefix);
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (mut name, mut seq, mut kind) = (None, None, None);
+            for pair in fields.chunks(2) {
+                match pair {
+                    ["file", value] => name = Some(value.to_string()),
+                    ["seq", value] => seq = value.parse::<u64>().ok(),
+                    ["type", value] => kind = Some(*value),
+                    _ => {}
+                }
+            }
+
+            let (Some(name), Some(seq), Some(kind)) = (name, seq, kind) else {
+                problems.push(format!("invalid manifest line {}: '{}'", number + 1, line));
+                continue;
+            };
+            let file = AofFile { name, seq };
+            match kind {
+                "b" => {
+                    if let Some(base) = &manifest.base {
+                        problems.push(format!("more than one base file: {} and {}", base.name, file.name));
+                    }
+                    manifest.base = Some(file);
+                }
+                "i" => {
+                    if manifest.incrs.last().is_some_and(|last| last.seq >= file.seq) {
+                        problems.push(format!("incremental file {} is out of order", file.name));
+                        continue;
+                    }
+                    manifest.incrs.push(file);
+                }
+                // history files are left over from a rewrite, they are not part of the data
+                "h" => {}
+                other => problems.push(format!("unknown file type '{}' on manifest line {}", other, number + 1)),
+            }
+        }
+        manifest
+    }
+
+    fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (file, kind) in self.base.iter().map(|base| (base, 'b')).chain(self.incrs.iter().map(|incr| (incr, 'i'))) {
+            let _ = writeln!(text, "file {} seq {} type {}", file.name, file.seq, kind);
+        }
+        text
+    }
+
+    /// write the manifest through a temp file, it is the one thing that has to be consistent
+    fn save(&self, dir: &Path) -> io::Result<()> {
+        let tmp = dir.join(format!("temp-{}.manifest", self.prefix));
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(self.to_text().as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(tmp, Self::path(dir, &self.prefix))
+    }
+
+    fn files(&self) -> impl Iterator<Item = &AofFile> {
+        self.base.iter().chain(self.incrs.iter())
+    }
+
+    /// drop the files that do not exist, reporting each of them
+    fn drop_missing(&mut self, dir: &Path, problems: &mut Vec<String>) {
+        let mut exists = |file: &AofFile| {
+            let exists = dir.join(&file.name).exists();
+            if !exists {
+                problems.push(format!("{} is listed in the manifest but does not exist", file.name));
+            }
+            exists
+        };
+        if self.base.as_ref().is_some_and(|base| !exists(base)) {
+            self.base = None;
+        }
+        self.incrs.retain(exists);
+    }
+
+    fn next_base_seq(&self) -> u64 {
+        self.base.as_ref().map_or(1, |base| base.seq + 1)
+    }
+
+    fn next_incr_seq(&self) -> u64 {
+        self.incrs.last().map_or(1, |incr| incr.seq + 1)
+    }
+
+    fn base_name(&self, seq: u64) -> String {
+        format!("{}.{}.base.rdb", self.prefix, seq)
+    }
+
+    fn incr_name(&self, seq: u64) -> String {
+        format!("{}.{}.incr.aof", self.prefix, seq)
+    }
+}
+
+impl Aof {
+    /// start a new append only file in `dir` from a snapshot of the keyspace
+    pub(crate) fn create(dir: &Path, prefix: &str, fsync: AppendFsync, entries: &[SnapshotEntry]) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut manifest = Manifest::new(prefix);
+        let base = AofFile { name: manifest.base_name(1), seq: 1 };
+        write_base(&dir.join(&base.name), entries)?;
+        manifest.base = Some(base);
+        Self::open_with(dir, manifest, fsync)
+    }
+
+    /// like `create`, but the base is written from another thread as by BGREWRITEAOF, for
+    /// CONFIG SET appendonly yes. Commands go to the incremental file right away, the manifest
+    /// is only saved once the base is complete so that a half written AOF is never loaded
+    pub(crate) fn create_in_background(dir: &Path, prefix: &str, fsync: AppendFsync, entries: Vec<SnapshotEntry>) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut manifest = Manifest::new(prefix);
+        let seq = manifest.next_incr_seq();
+        let incr = AofFile { name: manifest.incr_name(seq), seq };
+        // a leftover of an earlier run would be replayed on top of the new base
+        File::create(dir.join(&incr.name))?;
+        let file = OpenOptions::new().append(true).open(dir.join(&incr.name))?;
+        manifest.incrs.push(incr);
+
+        let aof = Self {
+            dir: dir.to_path_buf(),
+            manifest,
+            file: Arc::new(file),
+            fsync,
+            unsynced: false,
+            last_fsync: Instant::now(),
+            pending: vec![],
+            write_error: None,
+            rewrite_incr: Some(seq),
+            rewrite_result: Arc::new(Mutex::new(None)),
+        };
+        aof.write_base_in_background(entries);
+        Ok(aof)
+    }
+
+    /// open the existing append only file of `dir` for appending, after it was loaded
+    pub(crate) fn open(dir: &Path, prefix: &str, fsync: AppendFsync) -> io::Result<Self> {
+        let text = fs::read_to_string(Manifest::path(dir, prefix))?;
+        let manifest = Manifest::parse(prefix, &text, &mut vec![]);
+        Self::open_with(dir, manifest, fsync)
+    }
+
+    fn open_with(dir: &Path, mut manifest: Manifest, fsync: AppendFsync) -> io::Result<Self> {
+        // keep appending to the last incremental file, or start one
+        let name = match manifest.incrs.last() {
+            Some(incr) => incr.name.clone(),
+            None => {
+                let seq = manifest.next_incr_seq();
+                let incr = AofFile { name: manifest.incr_name(seq), seq };
+                File::create(dir.join(&incr.name))?;
+                let name = incr.name.clone();
+                manifest.incrs.push(incr);
+                manifest.save(dir)?;
+                name
+            }
+        };
+        let file = OpenOptions::new().create(true).append(true).open(dir.join(name))?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            manifest,
+            file: Arc::new(file),
+            fsync,
+            unsynced: false,
+            last_fsync: Instant::now(),
+            pending: vec![],
+            write_error: None,
+            rewrite_incr: None,
+            rewrite_result: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// log one command, already encoded as RESP. If it can't be written it is kept, and
+    /// written before the next command or by `poll`, which retries every period
+    pub(crate) fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.pending.extend_from_slice(data);
+        self.flush(self.fsync == AppendFsync::Always)
+    }
+
+    /// write the pending commands, then fsync them if `sync`. A failure is kept as the
+    /// `write_error` until a later flush succeeds
+    fn flush(&mut self, sync: bool) -> io::Result<()> {
+        let result = self.write_pending().and_then(|()| match sync {
+            true => self.file.sync_data().map(|()| self.unsynced = false),
+            false => {
+                self.unsynced = true;
+                Ok(())
+            }
+        });
+        self.write_error = result.as_ref().err().map(|e| e.to_string());
+        result
+    }
+
+    /// like `write_all`, but what was written is taken off `pending`, so a retry does not
+    /// write half a command twice
+    fn write_pending(&mut self) -> io::Result<()> {
+        while !self.pending.is_empty() {
+            match (&*self.file).write(&self.pending) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    self.pending.drain(..n);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// the last write or fsync failed, `None` once one succeeded again
+    pub(crate) fn write_error(&self) -> Option<&str> {
+        self.write_error.as_deref()
+    }
+
+    /// an `everysec` fsync run by the caller of `poll` failed
+    pub(crate) fn fsync_failed(&mut self, e: &io::Error) {
+        self.write_error = Some(e.to_string());
+    }
+
+    pub(crate) fn rewrite_in_progress(&self) -> bool {
+        self.rewrite_incr.is_some()
+    }
+
+    /// BGREWRITEAOF: switch to a new incremental file, then write `entries` as the new base
+    /// from another thread. Until it is done the manifest still replays the old base and
+    /// every incremental file
+    pub(crate) fn start_rewrite(&mut self, entries: Vec<SnapshotEntry>) -> io::Result<()> {
+        let seq = self.manifest.next_incr_seq();
+        let incr = AofFile { name: self.manifest.incr_name(seq), seq };
+        let file = OpenOptions::new().create(true).append(true).open(self.dir.join(&incr.name))?;
+        self.manifest.incrs.push(incr);
+        self.manifest.save(&self.dir)?;
+
+        // the old file is done with, make sure it is on disk before it can be replaced
+        let old = std::mem::replace(&mut self.file, Arc::new(file));
+        old.sync_data()?;
+        self.unsynced = false;
+        self.rewrite_incr = Some(seq);
+        self.write_base_in_background(entries);
+        Ok(())
+    }
+
+    /// write `entries` to a temp file from another thread, `poll` installs it as the new base
+    fn write_base_in_background(&self, entries: Vec<SnapshotEntry>) {
+        let tmp = self.dir.join(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+        let result = self.rewrite_result.clone();
+        std::thread::spawn(move || {
+            let written = write_base(&tmp, &entries).map(|()| tmp);
+            *result.lock().unwrap() = Some(written);
+        });
+    }
+
+    /// called periodically under the store lock: finish a rewrite whose base is written and
+    /// hand back the file if it is due for an `everysec` fsync, which the caller runs
+    /// without holding the lock
+    pub(crate) fn poll(&mut self) -> Option<Arc<File>> {
+        let finished = self.rewrite_result.lock().unwrap().take();
+        if let Some(result) = finished {
+            let seq = self.rewrite_incr.take().unwrap_or_default();
+            if let Err(e) = result.and_then(|tmp| self.finish_rewrite(&tmp, seq)) {
+                eprintln!("Background AOF rewrite failed: {}", e);
+            }
+        }
+
+        // 写失败之后每个周期都重试，成功之前客户端不能写
+        if self.write_error.is_some() {
+            let _ = self.flush(true);
+        }
+
+        if self.fsync == AppendFsync::EverySec && self.unsynced && self.last_fsync.elapsed() >= FSYNC_PERIOD {
+            self.unsynced = false;
+            self.last_fsync = Instant::now();
+            return Some(self.file.clone());
+        }
+        None
+    }
+
+    /// install the new base, keep the incremental files from `incr_seq` on and delete the rest
+    fn finish_rewrite(&mut self, tmp: &Path, incr_seq: u64) -> io::Result<()> {
+        let seq = self.manifest.next_base_seq();
+        let base = AofFile { name: self.manifest.base_name(seq), seq };
+        fs::rename(tmp, self.dir.join(&base.name))?;
+
+        let old = self.manifest.clone();
+        self.manifest.base = Some(base);
+        self.manifest.incrs.retain(|incr| incr.seq >= incr_seq);
+        self.manifest.save(&self.dir)?;
+
+        for file in old.files().filter(|file| !self.manifest.files().any(|kept| kept == *file)) {
+            let _ = fs::remove_file(self.dir.join(&file.name));
+        }
+        Ok(())
+    }
+
+    /// apply a CONFIG SET of `appendfsync`
+    pub(crate) fn set_fsync(&mut self, fsync: AppendFsync) {
+        self.fsync = fsync;
+    }
+
+    /// flush everything to disk, at shutdown
+    pub(crate) fn sync(&mut self) -> io::Result<()> {
+        self.flush(true)
+    }
+}
+
+/// write a base file holding the RDB snapshot of `entries`
+fn write_base(path: &Path, entries: &[SnapshotEntry]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(&rdb::encode(entries))?;
+    file.sync_all()
+}
+
+/// read the append only file of `dir`, `Ok(None)` if there is none.
+///
+/// A single file AOF from an older version found in `legacy` is moved into `dir` as the base.
+/// Files listed in the manifest that are missing or an unreadable manifest stop the load
+/// unless `repair` is set, which drops them and fixes the manifest.
+/// A command cut short at the end of the last file is what a crash in the middle of
+/// a write leaves behind: with `load_truncated` it is dropped. A truncated file anywhere
+/// else means lost data and is only tolerated with `repair`
+pub(crate) fn load(dir: &Path, prefix: &str, legacy: &Path, load_truncated: bool, repair: bool) -> io::Result<Option<AofContents>> {
+    if !Manifest::path(dir, prefix).exists() {
+        if !legacy.exists() {
+            return Ok(None);
+        }
+        upgrade(dir, prefix, legacy)?;
+    }
+
+    let mut report = CheckReport::default();
+    let manifest = read_manifest(dir, prefix, repair, &mut report)?;
+    if !report.problems.is_empty() && !repair {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!(
+            "the AOF manifest is inconsistent: {}. Fix it with aof_check --fix or set aof_load_repair",
+            report.problems.join("; "))));
+    }
+
+    let mut contents = AofContents::default();
+    let count = manifest.files().count();
+    for (i, file) in manifest.files().enumerate() {
+        let path = dir.join(&file.name);
+        let last = i + 1 == count;
+        let file_report = read_file(&path, &mut contents)?;
+        if let Some((valid, _)) = file_report.truncated {
+            if !(repair || (last && load_truncated)) {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, format!(
+                    "unexpected end of file reading the append only file {}", path.display())));
+            }
+            eprintln!("!!! Warning: short read while loading the AOF file {}, truncating it to {} bytes", path.display(), valid);
+            OpenOptions::new().write(true).open(&path)?.set_len(valid)?;
+        }
+    }
+    Ok(Some(contents))
+}
+
+/// check an append only file without loading it into a server, like `redis-check-aof`.
+/// `path` is either a manifest or a single AOF file. With `fix`, truncated files are cut back
+/// to their last complete command and missing files are dropped from the manifest
+pub fn check(path: &Path, fix: bool) -> io::Result<CheckReport> {
+    let mut report = CheckReport::default();
+    let mut contents = AofContents::default();
+
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+    let files = match name.strip_suffix(".manifest") {
+        Some(prefix) => {
+            let dir = path.parent().unwrap_or(Path::new("."));
+            let manifest = read_manifest(dir, prefix, fix, &mut report)?;
+            manifest.files().map(|file| dir.join(&file.name)).collect()
+        }
+        None => vec![path.to_path_buf()],
+    };
+
+    for file in files {
+        let file_report = read_file(&file, &mut contents)?;
+        if let (true, Some((valid, _))) = (fix, file_report.truncated) {
+            OpenOptions::new().write(true).open(&file)?.set_len(valid)?;
+        }
+        report.files.push(file_report);
+    }
+    Ok(report)
+}
+
+/// read and check the manifest, saving the repaired version when asked to
+fn read_manifest(dir: &Path, prefix: &str, repair: bool, report: &mut CheckReport) -> io::Result<Manifest> {
+    let text = fs::read_to_string(Manifest::path(dir, prefix))?;
+    let mut manifest = Manifest::parse(prefix, &text, &mut report.problems);
+    manifest.drop_missing(dir, &mut report.problems);
+    if manifest.base.is_none() && manifest.incrs.is_empty() {
+        report.problems.push("the manifest lists no file".to_string());
+    }
+
+    if repair && !report.problems.is_empty() {
+        for problem in &report.problems {
+            eprintln!("Repairing the AOF manifest: {}", problem);
+        }
+        manifest.save(dir)?;
+    }
+    Ok(manifest)
+}
+
+/// turn the single file AOF of older versions into the base of a multi-part one
+fn upgrade(dir: &Path, prefix: &str, legacy: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let mut manifest = Manifest::new(prefix);
+    let base = AofFile { name: format!("{}.1.base.aof", prefix), seq: 1 };
+    fs::rename(legacy, dir.join(&base.name))?;
+    manifest.base = Some(base);
+    manifest.save(dir)
+}
+
+/// add the keys and commands of one file to `contents`. A transaction without its EXEC
+/// at the end of the file counts as truncated like an incomplete command
+fn read_file(path: &Path, contents: &mut AofContents) -> io::Result<FileReport> {
+    let data = fs::read(path)?;
+    let mut report = FileReport { path: path.to_path_buf(), keys: 0, commands: 0, truncated: None };
+
+    let mut pos = 0;
+    if data.starts_with(b"REDIS") {
+        let (preamble, len) = rdb::decode_prefix(&data)?;
+        report.keys = preamble.len();
+        contents.preamble.extend(preamble);
+        pos = len;
+    }
+
+    // the end of the last complete command, or of the last EXEC inside a transaction
+    let mut valid = pos;
+    let mut transaction: Option<Vec<Frame>> = None;
+    while pos < data.len() {
+        let mut cursor = Cursor::new(&data[pos..]);
+        match check_frame(&mut cursor) {
+            Ok(()) => {}
+            Err(FrameError::Incomplete) => break,
+            Err(e) => return Err(io::Error::new(ErrorKind::InvalidData, format!(
+                "bad AOF format in {} at offset {}: {}", path.display(), pos, e))),
+        }
+        let len = cursor.position() as usize;
+        let frame = Frame::parse_protocol(&data[pos..pos + len])
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+        pos += len;
+
+        match command_name(&frame).as_str() {
+            "MULTI" => transaction = Some(vec![]),
+            "EXEC" => {
+                let queued = transaction.take().unwrap_or_default();
+                report.commands += queued.len();
+                contents.commands.extend(queued);
+                valid = pos;
+            }
+            _ => match transaction.as_mut() {
+                Some(queued) => queued.push(frame),
+                None => {
+                    report.commands += 1;
+                    contents.commands.push(frame);
+                    valid = pos;
+                }
+            },
+        }
+    }
+
+    if valid < data.len() {
+        report.truncated = Some((valid as u64, data.len() as u64));
+    }
+    Ok(report)
+}
+
+fn command_name(frame: &Frame) -> String {
+    match frame {
+        Frame::Array(array) => match array.first() {
+            Some(Frame::Bulk(name)) => String::from_utf8_lossy(name).to_uppercase(),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+
+    use crate::aof::{check, load, Aof, Manifest};
+    use crate::config::AppendFsync;
+    use crate::db::RedisDataType;
+    use crate::frame::encode_command;
+
+    fn argv(args: &str) -> Vec<Bytes> {
+        args.split(' ').map(|arg| Bytes::from(arg.to_string())).collect()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mini-redis-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn manifest_test() {
+        let text = "file appendonly.aof.2.base.rdb seq 2 type b\nfile appendonly.aof.1.incr.aof seq 1 type h\n\
+                    file appendonly.aof.3.incr.aof seq 3 type i\nfile appendonly.aof.4.incr.aof seq 4 type i\n";
+        let mut problems = vec![];
+        let manifest = Manifest::parse("appendonly.aof", text, &mut problems);
+        assert!(problems.is_empty());
+        assert_eq!(manifest.files().count(), 3);
+        assert_eq!(manifest.next_incr_seq(), 5);
+        assert_eq!(Manifest::parse("appendonly.aof", &manifest.to_text(), &mut problems), manifest);
+
+        Manifest::parse("appendonly.aof", "file a seq 1 type b\nfile b seq 2 type b\nbroken\n", &mut problems);
+        assert_eq!(problems.len(), 2);
+    }
+
+    #[test]
+    fn append_load_test() {
+        let dir = temp_dir("aof");
+        let legacy = dir.join("missing.aof");
+
+        let entries = vec![("base".to_string(), RedisDataType::Bytes(Bytes::from("v")), None)];
+        let mut aof = Aof::create(&dir, "appendonly.aof", AppendFsync::Always, &entries).unwrap();
+        for args in ["SET a 1", "MULTI", "INCR a", "EXEC"] {
+            aof.append(&encode_command(&argv(args))).unwrap();
+        }
+        drop(aof);
+
+        let incr = dir.join("appendonly.aof.1.incr.aof");
+        let complete = std::fs::metadata(&incr).unwrap().len();
+        // a crash in the middle of the next command
+        std::fs::OpenOptions::new().append(true).open(&incr).unwrap()
+            .write_all(b"*3\r\n$3\r\nSET\r\n$1\r\nb").unwrap();
+
+        assert!(!check(&dir.join("appendonly.aof.manifest"), false).unwrap().is_ok());
+        assert!(load(&dir, "appendonly.aof", &legacy, false, false).is_err());
+        let contents = load(&dir, "appendonly.aof", &legacy, true, false).unwrap().unwrap();
+        assert_eq!(contents.preamble.len(), 1);
+        assert_eq!(contents.commands.len(), 2);
+        assert_eq!(std::fs::metadata(&incr).unwrap().len(), complete);
+
+        // a file listed in the manifest is gone
+        std::fs::remove_file(dir.join("appendonly.aof.1.base.rdb")).unwrap();
+        assert!(load(&dir, "appendonly.aof", &legacy, true, false).is_err());
+        let contents = load(&dir, "appendonly.aof", &legacy, true, true).unwrap().unwrap();
+        assert_eq!(contents.commands.len(), 2);
+        assert!(check(&dir.join("appendonly.aof.manifest"), false).unwrap().is_ok());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_error_test() {
+        let dir = temp_dir("aof-write-error");
+        let mut aof = Aof::create(&dir, "appendonly.aof", AppendFsync::Always, &[]).unwrap();
+        let incr = dir.join("appendonly.aof.1.incr.aof");
+
+        // the disk is full: the command is kept, and written once there is room again
+        let file = std::mem::replace(&mut aof.file, Arc::new(OpenOptions::new().append(true).open("/dev/full").unwrap()));
+        assert!(aof.append(&encode_command(&argv("SET a 1"))).is_err());
+        assert!(aof.write_error().is_some());
+        aof.poll();
+        assert!(aof.write_error().is_some());
+
+        aof.file = file;
+        aof.poll();
+        assert!(aof.write_error().is_none());
+        aof.append(&encode_command(&argv("SET b 2"))).unwrap();
+        let written = std::fs::read(&incr).unwrap();
+        assert_eq!(written, [encode_command(&argv("SET a 1")), encode_command(&argv("SET b 2"))].concat());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn upgrade_test() {
+        let dir = temp_dir("aof-upgrade");
+        std::fs::create_dir_all(&dir).unwrap();
+        let legacy = dir.join("appendonly.aof");
+        std::fs::write(&legacy, b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n").unwrap();
+
+        let aof_dir = dir.join("appendonlydir");
+        let contents = load(&aof_dir, "appendonly.aof", &legacy, true, false).unwrap().unwrap();
+        assert_eq!(contents.commands.len(), 1);
+        assert!(!legacy.exists());
+        assert!(aof_dir.join("appendonly.aof.1.base.aof").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,58 @@
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use my_own_mini_redis::aof::check;
+
+/// Check an append only file, like `redis-check-aof`.
+///
+/// Usage: aof_check [--fix] <appendonly.aof.manifest | file.aof>
+///
+/// With `--fix`, files ending with an incomplete command are truncated to the last complete
+/// one and files missing from the manifest are dropped from it
+fn main() -> ExitCode {
+    let mut fix = false;
+    let mut path = None;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--fix" => fix = true,
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+
+    let Some(path) = path else {
+        eprintln!("Usage: aof_check [--fix] <appendonly.aof.manifest | file.aof>");
+        return ExitCode::FAILURE;
+    };
+
+    let report = match check(&path, fix) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Bad file format reading the append only file {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for problem in &report.problems {
+        println!("{}{}", if fix { "Fixed: " } else { "" }, problem);
+    }
+    for file in &report.files {
+        match file.truncated {
+            Some((valid, total)) => println!(
+                "{}: {} keys, {} commands, {} of {} bytes valid{}",
+                file.path.display(), file.keys, file.commands, valid, total,
+                if fix { ", truncated" } else { "" }),
+            None => println!("{}: {} keys, {} commands, OK", file.path.display(), file.keys, file.commands),
+        }
+    }
+
+    if report.is_ok() {
+        println!("AOF is valid");
+        ExitCode::SUCCESS
+    } else if fix {
+        println!("Successfully repaired the AOF");
+        ExitCode::SUCCESS
+    } else {
+        println!("AOF is not valid, use --fix to repair it");
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,109 @@
+//! Splitting a typed line into arguments, with the quoting rules of redis-cli
+
+use std::iter::Peekable;
+use std::str;
+
+use bytes::Bytes;
+
+/// split a line on whitespace. Double quoted arguments understand `\n`, `\r`, `\t`, `\b`,
+/// `\a`, `\\`, `\"` and `\xHH`, single quoted ones only `\'`. A closing quote has to be
+/// followed by a space or the end of the line. `None` for unbalanced quotes
+pub(crate) fn split_args(line: &str) -> Option<Vec<Bytes>> {
+    let mut args = vec![];
+    let mut chars = line.bytes().peekable();
+    loop {
+        while chars.next_if(u8::is_ascii_whitespace).is_some() {}
+        if chars.peek().is_none() {
+            return Some(args);
+        }
+
+        let mut arg = vec![];
+        let mut in_double = false;
+        let mut in_single = false;
+        loop {
+            let c = chars.next();
+            if in_double {
+                match c? {
+                    b'\\' => match chars.next()? {
+                        // 不是两位十六进制时和 redis-cli 一样只留下 x
+                        b'x' => match hex_byte(&mut chars) {
+                            Some(byte) => arg.push(byte),
+                            None => arg.push(b'x'),
+                        },
+                        b'n' => arg.push(b'\n'),
+                        b'r' => arg.push(b'\r'),
+                        b't' => arg.push(b'\t'),
+                        b'b' => arg.push(0x08),
+                        b'a' => arg.push(0x07),
+                        other => arg.push(other),
+                    },
+                    b'"' => {
+                        if chars.peek().is_some_and(|c| !c.is_ascii_whitespace()) {
+                            return None;
+                        }
+                        break;
+                    }
+                    other => arg.push(other),
+                }
+            } else if in_single {
+                match c? {
+                    b'\\' if chars.peek() == Some(&b'\'') => {
+                        chars.next();
+                        arg.push(b'\'');
+                    }
+                    b'\'' => {
+                        if chars.peek().is_some_and(|c| !c.is_ascii_whitespace()) {
+                            return None;
+                        }
+                        break;
+                    }
+                    other => arg.push(other),
+                }
+            } else {
+                match c {
+                    None => break,
+                    Some(c) if c.is_ascii_whitespace() => break,
+                    Some(b'"') => in_double = true,
+                    Some(b'\'') => in_single = true,
+                    Some(other) => arg.push(other),
+                }
+            }
+        }
+        args.push(Bytes::from(arg));
+    }
+}
+
+/// the byte of the next two hex digits, consumed only when both are there
+fn hex_byte(chars: &mut Peekable<str::Bytes<'_>>) -> Option<u8> {
+    let mut lookahead = chars.clone();
+    let high = (lookahead.next()? as char).to_digit(16)?;
+    let low = (lookahead.next()? as char).to_digit(16)?;
+    *chars = lookahead;
+    Some((high * 16 + low) as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use crate::args::split_args;
+
+    fn split(line: &str) -> Option<Vec<&'static str>> {
+        split_args(line).map(|args| args.into_iter()
+            .map(|arg: Bytes| &*String::from_utf8(arg.to_vec()).unwrap().leak())
+            .collect())
+    }
+
+    #[test]
+    fn split_args_test() {
+        assert_eq!(split("  set  key value "), Some(vec!["set", "key", "value"]));
+        assert_eq!(split(r#"set "hello world" 'it\'s'"#), Some(vec!["set", "hello world", "it's"]));
+        assert_eq!(split(r#"set k "a\nb\x41\"""#), Some(vec!["set", "k", "a\nbA\""]));
+        assert_eq!(split(r#"set k "\xZZ""#), Some(vec!["set", "k", "xZZ"]));
+        assert_eq!(split(r#"set k 'a\nb'"#), Some(vec!["set", "k", "a\\nb"]));
+        assert_eq!(split(r#"set "" ''"#), Some(vec!["set", "", ""]));
+        assert_eq!(split(""), Some(vec![]));
+        assert_eq!(split(r#"set "unbalanced"#), None);
+        assert_eq!(split(r#"set "a"b"#), None);
+    }
+}
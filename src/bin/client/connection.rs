@@ -0,0 +1,77 @@
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+use my_own_mini_redis::codec::{RedisCodec, RedisFrame};
+use my_own_mini_redis::RedisResult;
+
+use crate::Options;
+
+pub(crate) type Connection = Framed<TcpStream, RedisCodec>;
+
+/// open a connection, authenticate and select the database asked for on the command line
+pub(crate) async fn connect(options: &Options) -> RedisResult<Connection> {
+    let stream = TcpStream::connect((options.host.as_str(), options.port)).await
+        .map_err(|e| format!("Could not connect to Redis at {}:{}: {}", options.host, options.port, io_message(&e)))?;
+    let mut connection = Framed::new(stream, RedisCodec);
+
+    if let Some(password) = &options.password {
+        let mut argv = vec!["AUTH"];
+        argv.extend(options.user.as_deref());
+        argv.push(password);
+        call(&mut connection, &argv).await.map_err(|e| format!("AUTH failed: {}", e))?;
+    }
+    if options.db != 0 {
+        call(&mut connection, &["SELECT", &options.db.to_string()]).await
+            .map_err(|e| format!("SELECT {} failed: {}", options.db, e))?;
+    }
+    Ok(connection)
+}
+
+/// an io error the way redis-cli words it, without the os error number
+pub(crate) fn io_message(e: &std::io::Error) -> String {
+    let message = e.to_string();
+    match message.find(" (os error") {
+        Some(at) => message[..at].to_string(),
+        None => message,
+    }
+}
+
+/// send a command and read its reply, whatever it is
+pub(crate) async fn send(connection: &mut Connection, argv: Vec<Bytes>) -> RedisResult<RedisFrame> {
+    connection.send(RedisFrame::Array(argv.into_iter().map(RedisFrame::Bulk).collect())).await?;
+    read(connection).await
+}
+
+/// the next frame the server sends
+pub(crate) async fn read(connection: &mut Connection) -> RedisResult<RedisFrame> {
+    match connection.next().await {
+        Some(reply) => Ok(reply?),
+        None => Err("Server closed the connection".into()),
+    }
+}
+
+/// send several commands at once and read their replies
+pub(crate) async fn pipeline(connection: &mut Connection, commands: Vec<Vec<Bytes>>) -> RedisResult<Vec<RedisFrame>> {
+    let count = commands.len();
+    for argv in commands {
+        connection.feed(RedisFrame::Array(argv.into_iter().map(RedisFrame::Bulk).collect())).await?;
+    }
+    connection.flush().await?;
+
+    let mut replies = Vec::with_capacity(count);
+    while replies.len() < count {
+        replies.push(read(connection).await?);
+    }
+    Ok(replies)
+}
+
+/// send a command, an error reply becomes an `Err`
+pub(crate) async fn call(connection: &mut Connection, argv: &[&str]) -> RedisResult<RedisFrame> {
+    let argv = argv.iter().map(|arg| Bytes::from(arg.to_string())).collect();
+    match send(connection, argv).await? {
+        RedisFrame::Error(e) => Err(e.into()),
+        reply => Ok(reply),
+    }
+}
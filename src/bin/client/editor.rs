@@ -0,0 +1,283 @@
+//! A small line editor for the interactive mode: cursor moves, history browsing and a
+//! history file kept between sessions, in the spirit of the linenoise used by redis-cli
+
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// entries kept in memory and in the history file
+const HISTORY_MAX: usize = 100;
+
+/// 按键对应的字节
+const CTRL_A: u8 = 1;
+const CTRL_B: u8 = 2;
+const CTRL_C: u8 = 3;
+const CTRL_D: u8 = 4;
+const CTRL_E: u8 = 5;
+const CTRL_F: u8 = 6;
+const CTRL_H: u8 = 8;
+const CTRL_K: u8 = 11;
+const CTRL_L: u8 = 12;
+const CTRL_N: u8 = 14;
+const CTRL_P: u8 = 16;
+const CTRL_U: u8 = 21;
+const CTRL_W: u8 = 23;
+const ESC: u8 = 27;
+const BACKSPACE: u8 = 127;
+
+pub(crate) struct Editor {
+    history: Vec<String>,
+    /// where the history is saved, `None` when there is no home to save it in
+    path: Option<PathBuf>,
+    terminal: bool,
+}
+
+impl Editor {
+    /// an editor with the history of the previous sessions, from `$REDISCLI_HISTFILE`
+    /// or `~/.rediscli_history`
+    pub(crate) fn new() -> Self {
+        let path = match std::env::var_os("REDISCLI_HISTFILE") {
+            Some(path) if path.is_empty() => None,
+            Some(path) => Some(PathBuf::from(path)),
+            None => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rediscli_history")),
+        };
+        let history = path.as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|content| content.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+
+        Self { history, path, terminal: io::stdin().is_terminal() }
+    }
+
+    /// whether a person is typing, otherwise lines are read as they come without a prompt
+    pub(crate) fn is_terminal(&self) -> bool {
+        self.terminal
+    }
+
+    /// the next line, `None` at the end of the input or when the user pressed Ctrl-C or
+    /// Ctrl-D on an empty line
+    pub(crate) fn read_line(&mut self, prompt: &str) -> io::Result<Option<String>> {
+        if !self.terminal {
+            let mut line = String::new();
+            if io::stdin().lock().read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            return Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()));
+        }
+
+        let _raw = RawMode::enable()?;
+        let line = LineState::new(prompt, &self.history).edit();
+        // 回车之后换到新的一行
+        print!("\r\n");
+        io::stdout().flush()?;
+        line
+    }
+
+    /// remember a line, and save the history right away so a killed client keeps it
+    pub(crate) fn add_history(&mut self, line: &str) {
+        if self.history.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        self.history.push(line.to_string());
+        if self.history.len() > HISTORY_MAX {
+            self.history.remove(0);
+        }
+        if let Some(path) = &self.path {
+            let mut content = self.history.join("\n");
+            content.push('\n');
+            let _ = fs::write(path, content);
+        }
+    }
+}
+
+/// clear the screen and move the cursor to the top
+pub(crate) fn clear_screen() {
+    print!("\x1b[H\x1b[2J");
+    let _ = io::stdout().flush();
+}
+
+/// the terminal settings as `stty -g` printed them, put back when dropped
+struct RawMode {
+    saved: String,
+}
+
+impl RawMode {
+    /// keys are read one by one, not echoed, and Ctrl-C comes as a byte instead of a signal
+    fn enable() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "-isig", "-ixon", "-icrnl", "min", "1", "time", "0"])?;
+        Ok(Self { saved: saved.trim().to_string() })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!("stty {} failed", args.join(" "))));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// the line being edited
+struct LineState<'a> {
+    prompt: &'a str,
+    history: &'a [String],
+    chars: Vec<char>,
+    cursor: usize,
+    /// the history entry shown, `history.len()` for the line being typed
+    index: usize,
+    /// what was typed before browsing the history
+    typed: Vec<char>,
+}
+
+impl<'a> LineState<'a> {
+    fn new(prompt: &'a str, history: &'a [String]) -> Self {
+        Self { prompt, history, chars: vec![], cursor: 0, index: history.len(), typed: vec![] }
+    }
+
+    fn edit(mut self) -> io::Result<Option<String>> {
+        self.refresh()?;
+        let mut stdin = io::stdin().lock();
+        loop {
+            let Some(byte) = read_byte(&mut stdin)? else {
+                return Ok(None);
+            };
+            match byte {
+                b'\r' | b'\n' => return Ok(Some(self.chars.iter().collect())),
+                CTRL_C => return Ok(None),
+                CTRL_D if self.chars.is_empty() => return Ok(None),
+                CTRL_D => self.delete(),
+                BACKSPACE | CTRL_H => self.backspace(),
+                CTRL_A => self.cursor = 0,
+                CTRL_E => self.cursor = self.chars.len(),
+                CTRL_B => self.cursor = self.cursor.saturating_sub(1),
+                CTRL_F => self.cursor = (self.cursor + 1).min(self.chars.len()),
+                CTRL_P => self.browse(-1),
+                CTRL_N => self.browse(1),
+                CTRL_U => {
+                    self.chars.drain(..self.cursor);
+                    self.cursor = 0;
+                }
+                CTRL_K => self.chars.truncate(self.cursor),
+                CTRL_W => {
+                    // 删除光标前的一个单词
+                    let mut start = self.cursor;
+                    while start > 0 && self.chars[start - 1] == ' ' {
+                        start -= 1;
+                    }
+                    while start > 0 && self.chars[start - 1] != ' ' {
+                        start -= 1;
+                    }
+                    self.chars.drain(start..self.cursor);
+                    self.cursor = start;
+                }
+                CTRL_L => clear_screen(),
+                ESC => self.escape(&mut stdin)?,
+                byte if byte >= b' ' => {
+                    if let Some(c) = read_char(&mut stdin, byte)? {
+                        self.chars.insert(self.cursor, c);
+                        self.cursor += 1;
+                    }
+                }
+                _ => {}
+            }
+            self.refresh()?;
+        }
+    }
+
+    /// arrows, Home, End and Delete come as `ESC [ x` or `ESC O x`
+    fn escape(&mut self, stdin: &mut impl Read) -> io::Result<()> {
+        let (Some(kind), Some(key)) = (read_byte(stdin)?, read_byte(stdin)?) else {
+            return Ok(());
+        };
+        match (kind, key) {
+            (b'[', b'A') | (b'O', b'A') => self.browse(-1),
+            (b'[', b'B') | (b'O', b'B') => self.browse(1),
+            (b'[', b'C') | (b'O', b'C') => self.cursor = (self.cursor + 1).min(self.chars.len()),
+            (b'[', b'D') | (b'O', b'D') => self.cursor = self.cursor.saturating_sub(1),
+            (b'[', b'H') | (b'O', b'H') => self.cursor = 0,
+            (b'[', b'F') | (b'O', b'F') => self.cursor = self.chars.len(),
+            (b'[', digit) if digit.is_ascii_digit() && read_byte(stdin)? == Some(b'~') => match digit {
+                b'1' | b'7' => self.cursor = 0,
+                b'4' | b'8' => self.cursor = self.chars.len(),
+                b'3' => self.delete(),
+                _ => {}
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.chars.len() {
+            self.chars.remove(self.cursor);
+        }
+    }
+
+    /// move through the history, -1 for older entries and 1 for newer ones
+    fn browse(&mut self, step: isize) {
+        let Some(index) = self.index.checked_add_signed(step).filter(|&index| index <= self.history.len()) else {
+            return;
+        };
+        if self.index == self.history.len() {
+            self.typed = self.chars.clone();
+        }
+        self.index = index;
+        self.chars = match self.history.get(index) {
+            Some(entry) => entry.chars().collect(),
+            None => self.typed.clone(),
+        };
+        self.cursor = self.chars.len();
+    }
+
+    /// redraw the prompt and the line, and put the cursor back where it belongs
+    fn refresh(&self) -> io::Result<()> {
+        let line: String = self.chars.iter().collect();
+        let column = self.prompt.chars().count() + self.cursor;
+        let mut out = io::stdout().lock();
+        write!(out, "\r{}{}\x1b[0K\r", self.prompt, line)?;
+        if column > 0 {
+            write!(out, "\x1b[{}C", column)?;
+        }
+        out.flush()
+    }
+}
+
+fn read_byte(input: &mut impl Read) -> io::Result<Option<u8>> {
+    let mut byte = [0u8];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// the character `first` starts, reading the rest of its UTF-8 sequence
+fn read_char(input: &mut impl Read, first: u8) -> io::Result<Option<char>> {
+    let len = match first {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => 1,
+    };
+    let mut bytes = vec![first; len];
+    input.read_exact(&mut bytes[1..])?;
+    Ok(std::str::from_utf8(&bytes).ok().and_then(|s| s.chars().next()))
+}
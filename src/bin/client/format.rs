@@ -0,0 +1,140 @@
+//! How replies are printed, the three output modes of redis-cli
+
+use std::fmt::Write;
+
+use my_own_mini_redis::codec::RedisFrame;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Output {
+    /// typed and indented for humans: `(integer) 1`, `"value"`, `1) ...`
+    Tty,
+    /// the bare values, one per line, the default when stdout is not a terminal
+    Raw,
+    /// comma separated and quoted
+    Csv,
+}
+
+/// a whole reply ready to be written, with its trailing newline
+pub(crate) fn format_reply(reply: &RedisFrame, output: Output) -> Vec<u8> {
+    match output {
+        Output::Tty => tty(reply, "").into_bytes(),
+        Output::Raw => {
+            let mut out = raw(reply);
+            out.push(b'\n');
+            out
+        }
+        Output::Csv => {
+            let mut out = csv(reply);
+            out.push('\n');
+            out.into_bytes()
+        }
+    }
+}
+
+/// nested arrays are indented under the index of their parent by `prefix`
+fn tty(reply: &RedisFrame, prefix: &str) -> String {
+    match reply {
+        RedisFrame::Simple(status) => format!("{}\n", status),
+        RedisFrame::Error(e) => format!("(error) {}\n", e),
+        RedisFrame::Integer(n) => format!("(integer) {}\n", n),
+        RedisFrame::Bulk(bytes) => format!("{}\n", repr(bytes)),
+        RedisFrame::Null | RedisFrame::NullArray => "(nil)\n".to_string(),
+        RedisFrame::Array(items) if items.is_empty() => "(empty array)\n".to_string(),
+        RedisFrame::Array(items) => {
+            // indexes are right aligned on the width of the largest one
+            let width = items.len().to_string().len();
+            let nested = format!("{}{}", prefix, " ".repeat(width + 2));
+            let mut out = String::new();
+            for (i, item) in items.iter().enumerate() {
+                // the parent already wrote the prefix of the first line
+                let _ = write!(out, "{}{:>width$}) ", if i == 0 { "" } else { prefix }, i + 1, width = width);
+                out.push_str(&tty(item, &nested));
+            }
+            out
+        }
+    }
+}
+
+fn raw(reply: &RedisFrame) -> Vec<u8> {
+    match reply {
+        RedisFrame::Simple(s) | RedisFrame::Error(s) => s.clone().into_bytes(),
+        RedisFrame::Integer(n) => n.to_string().into_bytes(),
+        RedisFrame::Bulk(bytes) => bytes.to_vec(),
+        RedisFrame::Null | RedisFrame::NullArray => vec![],
+        RedisFrame::Array(items) => items.iter()
+            .map(raw)
+            .collect::<Vec<_>>()
+            .join(&b'\n'),
+    }
+}
+
+fn csv(reply: &RedisFrame) -> String {
+    match reply {
+        RedisFrame::Simple(s) => repr(s.as_bytes()),
+        RedisFrame::Error(e) => format!("ERROR,{}", repr(e.as_bytes())),
+        RedisFrame::Integer(n) => n.to_string(),
+        RedisFrame::Bulk(bytes) => repr(bytes),
+        RedisFrame::Null | RedisFrame::NullArray => "NULL".to_string(),
+        RedisFrame::Array(items) => items.iter().map(csv).collect::<Vec<_>>().join(","),
+    }
+}
+
+/// a string quoted and escaped the way redis-cli prints it
+pub(crate) fn repr(bytes: &[u8]) -> String {
+    let mut repr = String::from("\"");
+    for &byte in bytes {
+        match byte {
+            b'\\' => repr.push_str("\\\\"),
+            b'"' => repr.push_str("\\\""),
+            b'\n' => repr.push_str("\\n"),
+            b'\r' => repr.push_str("\\r"),
+            b'\t' => repr.push_str("\\t"),
+            0x07 => repr.push_str("\\a"),
+            0x08 => repr.push_str("\\b"),
+            byte if byte.is_ascii_graphic() || byte == b' ' => repr.push(byte as char),
+            byte => {
+                let _ = write!(repr, "\\x{:02x}", byte);
+            }
+        }
+    }
+    repr.push('"');
+    repr
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use my_own_mini_redis::codec::RedisFrame;
+
+    use crate::format::{format_reply, Output};
+
+    fn format(reply: &RedisFrame, output: Output) -> String {
+        String::from_utf8(format_reply(reply, output)).unwrap()
+    }
+
+    #[test]
+    fn format_test() {
+        let bulk = |s: &'static str| RedisFrame::Bulk(Bytes::from(s));
+        let mut items: Vec<RedisFrame> = (0..9).map(|_| bulk("x")).collect();
+        items.push(RedisFrame::Array(vec![RedisFrame::Integer(1), RedisFrame::Array(vec![bulk("a\n"), RedisFrame::Null])]));
+        items.push(RedisFrame::Array(vec![]));
+        let reply = RedisFrame::Array(items);
+
+        let tty = format(&reply, Output::Tty);
+        let lines: Vec<&str> = tty.lines().collect();
+        assert_eq!(lines[0], r#" 1) "x""#);
+        assert_eq!(lines[9], "10) 1) (integer) 1");
+        assert_eq!(lines[10], "    2) 1) \"a\\n\"");
+        assert_eq!(lines[11], "       2) (nil)");
+        assert_eq!(lines[12], "11) (empty array)");
+
+        assert_eq!(format(&RedisFrame::Error("ERR oops".to_string()), Output::Tty), "(error) ERR oops\n");
+        assert_eq!(format(&RedisFrame::Simple("OK".to_string()), Output::Tty), "OK\n");
+
+        let reply = RedisFrame::Array(vec![bulk("a b"), RedisFrame::Integer(2), RedisFrame::Null]);
+        assert_eq!(format(&reply, Output::Raw), "a b\n2\n\n");
+        assert_eq!(format(&reply, Output::Csv), "\"a b\",2,NULL\n");
+        assert_eq!(format(&RedisFrame::Error("ERR x".to_string()), Output::Csv), "ERROR,\"ERR x\"\n");
+    }
+}
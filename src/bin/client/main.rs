@@ -0,0 +1,368 @@
+//! A redis-cli style client: an interactive prompt, one-shot commands, mass insertion
+//! with --pipe and the keyspace reports of --bigkeys, --memkeys and --hotkeys
+
+mod args;
+mod connection;
+mod editor;
+mod format;
+mod scan;
+
+use std::io::{IsTerminal, Write};
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::codec::{Decoder, FramedRead};
+
+use my_own_mini_redis::codec::{RedisCodec, RedisFrame};
+use my_own_mini_redis::RedisResult;
+
+use crate::args::split_args;
+use crate::connection::{connect, read, send, Connection};
+use crate::editor::{clear_screen, Editor};
+use crate::format::{format_reply, Output};
+
+const USAGE: &str = "\
+Usage: client [OPTIONS] [cmd [arg [arg ...]]]
+  -h <hostname>      Server hostname (default: 127.0.0.1).
+  -p <port>          Server port (default: 6379).
+  -n <db>            Database number.
+  -a <password>      Password to use when connecting to the server.
+                     You can also use the REDISCLI_AUTH environment variable.
+  --pass <password>  Alias of -a.
+  --user <username>  Used to send ACL style 'AUTH username pass'. Needs -a.
+  -r <repeat>        Execute specified command N times, -1 for ever.
+  -i <interval>      When -r is used, waits <interval> seconds per command.
+                     It is possible to specify sub-second times like -i 0.1.
+                     Also the pause between SCAN calls of the modes below.
+  --raw              Use raw formatting for replies (default when STDOUT is
+                     not a tty).
+  --no-raw           Force formatted output even when STDOUT is not a tty.
+  --csv              Output in CSV format.
+  --pipe             Transfer raw Redis protocol from stdin to server.
+  --bigkeys          Sample Redis keys looking for keys with many elements (complexity).
+  --memkeys          Sample Redis keys looking for keys consuming a lot of memory.
+  --memkeys-samples <n> Sample Redis keys looking for keys consuming a lot of memory.
+                     And define number of key elements to sample
+  --hotkeys          Sample Redis keys looking for hot keys.
+                     only works when maxmemory-policy is *lfu.
+  --help             Output this help and exit.";
+
+/// where to connect and how to log in, kept up to date by SELECT and AUTH in the prompt
+/// so a reconnection lands in the same place
+pub(crate) struct Options {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    pub(crate) db: u32,
+    pub(crate) user: Option<String>,
+    pub(crate) password: Option<String>,
+}
+
+enum Mode {
+    /// the prompt, or the commands of stdin one per line when it is not a terminal
+    Repl,
+    Command(Vec<Bytes>),
+    Pipe,
+    BigKeys,
+    MemKeys(u64),
+    HotKeys,
+}
+
+/// everything the command line asks for
+struct Cli {
+    options: Options,
+    mode: Mode,
+    output: Output,
+    /// -1 repeats for ever
+    repeat: i64,
+    interval: Option<Duration>,
+}
+
+impl Cli {
+    fn parse(mut args: impl Iterator<Item = String>) -> RedisResult<Cli> {
+        let mut options = Options {
+            host: "127.0.0.1".to_string(),
+            port: 6379,
+            db: 0,
+            user: None,
+            password: std::env::var("REDISCLI_AUTH").ok(),
+        };
+        let mut mode = Mode::Repl;
+        let mut output = if std::io::stdout().is_terminal() { Output::Tty } else { Output::Raw };
+        let mut repeat = 1;
+        let mut interval = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("Unrecognized option or bad number of args for: '{}'", arg));
+            match arg.as_str() {
+                "-h" => options.host = value()?,
+                "-p" => options.port = value()?.parse()?,
+                "-n" => options.db = value()?.parse()?,
+                "-a" | "--pass" => {
+                    options.password = Some(value()?);
+                    eprintln!("Warning: Using a password with '-a' or '-u' option on the command line interface may not be safe.");
+                }
+                "--user" => options.user = Some(value()?),
+                "-r" => repeat = value()?.parse()?,
+                "-i" => interval = Some(Duration::from_secs_f64(value()?.parse()?)),
+                "--raw" => output = Output::Raw,
+                "--no-raw" => output = Output::Tty,
+                "--csv" => output = Output::Csv,
+                "--pipe" => mode = Mode::Pipe,
+                "--bigkeys" => mode = Mode::BigKeys,
+                "--memkeys" => mode = Mode::MemKeys(0),
+                "--memkeys-samples" => mode = Mode::MemKeys(value()?.parse()?),
+                "--hotkeys" => mode = Mode::HotKeys,
+                "--help" => {
+                    println!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                other if other.starts_with('-') && other.len() > 1 => {
+                    return Err(format!("Unrecognized option or bad number of args for: '{}'", other).into());
+                }
+                // 第一个不是选项的参数开始就是要执行的命令
+                _ => {
+                    let argv = std::iter::once(arg).chain(args.by_ref()).map(Bytes::from).collect();
+                    mode = Mode::Command(argv);
+                }
+            }
+        }
+
+        Ok(Cli { options, mode, output, repeat, interval })
+    }
+}
+
+#[tokio::main]
+pub async fn main() {
+    let result = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => run(cli).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(mut cli: Cli) -> RedisResult<()> {
+    match cli.mode {
+        Mode::Repl => repl(&mut cli.options, cli.output).await,
+        Mode::Command(argv) => {
+            let mut connection = connect(&cli.options).await?;
+            let ok = command(&mut connection, argv, cli.output, cli.repeat, cli.interval).await?;
+            if !ok {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Mode::Pipe => {
+            let (errors, replies) = pipe(&cli.options).await?;
+            println!("errors: {}, replies: {}", errors, replies);
+            if errors > 0 {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+        Mode::BigKeys => scan::find_big_keys(&mut connect(&cli.options).await?, cli.interval, None, &mut std::io::stdout()).await,
+        Mode::MemKeys(samples) => scan::find_big_keys(&mut connect(&cli.options).await?, cli.interval, Some(samples), &mut std::io::stdout()).await,
+        Mode::HotKeys => scan::find_hot_keys(&mut connect(&cli.options).await?, cli.interval, &mut std::io::stdout()).await,
+    }
+}
+
+/// the command given on the command line, `repeat` times. false when the last reply was an error
+async fn command(connection: &mut Connection, argv: Vec<Bytes>, output: Output, repeat: i64, interval: Option<Duration>) -> RedisResult<bool> {
+    let mut ok = true;
+    let mut done = 0;
+    while repeat < 0 || done < repeat {
+        let reply = send(connection, argv.clone()).await?;
+        ok = !matches!(reply, RedisFrame::Error(_));
+        print_reply(&reply, output);
+        if ok && is_subscribe(&argv) {
+            follow(connection, output).await?;
+        }
+
+        done += 1;
+        if let Some(interval) = interval {
+            tokio::time::sleep(interval).await;
+        }
+    }
+    Ok(ok)
+}
+
+/// the interactive prompt
+async fn repl(options: &mut Options, output: Output) -> RedisResult<()> {
+    let mut editor = Editor::new();
+    let mut connection = match connect(options).await {
+        Ok(connection) => Some(connection),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    };
+
+    loop {
+        let prompt = if !editor.is_terminal() {
+            String::new()
+        } else if connection.is_none() {
+            "not connected> ".to_string()
+        } else if options.db != 0 {
+            format!("{}:{}[{}]> ", options.host, options.port, options.db)
+        } else {
+            format!("{}:{}> ", options.host, options.port)
+        };
+        let Some(line) = editor.read_line(&prompt)? else {
+            return Ok(());
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let Some(mut argv) = split_args(line) else {
+            println!("Invalid argument(s)");
+            continue;
+        };
+        if editor.is_terminal() && !is_sensitive(&argv) {
+            editor.add_history(line);
+        }
+
+        // "3 incr counter" 执行三次
+        let mut repeat = 1;
+        if argv.len() > 1 {
+            if let Some(n) = std::str::from_utf8(&argv[0]).ok().and_then(|n| n.parse::<u64>().ok()) {
+                repeat = n;
+                argv.remove(0);
+            }
+        }
+
+        let name = String::from_utf8_lossy(&argv[0]).to_lowercase();
+        match name.as_str() {
+            "quit" | "exit" => return Ok(()),
+            "clear" => {
+                clear_screen();
+                continue;
+            }
+            _ => {}
+        }
+
+        for _ in 0..repeat {
+            if connection.is_none() {
+                match connect(options).await {
+                    Ok(reconnected) => connection = Some(reconnected),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        break;
+                    }
+                }
+            }
+            let Some(conn) = connection.as_mut() else { break };
+
+            let reply = match send(conn, argv.clone()).await {
+                Ok(reply) => reply,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    connection = None;
+                    break;
+                }
+            };
+            print_reply(&reply, output);
+            if matches!(reply, RedisFrame::Error(_)) {
+                continue;
+            }
+
+            match name.as_str() {
+                "select" if argv.len() == 2 => {
+                    if let Some(db) = std::str::from_utf8(&argv[1]).ok().and_then(|db| db.parse().ok()) {
+                        options.db = db;
+                    }
+                }
+                "auth" if argv.len() == 2 || argv.len() == 3 => {
+                    options.user = (argv.len() == 3).then(|| String::from_utf8_lossy(&argv[1]).into_owned());
+                    options.password = argv.last().map(|password| String::from_utf8_lossy(password).into_owned());
+                }
+                _ if is_subscribe(&argv) => {
+                    println!("Reading messages... (press Ctrl-C to quit)");
+                    if let Err(e) = follow(conn, output).await {
+                        eprintln!("Error: {}", e);
+                    }
+                    // 订阅状态的连接不能再执行普通命令
+                    connection = None;
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// print the messages of a subscribed connection until it closes
+async fn follow(connection: &mut Connection, output: Output) -> RedisResult<()> {
+    loop {
+        let message = read(connection).await?;
+        print_reply(&message, output);
+    }
+}
+
+fn print_reply(reply: &RedisFrame, output: Output) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = stdout.write_all(&format_reply(reply, output));
+    let _ = stdout.flush();
+}
+
+fn is_subscribe(argv: &[Bytes]) -> bool {
+    ["subscribe", "psubscribe", "ssubscribe"].iter().any(|name| argv[0].eq_ignore_ascii_case(name.as_bytes()))
+}
+
+/// commands carrying a password, kept out of the history file
+fn is_sensitive(argv: &[Bytes]) -> bool {
+    let is = |i: usize, name: &str| argv.get(i).is_some_and(|arg| arg.eq_ignore_ascii_case(name.as_bytes()));
+    is(0, "auth") || is(0, "hello") || is(0, "migrate") || (is(0, "acl") && is(1, "setuser"))
+}
+
+/// --pipe: send the RESP commands of stdin as fast as possible while counting the replies.
+/// Returns the number of error replies and of all replies
+async fn pipe(options: &Options) -> RedisResult<(u64, u64)> {
+    let mut data = vec![];
+    tokio::io::stdin().read_to_end(&mut data).await?;
+
+    // 先数出有多少条命令, 才知道要等多少个回复
+    let mut buffer = BytesMut::from(&data[..]);
+    let mut commands = 0u64;
+    while RedisCodec.decode(&mut buffer)?.is_some() {
+        commands += 1;
+    }
+    if !buffer.is_empty() {
+        return Err("ERR: the data on stdin is not complete Redis protocol".into());
+    }
+
+    let (reader, mut writer) = connect(options).await?.into_inner().into_split();
+    let write = async move {
+        writer.write_all(&data).await?;
+        writer.flush().await?;
+        eprintln!("All data transferred. Waiting for the last reply...");
+        Ok::<_, std::io::Error>(writer)
+    };
+    let read = async {
+        let mut replies = FramedRead::new(reader, RedisCodec);
+        let (mut errors, mut count) = (0, 0);
+        while count < commands {
+            match replies.next().await {
+                Some(reply) => {
+                    if let RedisFrame::Error(e) = reply? {
+                        eprintln!("{}", e);
+                        errors += 1;
+                    }
+                    count += 1;
+                }
+                None => return Err("Server closed the connection".into()),
+            }
+        }
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>((errors, count))
+    };
+    // 写完之前 writer 不能被丢掉, 否则连接会被半关闭
+    let (writer, replies) = tokio::join!(write, read);
+    let _writer = writer?;
+    let replies = replies?;
+    eprintln!("Last reply received from server.");
+    Ok(replies)
+}
@@ -0,0 +1,342 @@
+//! --bigkeys, --memkeys and --hotkeys: walk the whole keyspace with SCAN and report
+//! the biggest or the most accessed keys, in the format of redis-cli
+
+use std::io::Write;
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use my_own_mini_redis::codec::RedisFrame;
+use my_own_mini_redis::RedisResult;
+
+use crate::connection::{call, pipeline, Connection};
+use crate::format::repr;
+
+/// hottest keys kept by --hotkeys
+const HOTKEYS_SAMPLE: usize = 16;
+
+/// the types --bigkeys and --memkeys report on: name, command giving the size, unit
+const TYPES: [(&str, &str, &str); 6] = [
+    ("string", "STRLEN", "bytes"),
+    ("list", "LLEN", "items"),
+    ("set", "SCARD", "members"),
+    ("hash", "HLEN", "fields"),
+    ("zset", "ZCARD", "members"),
+    ("stream", "XLEN", "entries"),
+];
+
+/// walks the keyspace one SCAN page at a time
+struct Scanner {
+    cursor: Option<String>,
+    calls: u64,
+    interval: Option<Duration>,
+}
+
+impl Scanner {
+    fn new(interval: Option<Duration>) -> Self {
+        Self { cursor: Some("0".to_string()), calls: 0, interval }
+    }
+
+    /// the keys of the next page, `None` once the cursor came back to 0
+    async fn next_page(&mut self, connection: &mut Connection) -> RedisResult<Option<Vec<Bytes>>> {
+        let Some(cursor) = self.cursor.take() else { return Ok(None) };
+        if let Some(interval) = self.interval {
+            if self.calls > 0 && self.calls.is_multiple_of(100) {
+                tokio::time::sleep(interval).await;
+            }
+        }
+        self.calls += 1;
+
+        let RedisFrame::Array(mut reply) = call(connection, &["SCAN", &cursor]).await? else {
+            return Err("SCAN replied with something else than an array".into());
+        };
+        let (Some(RedisFrame::Array(keys)), Some(RedisFrame::Bulk(cursor))) = (reply.pop(), reply.pop()) else {
+            return Err("SCAN replied with an unexpected array".into());
+        };
+        if cursor.as_ref() != b"0" {
+            self.cursor = Some(String::from_utf8_lossy(&cursor).to_string());
+        }
+        Ok(Some(keys.into_iter()
+            .filter_map(|key| match key {
+                RedisFrame::Bulk(key) => Some(key),
+                _ => None,
+            })
+            .collect()))
+    }
+}
+
+/// the size of a type seen so far
+#[derive(Default)]
+struct TypeInfo {
+    count: u64,
+    total_size: u64,
+    biggest: u64,
+    biggest_key: Option<String>,
+}
+
+/// --bigkeys, or --memkeys with the given MEMORY USAGE samples
+pub(crate) async fn find_big_keys(connection: &mut Connection, interval: Option<Duration>, memkeys: Option<u64>,
+                                  out: &mut impl Write) -> RedisResult<()> {
+    let total_keys = match call(connection, &["DBSIZE"]).await? {
+        RedisFrame::Integer(n) => n.max(0) as u64,
+        _ => 0,
+    };
+
+    writeln!(out, "\n# Scanning the entire keyspace to find biggest keys as well as")?;
+    writeln!(out, "# average sizes per key type.  You can use -i 0.1 to sleep 0.1 sec")?;
+    writeln!(out, "# per 100 SCAN commands (not usually needed).\n")?;
+
+    let mut types: Vec<TypeInfo> = TYPES.iter().map(|_| TypeInfo::default()).collect();
+    let mut sampled = 0u64;
+    let mut total_len = 0u64;
+    let mut scanner = Scanner::new(interval);
+    loop {
+        let pct = percentage(sampled, total_keys);
+        let Some(keys) = scanner.next_page(connection).await? else { break };
+
+        let type_commands = keys.iter()
+            .map(|key| vec![Bytes::from_static(b"TYPE"), key.clone()])
+            .collect();
+        // keys gone since the SCAN are `none` and skipped
+        let key_types: Vec<Option<usize>> = pipeline(connection, type_commands).await?
+            .into_iter()
+            .map(|reply| match reply {
+                RedisFrame::Simple(name) => TYPES.iter().position(|(type_name, _, _)| *type_name == name),
+                _ => None,
+            })
+            .collect();
+
+        let size_commands = keys.iter()
+            .zip(&key_types)
+            .filter_map(|(key, index)| {
+                let index = (*index)?;
+                Some(match memkeys {
+                    Some(0) => vec![Bytes::from_static(b"MEMORY"), Bytes::from_static(b"USAGE"), key.clone()],
+                    Some(samples) => vec![Bytes::from_static(b"MEMORY"), Bytes::from_static(b"USAGE"), key.clone(),
+                                          Bytes::from_static(b"SAMPLES"), Bytes::from(samples.to_string())],
+                    None => vec![Bytes::from(TYPES[index].1), key.clone()],
+                })
+            })
+            .collect();
+        let mut sizes = pipeline(connection, size_commands).await?.into_iter();
+
+        for (key, index) in keys.iter().zip(key_types) {
+            let Some(index) = index else { continue };
+            let size = match sizes.next() {
+                Some(RedisFrame::Integer(n)) => n.max(0) as u64,
+                _ => 0,
+            };
+            let (name, _, unit) = TYPES[index];
+            let unit = if memkeys.is_some() { "bytes" } else { unit };
+            let info = &mut types[index];
+            info.total_size += size;
+            info.count += 1;
+            total_len += key.len() as u64;
+            sampled += 1;
+
+            if info.biggest < size {
+                let key = repr(key);
+                writeln!(out, "[{:05.2}%] Biggest {:<6} found so far '{}' with {} {}", pct, name, key, size, unit)?;
+                info.biggest = size;
+                info.biggest_key = Some(key);
+            }
+            if sampled.is_multiple_of(1000000) {
+                writeln!(out, "[{:05.2}%] Sampled {} keys so far", pct, sampled)?;
+            }
+        }
+    }
+
+    writeln!(out, "\n-------- summary -------\n")?;
+    writeln!(out, "Sampled {} keys in the keyspace!", sampled)?;
+    writeln!(out, "Total key length in bytes is {} (avg len {:.2})\n", total_len, ratio(total_len, sampled))?;
+
+    for ((name, _, unit), info) in TYPES.iter().zip(&types) {
+        if let Some(key) = &info.biggest_key {
+            let unit = if memkeys.is_some() { "bytes" } else { unit };
+            writeln!(out, "Biggest {:>6} found '{}' has {} {}", name, key, info.biggest, unit)?;
+        }
+    }
+    writeln!(out)?;
+    for ((name, _, unit), info) in TYPES.iter().zip(&types) {
+        let unit = if memkeys.is_some() { "bytes" } else { unit };
+        writeln!(out, "{} {}s with {} {} ({:05.2}% of keys, avg size {:.2})",
+                 info.count, name, info.total_size, unit, percentage(info.count, sampled), ratio(info.total_size, info.count))?;
+    }
+    Ok(())
+}
+
+/// --hotkeys
+pub(crate) async fn find_hot_keys(connection: &mut Connection, interval: Option<Duration>, out: &mut impl Write) -> RedisResult<()> {
+    let total_keys = match call(connection, &["DBSIZE"]).await? {
+        RedisFrame::Integer(n) => n.max(0) as u64,
+        _ => 0,
+    };
+
+    writeln!(out, "\n# Scanning the entire keyspace to find hot keys as well as")?;
+    writeln!(out, "# average sizes per key type.  You can use -i 0.1 to sleep 0.1 sec")?;
+    writeln!(out, "# per 100 SCAN commands (not usually needed).\n")?;
+
+    // the hottest keys so far, coldest first
+    let mut counters = [0u64; HOTKEYS_SAMPLE];
+    let mut hot_keys: [Option<String>; HOTKEYS_SAMPLE] = Default::default();
+    let mut sampled = 0u64;
+    let mut scanner = Scanner::new(interval);
+    loop {
+        let pct = percentage(sampled, total_keys);
+        let Some(keys) = scanner.next_page(connection).await? else { break };
+
+        let commands = keys.iter()
+            .map(|key| vec![Bytes::from_static(b"OBJECT"), Bytes::from_static(b"FREQ"), key.clone()])
+            .collect();
+        let replies = pipeline(connection, commands).await?;
+
+        for (key, reply) in keys.iter().zip(replies) {
+            sampled += 1;
+            let freq = match reply {
+                RedisFrame::Integer(n) => n.max(0) as u64,
+                RedisFrame::Error(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+                // the key is gone since the SCAN
+                _ => continue,
+            };
+
+            let slot = counters.iter().take_while(|counter| freq > **counter).count();
+            if slot == 0 {
+                continue;
+            }
+            // the coldest key makes room, the colder ones shift down
+            let slot = slot - 1;
+            counters.copy_within(1..=slot, 0);
+            hot_keys[..=slot].rotate_left(1);
+            counters[slot] = freq;
+            hot_keys[slot] = Some(repr(key));
+            writeln!(out, "[{:05.2}%] Hot key '{}' found so far with counter {}", pct, repr(key), freq)?;
+        }
+    }
+
+    writeln!(out, "\n-------- summary -------\n")?;
+    writeln!(out, "Sampled {} keys in the keyspace!", sampled)?;
+    for (counter, key) in counters.iter().zip(&hot_keys).rev() {
+        if let (true, Some(key)) = (*counter > 0, key) {
+            writeln!(out, "hot key found with counter: {}\tkeyname: {}", counter, key)?;
+        }
+    }
+    Ok(())
+}
+
+fn percentage(part: u64, whole: u64) -> f64 {
+    if whole == 0 { 0.0 } else { 100.0 * part as f64 / whole as f64 }
+}
+
+fn ratio(total: u64, count: u64) -> f64 {
+    if count == 0 { 0.0 } else { total as f64 / count as f64 }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_util::codec::Framed;
+
+    use my_own_mini_redis::codec::{RedisCodec, RedisFrame};
+    use my_own_mini_redis::config::{Config, MaxmemoryPolicy};
+    use my_own_mini_redis::server::Server;
+
+    use crate::connection::{call, Connection};
+    use crate::scan::{find_big_keys, find_hot_keys};
+
+    /// a connection to a server of its own, in process
+    async fn connect(config: Config) -> Connection {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Config { save: vec![], dir: std::env::temp_dir(), ..config };
+        tokio::spawn(Server::with_config(listener, config).run());
+        Framed::new(TcpStream::connect(addr).await.unwrap(), RedisCodec)
+    }
+
+    async fn run(connection: &mut Connection, command: &str) -> RedisFrame {
+        call(connection, &command.split(' ').collect::<Vec<_>>()).await.unwrap()
+    }
+
+    async fn big_keys(connection: &mut Connection, memkeys: Option<u64>) -> String {
+        let mut out = vec![];
+        find_big_keys(connection, None, memkeys, &mut out).await.unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    async fn fill(connection: &mut Connection) {
+        run(connection, "SET small a").await;
+        run(connection, &format!("SET big {}", "x".repeat(100))).await;
+        run(connection, "LPUSH list a b c").await;
+        run(connection, "HSET hash f1 v1 f2 v2").await;
+        run(connection, "ZADD zset 1 a 2 b 3 c 4 d").await;
+    }
+
+    #[tokio::test]
+    async fn bigkeys_test() {
+        let mut connection = connect(Config::default()).await;
+        fill(&mut connection).await;
+
+        let out = big_keys(&mut connection, None).await;
+        assert!(out.contains("Sampled 5 keys in the keyspace!"), "{}", out);
+        assert!(out.contains("Biggest string found '\"big\"' has 100 bytes"), "{}", out);
+        assert!(out.contains("Biggest   list found '\"list\"' has 3 items"), "{}", out);
+        assert!(out.contains("Biggest   hash found '\"hash\"' has 2 fields"), "{}", out);
+        assert!(out.contains("Biggest   zset found '\"zset\"' has 4 members"), "{}", out);
+        assert!(out.contains("2 strings with 101 bytes (40.00% of keys, avg size 50.50)"), "{}", out);
+        assert!(out.contains("0 sets with 0 members (00.00% of keys, avg size 0.00)"), "{}", out);
+    }
+
+    #[tokio::test]
+    async fn memkeys_test() {
+        let mut connection = connect(Config::default()).await;
+        fill(&mut connection).await;
+
+        let out = big_keys(&mut connection, Some(0)).await;
+        for (name, key) in [("string", "big"), ("list", "list"), ("hash", "hash"), ("zset", "zset")] {
+            let RedisFrame::Integer(usage) = run(&mut connection, &format!("MEMORY USAGE {}", key)).await else { panic!() };
+            let line = format!("Biggest {:>6} found '\"{}\"' has {} bytes", name, key, usage);
+            assert!(out.contains(&line), "{} in {}", line, out);
+        }
+    }
+
+    #[tokio::test]
+    async fn hotkeys_test() {
+        // a log factor of 0 counts every access
+        let mut connection = connect(Config { maxmemory_policy: MaxmemoryPolicy::AllKeysLfu, lfu_log_factor: 0, ..Config::default() }).await;
+        fill(&mut connection).await;
+        for _ in 0..20 {
+            run(&mut connection, "GET big").await;
+        }
+        for _ in 0..10 {
+            run(&mut connection, "LLEN list").await;
+        }
+
+        let mut out = vec![];
+        find_hot_keys(&mut connection, None, &mut out).await.unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Sampled 5 keys in the keyspace!"), "{}", out);
+        let summary: Vec<&str> = out.lines().filter(|line| line.starts_with("hot key found")).collect();
+        assert_eq!(summary.len(), 5, "{}", out);
+        assert!(summary[0].ends_with("keyname: \"big\""), "{}", out);
+        assert!(summary[1].ends_with("keyname: \"list\""), "{}", out);
+    }
+
+    #[tokio::test]
+    async fn expiring_keys_test() {
+        let mut connection = connect(Config::default()).await;
+        run(&mut connection, &format!("SET keeper {}", "x".repeat(50))).await;
+        for i in 0..2000 {
+            run(&mut connection, &format!("SET volatile:{} v PX {}", i, 1 + i % 50)).await;
+        }
+
+        // keys that expire between SCAN and TYPE are skipped, the others are still reported
+        let out = big_keys(&mut connection, None).await;
+        assert!(out.contains("Biggest string found '\"keeper\"' has 50 bytes"), "{}", out);
+        let sampled: u64 = out.lines()
+            .find_map(|line| line.strip_prefix("Sampled ")?.strip_suffix(" keys in the keyspace!")?.parse().ok())
+            .unwrap();
+        assert!((1..=2001).contains(&sampled), "{}", out);
+    }
+}
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+use my_own_mini_redis::RedisResult;
+use my_own_mini_redis::sentinel::{MasterConfig, Sentinel, SentinelConfig};
+
+/// sentinel [--port <port>] --monitor <name> <host> <port> <quorum>
+///          [--down-after-milliseconds <name> <ms>] [--failover-timeout <name> <ms>]
+///
+/// `--monitor` may be repeated, the other options apply to the master with that name
+#[tokio::main]
+pub async fn main() -> RedisResult<()> {
+    let mut port = 26379;
+    let mut config = SentinelConfig::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => port = args.next().ok_or("--port needs a value")?.parse()?,
+            "--monitor" => {
+                let usage = "--monitor needs a name, a host, a port and a quorum";
+                let name = args.next().ok_or(usage)?;
+                let host = args.next().ok_or(usage)?;
+                let master_port = args.next().ok_or(usage)?.parse()?;
+                let quorum = args.next().ok_or(usage)?.parse()?;
+                config.masters.push(MasterConfig::new(name, host, master_port, quorum));
+            }
+            "--down-after-milliseconds" | "--failover-timeout" => {
+                let name = args.next().ok_or(format!("{} needs a master name and a value", arg))?;
+                let ms = Duration::from_millis(args.next().ok_or(format!("{} needs a master name and a value", arg))?.parse()?);
+                let master = config.masters.iter_mut()
+                    .find(|master| master.name == name)
+                    .ok_or(format!("no master named '{}' is monitored", name))?;
+                if arg == "--down-after-milliseconds" {
+                    master.down_after = ms;
+                } else {
+                    master.failover_timeout = ms;
+                }
+            }
+            other => return Err(format!("unknown argument '{}'", other).into()),
+        }
+    }
+    if config.masters.is_empty() {
+        return Err("nothing to monitor, use --monitor <name> <host> <port> <quorum>".into());
+    }
+
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    println!("Sentinel listening on port {}", port);
+    Sentinel::new(listener, config).run().await
+}
@@ -1,18 +1,42 @@
 use tokio::net::TcpListener;
-use tokio::signal;
 
 use my_own_mini_redis::RedisResult;
+use my_own_mini_redis::config::Config;
 use my_own_mini_redis::server::Server;
 
+/// server [/path/to/redis.conf] [--<directive> <value> ...]
+///
+/// Like redis-server, the config file is read first and the options override it, each option
+/// being a `redis.conf` directive: `--port 7000 --maxmemory 1gb --save "900 1"`
 #[tokio::main]
 pub async fn main() -> RedisResult<()> {
-    let listener = TcpListener::bind("127.0.0.1:6379").await?;
+    let config = Config::from_args(std::env::args().skip(1))?;
 
-    let server = Server::new(listener);
+    // 监听 bind 的每个地址，开头的 - 表示这个地址不可用也没关系
+    let mut listeners = vec![];
+    for bind in &config.bind {
+        let optional = bind.starts_with('-');
+        let addr = match bind.trim_start_matches('-') {
+            "*" => "0.0.0.0",
+            addr => addr,
+        };
+        match TcpListener::bind((addr, config.port)).await {
+            Ok(listener) => listeners.push(listener),
+            Err(e) if optional => eprintln!("Warning: could not bind {}:{}: {}", addr, config.port, e),
+            Err(e) => return Err(format!("Could not bind {}:{}: {}", addr, config.port, e).into()),
+        }
+    }
+    if listeners.is_empty() {
+        listeners.push(TcpListener::bind(("127.0.0.1", config.port)).await?);
+    }
+
+    let server = Server::with_listeners(listeners, config);
+
+    let loaded = server.load()?;
+    println!("DB loaded from disk: {} keys", loaded);
 
     server.run().await?;
 
 
     Ok(())
 }
-
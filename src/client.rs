@@ -0,0 +1,647 @@
+//! A client for this server, or any Redis speaking RESP2: typed methods for the common
+//! commands, explicit pipelines, MULTI/EXEC transactions and Pub/Sub.
+//!
+//! Error replies of the server come back as an `Err` holding the message of the server,
+//! like `WRONGTYPE Operation against a key holding the wrong kind of value`. Commands
+//! without a typed method go through `Client::command`
+
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_util::codec::Framed;
+
+use crate::codec::{RedisCodec, RedisFrame};
+use crate::RedisResult;
+
+mod pool;
+
+pub use pool::{Pool, PoolConfig, PooledClient};
+
+/// A connection to a server
+pub struct Client {
+    framed: Framed<TcpStream, RedisCodec>,
+    /// how long a reply may take, forever when `None`
+    timeout: Option<Duration>,
+    /// set once a request failed half way or timed out: the replies still to come would be
+    /// taken for the replies of the next requests, so the connection cannot be used anymore
+    broken: bool,
+    /// set from the moment a request is written until all of its replies are read. Still set
+    /// when the request was cancelled half way, and then its reply is still to come
+    busy: bool,
+    /// keys are watched, for the next transaction
+    watching: bool,
+}
+
+/// When a key set by `Client::set_with` expires
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expiry {
+    /// after this long, PX
+    In(Duration),
+    /// at this time, PXAT
+    At(SystemTime),
+    /// keep the TTL the key already has, KEEPTTL
+    KeepTtl,
+}
+
+/// Whether `Client::set_with` depends on the key being there
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Condition {
+    /// only set a key that does not exist, NX
+    NotExists,
+    /// only set a key that already exists, XX
+    Exists,
+}
+
+/// The options of SET
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SetOptions {
+    pub expiry: Option<Expiry>,
+    pub condition: Option<Condition>,
+}
+
+impl SetOptions {
+    fn append_to(&self, argv: &mut Vec<Bytes>) {
+        match self.condition {
+            Some(Condition::NotExists) => argv.push(Bytes::from_static(b"NX")),
+            Some(Condition::Exists) => argv.push(Bytes::from_static(b"XX")),
+            None => {}
+        }
+        match self.expiry {
+            Some(Expiry::In(ttl)) => {
+                argv.push(Bytes::from_static(b"PX"));
+                argv.push(Bytes::from(ttl.as_millis().max(1).to_string()));
+            }
+            Some(Expiry::At(when)) => {
+                let at = when.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis().max(1);
+                argv.push(Bytes::from_static(b"PXAT"));
+                argv.push(Bytes::from(at.to_string()));
+            }
+            Some(Expiry::KeepTtl) => argv.push(Bytes::from_static(b"KEEPTTL")),
+            None => {}
+        }
+    }
+}
+
+/// Commands sent together and answered together, saving a round trip per command.
+/// With `atomic` they are wrapped in MULTI/EXEC and run as a transaction
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    commands: Vec<Vec<Bytes>>,
+    atomic: bool,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// run the commands as a MULTI/EXEC transaction
+    pub fn atomic(&mut self) -> &mut Self {
+        self.atomic = true;
+        self
+    }
+
+    /// queue any command
+    pub fn cmd<A: AsRef<[u8]>>(&mut self, argv: impl IntoIterator<Item = A>) -> &mut Self {
+        self.commands.push(argv.into_iter().map(arg).collect());
+        self
+    }
+
+    pub fn get(&mut self, key: impl AsRef<[u8]>) -> &mut Self {
+        self.commands.push(vec![Bytes::from_static(b"GET"), arg(key)]);
+        self
+    }
+
+    pub fn set(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> &mut Self {
+        self.commands.push(vec![Bytes::from_static(b"SET"), arg(key), arg(value)]);
+        self
+    }
+
+    pub fn incr(&mut self, key: impl AsRef<[u8]>) -> &mut Self {
+        self.commands.push(vec![Bytes::from_static(b"INCR"), arg(key)]);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+/// A message received by a `Subscriber`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub channel: Bytes,
+    /// the pattern that matched the channel, for PSUBSCRIBE
+    pub pattern: Option<Bytes>,
+    pub payload: Bytes,
+}
+
+/// A connection in Pub/Sub mode, which only receives messages until it is dropped
+pub struct Subscriber {
+    client: Client,
+    /// channels and patterns subscribed to
+    subscriptions: usize,
+    /// messages that arrived while waiting for the confirmations of a subscription
+    pending: VecDeque<Message>,
+}
+
+/// what the server pushes to a subscribed connection
+enum Push {
+    Message(Message),
+    /// a subscribe, psubscribe, unsubscribe or punsubscribe confirmation, with the number of
+    /// subscriptions left
+    Confirmation(usize),
+}
+
+impl Client {
+    pub async fn connect(addr: impl ToSocketAddrs) -> RedisResult<Client> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(Client { framed: Framed::new(stream, RedisCodec), timeout: None, broken: false, busy: false, watching: false })
+    }
+
+    /// fail requests whose reply takes longer than `timeout`. A connection that timed out is broken
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// whether an io error or a timeout left the connection unusable
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// whether a request was written and not all of its replies were read, which is only
+    /// the case between the two or when the future of the request was dropped in between
+    pub fn is_busy(&self) -> bool {
+        self.busy
+    }
+
+    /// whether `watch` was called and no transaction or `unwatch` followed
+    pub fn is_watching(&self) -> bool {
+        self.watching
+    }
+
+    /// send any command. The reply is returned as is, except error replies which become an `Err`
+    pub async fn command<A: AsRef<[u8]>>(&mut self, argv: impl IntoIterator<Item = A>) -> RedisResult<RedisFrame> {
+        self.call(argv.into_iter().map(arg).collect()).await
+    }
+
+    pub async fn ping(&mut self) -> RedisResult<()> {
+        self.call(vec![Bytes::from_static(b"PING")]).await?;
+        Ok(())
+    }
+
+    /// AUTH password, or AUTH username password with a `user`
+    pub async fn auth(&mut self, user: Option<&str>, password: &str) -> RedisResult<()> {
+        let mut argv = vec![Bytes::from_static(b"AUTH")];
+        argv.extend(user.map(arg));
+        argv.push(arg(password));
+        self.call(argv).await?;
+        Ok(())
+    }
+
+    /// the string stored at key, `None` if the key does not exist
+    pub async fn get(&mut self, key: impl AsRef<[u8]>) -> RedisResult<Option<Bytes>> {
+        let reply = self.call(vec![Bytes::from_static(b"GET"), arg(key)]).await?;
+        bulk(reply)
+    }
+
+    pub async fn set(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> RedisResult<()> {
+        self.call(vec![Bytes::from_static(b"SET"), arg(key), arg(value)]).await?;
+        Ok(())
+    }
+
+    /// SET with an expiry or a condition. Returns false if the condition prevented the write
+    pub async fn set_with(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>, options: SetOptions) -> RedisResult<bool> {
+        let mut argv = vec![Bytes::from_static(b"SET"), arg(key), arg(value)];
+        options.append_to(&mut argv);
+        Ok(!matches!(self.call(argv).await?, RedisFrame::Null))
+    }
+
+    /// SET with the GET option: the string stored at key before, `None` if there was none
+    pub async fn set_get(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>, options: SetOptions) -> RedisResult<Option<Bytes>> {
+        let mut argv = vec![Bytes::from_static(b"SET"), arg(key), arg(value), Bytes::from_static(b"GET")];
+        options.append_to(&mut argv);
+        bulk(self.call(argv).await?)
+    }
+
+    pub async fn incr(&mut self, key: impl AsRef<[u8]>) -> RedisResult<i64> {
+        integer(self.call(vec![Bytes::from_static(b"INCR"), arg(key)]).await?)
+    }
+
+    pub async fn incr_by(&mut self, key: impl AsRef<[u8]>, increment: i64) -> RedisResult<i64> {
+        integer(self.call(vec![Bytes::from_static(b"INCRBY"), arg(key), arg(increment.to_string())]).await?)
+    }
+
+    /// delete the keys, returns how many existed
+    pub async fn del<K: AsRef<[u8]>>(&mut self, keys: impl IntoIterator<Item = K>) -> RedisResult<usize> {
+        let mut argv = vec![Bytes::from_static(b"DEL")];
+        argv.extend(keys.into_iter().map(arg));
+        count(self.call(argv).await?)
+    }
+
+    /// set fields of a hash, returns how many of them are new
+    pub async fn hset<F: AsRef<[u8]>, V: AsRef<[u8]>>(&mut self, key: impl AsRef<[u8]>, fields: impl IntoIterator<Item = (F, V)>) -> RedisResult<usize> {
+        let mut argv = vec![Bytes::from_static(b"HSET"), arg(key)];
+        for (field, value) in fields {
+            argv.push(arg(field));
+            argv.push(arg(value));
+        }
+        count(self.call(argv).await?)
+    }
+
+    /// push elements at the head of a list, returns the length of the list
+    pub async fn lpush<V: AsRef<[u8]>>(&mut self, key: impl AsRef<[u8]>, elements: impl IntoIterator<Item = V>) -> RedisResult<usize> {
+        let mut argv = vec![Bytes::from_static(b"LPUSH"), arg(key)];
+        argv.extend(elements.into_iter().map(arg));
+        count(self.call(argv).await?)
+    }
+
+    /// add members with their scores to a sorted set, returns how many of them are new
+    pub async fn zadd<M: AsRef<[u8]>>(&mut self, key: impl AsRef<[u8]>, members: impl IntoIterator<Item = (f64, M)>) -> RedisResult<usize> {
+        let mut argv = vec![Bytes::from_static(b"ZADD"), arg(key)];
+        for (score, member) in members {
+            argv.push(arg(score.to_string()));
+            argv.push(arg(member));
+        }
+        count(self.call(argv).await?)
+    }
+
+    /// returns how many subscribers received the message
+    pub async fn publish(&mut self, channel: impl AsRef<[u8]>, message: impl AsRef<[u8]>) -> RedisResult<usize> {
+        count(self.call(vec![Bytes::from_static(b"PUBLISH"), arg(channel), arg(message)]).await?)
+    }
+
+    /// turn the connection into a subscriber of the channels
+    pub async fn subscribe<C: AsRef<[u8]>>(self, channels: impl IntoIterator<Item = C>) -> RedisResult<Subscriber> {
+        let mut subscriber = Subscriber { client: self, subscriptions: 0, pending: VecDeque::new() };
+        subscriber.subscribe(channels).await?;
+        Ok(subscriber)
+    }
+
+    /// turn the connection into a subscriber of the channels matching the patterns
+    pub async fn psubscribe<P: AsRef<[u8]>>(self, patterns: impl IntoIterator<Item = P>) -> RedisResult<Subscriber> {
+        let mut subscriber = Subscriber { client: self, subscriptions: 0, pending: VecDeque::new() };
+        subscriber.psubscribe(patterns).await?;
+        Ok(subscriber)
+    }
+
+    /// WATCH keys for the next `transaction`
+    pub async fn watch<K: AsRef<[u8]>>(&mut self, keys: impl IntoIterator<Item = K>) -> RedisResult<()> {
+        let mut argv = vec![Bytes::from_static(b"WATCH")];
+        argv.extend(keys.into_iter().map(arg));
+        self.call(argv).await?;
+        Ok(())
+    }
+
+    /// forget the keys watched by `watch`
+    pub async fn unwatch(&mut self) -> RedisResult<()> {
+        self.call(vec![Bytes::from_static(b"UNWATCH")]).await?;
+        Ok(())
+    }
+
+    /// send the commands of the pipeline at once and read their replies, in order.
+    /// Error replies stay in the list, one failed command does not fail the others.
+    /// An atomic pipeline replies with the replies of EXEC
+    pub async fn pipeline(&mut self, pipeline: &Pipeline) -> RedisResult<Vec<RedisFrame>> {
+        if pipeline.atomic {
+            return self.transaction(pipeline).await?.ok_or_else(|| "EXECABORT the transaction was aborted by WATCH".into());
+        }
+        self.write(pipeline.commands.iter().map(|argv| request(argv)).collect()).await?;
+
+        let mut replies = Vec::with_capacity(pipeline.len());
+        for _ in 0..pipeline.len() {
+            replies.push(self.read().await?);
+        }
+        self.busy = false;
+        Ok(replies)
+    }
+
+    /// run the commands of the pipeline as a MULTI/EXEC transaction, in a single round trip.
+    /// `None` if a watched key changed and the transaction did not run. A command the server
+    /// refused to queue fails the whole transaction
+    pub async fn transaction(&mut self, pipeline: &Pipeline) -> RedisResult<Option<Vec<RedisFrame>>> {
+        let mut frames = vec![request(&[Bytes::from_static(b"MULTI")])];
+        frames.extend(pipeline.commands.iter().map(|argv| request(argv)));
+        frames.push(request(&[Bytes::from_static(b"EXEC")]));
+        self.write(frames).await?;
+
+        // MULTI 和每条命令的 QUEUED 都要读完, 即使中间出错
+        let mut error = None;
+        for _ in 0..=pipeline.len() {
+            if let RedisFrame::Error(e) = self.read().await? {
+                error.get_or_insert(e);
+            }
+        }
+        let exec = self.read().await?;
+        // EXEC 总是会清掉 WATCH
+        self.busy = false;
+        self.watching = false;
+        match exec {
+            RedisFrame::Array(replies) => Ok(Some(replies)),
+            RedisFrame::Null | RedisFrame::NullArray => Ok(None),
+            RedisFrame::Error(e) => Err(error.unwrap_or(e).into()),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    async fn call(&mut self, argv: Vec<Bytes>) -> RedisResult<RedisFrame> {
+        self.write(vec![request(&argv)]).await?;
+        let reply = self.read().await?;
+        self.busy = false;
+        let name = argv[0].to_ascii_uppercase();
+        match name.as_slice() {
+            b"WATCH" => self.watching |= !matches!(reply, RedisFrame::Error(_)),
+            b"UNWATCH" | b"EXEC" | b"DISCARD" | b"RESET" => self.watching = false,
+            _ => {}
+        }
+        match reply {
+            RedisFrame::Error(e) => Err(e.into()),
+            reply => Ok(reply),
+        }
+    }
+
+    async fn write(&mut self, frames: Vec<RedisFrame>) -> RedisResult<()> {
+        if self.broken {
+            return Err("the connection is broken by an earlier error".into());
+        }
+        // the reply of a cancelled request would be taken for the reply of this one
+        if self.busy {
+            self.broken = true;
+            return Err("the connection is broken by a request cancelled before its reply was read".into());
+        }
+        self.busy = true;
+        for frame in frames {
+            if let Err(e) = self.framed.feed(frame).await {
+                self.broken = true;
+                return Err(e.into());
+            }
+        }
+        if let Err(e) = self.framed.flush().await {
+            self.broken = true;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    async fn read(&mut self) -> RedisResult<RedisFrame> {
+        let next = match self.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, self.framed.next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    self.broken = true;
+                    return Err(format!("no reply from the server within {}ms", timeout.as_millis()).into());
+                }
+            },
+            None => self.framed.next().await,
+        };
+        match next {
+            Some(Ok(reply)) => Ok(reply),
+            Some(Err(e)) => {
+                self.broken = true;
+                Err(e.into())
+            }
+            None => {
+                self.broken = true;
+                Err("connection closed by the server".into())
+            }
+        }
+    }
+}
+
+impl Subscriber {
+    pub async fn subscribe<C: AsRef<[u8]>>(&mut self, channels: impl IntoIterator<Item = C>) -> RedisResult<()> {
+        self.send(b"SUBSCRIBE", channels).await
+    }
+
+    pub async fn psubscribe<P: AsRef<[u8]>>(&mut self, patterns: impl IntoIterator<Item = P>) -> RedisResult<()> {
+        self.send(b"PSUBSCRIBE", patterns).await
+    }
+
+    /// the channels and patterns subscribed to
+    pub fn subscriptions(&self) -> usize {
+        self.subscriptions
+    }
+
+    /// the next message published on one of the channels, `None` once the connection is closed
+    pub async fn next_message(&mut self) -> RedisResult<Option<Message>> {
+        if let Some(message) = self.pending.pop_front() {
+            return Ok(Some(message));
+        }
+        loop {
+            let frame = match self.client.framed.next().await {
+                Some(frame) => frame?,
+                None => return Ok(None),
+            };
+            match push(frame)? {
+                Push::Message(message) => return Ok(Some(message)),
+                Push::Confirmation(count) => self.subscriptions = count,
+            }
+        }
+    }
+
+    /// send a subscription command and wait for its confirmations, one per channel.
+    /// Messages published in the meantime are kept for `next_message`
+    async fn send<A: AsRef<[u8]>>(&mut self, command: &'static [u8], args: impl IntoIterator<Item = A>) -> RedisResult<()> {
+        let mut argv = vec![Bytes::from_static(command)];
+        argv.extend(args.into_iter().map(arg));
+        let mut confirmations = argv.len() - 1;
+        self.client.write(vec![request(&argv)]).await?;
+
+        while confirmations > 0 {
+            match push(self.client.read().await?) {
+                Ok(Push::Message(message)) => self.pending.push_back(message),
+                Ok(Push::Confirmation(count)) => {
+                    self.subscriptions = count;
+                    confirmations -= 1;
+                }
+                // the server refused the command with a single error reply
+                Err(e) => {
+                    self.client.busy = false;
+                    return Err(e);
+                }
+            }
+        }
+        self.client.busy = false;
+        Ok(())
+    }
+}
+
+/// tell the messages from the confirmations by the kind in their first element
+fn push(frame: RedisFrame) -> RedisResult<Push> {
+    let items = match frame {
+        RedisFrame::Array(items) => items,
+        RedisFrame::Error(e) => return Err(e.into()),
+        other => return Err(unexpected(&other)),
+    };
+    let bulk = |i: usize| match items.get(i) {
+        Some(RedisFrame::Bulk(bytes)) => Some(bytes.clone()),
+        _ => None,
+    };
+    let push = match (bulk(0).as_deref(), items.len()) {
+        (Some(b"message" | b"smessage"), 3) => bulk(1).zip(bulk(2))
+            .map(|(channel, payload)| Push::Message(Message { channel, pattern: None, payload })),
+        (Some(b"pmessage"), 4) => bulk(2).zip(bulk(3))
+            .map(|(channel, payload)| Push::Message(Message { channel, pattern: bulk(1), payload })),
+        (Some(b"subscribe" | b"psubscribe" | b"unsubscribe" | b"punsubscribe"), 3) => match items[2] {
+            RedisFrame::Integer(count) => Some(Push::Confirmation(count.max(0) as usize)),
+            _ => None,
+        },
+        _ => None,
+    };
+    push.ok_or_else(|| unexpected(&RedisFrame::Array(items)))
+}
+
+fn arg(value: impl AsRef<[u8]>) -> Bytes {
+    Bytes::copy_from_slice(value.as_ref())
+}
+
+fn request(argv: &[Bytes]) -> RedisFrame {
+    RedisFrame::Array(argv.iter().cloned().map(RedisFrame::Bulk).collect())
+}
+
+fn bulk(reply: RedisFrame) -> RedisResult<Option<Bytes>> {
+    match reply {
+        RedisFrame::Bulk(bytes) => Ok(Some(bytes)),
+        RedisFrame::Null => Ok(None),
+        other => Err(unexpected(&other)),
+    }
+}
+
+fn integer(reply: RedisFrame) -> RedisResult<i64> {
+    match reply {
+        RedisFrame::Integer(n) => Ok(n),
+        other => Err(unexpected(&other)),
+    }
+}
+
+fn count(reply: RedisFrame) -> RedisResult<usize> {
+    Ok(integer(reply)?.max(0) as usize)
+}
+
+fn unexpected(reply: &RedisFrame) -> Box<dyn std::error::Error + Send + Sync> {
+    format!("unexpected reply from the server: {:?}", reply).into()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::net::TcpListener;
+
+    use crate::client::{Client, Condition, Expiry, Message, Pipeline, SetOptions};
+    use crate::codec::RedisFrame;
+    use crate::config::Config;
+    use crate::server::Server;
+
+    /// a server of this crate on a free port, running until the test ends
+    async fn server() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let config = Config { save: vec![], dir: std::env::temp_dir(), ..Config::default() };
+        tokio::spawn(Server::with_config(listener, config).run());
+        port
+    }
+
+    #[tokio::test]
+    async fn commands_test() {
+        let mut client = Client::connect(("127.0.0.1", server().await)).await.unwrap();
+        client.ping().await.unwrap();
+
+        assert_eq!(client.get("missing").await.unwrap(), None);
+        client.set("key", "value").await.unwrap();
+        assert_eq!(client.get("key").await.unwrap(), Some(Bytes::from("value")));
+
+        let nx = SetOptions { condition: Some(Condition::NotExists), ..SetOptions::default() };
+        assert!(!client.set_with("key", "other", nx).await.unwrap());
+        let ttl = SetOptions { expiry: Some(Expiry::In(Duration::from_millis(50))), ..SetOptions::default() };
+        assert!(client.set_with("short", "lived", ttl).await.unwrap());
+        assert_eq!(client.set_get("key", "new", SetOptions::default()).await.unwrap(), Some(Bytes::from("value")));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(client.get("short").await.unwrap(), None);
+
+        assert_eq!(client.incr("counter").await.unwrap(), 1);
+        assert_eq!(client.incr_by("counter", 10).await.unwrap(), 11);
+        let e = client.incr("key").await.unwrap_err();
+        assert_eq!(e.to_string(), "ERR value is not an integer or out of range");
+
+        assert_eq!(client.hset("hash", [("a", "1"), ("b", "2")]).await.unwrap(), 2);
+        assert_eq!(client.hset("hash", [("b", "3"), ("c", "4")]).await.unwrap(), 1);
+        assert_eq!(client.lpush("list", ["a", "b", "c"]).await.unwrap(), 3);
+        assert_eq!(client.lpush("list", ["d"]).await.unwrap(), 4);
+        assert_eq!(client.zadd("zset", [(1.0, "a"), (2.5, "b")]).await.unwrap(), 2);
+        assert_eq!(client.zadd("zset", [(3.0, "a")]).await.unwrap(), 0);
+        assert_eq!(client.command(["HLEN", "hash"]).await.unwrap(), RedisFrame::Integer(3));
+        assert_eq!(client.command(["ZCARD", "zset"]).await.unwrap(), RedisFrame::Integer(2));
+        assert!(client.lpush("hash", ["x"]).await.unwrap_err().to_string().starts_with("WRONGTYPE"));
+
+        assert_eq!(client.del(["key", "hash", "missing"]).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn pipeline_test() {
+        let port = server().await;
+        let mut client = Client::connect(("127.0.0.1", port)).await.unwrap();
+
+        let mut pipeline = Pipeline::new();
+        pipeline.set("a", "1").incr("a").cmd(["LPUSH", "a", "x"]).get("a");
+        let replies = client.pipeline(&pipeline).await.unwrap();
+        assert_eq!(replies.len(), 4);
+        assert_eq!(replies[1], RedisFrame::Integer(2));
+        assert!(matches!(&replies[2], RedisFrame::Error(e) if e.starts_with("WRONGTYPE")));
+        assert_eq!(replies[3], RedisFrame::Bulk(Bytes::from("2")));
+
+        let mut transaction = Pipeline::new();
+        transaction.atomic().incr("a").incr("a");
+        let replies = client.pipeline(&transaction).await.unwrap();
+        assert_eq!(replies, vec![RedisFrame::Integer(3), RedisFrame::Integer(4)]);
+
+        // a watched key changed by another client aborts the transaction
+        let mut other = Client::connect(("127.0.0.1", port)).await.unwrap();
+        client.watch(["a"]).await.unwrap();
+        other.set("a", "0").await.unwrap();
+        assert_eq!(client.transaction(&transaction).await.unwrap(), None);
+        assert_eq!(client.get("a").await.unwrap(), Some(Bytes::from("0")));
+
+        // a command that cannot be queued fails the whole transaction
+        let mut invalid = Pipeline::new();
+        invalid.incr("a").cmd(["SET", "a"]);
+        assert!(client.transaction(&invalid).await.is_err());
+        assert_eq!(client.get("a").await.unwrap(), Some(Bytes::from("0")));
+    }
+
+    #[tokio::test]
+    async fn pub_sub_test() {
+        let port = server().await;
+        let mut publisher = Client::connect(("127.0.0.1", port)).await.unwrap();
+        let mut subscriber = Client::connect(("127.0.0.1", port)).await.unwrap().subscribe(["news"]).await.unwrap();
+        subscriber.psubscribe(["sport.*"]).await.unwrap();
+        assert_eq!(subscriber.subscriptions(), 2);
+
+        assert_eq!(publisher.publish("news", "hello").await.unwrap(), 1);
+        assert_eq!(publisher.publish("sport.tennis", "ace").await.unwrap(), 1);
+        assert_eq!(publisher.publish("weather", "rain").await.unwrap(), 0);
+
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        assert_eq!(message, Message { channel: Bytes::from("news"), pattern: None, payload: Bytes::from("hello") });
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        assert_eq!(message.pattern, Some(Bytes::from("sport.*")));
+        assert_eq!(message.payload, Bytes::from("ace"));
+
+        // a message that arrives before the confirmations of a new subscription is not lost
+        assert_eq!(publisher.publish("news", "first").await.unwrap(), 1);
+        subscriber.subscribe(["weather", "traffic"]).await.unwrap();
+        assert_eq!(subscriber.subscriptions(), 4);
+        let message = subscriber.next_message().await.unwrap().unwrap();
+        assert_eq!(message.payload, Bytes::from("first"));
+        assert_eq!(publisher.publish("weather", "rain").await.unwrap(), 1);
+        assert_eq!(subscriber.next_message().await.unwrap().unwrap().channel, Bytes::from("weather"));
+    }
+}
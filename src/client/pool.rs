@@ -0,0 +1,347 @@
+//! A pool of client connections shared by many tasks.
+//!
+//! `Pool::get` hands out an idle connection, or opens one while fewer than `size` are open,
+//! and the connection goes back to the pool when the `PooledClient` is dropped. Connections
+//! idle for longer than `idle_timeout` are closed, those idle for longer than
+//! `health_check_after` are sent a PING before being handed out. A connection that failed
+//! or timed out is closed rather than returned, and so is one whose request was cancelled
+//! before its reply was read. Keys left watched are unwatched before the connection is
+//! handed out again.
+//!
+//! When the server cannot be reached, reconnection attempts are spaced with an exponential
+//! backoff, and in between requests fail right away with the last connection error instead
+//! of piling up on a dead server
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::{self, Instant};
+
+use crate::client::Client;
+use crate::RedisResult;
+
+/// How a `Pool` manages its connections
+#[derive(Debug, Clone)]
+pub struct PoolConfig {
+    /// the most connections open at once, in use or idle
+    pub size: usize,
+    /// idle connections are closed after this long
+    pub idle_timeout: Duration,
+    /// connections idle for longer than this are checked with a PING before being used
+    pub health_check_after: Duration,
+    pub connect_timeout: Duration,
+    /// how long a reply may take, and how long `get` waits for a connection when all are in use
+    pub request_timeout: Duration,
+    /// the wait after the first failed connection, doubled after each failure up to `max_backoff`
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            size: 16,
+            idle_timeout: Duration::from_secs(300),
+            health_check_after: Duration::from_secs(5),
+            connect_timeout: Duration::from_secs(1),
+            request_timeout: Duration::from_secs(5),
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// A pool of connections to one server, cheap to clone and share between tasks
+#[derive(Clone)]
+pub struct Pool {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    addr: String,
+    config: PoolConfig,
+    /// one permit per connection that may be open
+    permits: Arc<Semaphore>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// the connections not in use with when they were returned, the most recent last
+    idle: Vec<(Client, Instant)>,
+    /// connection attempts that failed in a row
+    failures: u32,
+    /// no connection is attempted before this, after a failure
+    retry_at: Option<Instant>,
+    last_error: String,
+}
+
+/// A connection taken from a `Pool`, given back when dropped
+pub struct PooledClient {
+    /// only `None` while being dropped
+    client: Option<Client>,
+    shared: Arc<Shared>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Pool {
+    /// a pool of connections to `addr`, `host:port`. No connection is opened before the first `get`
+    pub fn new(addr: impl ToString, config: PoolConfig) -> Pool {
+        let shared = Shared {
+            addr: addr.to_string(),
+            permits: Arc::new(Semaphore::new(config.size)),
+            config,
+            state: Mutex::new(State::default()),
+        };
+        Pool { shared: Arc::new(shared) }
+    }
+
+    /// a connection to send requests on, an idle one if there is a healthy one
+    pub async fn get(&self) -> RedisResult<PooledClient> {
+        let config = &self.shared.config;
+        let permit = time::timeout(config.request_timeout, self.shared.permits.clone().acquire_owned()).await
+            .map_err(|_| format!("no free connection in the pool of {} within {}ms", self.shared.addr, config.request_timeout.as_millis()))?
+            .map_err(|_| "the pool is closed")?;
+
+        loop {
+            let Some((mut client, since)) = self.shared.state().idle.pop() else { break };
+            if since.elapsed() > config.idle_timeout {
+                continue;
+            }
+            if since.elapsed() > config.health_check_after && client.ping().await.is_err() {
+                continue;
+            }
+            // 上一个使用者留下的 WATCH 会让这个使用者的事务失败
+            if client.is_watching() && client.unwatch().await.is_err() {
+                continue;
+            }
+            return Ok(PooledClient { client: Some(client), shared: self.shared.clone(), _permit: permit });
+        }
+
+        let client = self.shared.connect().await?;
+        Ok(PooledClient { client: Some(client), shared: self.shared.clone(), _permit: permit })
+    }
+
+    /// connections open and not in use
+    pub fn idle_connections(&self) -> usize {
+        self.shared.state().idle.len()
+    }
+}
+
+impl Shared {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// open a new connection, unless the last attempts failed and the backoff is not over
+    async fn connect(&self) -> RedisResult<Client> {
+        {
+            let state = self.state();
+            if let Some(retry_at) = state.retry_at.filter(|retry_at| *retry_at > Instant::now()) {
+                return Err(format!("{} is down ({}), next connection attempt in {}ms",
+                    self.addr, state.last_error, (retry_at - Instant::now()).as_millis()).into());
+            }
+        }
+
+        let connected = match time::timeout(self.config.connect_timeout, Client::connect(self.addr.as_str())).await {
+            Ok(connected) => connected.map_err(|e| e.to_string()),
+            Err(_) => Err(format!("no answer within {}ms", self.config.connect_timeout.as_millis())),
+        };
+        let mut state = self.state();
+        match connected {
+            Ok(mut client) => {
+                state.failures = 0;
+                state.retry_at = None;
+                client.set_timeout(Some(self.config.request_timeout));
+                Ok(client)
+            }
+            Err(e) => {
+                // 100ms, 200ms, 400ms ... 直到 max_backoff
+                let backoff = self.config.min_backoff
+                    .saturating_mul(1 << state.failures.min(16))
+                    .min(self.config.max_backoff);
+                state.failures += 1;
+                state.retry_at = Some(Instant::now() + backoff);
+                state.last_error = e;
+                Err(format!("can't connect to {}: {}", self.addr, state.last_error).into())
+            }
+        }
+    }
+}
+
+impl Deref for PooledClient {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().expect("the client is only taken when dropped")
+    }
+}
+
+impl DerefMut for PooledClient {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().expect("the client is only taken when dropped")
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        let Some(client) = self.client.take() else { return };
+        // 请求被取消的连接上还有没读的回复
+        if client.is_broken() || client.is_busy() {
+            return;
+        }
+        let idle_timeout = self.shared.config.idle_timeout;
+        let mut state = self.shared.state();
+        // 顺便关掉空闲太久的连接
+        state.idle.retain(|(_, since)| since.elapsed() <= idle_timeout);
+        state.idle.push((client, Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures::FutureExt;
+    use tokio::net::TcpListener;
+    use tokio::time::Instant;
+
+    use crate::client::{Client, Pipeline, Pool, PoolConfig};
+    use crate::codec::RedisFrame;
+    use crate::config::Config;
+    use crate::server::Server;
+
+    async fn server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let config = Config { save: vec![], dir: std::env::temp_dir(), ..Config::default() };
+        tokio::spawn(Server::with_config(listener, config).run());
+        addr
+    }
+
+    #[tokio::test]
+    async fn pool_test() {
+        let config = PoolConfig { size: 2, request_timeout: Duration::from_millis(200), ..PoolConfig::default() };
+        let pool = Pool::new(server().await, config);
+
+        let mut client = pool.get().await.unwrap();
+        client.set("key", "1").await.unwrap();
+        drop(client);
+        assert_eq!(pool.idle_connections(), 1);
+
+        // the idle connection is handed out again, a second one is opened next to it
+        let mut first = pool.get().await.unwrap();
+        assert_eq!(pool.idle_connections(), 0);
+        let mut second = pool.get().await.unwrap();
+        assert_eq!(first.incr("key").await.unwrap(), 2);
+        assert_eq!(second.get("key").await.unwrap(), Some(Bytes::from("2")));
+
+        // every connection is in use
+        let e = pool.get().await.err().unwrap();
+        assert!(e.to_string().starts_with("no free connection"));
+        drop(first);
+        drop(second);
+        assert_eq!(pool.idle_connections(), 2);
+
+        // many tasks share the two connections
+        let tasks: Vec<_> = (0..10).map(|_| {
+            let pool = pool.clone();
+            tokio::spawn(async move { pool.get().await?.incr("key").await })
+        }).collect();
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        assert_eq!(pool.get().await.unwrap().get("key").await.unwrap(), Some(Bytes::from("12")));
+
+        // connections idle for too long are closed instead of being reused
+        let config = PoolConfig { idle_timeout: Duration::ZERO, ..PoolConfig::default() };
+        let pool = Pool::new(server().await, config);
+        drop(pool.get().await.unwrap());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        drop(pool.get().await.unwrap());
+        assert_eq!(pool.idle_connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn cancelled_request_test() {
+        let pool = Pool::new(server().await, PoolConfig { size: 1, ..PoolConfig::default() });
+        let mut client = pool.get().await.unwrap();
+        client.set("a", "from-a").await.unwrap();
+        client.set("b", "from-b").await.unwrap();
+
+        // the request is written, then given up before its reply arrives
+        assert!(client.get("a").now_or_never().is_none());
+        assert!(client.is_busy());
+        drop(client);
+        assert_eq!(pool.idle_connections(), 0);
+        assert_eq!(pool.get().await.unwrap().get("b").await.unwrap(), Some(Bytes::from("from-b")));
+    }
+
+    #[tokio::test]
+    async fn watch_test() {
+        let pool = Pool::new(server().await, PoolConfig { size: 1, ..PoolConfig::default() });
+        let mut client = pool.get().await.unwrap();
+        client.watch(["a"]).await.unwrap();
+        assert!(client.is_watching());
+        drop(client);
+
+        // the next user of the connection does not inherit the WATCH
+        let mut other = Client::connect(pool.shared.addr.as_str()).await.unwrap();
+        other.set("a", "1").await.unwrap();
+        let mut client = pool.get().await.unwrap();
+        assert!(!client.is_watching());
+        let mut transaction = Pipeline::new();
+        transaction.incr("a");
+        assert_eq!(client.transaction(&transaction).await.unwrap(), Some(vec![RedisFrame::Integer(2)]));
+    }
+
+    #[tokio::test]
+    async fn timeout_test() {
+        // a server that accepts connections and never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut sockets = vec![];
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let config = PoolConfig { request_timeout: Duration::from_millis(100), ..PoolConfig::default() };
+        let pool = Pool::new(addr, config);
+        let mut client = pool.get().await.unwrap();
+        let e = client.ping().await.unwrap_err();
+        assert_eq!(e.to_string(), "no reply from the server within 100ms");
+        assert!(client.is_broken());
+        drop(client);
+        assert_eq!(pool.idle_connections(), 0);
+    }
+
+    #[tokio::test]
+    async fn server_down_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let config = PoolConfig { min_backoff: Duration::from_millis(200), ..PoolConfig::default() };
+        let pool = Pool::new(addr.clone(), config);
+        let e = pool.get().await.err().unwrap();
+        assert!(e.to_string().starts_with(&format!("can't connect to {}", addr)));
+
+        // no new attempt during the backoff, the request is refused right away
+        let start = Instant::now();
+        let e = pool.get().await.err().unwrap();
+        assert!(e.to_string().starts_with(&format!("{} is down", addr)), "{}", e);
+        assert!(start.elapsed() < Duration::from_millis(50));
+
+        // the next attempt fails again and doubles the backoff
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(pool.get().await.err().unwrap().to_string().starts_with("can't connect"));
+        let state = pool.shared.state();
+        assert_eq!(state.failures, 2);
+        assert!(state.retry_at.unwrap() > Instant::now() + Duration::from_millis(300));
+    }
+}
@@ -0,0 +1,36 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::time::Instant;
+
+/// milliseconds since the unix epoch
+pub(crate) fn unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64)
+}
+
+/// seconds since the unix epoch
+pub(crate) fn unix_secs() -> u64 {
+    unix_ms() / 1000
+}
+
+/// expiries are kept as monotonic `Instant`s, but persisted and propagated as unix time
+pub(crate) fn instant_to_unix_ms(when: Instant) -> u64 {
+    let now = Instant::now();
+    let unix_now = unix_ms();
+    if when >= now {
+        unix_now + (when - now).as_millis() as u64
+    } else {
+        unix_now.saturating_sub((now - when).as_millis() as u64)
+    }
+}
+
+/// turn an absolute unix time in milliseconds into an `Instant`.
+/// A time in the past maps to an already elapsed instant
+pub(crate) fn unix_ms_to_instant(unix: u64) -> Instant {
+    let now = Instant::now();
+    let unix_now = unix_ms();
+    if unix >= unix_now {
+        now + Duration::from_millis(unix - unix_now)
+    } else {
+        now.checked_sub(Duration::from_millis(unix_now - unix)).unwrap_or(now)
+    }
+}
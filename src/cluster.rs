@@ -0,0 +1,852 @@
+//! Cluster mode: the keyspace is split into 16384 hash slots and every node serves the
+//! slots it owns, redirecting clients to the owner of the others with `-MOVED`.
+//! A slot being resharded is `MIGRATING` on its owner and `IMPORTING` on its next owner:
+//! keys already moved are served by the new owner to clients that send `ASKING`.
+//!
+//! There is no cluster bus yet. Nodes learn about each other with `CLUSTER MEET` and then
+//! poll the `CLUSTER NODES` of every node they know about once per period over a plain
+//! client connection. Every node is the authority on the slots it owns itself.
+//! The view of a node is saved to `cluster-config-file` (`nodes.conf`) in the format of
+//! `CLUSTER NODES`, so it keeps its ID and slots across restarts
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio::time;
+use tokio_util::codec::Framed;
+
+use crate::clock::unix_ms;
+use crate::codec::{RedisCodec, RedisFrame};
+use crate::config::Config;
+use crate::db::{SharedDb, Store};
+use crate::frame::Frame;
+use crate::replication::random_id;
+use crate::slot::{key_hash_slot, SLOT_COUNT};
+use crate::RedisResult;
+
+/// how often every known node is polled
+const CLUSTER_POLL_PERIOD: Duration = Duration::from_secs(1);
+
+/// a poll that takes longer than this failed
+const POLL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// how long a node removed with CLUSTER FORGET is not added back from what other nodes know
+const FORGET_BAN: u64 = 60_000;
+
+/// the offset of the cluster bus port Redis shows next to the client port
+const BUS_PORT_OFFSET: u32 = 10000;
+
+/// The cluster as this node sees it
+#[derive(Debug)]
+pub(crate) struct Cluster {
+    myself: String,
+    /// every known node by ID, this one included
+    nodes: HashMap<String, Node>,
+    /// the ID of the owner of every slot
+    slots: Vec<Option<String>>,
+    /// slot -> the node its keys are moved to
+    migrating: BTreeMap<u16, String>,
+    /// slot -> the node its keys are moved from
+    importing: BTreeMap<u16, String>,
+    /// forgotten node ID -> end of its ban, unix ms
+    banned: HashMap<String, u64>,
+    path: PathBuf,
+    /// milliseconds without an answer after which a node is flagged `fail`
+    node_timeout: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    id: String,
+    ip: String,
+    port: u16,
+    /// met by address, its real ID is not known yet
+    handshake: bool,
+    /// the last poll that got an answer, unix ms
+    pong_recv: u64,
+    /// the last poll sent, unix ms
+    ping_sent: u64,
+    /// the last poll got an answer
+    connected: bool,
+}
+
+/// one line of `CLUSTER NODES` or `nodes.conf`
+#[derive(Debug, Clone, PartialEq)]
+struct NodeLine {
+    id: String,
+    ip: String,
+    port: u16,
+    myself: bool,
+    handshake: bool,
+    slots: Vec<(u16, u16)>,
+    migrating: Vec<(u16, String)>,
+    importing: Vec<(u16, String)>,
+}
+
+/// CLUSTER SETSLOT
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SetSlot {
+    Importing(String),
+    Migrating(String),
+    Stable,
+    Node(String),
+}
+
+impl Node {
+    fn new(id: String, ip: String, port: u16) -> Self {
+        Self { id, ip, port, handshake: false, pong_recv: 0, ping_sent: 0, connected: false }
+    }
+
+    fn addr(&self) -> String {
+        format!("{}:{}", self.ip, self.port)
+    }
+}
+
+impl Cluster {
+    pub(crate) fn new(config: &Config) -> Self {
+        let myself = Node::new(random_id(), "127.0.0.1".to_string(), 0);
+        let id = myself.id.clone();
+        Self {
+            myself: id.clone(),
+            nodes: HashMap::from([(id, myself)]),
+            slots: vec![None; SLOT_COUNT as usize],
+            migrating: BTreeMap::new(),
+            importing: BTreeMap::new(),
+            banned: HashMap::new(),
+            path: config.dir.join(&config.cluster_config_file),
+            node_timeout: config.cluster_node_timeout,
+        }
+    }
+
+    /// apply a CONFIG SET of `cluster-node-timeout`
+    pub(crate) fn configure(&mut self, config: &Config) {
+        self.node_timeout = config.cluster_node_timeout;
+    }
+
+    pub(crate) fn myself(&self) -> &str {
+        &self.myself
+    }
+
+    /// the address clients reach this node at, known once the server listens
+    pub(crate) fn set_address(&mut self, ip: String, port: u16) {
+        if let Some(myself) = self.nodes.get_mut(&self.myself) {
+            myself.ip = ip;
+            myself.port = port;
+        }
+    }
+
+    /// load `nodes.conf`, or create it on the first start. Our address is the one we listen on,
+    /// not the saved one
+    pub(crate) fn load(&mut self) -> io::Result<()> {
+        let text = match fs::read_to_string(&self.path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return self.save(),
+            Err(e) => return Err(e),
+        };
+        let lines = parse_nodes(&text);
+        let own = lines.iter().find(|line| line.myself)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "no myself node in the cluster config file"))?;
+
+        let (ip, port) = {
+            let myself = &self.nodes[&self.myself];
+            (myself.ip.clone(), myself.port)
+        };
+        self.nodes.clear();
+        self.slots = vec![None; SLOT_COUNT as usize];
+        self.myself = own.id.clone();
+        for line in &lines {
+            let mut node = Node::new(line.id.clone(), line.ip.clone(), line.port);
+            node.handshake = line.handshake;
+            self.nodes.insert(line.id.clone(), node);
+            self.assign(&line.id, &line.slots);
+        }
+        self.migrating = own.migrating.iter().cloned().collect();
+        self.importing = own.importing.iter().cloned().collect();
+        self.set_address(ip, port);
+        Ok(())
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let mut text = self.nodes_text();
+        text.push_str("vars currentEpoch 0 lastVoteEpoch 0\n");
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, text)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    /// save the config after a change, a failure is only logged
+    fn changed(&self) {
+        if let Err(e) = self.save() {
+            eprintln!("Can't save the cluster config file {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn assign(&mut self, id: &str, ranges: &[(u16, u16)]) {
+        for &(start, end) in ranges {
+            for slot in start..=end.min(SLOT_COUNT - 1) {
+                self.slots[slot as usize] = Some(id.to_string());
+            }
+        }
+    }
+
+    /// the node serving a slot, `None` while it is unassigned
+    fn owner(&self, slot: u16) -> Option<&Node> {
+        self.slots[slot as usize].as_ref().and_then(|id| self.nodes.get(id))
+    }
+
+    fn owns(&self, slot: u16) -> bool {
+        self.slots[slot as usize].as_deref() == Some(self.myself.as_str())
+    }
+
+    /// CLUSTER ADDSLOTS and ADDSLOTSRANGE
+    pub(crate) fn add_slots(&mut self, slots: &[u16]) -> Result<(), String> {
+        let mut seen = vec![false; SLOT_COUNT as usize];
+        for &slot in slots {
+            if self.slots[slot as usize].is_some() {
+                return Err(format!("ERR Slot {} is already busy", slot));
+            }
+            if std::mem::replace(&mut seen[slot as usize], true) {
+                return Err(format!("ERR Slot {} specified multiple times", slot));
+            }
+        }
+        for &slot in slots {
+            self.slots[slot as usize] = Some(self.myself.clone());
+            self.importing.remove(&slot);
+        }
+        self.changed();
+        Ok(())
+    }
+
+    /// CLUSTER DELSLOTS and DELSLOTSRANGE
+    pub(crate) fn del_slots(&mut self, slots: &[u16]) -> Result<(), String> {
+        for &slot in slots {
+            if self.slots[slot as usize].is_none() {
+                return Err(format!("ERR Slot {} is already unassigned", slot));
+            }
+        }
+        for &slot in slots {
+            self.slots[slot as usize] = None;
+            self.migrating.remove(&slot);
+            self.importing.remove(&slot);
+        }
+        self.changed();
+        Ok(())
+    }
+
+    /// CLUSTER SETSLOT. `keys` is how many keys of the slot this node holds
+    pub(crate) fn set_slot(&mut self, slot: u16, action: SetSlot, keys: usize) -> Result<(), String> {
+        let known = |cluster: &Self, id: &str| if cluster.nodes.contains_key(id) {
+            Ok(())
+        } else {
+            Err(format!("ERR I don't know about node {}", id))
+        };
+
+        match action {
+            SetSlot::Migrating(id) => {
+                if !self.owns(slot) {
+                    return Err(format!("ERR I'm not the owner of hash slot {}", slot));
+                }
+                known(self, &id)?;
+                if id == self.myself {
+                    return Err("ERR Target node is myself".to_string());
+                }
+                self.migrating.insert(slot, id);
+            }
+            SetSlot::Importing(id) => {
+                if self.owns(slot) {
+                    return Err(format!("ERR I'm already the owner of hash slot {}", slot));
+                }
+                known(self, &id)?;
+                if id == self.myself {
+                    return Err("ERR Source node is myself".to_string());
+                }
+                self.importing.insert(slot, id);
+            }
+            SetSlot::Stable => {
+                self.migrating.remove(&slot);
+                self.importing.remove(&slot);
+            }
+            SetSlot::Node(id) => {
+                known(self, &id)?;
+                if self.owns(slot) && id != self.myself && keys > 0 {
+                    return Err(format!(
+                        "ERR Can't assign hashslot {} to a different node while I still hold keys for this hash slot.", slot));
+                }
+                // the end of a migration on either side
+                if id != self.myself {
+                    self.migrating.remove(&slot);
+                }
+                if id == self.myself {
+                    self.importing.remove(&slot);
+                }
+                self.slots[slot as usize] = Some(id);
+            }
+        }
+        self.changed();
+        Ok(())
+    }
+
+    /// CLUSTER MEET: the node is added in the handshake state until a poll tells its ID
+    pub(crate) fn meet(&mut self, ip: String, port: u16) {
+        if self.nodes.values().any(|node| node.ip == ip && node.port == port) {
+            return;
+        }
+        let mut node = Node::new(random_id(), ip, port);
+        node.handshake = true;
+        self.nodes.insert(node.id.clone(), node);
+        self.changed();
+    }
+
+    /// CLUSTER FORGET: drop a node and unassign its slots
+    pub(crate) fn forget(&mut self, id: &str) -> Result<(), String> {
+        if id == self.myself {
+            return Err("ERR I tried hard but I can't forget myself...".to_string());
+        }
+        if self.nodes.remove(id).is_none() {
+            return Err(format!("ERR Unknown node {}", id));
+        }
+        self.drop_slots_of(id);
+        self.banned.insert(id.to_string(), unix_ms() + FORGET_BAN);
+        self.changed();
+        Ok(())
+    }
+
+    fn drop_slots_of(&mut self, id: &str) {
+        for owner in self.slots.iter_mut() {
+            if owner.as_deref() == Some(id) {
+                *owner = None;
+            }
+        }
+        self.migrating.retain(|_, target| target != id);
+        self.importing.retain(|_, source| source != id);
+    }
+
+    fn is_banned(&self, id: &str) -> bool {
+        self.banned.get(id).is_some_and(|&until| until > unix_ms())
+    }
+
+    /// the flags column of CLUSTER NODES
+    fn flags(&self, node: &Node) -> String {
+        let mut flags = vec![];
+        if node.id == self.myself {
+            flags.push("myself");
+        }
+        flags.push("master");
+        if node.handshake {
+            flags.push("handshake");
+        } else if node.id != self.myself && !node.connected
+            && unix_ms().saturating_sub(node.pong_recv) > self.node_timeout {
+            flags.push("fail");
+        }
+        flags.join(",")
+    }
+
+    /// the slots of a node as inclusive ranges
+    fn ranges_of(&self, id: &str) -> Vec<(u16, u16)> {
+        let mut ranges: Vec<(u16, u16)> = vec![];
+        for (slot, owner) in self.slots.iter().enumerate() {
+            if owner.as_deref() != Some(id) {
+                continue;
+            }
+            let slot = slot as u16;
+            match ranges.last_mut() {
+                Some((_, end)) if *end + 1 == slot => *end = slot,
+                _ => ranges.push((slot, slot)),
+            }
+        }
+        ranges
+    }
+
+    /// the nodes sorted by ID, so that the output is stable
+    fn sorted_nodes(&self) -> Vec<&Node> {
+        let mut nodes: Vec<&Node> = self.nodes.values().collect();
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        nodes
+    }
+
+    /// CLUSTER NODES, also the content of `nodes.conf`
+    pub(crate) fn nodes_text(&self) -> String {
+        let mut text = String::new();
+        for node in self.sorted_nodes() {
+            let link = if node.id == self.myself || node.connected { "connected" } else { "disconnected" };
+            let _ = write!(text, "{} {}:{}@{} {} - {} {} 0 {}", node.id, node.ip, node.port,
+                           node.port as u32 + BUS_PORT_OFFSET, self.flags(node), node.ping_sent, node.pong_recv, link);
+            for (start, end) in self.ranges_of(&node.id) {
+                if start == end {
+                    let _ = write!(text, " {}", start);
+                } else {
+                    let _ = write!(text, " {}-{}", start, end);
+                }
+            }
+            if node.id == self.myself {
+                for (slot, target) in &self.migrating {
+                    let _ = write!(text, " [{}->-{}]", slot, target);
+                }
+                for (slot, source) in &self.importing {
+                    let _ = write!(text, " [{}-<-{}]", slot, source);
+                }
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// CLUSTER SLOTS: `[start, end, [ip, port, id]]` for every range of slots
+    pub(crate) fn slots_reply(&self) -> Frame {
+        let mut ranges: Vec<(u16, u16, &Node)> = self.nodes.values()
+            .flat_map(|node| self.ranges_of(&node.id).into_iter().map(move |(start, end)| (start, end, node)))
+            .collect();
+        ranges.sort_by_key(|(start, _, _)| *start);
+
+        Frame::Array(ranges.into_iter().map(|(start, end, node)| Frame::Array(vec![
+            Frame::Integer(start as i64),
+            Frame::Integer(end as i64),
+            Frame::Array(vec![
+                Frame::Bulk(Bytes::from(node.ip.clone())),
+                Frame::Integer(node.port as i64),
+                Frame::Bulk(Bytes::from(node.id.clone())),
+            ]),
+        ])).collect())
+    }
+
+    /// CLUSTER SHARDS: the slots and nodes of every shard. Without replicas a shard is one node
+    pub(crate) fn shards_reply(&self) -> Frame {
+        let bulk = |s: &str| Frame::Bulk(Bytes::from(s.to_string()));
+        Frame::Array(self.sorted_nodes().into_iter().filter(|node| !node.handshake).map(|node| {
+            let slots = self.ranges_of(&node.id).into_iter()
+                .flat_map(|(start, end)| [Frame::Integer(start as i64), Frame::Integer(end as i64)])
+                .collect();
+            let health = if self.flags(node).contains("fail") { "fail" } else { "online" };
+            Frame::Array(vec![
+                bulk("slots"),
+                Frame::Array(slots),
+                bulk("nodes"),
+                Frame::Array(vec![Frame::Array(vec![
+                    bulk("id"), bulk(&node.id),
+                    bulk("port"), Frame::Integer(node.port as i64),
+                    bulk("ip"), bulk(&node.ip),
+                    bulk("endpoint"), bulk(&node.ip),
+                    bulk("role"), bulk("master"),
+                    bulk("replication-offset"), Frame::Integer(0),
+                    bulk("health"), bulk(health),
+                ])]),
+            ])
+        }).collect())
+    }
+
+    /// CLUSTER INFO
+    pub(crate) fn info(&self) -> String {
+        let assigned = self.slots.iter().filter(|owner| owner.is_some()).count();
+        let failed = self.slots.iter().enumerate()
+            .filter(|(slot, _)| self.owner(*slot as u16).is_some_and(|node| self.flags(node).contains("fail")))
+            .count();
+        let size = self.nodes.keys().filter(|id| self.slots.iter().any(|owner| owner.as_ref() == Some(*id))).count();
+        let ok = assigned == SLOT_COUNT as usize && failed == 0;
+
+        let mut info = String::new();
+        let _ = write!(info, "cluster_state:{}\r\n", if ok { "ok" } else { "fail" });
+        let _ = write!(info, "cluster_slots_assigned:{}\r\n", assigned);
+        let _ = write!(info, "cluster_slots_ok:{}\r\n", assigned - failed);
+        let _ = write!(info, "cluster_slots_pfail:0\r\ncluster_slots_fail:{}\r\n", failed);
+        let _ = write!(info, "cluster_known_nodes:{}\r\ncluster_size:{}\r\n", self.nodes.len(), size);
+        let _ = write!(info, "cluster_current_epoch:0\r\ncluster_my_epoch:0\r\n");
+        info
+    }
+
+    /// the nodes to poll: every node but this one
+    fn peers(&self) -> Vec<(String, String, u16)> {
+        self.nodes.values()
+            .filter(|node| node.id != self.myself)
+            .map(|node| (node.id.clone(), node.ip.clone(), node.port))
+            .collect()
+    }
+
+    /// take in the answer of a poll of node `id`: its real ID, its slots and the nodes it knows
+    fn polled(&mut self, id: &str, sent: u64, result: RedisResult<Vec<NodeLine>>) {
+        let Some(node) = self.nodes.get_mut(id) else { return };
+        node.ping_sent = sent;
+        let lines = match result {
+            Ok(lines) => lines,
+            Err(_) => {
+                node.connected = false;
+                return;
+            }
+        };
+        let Some(own) = lines.iter().find(|line| line.myself) else { return };
+
+        let mut changed = false;
+        let mut id = id.to_string();
+        if own.id != id {
+            // the answer of a node met by address, or of another node now at that address
+            let mut node = self.nodes.remove(&id).unwrap();
+            self.drop_slots_of(&id);
+            changed = true;
+            if own.id == self.myself || self.is_banned(&own.id) || self.nodes.contains_key(&own.id) {
+                self.changed();
+                return;
+            }
+            node.id = own.id.clone();
+            node.handshake = false;
+            id = own.id.clone();
+            self.nodes.insert(id.clone(), node);
+        }
+        let node = self.nodes.get_mut(&id).unwrap();
+        node.connected = true;
+        node.pong_recv = unix_ms();
+
+        // the node is the authority on its own slots, unless we claim them
+        let before = self.ranges_of(&id);
+        for owner in self.slots.iter_mut() {
+            if owner.as_deref() == Some(id.as_str()) {
+                *owner = None;
+            }
+        }
+        for &(start, end) in &own.slots {
+            for slot in start..=end.min(SLOT_COUNT - 1) {
+                if !self.owns(slot) {
+                    self.slots[slot as usize] = Some(id.clone());
+                }
+            }
+        }
+        changed |= before != self.ranges_of(&id);
+
+        // nodes it knows and we do not
+        for line in lines.iter().filter(|line| !line.myself && !line.handshake) {
+            let known = line.id == self.myself || self.nodes.contains_key(&line.id)
+                || self.nodes.values().any(|node| node.ip == line.ip && node.port == line.port);
+            if !known && !self.is_banned(&line.id) {
+                self.nodes.insert(line.id.clone(), Node::new(line.id.clone(), line.ip.clone(), line.port));
+                changed = true;
+            }
+        }
+
+        if changed {
+            self.changed();
+        }
+    }
+}
+
+/// parse `CLUSTER NODES` or `nodes.conf`, skipping lines it does not understand
+fn parse_nodes(text: &str) -> Vec<NodeLine> {
+    text.lines().filter_map(parse_node_line).collect()
+}
+
+fn parse_node_line(line: &str) -> Option<NodeLine> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 8 || fields[0] == "vars" {
+        return None;
+    }
+    let addr = fields[1].split(['@', ',']).next()?;
+    let (ip, port) = addr.rsplit_once(':')?;
+    let flags: Vec<&str> = fields[2].split(',').collect();
+
+    let mut node = NodeLine {
+        id: fields[0].to_string(),
+        ip: ip.to_string(),
+        port: port.parse().ok()?,
+        myself: flags.contains(&"myself"),
+        handshake: flags.contains(&"handshake"),
+        slots: vec![],
+        migrating: vec![],
+        importing: vec![],
+    };
+    for slot in &fields[8..] {
+        if let Some(state) = slot.strip_prefix('[').and_then(|slot| slot.strip_suffix(']')) {
+            if let Some((slot, target)) = state.split_once("->-") {
+                node.migrating.push((slot.parse().ok()?, target.to_string()));
+            } else if let Some((slot, source)) = state.split_once("-<-") {
+                node.importing.push((slot.parse().ok()?, source.to_string()));
+            }
+        } else if let Some((start, end)) = slot.split_once('-') {
+            node.slots.push((start.parse().ok()?, end.parse().ok()?));
+        } else {
+            let slot = slot.parse().ok()?;
+            node.slots.push((slot, slot));
+        }
+    }
+    Some(node)
+}
+
+/// where a request about `keys` has to go, `None` if this node serves it.
+/// `asking` is set when the client sent ASKING right before
+pub(crate) fn redirect(store: &Store, keys: &[Bytes], asking: bool) -> Option<Frame> {
+    let cluster = store.cluster.as_ref()?;
+    let first = keys.first()?;
+    let slot = key_hash_slot(first);
+    if keys.iter().any(|key| key_hash_slot(key) != slot) {
+        return Some(Frame::Error("CROSSSLOT Keys in request don't hash to the same slot".to_string()));
+    }
+
+    let owner = match cluster.owner(slot) {
+        Some(owner) => owner,
+        None => return Some(Frame::Error("CLUSTERDOWN Hash slot not served".to_string())),
+    };
+    let missing = keys.iter()
+        .filter(|key| store.peek_data(&String::from_utf8_lossy(key)).is_none())
+        .count();
+
+    if owner.id == cluster.myself {
+        // keys that are gone were already moved to the target of the migration
+        if let Some(target) = cluster.migrating.get(&slot).and_then(|id| cluster.nodes.get(id)) {
+            if missing == keys.len() {
+                return Some(Frame::Error(format!("ASK {} {}", slot, target.addr())));
+            }
+            if missing > 0 {
+                return Some(Frame::Error("TRYAGAIN Multiple keys request during rehashing of slot".to_string()));
+            }
+        }
+        return None;
+    }
+
+    if asking && cluster.importing.contains_key(&slot) {
+        if keys.len() > 1 && missing > 0 {
+            return Some(Frame::Error("TRYAGAIN Multiple keys request during rehashing of slot".to_string()));
+        }
+        return None;
+    }
+    Some(Frame::Error(format!("MOVED {} {}", slot, owner.addr())))
+}
+
+/// whether the slot of `keys` is being migrated from or imported into this node
+pub(crate) fn is_slot_open(store: &Store, keys: &[Bytes]) -> bool {
+    let (Some(cluster), Some(first)) = (store.cluster.as_ref(), keys.first()) else { return false };
+    let slot = key_hash_slot(first);
+    cluster.migrating.contains_key(&slot) || cluster.importing.contains_key(&slot)
+}
+
+/// poll every known node, forever, while cluster mode is on
+pub(crate) async fn cluster_task(db: SharedDb) {
+    let mut interval = time::interval(CLUSTER_POLL_PERIOD);
+    loop {
+        interval.tick().await;
+
+        let (peers, myself) = {
+            let store = db.lock();
+            let Some(cluster) = store.cluster.as_ref() else { return };
+            let myself = &cluster.nodes[&cluster.myself];
+            (cluster.peers(), (myself.id.clone(), myself.ip.clone(), myself.port))
+        };
+        // 所有节点一起轮询，一个节点连不上不会拖慢其他节点
+        let polls = peers.into_iter().map(|(id, ip, port)| {
+            let myself = &myself;
+            async move {
+                let sent = unix_ms();
+                let result = match time::timeout(POLL_TIMEOUT, poll(&ip, port, myself)).await {
+                    Ok(result) => result,
+                    Err(_) => Err("timed out".into()),
+                };
+                (id, sent, result)
+            }
+        });
+        let results = futures::future::join_all(polls).await;
+
+        let mut store = db.lock();
+        if let Some(cluster) = store.cluster.as_mut() {
+            for (id, sent, result) in results {
+                cluster.polled(&id, sent, result);
+            }
+        }
+    }
+}
+
+/// ask a node for its CLUSTER NODES, and introduce ourselves if it does not know us
+async fn poll(ip: &str, port: u16, myself: &(String, String, u16)) -> RedisResult<Vec<NodeLine>> {
+    let mut framed = Framed::new(TcpStream::connect((ip, port)).await?, RedisCodec);
+    let nodes = match request(&mut framed, &["CLUSTER", "NODES"]).await? {
+        Frame::Bulk(nodes) => parse_nodes(&String::from_utf8_lossy(&nodes)),
+        other => return Err(format!("unexpected CLUSTER NODES reply {:?}", other).into()),
+    };
+    if !nodes.iter().any(|node| node.id == myself.0) {
+        request(&mut framed, &["CLUSTER", "MEET", &myself.1, &myself.2.to_string()]).await?;
+    }
+    Ok(nodes)
+}
+
+async fn request(framed: &mut Framed<TcpStream, RedisCodec>, argv: &[&str]) -> RedisResult<Frame> {
+    let request = argv.iter().map(|arg| RedisFrame::Bulk(Bytes::from(arg.to_string()))).collect();
+    framed.send(RedisFrame::Array(request)).await?;
+    match framed.next().await {
+        Some(Ok(reply)) => match Frame::from(reply) {
+            Frame::Error(e) => Err(e.into()),
+            reply => Ok(reply),
+        },
+        Some(Err(e)) => Err(e.into()),
+        None => Err("connection closed".into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::net::TcpListener;
+    use tokio::sync::broadcast;
+
+    use crate::cluster::{is_slot_open, parse_nodes, redirect, Cluster, SetSlot};
+    use crate::cmd::Cmd;
+    use crate::config::{Config, MaxmemoryPolicy};
+    use crate::db::{SharedDb, test_config, test_db};
+    use crate::frame::Frame;
+    use crate::server::process;
+    use crate::slot::key_hash_slot;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("mini-redis-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn cluster_db(dir: std::path::PathBuf) -> SharedDb {
+        test_db(Config { dir, cluster_enabled: true, ..test_config() })
+    }
+
+    fn keys(args: &str) -> Vec<Bytes> {
+        let frame = Frame::Array(args.split(' ').map(|arg| Frame::Bulk(Bytes::from(arg.to_string()))).collect());
+        Cmd::try_from(frame).unwrap().keys()
+    }
+
+    fn error(frame: Option<Frame>) -> String {
+        match frame {
+            Some(Frame::Error(e)) => e,
+            other => panic!("unexpected redirect {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nodes_text_test() {
+        let dir = temp_dir("cluster-nodes");
+        let mut cluster = Cluster::new(&Config { dir: dir.clone(), ..Config::default() });
+        cluster.set_address("127.0.0.1".to_string(), 7000);
+        cluster.meet("127.0.0.1".to_string(), 7001);
+        let other = cluster.peers()[0].0.clone();
+        cluster.add_slots(&[0, 1, 2, 5]).unwrap();
+        cluster.set_slot(5, SetSlot::Migrating(other.clone()), 1).unwrap();
+
+        let lines = parse_nodes(&cluster.nodes_text());
+        let myself = lines.iter().find(|line| line.myself).unwrap();
+        assert_eq!(myself.slots, vec![(0, 2), (5, 5)]);
+        assert_eq!(myself.migrating, vec![(5, other.clone())]);
+        assert!(lines.iter().any(|line| line.id == other && line.handshake && line.port == 7001));
+
+        // nodes.conf keeps the ID and the slots across restarts
+        let mut restarted = Cluster::new(&Config { dir: dir.clone(), ..Config::default() });
+        restarted.load().unwrap();
+        assert_eq!(restarted.myself(), cluster.myself());
+        assert_eq!(restarted.ranges_of(cluster.myself()), vec![(0, 2), (5, 5)]);
+        assert_eq!(restarted.migrating.get(&5), Some(&other));
+
+        assert!(cluster.add_slots(&[2]).unwrap_err().contains("busy"));
+        assert!(cluster.set_slot(9, SetSlot::Migrating(other), 0).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn command_keys_test() {
+        assert_eq!(keys("GET foo"), vec![Bytes::from("foo")]);
+        assert_eq!(keys("DEL a b"), vec![Bytes::from("a"), Bytes::from("b")]);
+        assert_eq!(keys("SORT a LIMIT 0 1 STORE b"), vec![Bytes::from("a"), Bytes::from("b")]);
+        assert_eq!(keys("MIGRATE 127.0.0.1 7001 a 0 1000"), vec![Bytes::from("a")]);
+        assert_eq!(keys("MIGRATE 127.0.0.1 7001  0 1000 COPY KEYS a b"), vec![Bytes::from("a"), Bytes::from("b")]);
+        assert_eq!(keys("MEMORY USAGE a SAMPLES 0"), vec![Bytes::from("a")]);
+        assert!(keys("MEMORY STATS").is_empty());
+        assert!(keys("PING").is_empty());
+    }
+
+    #[tokio::test]
+    async fn redirect_test() {
+        let dir = temp_dir("cluster-redirect");
+        let db = cluster_db(dir.clone());
+        let mut store = db.lock();
+        let other = {
+            let cluster = store.cluster.as_mut().unwrap();
+            cluster.meet("127.0.0.1".to_string(), 7001);
+            let other = cluster.peers()[0].0.clone();
+            cluster.add_slots(&(0..8192).collect::<Vec<_>>()).unwrap();
+            other
+        };
+
+        // "a" is in slot 15495, "b" in 3300, "foo" in 12182, "x" in 16287
+        assert!(redirect(&store, &keys("GET b"), false).is_none());
+        assert!(error(redirect(&store, &keys("MGET a b"), false)).starts_with("CROSSSLOT"));
+        assert!(error(redirect(&store, &keys("GET a"), false)).starts_with("CLUSTERDOWN"));
+        store.cluster.as_mut().unwrap().set_slot(key_hash_slot(b"foo"), SetSlot::Node(other.clone()), 0).unwrap();
+        assert_eq!(error(redirect(&store, &keys("GET foo"), false)), "MOVED 12182 127.0.0.1:7001");
+
+        // a slot moving away: missing keys are asked to the target
+        let b = key_hash_slot(b"b");
+        store.cluster.as_mut().unwrap().set_slot(b, SetSlot::Migrating(other.clone()), 0).unwrap();
+        store.set_bytes("b", Bytes::from("1"), None);
+        assert!(redirect(&store, &keys("GET b"), false).is_none());
+        assert_eq!(error(redirect(&store, &keys("GET {b}x"), false)), format!("ASK {} 127.0.0.1:7001", b));
+        assert!(error(redirect(&store, &keys("MGET b {b}x"), false)).starts_with("TRYAGAIN"));
+        // looking for the keys is not an access of them
+        store.config.maxmemory_policy = MaxmemoryPolicy::AllKeysLfu;
+        store.config.lfu_log_factor = 0;
+        let freq = store.object_freq("b");
+        for _ in 0..10 {
+            assert!(redirect(&store, &keys("GET b"), false).is_none());
+        }
+        assert_eq!(store.object_freq("b"), freq);
+        assert!(store.cluster.as_mut().unwrap().set_slot(b, SetSlot::Node(other.clone()), 1).is_err());
+        assert!(is_slot_open(&store, &keys("GET {b}x")));
+        assert!(!is_slot_open(&store, &keys("GET a")));
+
+        // a slot moving here is served to clients that sent ASKING
+        let cluster = store.cluster.as_mut().unwrap();
+        cluster.set_slot(key_hash_slot(b"foo"), SetSlot::Importing(other), 0).unwrap();
+        assert!(error(redirect(&store, &keys("GET foo"), false)).starts_with("MOVED"));
+        assert!(redirect(&store, &keys("GET foo"), true).is_none());
+        drop(store);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn meet_test() {
+        let dirs = [temp_dir("cluster-a"), temp_dir("cluster-b")];
+        let mut nodes = vec![];
+        for dir in &dirs {
+            let db = cluster_db(dir.clone());
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            db.lock().cluster.as_mut().unwrap().set_address("127.0.0.1".to_string(), port);
+            let (sender, _) = broadcast::channel(1);
+            let server = db.clone();
+            tokio::spawn(async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    tokio::spawn(process(socket, server.clone(), sender.subscribe()));
+                }
+            });
+            nodes.push((db, port));
+        }
+
+        nodes[0].0.lock().cluster.as_mut().unwrap().add_slots(&(0..8192).collect::<Vec<_>>()).unwrap();
+        nodes[1].0.lock().cluster.as_mut().unwrap().add_slots(&(8192..16384).collect::<Vec<_>>()).unwrap();
+        nodes[0].0.lock().cluster.as_mut().unwrap().meet("127.0.0.1".to_string(), nodes[1].1);
+
+        // B learns about A from the MEET A sends it, and both end up with every slot covered
+        for _ in 0..50 {
+            let ok = nodes.iter().all(|(db, _)| db.lock().cluster.as_ref().unwrap().info().starts_with("cluster_state:ok"));
+            if ok {
+                let store = nodes[1].0.lock();
+                let moved = error(redirect(&store, &keys("GET b"), false));
+                assert_eq!(moved, format!("MOVED 3300 127.0.0.1:{}", nodes[0].1));
+                drop(store);
+                for dir in &dirs {
+                    std::fs::remove_dir_all(dir).unwrap();
+                }
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("the cluster did not converge: {}", nodes[0].0.lock().cluster.as_ref().unwrap().nodes_text());
+    }
+}
@@ -0,0 +1,158 @@
+use bytes::Bytes;
+
+use crate::cluster::SetSlot;
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::slot::{key_hash_slot, SLOT_COUNT};
+use crate::RedisResult;
+
+/// https://redis.io/commands/cluster/
+/// Syntax:
+/// - CLUSTER INFO: the state of the cluster
+/// - CLUSTER MYID: the ID of this node
+/// - CLUSTER NODES: every known node with its flags and slots, the format of `nodes.conf`
+/// - CLUSTER SLOTS: the node serving each range of slots
+/// - CLUSTER SHARDS: the slots and nodes of each shard
+/// - CLUSTER KEYSLOT key: the hash slot of a key
+/// - CLUSTER COUNTKEYSINSLOT slot: how many keys of the slot this node holds
+/// - CLUSTER GETKEYSINSLOT slot count: up to `count` keys of the slot held by this node
+/// - CLUSTER ADDSLOTS slot [slot ...] / ADDSLOTSRANGE start end [start end ...]: take unassigned slots
+/// - CLUSTER DELSLOTS slot [slot ...] / DELSLOTSRANGE start end [start end ...]: unassign slots
+/// - CLUSTER SETSLOT slot IMPORTING node-id | MIGRATING node-id | STABLE | NODE node-id:
+///   the steps of moving a slot from one node to another
+/// - CLUSTER MEET ip port: add a node to the cluster
+/// - CLUSTER FORGET node-id: remove a node from the view of this node
+#[derive(Debug)]
+pub(crate) enum ClusterCommand {
+    Info,
+    MyId,
+    Nodes,
+    Slots,
+    Shards,
+    KeySlot(Bytes),
+    CountKeysInSlot(u16),
+    GetKeysInSlot(u16, usize),
+    AddSlots(Vec<u16>),
+    DelSlots(Vec<u16>),
+    SetSlot(u16, SetSlot),
+    Meet(String, u16),
+    Forget(String),
+}
+
+impl ClusterCommand {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let command = match iter.next_string()?.to_uppercase().as_str() {
+            "INFO" => ClusterCommand::Info,
+            "MYID" => ClusterCommand::MyId,
+            "NODES" => ClusterCommand::Nodes,
+            "SLOTS" => ClusterCommand::Slots,
+            "SHARDS" => ClusterCommand::Shards,
+            "KEYSLOT" => ClusterCommand::KeySlot(iter.next_bytes()?),
+            "COUNTKEYSINSLOT" => ClusterCommand::CountKeysInSlot(next_slot(iter)?),
+            "GETKEYSINSLOT" => {
+                let slot = next_slot(iter)?;
+                let count = iter.next_int().ok().filter(|count| *count >= 0)
+                    .ok_or_else(|| FrameError::from("ERR Invalid number of keys"))?;
+                ClusterCommand::GetKeysInSlot(slot, count as usize)
+            }
+            "ADDSLOTS" => ClusterCommand::AddSlots(remaining_slots(iter)?),
+            "ADDSLOTSRANGE" => ClusterCommand::AddSlots(remaining_ranges(iter)?),
+            "DELSLOTS" => ClusterCommand::DelSlots(remaining_slots(iter)?),
+            "DELSLOTSRANGE" => ClusterCommand::DelSlots(remaining_ranges(iter)?),
+            "SETSLOT" => {
+                let slot = next_slot(iter)?;
+                let action = match iter.next_string()?.to_uppercase().as_str() {
+                    "IMPORTING" => SetSlot::Importing(iter.next_string()?),
+                    "MIGRATING" => SetSlot::Migrating(iter.next_string()?),
+                    "STABLE" => SetSlot::Stable,
+                    "NODE" => SetSlot::Node(iter.next_string()?),
+                    _ => return Err("ERR Invalid CLUSTER SETSLOT action or number of arguments. Try CLUSTER HELP".into()),
+                };
+                ClusterCommand::SetSlot(slot, action)
+            }
+            "MEET" => {
+                let ip = iter.next_string()?;
+                let port = iter.next_string()?.parse::<u16>()
+                    .map_err(|_| FrameError::from("ERR Invalid base port specified"))?;
+                ClusterCommand::Meet(ip, port)
+            }
+            "FORGET" => ClusterCommand::Forget(iter.next_string()?),
+            other => return Err(format!("ERR unknown subcommand '{}'. Try CLUSTER HELP.", other).into()),
+        };
+
+        Ok(command)
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        // KEYSLOT works without cluster mode
+        if let ClusterCommand::KeySlot(key) = &self {
+            return Ok(Frame::Integer(key_hash_slot(key) as i64));
+        }
+        let keys = match &self {
+            ClusterCommand::CountKeysInSlot(slot) | ClusterCommand::GetKeysInSlot(slot, _) |
+            ClusterCommand::SetSlot(slot, SetSlot::Node(_)) => store.keys_in_slot(*slot).count(),
+            _ => 0,
+        };
+        let keys_in_slot = match &self {
+            ClusterCommand::GetKeysInSlot(slot, count) => store.keys_in_slot(*slot)
+                .take(*count)
+                .map(|key| Frame::Bulk(Bytes::from(key.clone())))
+                .collect(),
+            _ => vec![],
+        };
+
+        let Some(cluster) = store.cluster.as_mut() else {
+            return Ok(Frame::Error("ERR This instance has cluster support disabled".to_string()));
+        };
+        let result = match self {
+            ClusterCommand::Info => Ok(Frame::Bulk(Bytes::from(cluster.info()))),
+            ClusterCommand::MyId => Ok(Frame::Bulk(Bytes::from(cluster.myself().to_string()))),
+            ClusterCommand::Nodes => Ok(Frame::Bulk(Bytes::from(cluster.nodes_text()))),
+            ClusterCommand::Slots => Ok(cluster.slots_reply()),
+            ClusterCommand::Shards => Ok(cluster.shards_reply()),
+            ClusterCommand::KeySlot(_) => unreachable!(),
+            ClusterCommand::CountKeysInSlot(_) => Ok(Frame::Integer(keys as i64)),
+            ClusterCommand::GetKeysInSlot(..) => Ok(Frame::Array(keys_in_slot)),
+            ClusterCommand::AddSlots(slots) => cluster.add_slots(&slots).map(|_| Frame::ok()),
+            ClusterCommand::DelSlots(slots) => cluster.del_slots(&slots).map(|_| Frame::ok()),
+            ClusterCommand::SetSlot(slot, action) => cluster.set_slot(slot, action, keys).map(|_| Frame::ok()),
+            ClusterCommand::Meet(ip, port) => {
+                cluster.meet(ip, port);
+                Ok(Frame::ok())
+            }
+            ClusterCommand::Forget(id) => cluster.forget(&id).map(|_| Frame::ok()),
+        };
+        Ok(result.unwrap_or_else(Frame::Error))
+    }
+}
+
+fn next_slot(iter: &mut FrameIter) -> Result<u16, FrameError> {
+    iter.next_int().ok()
+        .filter(|slot| (0..SLOT_COUNT as i64).contains(slot))
+        .map(|slot| slot as u16)
+        .ok_or_else(|| "ERR Invalid or out of range slot".into())
+}
+
+fn remaining_slots(iter: &mut FrameIter) -> Result<Vec<u16>, FrameError> {
+    let mut slots = vec![next_slot(iter)?];
+    while iter.has_remaining() {
+        slots.push(next_slot(iter)?);
+    }
+    Ok(slots)
+}
+
+/// `start end` pairs, expanded into the slots they cover
+fn remaining_ranges(iter: &mut FrameIter) -> Result<Vec<u16>, FrameError> {
+    let mut slots = vec![];
+    loop {
+        let start = next_slot(iter)?;
+        let end = next_slot(iter)?;
+        if start > end {
+            return Err(format!("ERR start slot number {} is greater than end slot number {}", start, end).into());
+        }
+        slots.extend(start..=end);
+        if !iter.has_remaining() {
+            return Ok(slots);
+        }
+    }
+}
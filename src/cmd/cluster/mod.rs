@@ -0,0 +1,3 @@
+mod cluster_command;
+
+pub(crate) use cluster_command::ClusterCommand;
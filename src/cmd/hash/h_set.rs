@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use crate::config::KeyspaceEvents;
+use crate::db::{RedisDataType, Store};
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/hset/
+/// Syntax: HSET key field value [field value ...]
+///
+/// Set the fields of the hash stored at key, creating it if needed.
+/// Replies with the number of fields that were added, not counting the updated ones
+#[derive(Debug)]
+pub(crate) struct HSet {
+    key: String,
+    fields: Vec<(String, Bytes)>,
+}
+
+impl HSet {
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let key = iter.next_string()?;
+        let mut fields = vec![];
+        while iter.has_remaining() {
+            let field = iter.next_string()?;
+            let value = iter.next_bytes().map_err(|_| FrameError::from("ERR wrong number of arguments for 'hset' command"))?;
+            fields.push((field, value));
+        }
+        if fields.is_empty() {
+            return Err("ERR wrong number of arguments for 'hset' command".into());
+        }
+
+        Ok(Self { key, fields })
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let added = match store.get_data(&self.key) {
+            Some(RedisDataType::HASH(_)) => store.update_data(&self.key, |data, resize| match data {
+                RedisDataType::HASH(hash) => self.fields.iter()
+                    .filter(|(field, value)| {
+                        resize.add(value.len());
+                        match hash.insert(field.clone(), value.clone()) {
+                            Some(old) => {
+                                resize.remove(old.len());
+                                false
+                            }
+                            None => {
+                                resize.add(field.len());
+                                true
+                            }
+                        }
+                    })
+                    .count(),
+                _ => 0,
+            }).unwrap_or(0),
+            Some(_) => return Ok(Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())),
+            None => {
+                let hash: HashMap<String, Bytes> = self.fields.into_iter().collect();
+                let added = hash.len();
+                store.set_data(&self.key, RedisDataType::HASH(hash), None);
+                added
+            }
+        };
+        store.notify(KeyspaceEvents::HASH, "hset", &self.key);
+
+        Ok(Frame::Integer(added as i64))
+    }
+}
@@ -13,4 +13,7 @@ mod h_scan;
 mod h_set;
 mod h_set_nx;
 mod h_strlen;
-mod h_vals;
\ No newline at end of file
+mod h_vals;
+
+pub(crate) use h_len::HLen;
+pub(crate) use h_set::HSet;
@@ -0,0 +1,33 @@
+use bytes::Bytes;
+
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::rdb;
+use crate::RedisResult;
+
+/// https://redis.io/commands/dump/
+/// Syntax: DUMP key
+///
+/// The value at key serialized the way an RDB file stores it, followed by the RDB version
+/// and a CRC64 checksum. `RESTORE` turns it back into a key, here or on another instance
+#[derive(Debug)]
+pub(crate) struct Dump {
+    key: String,
+}
+
+impl Dump {
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        Ok(Self { key: iter.next_string()? })
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        match store.get_data(&self.key) {
+            Some(data) => Ok(Frame::Bulk(Bytes::from(rdb::dump(data)))),
+            None => Ok(Frame::Null),
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/type/
+/// Syntax: TYPE key
+///
+/// `string`, `list`, `set`, `zset` or `hash`, `none` if the key does not exist
+#[derive(Debug)]
+pub(crate) struct Type(String);
+
+impl Type {
+    pub(crate) fn key(&self) -> &str {
+        &self.0
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let key = iter.next_string()?;
+        Ok(Self(key))
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let name = store.peek_data(&self.0).map_or("none", |data| data.type_name());
+        Ok(Frame::Simple(name.to_string()))
+    }
+}
@@ -0,0 +1,339 @@
+use std::io::{self, Cursor, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use bytes::{Buf, Bytes, BytesMut};
+use tokio::time::Instant;
+
+use crate::cluster;
+use crate::db::{SharedDb, Store};
+use crate::frame::{self, encode_command, Frame, FrameError, FrameIter};
+use crate::rdb;
+use crate::slowlog::ClientInfo;
+
+/// https://redis.io/commands/migrate/
+/// Syntax: MIGRATE host port key|"" destination-db timeout [COPY] [REPLACE] [AUTH password] [AUTH2 username password] [KEYS key [key ...]]
+/// - timeout: milliseconds any single step of the transfer may take
+/// - COPY: do not remove the keys from this instance
+/// - REPLACE: replace existing keys on the target instance
+/// - AUTH password / AUTH2 username password: authenticate with the target instance first
+/// - KEYS key [key ...]: move several keys at once, the key argument must then be ""
+///
+/// Like Redis the command blocks this instance until the target answered:
+/// the keys are dumped, sent with RESTORE and deleted under the same lock,
+/// so no write can slip in between and get lost
+#[derive(Debug)]
+pub(crate) struct Migrate {
+    host: String,
+    port: u16,
+    db: i64,
+    timeout: Duration,
+    copy: bool,
+    replace: bool,
+    auth: Option<Vec<Bytes>>,
+    keys: Vec<String>,
+}
+
+impl Migrate {
+    pub(crate) fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let host = iter.next_string()?;
+        let port = iter.next_int().map_err(|_| FrameError::from("ERR value is not an integer or out of range"))?;
+        let key = iter.next_string()?;
+        let db = iter.next_int().map_err(|_| FrameError::from("ERR value is not an integer or out of range"))?;
+        let timeout = iter.next_int().map_err(|_| FrameError::from("ERR value is not an integer or out of range"))?;
+
+        let mut copy = false;
+        let mut replace = false;
+        let mut auth = None;
+        let mut keys = vec![];
+        while iter.has_remaining() {
+            match iter.next_string()?.to_uppercase().as_str() {
+                "COPY" => copy = true,
+                "REPLACE" => replace = true,
+                "AUTH" => auth = Some(vec![iter.next_bytes()?]),
+                "AUTH2" => auth = Some(vec![iter.next_bytes()?, iter.next_bytes()?]),
+                "KEYS" => {
+                    if !key.is_empty() {
+                        return Err("ERR When using MIGRATE KEYS option, the key argument must be set to the empty string".into());
+                    }
+                    // 剩下的参数全是 key
+                    while iter.has_remaining() {
+                        keys.push(iter.next_string()?);
+                    }
+                }
+                _ => return Err("ERR syntax error".into()),
+            }
+        }
+        if !key.is_empty() {
+            keys.push(key);
+        }
+        if keys.is_empty() {
+            return Err("ERR syntax error".into());
+        }
+
+        let port = u16::try_from(port).map_err(|_| FrameError::from("ERR Invalid port"))?;
+        // Redis 也是这样处理非正数的超时
+        let timeout = Duration::from_millis(if timeout <= 0 { 1000 } else { timeout as u64 });
+
+        Ok(Self { host, port, db, timeout, copy, replace, auth, keys })
+    }
+
+    /// run the whole transfer on a blocking thread, holding the lock of the keyspace.
+    /// `argv` is the request, for the slow log of `client`
+    pub(crate) async fn execute(self, db: &SharedDb, argv: Vec<Bytes>, asking: bool, client: ClientInfo) -> Frame {
+        let db = db.clone();
+        tokio::task::spawn_blocking(move || self.call(&db, argv, asking, &client))
+            .await
+            .unwrap_or_else(|e| Frame::Error(format!("ERR {}", e)))
+    }
+
+    /// what `Cmd::call_from` does for the commands of the store: a replica refuses it,
+    /// the call is counted for INFO commandstats and logged when slow
+    fn call(self, db: &SharedDb, argv: Vec<Bytes>, asking: bool, client: &ClientInfo) -> Frame {
+        let store: &mut Store = &mut db.lock();
+        let refused = if store.replication.is_read_only() {
+            Some("READONLY You can't write against a read only replica.".to_string())
+        } else {
+            store.aof_write_error().map(|e| format!("MISCONF Errors writing to the AOF file: {}", e))
+        };
+        if let Some(error) = refused {
+            store.stats.rejected("migrate", &error);
+            return Frame::Error(error);
+        }
+
+        let start = Instant::now();
+        let reply = self.transfer(store, asking);
+        let duration = start.elapsed();
+        store.slowlog.record(&store.config, &argv, duration, client);
+        let error = match &reply {
+            Frame::Error(e) => Some(e.as_str()),
+            _ => None,
+        };
+        store.stats.command("migrate", duration, error);
+        reply
+    }
+
+    fn transfer(&self, store: &mut Store, asking: bool) -> Frame {
+        let keys: Vec<Bytes> = self.keys.iter().map(|key| Bytes::from(key.clone())).collect();
+        // an open slot (migrating or importing) always runs MIGRATE here, that is how its keys leave
+        if !cluster::is_slot_open(store, &keys) {
+            if let Some(redirect) = cluster::redirect(store, &keys, asking) {
+                return redirect;
+            }
+        }
+
+        let now = Instant::now();
+        let dumps: Vec<(&String, u64, Bytes)> = self.keys.iter()
+            .filter_map(|key| {
+                let data = store.get_data(key)?;
+                // 0 means no expiry to RESTORE, a key about to expire keeps at least 1ms
+                let ttl = store.expire_at(key)
+                    .map_or(0, |when| (when.saturating_duration_since(now).as_millis() as u64).max(1));
+                Some((key, ttl, Bytes::from(rdb::dump(data))))
+            })
+            .collect();
+        if dumps.is_empty() {
+            return Frame::Simple("NOKEY".to_string());
+        }
+
+        let restore: &'static [u8] = if store.cluster.is_some() { b"RESTORE-ASKING" } else { b"RESTORE" };
+        let mut request = vec![];
+        if let Some(auth) = &self.auth {
+            let mut argv = vec![Bytes::from_static(b"AUTH")];
+            argv.extend(auth.iter().cloned());
+            request.push(encode_command(&argv));
+        }
+        if self.db != 0 {
+            request.push(encode_command(&[Bytes::from_static(b"SELECT"), Bytes::from(self.db.to_string())]));
+        }
+        for (key, ttl, payload) in &dumps {
+            let mut argv = vec![
+                Bytes::from_static(restore),
+                Bytes::from((*key).clone()),
+                Bytes::from(ttl.to_string()),
+                payload.clone(),
+            ];
+            if self.replace {
+                argv.push(Bytes::from_static(b"REPLACE"));
+            }
+            request.push(encode_command(&argv));
+        }
+
+        let replies = match self.send(&request) {
+            Ok(replies) => replies,
+            Err(e) => return Frame::Error(format!("IOERR error or timeout {} target instance", e)),
+        };
+
+        // AUTH and SELECT come first, if one of them failed no key was restored
+        let preamble = request.len() - dumps.len();
+        let mut error = replies[..preamble].iter().find_map(target_error);
+        let mut moved = vec![];
+        if error.is_none() {
+            for ((key, _, _), reply) in dumps.iter().zip(&replies[preamble..]) {
+                match target_error(reply) {
+                    Some(e) => error = error.or(Some(e)),
+                    None => moved.push((*key).clone()),
+                }
+            }
+        }
+
+        if !self.copy && !moved.is_empty() {
+            store.remove_vec(&moved);
+            let mut argv = vec![Bytes::from_static(b"DEL")];
+            argv.extend(moved.into_iter().map(Bytes::from));
+            store.propagate(&argv);
+        }
+
+        match error {
+            Some(e) => Frame::Error(format!("ERR Target instance replied with error: {}", e)),
+            None => Frame::ok(),
+        }
+    }
+
+    /// pipeline the commands to the target and read one reply for each
+    fn send(&self, request: &[Bytes]) -> Result<Vec<Frame>, String> {
+        let addr = (self.host.as_str(), self.port).to_socket_addrs()
+            .map_err(|e| format!("connecting to ({})", e))?
+            .next()
+            .ok_or("connecting to")?;
+        let mut stream = TcpStream::connect_timeout(&addr, self.timeout)
+            .map_err(|e| format!("connecting to ({})", e))?;
+        stream.set_read_timeout(Some(self.timeout)).map_err(|e| format!("connecting to ({})", e))?;
+        stream.set_write_timeout(Some(self.timeout)).map_err(|e| format!("connecting to ({})", e))?;
+
+        stream.write_all(&request.concat()).map_err(|e| format!("writing to ({})", e))?;
+
+        let mut buf = BytesMut::new();
+        let mut replies = Vec::with_capacity(request.len());
+        while replies.len() < request.len() {
+            match read_frame(&mut stream, &mut buf) {
+                Ok(frame) => replies.push(frame),
+                Err(e) => return Err(format!("reading from ({})", e)),
+            }
+        }
+        Ok(replies)
+    }
+}
+
+/// the message of an error reply
+fn target_error(frame: &Frame) -> Option<String> {
+    match frame {
+        Frame::Error(e) => Some(e.clone()),
+        _ => None,
+    }
+}
+
+/// block until a whole frame is buffered and parse it
+fn read_frame(stream: &mut TcpStream, buf: &mut BytesMut) -> io::Result<Frame> {
+    loop {
+        let mut cursor = Cursor::new(&buf[..]);
+        match frame::check(&mut cursor) {
+            Ok(()) => {
+                let len = cursor.position() as usize;
+                let frame = Frame::parse_protocol(&buf[..len])
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+                buf.advance(len);
+                return Ok(frame);
+            }
+            Err(FrameError::Incomplete) => {}
+            Err(e) => return Err(io::Error::new(ErrorKind::InvalidData, e.to_string())),
+        }
+
+        let mut chunk = [0u8; 4096];
+        match stream.read(&mut chunk)? {
+            0 => return Err(ErrorKind::UnexpectedEof.into()),
+            n => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+    use tokio::net::TcpListener;
+    use tokio::sync::broadcast;
+
+    use crate::config::Config;
+    use crate::db::{Db, SharedDb, test_config, test_db};
+    use crate::frame::Frame;
+    use crate::server::process;
+    use crate::session::Session;
+
+    /// a target with a runtime of its own: while MIGRATE holds the lock of the source,
+    /// the tasks of the source block the threads they run on
+    fn spawn_target() -> (SharedDb, u16) {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                let (shutdown, _) = broadcast::channel(1);
+                let db = Db::new(shutdown.subscribe(), test_config());
+                let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                sender.send((db.clone(), listener.local_addr().unwrap().port())).unwrap();
+                while let Ok((socket, _)) = listener.accept().await {
+                    tokio::spawn(process(socket, db.clone(), shutdown.subscribe()));
+                }
+            });
+        });
+        receiver.recv().unwrap()
+    }
+
+    async fn call(db: &SharedDb, args: &str) -> Frame {
+        let argv = args.split(' ')
+            .map(|arg| Frame::Bulk(Bytes::from(arg.replace("\"\"", ""))))
+            .collect();
+        Session::new(db.clone()).handle(Frame::Array(argv)).await.pop().unwrap()
+    }
+
+    #[tokio::test]
+    async fn migrate_test() {
+        let source = test_db(Config { slowlog_log_slower_than: 0, ..test_config() });
+        let (target, port) = spawn_target();
+
+        assert!(matches!(call(&source, &format!("MIGRATE 127.0.0.1 {} a 0 1000", port)).await, Frame::Simple(s) if s == "NOKEY"));
+
+        call(&source, "SET a 1 PX 100000").await;
+        assert!(matches!(call(&source, &format!("MIGRATE 127.0.0.1 {} a 0 1000", port)).await, Frame::Simple(s) if s == "OK"));
+        assert!(source.lock().get_data("a").is_none());
+        assert!(matches!(call(&target, "GET a").await, Frame::Bulk(b) if b == "1"));
+        assert!(target.lock().expire_at("a").is_some());
+
+        // an existing key needs REPLACE, and the failed key stays here
+        call(&source, "SET a 2").await;
+        call(&source, "SET b 3").await;
+        let reply = call(&source, &format!("MIGRATE 127.0.0.1 {} \"\" 0 1000 KEYS a b", port)).await;
+        assert!(matches!(reply, Frame::Error(e) if e.starts_with("ERR Target instance replied with error: BUSYKEY")));
+        assert!(source.lock().get_data("a").is_some());
+        assert!(source.lock().get_data("b").is_none());
+
+        let reply = call(&source, &format!("MIGRATE 127.0.0.1 {} a 0 1000 COPY REPLACE", port)).await;
+        assert!(matches!(reply, Frame::Simple(s) if s == "OK"));
+        assert!(source.lock().get_data("a").is_some());
+        assert!(matches!(call(&target, "GET a").await, Frame::Bulk(b) if b == "2"));
+        assert!(target.lock().expire_at("a").is_none());
+
+        let reply = call(&source, "MIGRATE 127.0.0.1 1 a 0 100").await;
+        assert!(matches!(reply, Frame::Error(e) if e.starts_with("IOERR")));
+        let reply = call(&source, "MIGRATE 127.0.0.1 1 a 0 100 KEYS b").await;
+        assert!(matches!(reply, Frame::Error(e) if e.starts_with("ERR When using MIGRATE KEYS")));
+
+        // counted and logged like the commands of the store
+        {
+            let store = source.lock();
+            let stats = &store.stats.commands["migrate"];
+            assert_eq!((stats.calls, stats.failed_calls), (5, 2));
+            assert!(store.slowlog.entries(10).any(|entry| entry.args[0] == "MIGRATE"));
+        }
+
+        // a replica keeps its keys, they are its master's
+        call(&source, "REPLICAOF 127.0.0.1 1").await;
+        let reply = call(&source, &format!("MIGRATE 127.0.0.1 {} a 0 1000 REPLACE", port)).await;
+        assert!(matches!(reply, Frame::Error(e) if e.starts_with("READONLY")));
+        assert!(source.lock().get_data("a").is_some());
+        assert_eq!(source.lock().stats.commands["migrate"].rejected_calls, 1);
+    }
+}
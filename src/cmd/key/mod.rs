@@ -1 +1,18 @@
-mod expire;
\ No newline at end of file
+mod dump;
+mod expire;
+mod key_type;
+mod migrate;
+mod object;
+mod restore;
+mod scan;
+mod sort;
+mod unlink;
+
+pub(crate) use dump::Dump;
+pub(crate) use key_type::Type;
+pub(crate) use migrate::Migrate;
+pub(crate) use object::Object;
+pub(crate) use restore::Restore;
+pub(crate) use scan::Scan;
+pub(crate) use sort::Sort;
+pub(crate) use unlink::Unlink;
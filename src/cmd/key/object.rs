@@ -0,0 +1,103 @@
+use bytes::Bytes;
+
+use crate::db::{RedisDataType, Store};
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/object/
+/// Syntax:
+/// - OBJECT ENCODING key: how the value is represented
+/// - OBJECT FREQ key: the logarithmic access counter, with an LFU `maxmemory-policy` only
+/// - OBJECT IDLETIME key: seconds since the last access, not with an LFU `maxmemory-policy`
+/// - OBJECT REFCOUNT key: always 1, values are not shared
+///
+/// None of them counts as an access of the key. A missing key replies nil
+#[derive(Debug)]
+pub(crate) enum Object {
+    Encoding(String),
+    Freq(String),
+    IdleTime(String),
+    RefCount(String),
+}
+
+impl Object {
+    pub(crate) fn key(&self) -> &str {
+        match self {
+            Object::Encoding(key) | Object::Freq(key) | Object::IdleTime(key) | Object::RefCount(key) => key,
+        }
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let object = match iter.next_string()?.to_uppercase().as_str() {
+            "ENCODING" => Object::Encoding(iter.next_string()?),
+            "FREQ" => Object::Freq(iter.next_string()?),
+            "IDLETIME" => Object::IdleTime(iter.next_string()?),
+            "REFCOUNT" => Object::RefCount(iter.next_string()?),
+            other => return Err(format!("ERR unknown subcommand '{}'. Try OBJECT HELP.", other).into()),
+        };
+
+        Ok(object)
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let lfu = store.config.maxmemory_policy.is_lfu();
+        let frame = match self {
+            Object::Encoding(key) => store.peek_data(&key)
+                .map(|data| Frame::Bulk(Bytes::from_static(encoding(data).as_bytes()))),
+            Object::Freq(_) if !lfu => Some(Frame::Error("ERR An LFU maxmemory policy is not selected, access frequency \
+                not tracked. Please note that when switching between policies at runtime LRU and LFU data will take \
+                some time to adjust.".to_string())),
+            Object::Freq(key) => store.object_freq(&key).map(|freq| Frame::Integer(freq as i64)),
+            Object::IdleTime(_) if lfu => Some(Frame::Error("ERR An LFU maxmemory policy is selected, idle time not \
+                tracked. Please note that when switching between policies at runtime LRU and LFU data will take \
+                some time to adjust.".to_string())),
+            Object::IdleTime(key) => store.object_idletime(&key).map(|idle| Frame::Integer(idle as i64)),
+            Object::RefCount(key) => store.peek_data(&key).map(|_| Frame::Integer(1)),
+        };
+        Ok(frame.unwrap_or_else(Frame::nil))
+    }
+}
+
+/// the encoding Redis would pick for a value of this size
+fn encoding(data: &RedisDataType) -> &'static str {
+    match data {
+        RedisDataType::Bytes(bytes) if std::str::from_utf8(bytes).is_ok_and(|s| s.parse::<i64>().is_ok()) => "int",
+        RedisDataType::Bytes(bytes) if bytes.len() <= 44 => "embstr",
+        RedisDataType::Bytes(_) | RedisDataType::BITMAP => "raw",
+        RedisDataType::List(_) => "quicklist",
+        RedisDataType::Set(_) | RedisDataType::HASH(_) => "hashtable",
+        RedisDataType::SortedSet(_) => "skiplist",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::MaxmemoryPolicy;
+    use crate::db::{run, test_config, test_db};
+    use crate::frame::Frame;
+
+    #[tokio::test]
+    async fn object_test() {
+        let db = test_db(test_config());
+        let mut store = db.lock();
+        run(&mut store, "SET n 12");
+        run(&mut store, "SET s hello");
+
+        assert!(matches!(run(&mut store, "OBJECT ENCODING n"), Frame::Bulk(b) if b == "int"));
+        assert!(matches!(run(&mut store, "OBJECT ENCODING s"), Frame::Bulk(b) if b == "embstr"));
+        assert!(matches!(run(&mut store, "OBJECT ENCODING missing"), Frame::Null));
+        assert!(matches!(run(&mut store, "OBJECT IDLETIME s"), Frame::Integer(0)));
+        assert!(matches!(run(&mut store, "OBJECT FREQ s"), Frame::Error(e) if e.contains("LFU maxmemory policy is not selected")));
+        assert!(matches!(run(&mut store, "TYPE s"), Frame::Simple(s) if s == "string"));
+        assert!(matches!(run(&mut store, "TYPE missing"), Frame::Simple(s) if s == "none"));
+
+        store.config.maxmemory_policy = MaxmemoryPolicy::AllKeysLfu;
+        store.set_access("s", None, Some(100));
+        assert!(matches!(run(&mut store, "OBJECT FREQ s"), Frame::Integer(100)));
+        // hits right after the key was created always count
+        let before = match run(&mut store, "OBJECT FREQ n") { Frame::Integer(n) => n, other => panic!("{:?}", other) };
+        run(&mut store, "GET n");
+        assert!(matches!(run(&mut store, "OBJECT FREQ n"), Frame::Integer(n) if n == before + 1));
+        assert!(matches!(run(&mut store, "OBJECT IDLETIME s"), Frame::Error(_)));
+    }
+}
@@ -0,0 +1,174 @@
+use std::io::ErrorKind;
+
+use bytes::Bytes;
+
+use crate::clock::{unix_ms, unix_ms_to_instant};
+use crate::config::KeyspaceEvents;
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::rdb;
+use crate::RedisResult;
+
+/// https://redis.io/commands/restore/
+/// Syntax: RESTORE key ttl serialized-value [REPLACE] [ABSTTL] [IDLETIME seconds] [FREQ frequency]
+/// - ttl: milliseconds the key lives, 0 for no expiry
+/// - REPLACE: overwrite the key if it exists, instead of failing with BUSYKEY
+/// - ABSTTL: ttl is a unix time in milliseconds
+/// - IDLETIME seconds: the idle time of the key, for the LRU eviction policies
+/// - FREQ frequency: the access frequency of the key, for the LFU eviction policies
+///
+/// The ttl is made absolute when parsed, so the command is written to the AOF with ABSTTL
+#[derive(Debug)]
+pub(crate) struct Restore {
+    key: String,
+    /// unix time in milliseconds, `None` without expiry
+    expire_at: Option<u64>,
+    payload: Bytes,
+    replace: bool,
+    /// seconds since the last access
+    idle_time: Option<u64>,
+    /// the LFU counter
+    freq: Option<u32>,
+}
+
+impl Restore {
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let key = iter.next_string()?;
+        let ttl = iter.next_int().map_err(|_| FrameError::from("ERR value is not an integer or out of range"))?;
+        let payload = iter.next_bytes()?;
+
+        let mut replace = false;
+        let mut absolute = false;
+        let mut idle_time = None;
+        let mut freq = None;
+        while iter.has_remaining() {
+            match iter.next_string()?.to_uppercase().as_str() {
+                "REPLACE" => replace = true,
+                "ABSTTL" => absolute = true,
+                "IDLETIME" if freq.is_none() => {
+                    let seconds = iter.next_int().map_err(|_| FrameError::from("ERR value is not an integer or out of range"))?;
+                    if seconds < 0 {
+                        return Err("ERR Invalid IDLETIME value, must be >= 0".into());
+                    }
+                    idle_time = Some(seconds as u64);
+                }
+                "FREQ" if idle_time.is_none() => {
+                    let frequency = iter.next_int().map_err(|_| FrameError::from("ERR value is not an integer or out of range"))?;
+                    if !(0..=255).contains(&frequency) {
+                        return Err("ERR Invalid FREQ value, must be >= 0 and <= 255".into());
+                    }
+                    freq = Some(frequency as u32);
+                }
+                _ => return Err("ERR syntax error".into()),
+            }
+        }
+
+        if ttl < 0 {
+            return Err("ERR Invalid TTL value, must be >= 0".into());
+        }
+        let expire_at = match (ttl, absolute) {
+            (0, _) => None,
+            (ttl, true) => Some(ttl as u64),
+            (ttl, false) => Some(unix_ms().checked_add(ttl as u64).filter(|&at| at <= i64::MAX as u64)
+                .ok_or_else(|| FrameError::from("ERR invalid expire time in 'restore' command"))?),
+        };
+
+        Ok(Self { key, expire_at, payload, replace, idle_time, freq })
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        if !self.replace && store.get_data(&self.key).is_some() {
+            return Ok(Frame::Error("BUSYKEY Target key name already exists.".to_string()));
+        }
+
+        let data = match rdb::restore(&self.payload) {
+            Ok(data) => data,
+            Err(e) if e.kind() == ErrorKind::InvalidInput => return Ok(Frame::Error(format!("ERR {}", e))),
+            Err(_) => return Ok(Frame::Error("ERR Bad data format".to_string())),
+        };
+
+        // an expiry in the past leaves nothing behind, like Redis does
+        if self.expire_at.is_some_and(|at| at <= unix_ms()) {
+            if self.replace {
+                store.remove_vec(std::slice::from_ref(&self.key));
+            }
+            return Ok(Frame::ok());
+        }
+
+        store.set_data(&self.key, data, self.expire_at.map(unix_ms_to_instant));
+        store.set_access(&self.key, self.idle_time, self.freq);
+        store.notify(KeyspaceEvents::GENERIC, "restore", &self.key);
+        Ok(Frame::ok())
+    }
+
+    /// the command as it is written to the AOF, with an absolute expiry
+    pub(crate) fn propagate(&self) -> Vec<Bytes> {
+        let mut argv = vec![
+            Bytes::from_static(b"RESTORE"),
+            Bytes::from(self.key.clone()),
+            Bytes::from(self.expire_at.unwrap_or(0).to_string()),
+            self.payload.clone(),
+        ];
+        if self.replace {
+            argv.push(Bytes::from_static(b"REPLACE"));
+        }
+        argv.push(Bytes::from_static(b"ABSTTL"));
+        if let Some(seconds) = self.idle_time {
+            argv.extend([Bytes::from_static(b"IDLETIME"), Bytes::from(seconds.to_string())]);
+        }
+        if let Some(freq) = self.freq {
+            argv.extend([Bytes::from_static(b"FREQ"), Bytes::from(freq.to_string())]);
+        }
+        argv
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use crate::cmd::key::{Dump, Restore};
+    use crate::db::{RedisDataType, test_config, test_db};
+    use crate::frame::{Frame, FrameIter};
+
+    fn frames(args: Vec<Bytes>) -> FrameIter {
+        FrameIter::new(args.into_iter().map(Frame::Bulk).collect())
+    }
+
+    #[tokio::test]
+    async fn dump_restore_test() {
+        let db = test_db(test_config());
+        let mut store = db.lock();
+
+        let set = ["a", "b", "c"].into_iter().map(Bytes::from).collect();
+        store.set_data("set", RedisDataType::Set(set), None);
+
+        let payload = match Dump::parse_frames(&mut frames(vec![Bytes::from("set")])).unwrap().execute(&mut store).unwrap() {
+            Frame::Bulk(payload) => payload,
+            other => panic!("unexpected reply {:?}", other),
+        };
+
+        let restore = |args: &str, payload: &Bytes| {
+            let mut args: Vec<Bytes> = args.split(' ').map(|arg| Bytes::from(arg.to_string())).collect();
+            args.insert(2, payload.clone());
+            Restore::parse_frames(&mut frames(args)).unwrap()
+        };
+
+        assert!(matches!(restore("set 0", &payload).execute(&mut store).unwrap(), Frame::Error(e) if e.starts_with("BUSYKEY")));
+        assert!(matches!(restore("copy 5000", &payload).execute(&mut store).unwrap(), Frame::Simple(_)));
+        assert!(matches!(store.get_data("copy"), Some(RedisDataType::Set(set)) if set.len() == 3));
+        assert!(store.expire_at("copy").is_some());
+
+        let propagated = restore("copy 5000 REPLACE", &payload).propagate();
+        assert_eq!(propagated.last(), Some(&Bytes::from("ABSTTL")));
+
+        let mut corrupted = payload.to_vec();
+        corrupted[0] = 99;
+        let reply = restore("other 0", &Bytes::from(corrupted)).execute(&mut store).unwrap();
+        assert!(matches!(reply, Frame::Error(e) if e.contains("checksum")));
+    }
+}
@@ -0,0 +1,117 @@
+use bytes::Bytes;
+
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::pattern::glob_match;
+use crate::RedisResult;
+
+/// https://redis.io/commands/scan/
+/// Syntax: SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+/// - cursor: 0 to start, then the cursor of the previous reply until it is 0 again
+/// - MATCH pattern: only the keys matching the glob-style pattern
+/// - COUNT count: how many keys to look at, 10 by default. The filters apply afterwards,
+///   a page may come back empty while the iteration is not over
+/// - TYPE type: only the keys holding a value of this type
+///
+/// Every key that exists during the whole iteration is returned at least once
+#[derive(Debug)]
+pub(crate) struct Scan {
+    cursor: usize,
+    pattern: Option<Bytes>,
+    count: usize,
+    key_type: Option<String>,
+}
+
+impl Scan {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let cursor = iter.next_string()?.parse().map_err(|_| FrameError::from("ERR invalid cursor"))?;
+
+        let mut pattern = None;
+        let mut count = 10;
+        let mut key_type = None;
+        while iter.has_remaining() {
+            match iter.next_string()?.to_uppercase().as_str() {
+                "MATCH" => pattern = Some(iter.next_bytes()?),
+                "COUNT" => {
+                    let n = iter.next_int().map_err(|_| FrameError::from("ERR value is not an integer or out of range"))?;
+                    if n < 1 {
+                        return Err("ERR syntax error".into());
+                    }
+                    count = n as usize;
+                }
+                "TYPE" => key_type = Some(iter.next_string()?.to_lowercase()),
+                _ => return Err("ERR syntax error".into()),
+            }
+        }
+
+        Ok(Self { cursor, pattern, count, key_type })
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let (cursor, found) = store.scan(self.cursor, self.count);
+        let keys = found.into_iter()
+            .filter(|(key, _)| self.pattern.as_ref().is_none_or(|pattern| glob_match(pattern, key.as_bytes())))
+            .filter(|(_, data)| self.key_type.as_ref().is_none_or(|key_type| data.type_name() == key_type))
+            .map(|(key, _)| Frame::Bulk(Bytes::from(key.clone())))
+            .collect();
+        Ok(Frame::Array(vec![Frame::Bulk(Bytes::from(cursor.to_string())), Frame::Array(keys)]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use bytes::Bytes;
+
+    use crate::db::{Store, run, test_config, test_db};
+    use crate::frame::Frame;
+
+    /// one SCAN call: the next cursor and the keys of the page
+    fn scan(store: &mut Store, cursor: &str, options: &str) -> (String, Vec<String>) {
+        let Frame::Array(mut reply) = run(store, &format!("SCAN {}{}", cursor, options)) else { panic!("SCAN replies with an array") };
+        let Some(Frame::Array(keys)) = reply.pop() else { panic!("no keys") };
+        let Some(Frame::Bulk(cursor)) = reply.pop() else { panic!("no cursor") };
+        let keys = keys.into_iter()
+            .map(|key| match key {
+                Frame::Bulk(key) => String::from_utf8(key.to_vec()).unwrap(),
+                other => panic!("unexpected key {:?}", other),
+            })
+            .collect();
+        (String::from_utf8(cursor.to_vec()).unwrap(), keys)
+    }
+
+    #[tokio::test]
+    async fn scan_test() {
+        let db = test_db(test_config());
+        let mut store = db.lock();
+        for i in 0..50 {
+            run(&mut store, &format!("SET key:{} {}", i, i));
+        }
+        store.set_list("list", vec![Bytes::from("a")]);
+
+        // keys deleted along the way must not hide the ones that stay
+        let mut seen = HashSet::new();
+        let mut cursor = "0".to_string();
+        let mut deleted = 0;
+        loop {
+            let (next, keys) = scan(&mut store, &cursor, " COUNT 7");
+            seen.extend(keys);
+            run(&mut store, &format!("DEL key:{}", deleted));
+            deleted += 1;
+            if next == "0" {
+                break;
+            }
+            cursor = next;
+        }
+        for i in deleted..50 {
+            assert!(seen.contains(&format!("key:{}", i)));
+        }
+        assert!(seen.contains("list"));
+
+        let (cursor, keys) = scan(&mut store, "0", " COUNT 100 TYPE list");
+        assert_eq!((cursor.as_str(), keys), ("0", vec!["list".to_string()]));
+        let (_, keys) = scan(&mut store, "0", " MATCH key:4? COUNT 100");
+        assert_eq!(keys.len(), 10);
+    }
+}
@@ -0,0 +1,266 @@
+use std::cmp::Ordering;
+
+use bytes::Bytes;
+
+use crate::config::KeyspaceEvents;
+use crate::db::{RedisDataType, Store};
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/sort/
+/// Syntax: SORT key [BY pattern] [LIMIT offset count] [GET pattern [GET pattern ...]] [ASC | DESC] [ALPHA] [STORE destination]
+/// - BY pattern: sort by the value of external keys, `*` is replaced by the element,
+///   `key->field` reads a hash field. A pattern without `*` skips sorting
+/// - LIMIT offset count: only return a window of the sorted elements
+/// - GET pattern: return the value of external keys instead of the element, `#` is the element itself
+/// - ALPHA: compare lexicographically instead of as double precision numbers
+/// - STORE destination: save the result as a list and return its length
+///
+/// `SORT_RO` is the same command without `STORE`
+#[derive(Debug)]
+pub(crate) struct Sort {
+    key: String,
+    by: Option<String>,
+    limit: Option<(i64, i64)>,
+    get: Vec<String>,
+    desc: bool,
+    alpha: bool,
+    store: Option<String>,
+}
+
+/// one element with the value it is compared by
+struct SortItem {
+    element: Bytes,
+    weight: Weight,
+}
+
+enum Weight {
+    Score(f64),
+    Alpha(Option<Bytes>),
+}
+
+impl Sort {
+    pub(crate) fn parse_frames(iter: &mut FrameIter, read_only: bool) -> Result<Self, FrameError> {
+        let mut sort = Self {
+            key: iter.next_string()?,
+            by: None,
+            limit: None,
+            get: vec![],
+            desc: false,
+            alpha: false,
+            store: None,
+        };
+
+        while iter.has_remaining() {
+            match iter.next_string()?.to_uppercase().as_str() {
+                "ASC" => sort.desc = false,
+                "DESC" => sort.desc = true,
+                "ALPHA" => sort.alpha = true,
+                "BY" => sort.by = Some(iter.next_string()?),
+                "GET" => sort.get.push(iter.next_string()?),
+                "LIMIT" => sort.limit = Some((iter.next_int()?, iter.next_int()?)),
+                "STORE" if !read_only => sort.store = Some(iter.next_string()?),
+                _ => return Err("ERR syntax error".into()),
+            }
+        }
+
+        Ok(sort)
+    }
+
+    /// only SORT ... STORE writes
+    pub(crate) fn is_store(&self) -> bool {
+        self.store.is_some()
+    }
+
+    /// the sorted key, then the STORE destination
+    pub(crate) fn keys(&self) -> Vec<&str> {
+        std::iter::once(self.key.as_str()).chain(self.store.as_deref()).collect()
+    }
+
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let elements = match store.get_data(&self.key) {
+            None => vec![],
+            Some(RedisDataType::List(list)) => list.clone(),
+            Some(RedisDataType::Set(set)) => set.iter().cloned().collect(),
+            // in score order, which is what a sorted set looks like when BY skips the sort
+            Some(RedisDataType::SortedSet(zset)) => {
+                let mut members: Vec<_> = zset.iter().collect();
+                members.sort_by(|a, b| a.1.total_cmp(b.1).then_with(|| a.0.cmp(b.0)));
+                members.into_iter().map(|(member, _)| member.clone()).collect()
+            }
+            Some(_) => return Ok(Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())),
+        };
+
+        // a BY pattern without `*` means "don't sort", handy to only GET external keys
+        let dont_sort = matches!(&self.by, Some(pattern) if !pattern.contains('*'));
+
+        let mut items = Vec::with_capacity(elements.len());
+        for element in elements {
+            let weight = if dont_sort {
+                Weight::Score(0.0)
+            } else {
+                let value = match &self.by {
+                    Some(pattern) => lookup_by_pattern(store, pattern, &element),
+                    None => Some(element.clone()),
+                };
+                if self.alpha {
+                    Weight::Alpha(value)
+                } else {
+                    match value {
+                        Some(value) => match parse_score(&value) {
+                            Some(score) => Weight::Score(score),
+                            None => return Ok(Frame::Error("ERR One or more scores can't be converted into double".to_string())),
+                        },
+                        None => Weight::Score(0.0),
+                    }
+                }
+            };
+            items.push(SortItem { element, weight });
+        }
+
+        if !dont_sort {
+            items.sort_by(|a, b| {
+                let ordering = compare(a, b);
+                if self.desc { ordering.reverse() } else { ordering }
+            });
+        }
+
+        let (start, end) = self.window(items.len());
+
+        let mut result = vec![];
+        for item in &items[start..end] {
+            if self.get.is_empty() {
+                result.push(Some(item.element.clone()));
+            }
+            for pattern in &self.get {
+                result.push(lookup_by_pattern(store, pattern, &item.element));
+            }
+        }
+
+        if let Some(destination) = self.store {
+            // missing GET values are stored as empty strings
+            let list: Vec<Bytes> = result.into_iter().map(Option::unwrap_or_default).collect();
+            let len = list.len();
+            let event = if list.is_empty() { "del" } else { "sortstore" };
+            store.set_list(&destination, list);
+            store.notify(KeyspaceEvents::GENERIC, event, &destination);
+            return Ok(Frame::Integer(len as i64));
+        }
+
+        Ok(Frame::Array(result.into_iter()
+            .map(|value| value.map(Frame::Bulk).unwrap_or_else(Frame::nil))
+            .collect()))
+    }
+
+    /// turn LIMIT offset count into a `start..end` range over `len` elements
+    fn window(&self, len: usize) -> (usize, usize) {
+        match self.limit {
+            None => (0, len),
+            Some((offset, count)) => {
+                let start = (offset.max(0) as usize).min(len);
+                let end = if count < 0 { len } else { start.saturating_add(count as usize).min(len) };
+                (start, end)
+            }
+        }
+    }
+}
+
+fn compare(a: &SortItem, b: &SortItem) -> Ordering {
+    let ordering = match (&a.weight, &b.weight) {
+        (Weight::Score(x), Weight::Score(y)) => x.partial_cmp(y).unwrap_or(Ordering::Equal),
+        (Weight::Alpha(x), Weight::Alpha(y)) => x.cmp(y),
+        _ => Ordering::Equal,
+    };
+    // equal weights fall back to the element itself so the result is deterministic
+    ordering.then_with(|| a.element.cmp(&b.element))
+}
+
+fn parse_score(value: &Bytes) -> Option<f64> {
+    std::str::from_utf8(value).ok()?.trim().parse::<f64>().ok().filter(|score| !score.is_nan())
+}
+
+/// resolve a BY/GET pattern for one element:
+/// `#` is the element, `weight_*` reads the string key, `object_*->name` reads a hash field
+fn lookup_by_pattern(store: &Store, pattern: &str, element: &Bytes) -> Option<Bytes> {
+    if pattern == "#" {
+        return Some(element.clone());
+    }
+
+    let star = pattern.find('*')?;
+    let (key_pattern, field) = match pattern[star..].find("->") {
+        Some(arrow) if star + arrow + 2 < pattern.len() => (&pattern[..star + arrow], Some(&pattern[star + arrow + 2..])),
+        _ => (pattern, None),
+    };
+
+    let key = key_pattern.replacen('*', &String::from_utf8_lossy(element), 1);
+
+    match (store.get_data(&key)?, field) {
+        (RedisDataType::Bytes(data), None) => Some(data.clone()),
+        (RedisDataType::HASH(hash), Some(field)) => hash.get(field).cloned(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use crate::cmd::key::Sort;
+    use crate::db::{SharedDb, test_config, test_db};
+    use crate::frame::{Frame, FrameIter};
+
+    fn init_db() -> SharedDb {
+        test_db(test_config())
+    }
+
+    fn parse(args: &str) -> Sort {
+        let frames = args.split(' ').map(|arg| Frame::Bulk(Bytes::from(arg.to_string()))).collect();
+        Sort::parse_frames(&mut FrameIter::new(frames), false).unwrap()
+    }
+
+    fn bulks(frame: Frame) -> Vec<String> {
+        match frame {
+            Frame::Array(array) => array.into_iter().map(|frame| match frame {
+                Frame::Bulk(data) => String::from_utf8(data.to_vec()).unwrap(),
+                other => format!("{:?}", other),
+            }).collect(),
+            other => panic!("expected array, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn sort_test() {
+        let db = init_db();
+        {
+            let mut store = db.lock();
+            let list = ["3", "10", "1", "2"].iter().map(|s| Bytes::from(s.to_string())).collect();
+            store.set_list("list", list);
+        }
+
+        assert_eq!(bulks(parse("list").execute(&mut db.lock()).unwrap()), vec!["1", "2", "3", "10"]);
+        assert_eq!(bulks(parse("list DESC LIMIT 1 2").execute(&mut db.lock()).unwrap()), vec!["3", "2"]);
+        assert_eq!(bulks(parse("list ALPHA").execute(&mut db.lock()).unwrap()), vec!["1", "10", "2", "3"]);
+    }
+
+    #[tokio::test]
+    async fn sort_by_get_test() {
+        let db = init_db();
+        {
+            let mut store = db.lock();
+            let list = ["a", "b", "c"].iter().map(|s| Bytes::from(s.to_string())).collect();
+            store.set_list("list", list);
+            store.set_bytes("weight_a", Bytes::from("3"), None);
+            store.set_bytes("weight_b", Bytes::from("1"), None);
+            store.set_bytes("weight_c", Bytes::from("2"), None);
+            store.set_bytes("object_b", Bytes::from("bee"), None);
+        }
+
+        let sorted = bulks(parse("list BY weight_* GET # GET object_*").execute(&mut db.lock()).unwrap());
+        assert_eq!(sorted, vec!["b", "bee", "c", "Null", "a", "Null"]);
+
+        let stored = parse("list BY weight_* STORE dest").execute(&mut db.lock()).unwrap();
+        assert!(matches!(stored, Frame::Integer(3)));
+        assert_eq!(bulks(parse("dest BY nosort").execute(&mut db.lock()).unwrap()), vec!["b", "c", "a"]);
+    }
+}
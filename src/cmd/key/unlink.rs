@@ -0,0 +1,30 @@
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/unlink/
+/// Syntax: UNLINK key [key ...]
+/// Like `DEL`, but the values are detached from the keyspace right away and big ones are freed
+/// on a background thread, so deleting a huge collection does not block other clients
+#[derive(Debug)]
+pub(crate) struct Unlink(Vec<String>);
+
+impl Unlink {
+    pub(crate) fn keys(&self) -> &[String] {
+        &self.0
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let mut keys = vec![iter.next_string()?];
+        while iter.has_remaining() {
+            keys.push(iter.next_string()?);
+        }
+
+        Ok(Self(keys))
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let count = store.unlink_vec(&self.0);
+        Ok(Frame::Integer(count as i64))
+    }
+}
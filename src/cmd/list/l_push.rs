@@ -0,0 +1,58 @@
+use bytes::Bytes;
+
+use crate::config::KeyspaceEvents;
+use crate::db::{RedisDataType, Store};
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/lpush/
+/// Syntax: LPUSH key element [element ...]
+///
+/// Insert the elements at the head of the list stored at key, one after the other, so the
+/// last one ends up first. Replies with the length of the list
+#[derive(Debug)]
+pub(crate) struct LPush {
+    key: String,
+    elements: Vec<Bytes>,
+}
+
+impl LPush {
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let key = iter.next_string()?;
+        let mut elements = vec![];
+        while iter.has_remaining() {
+            elements.push(iter.next_bytes()?);
+        }
+        if elements.is_empty() {
+            return Err("ERR wrong number of arguments for 'lpush' command".into());
+        }
+
+        Ok(Self { key, elements })
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let len = match store.get_data(&self.key) {
+            Some(RedisDataType::List(_)) => store.update_data(&self.key, |data, resize| match data {
+                RedisDataType::List(list) => {
+                    resize.add(self.elements.iter().map(Bytes::len).sum());
+                    list.splice(0..0, self.elements.into_iter().rev());
+                    list.len()
+                }
+                _ => 0,
+            }).unwrap_or(0),
+            Some(_) => return Ok(Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())),
+            None => {
+                let len = self.elements.len();
+                store.set_list(&self.key, self.elements.into_iter().rev().collect());
+                len
+            }
+        };
+        store.notify(KeyspaceEvents::LIST, "lpush", &self.key);
+
+        Ok(Frame::Integer(len as i64))
+    }
+}
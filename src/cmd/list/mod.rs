@@ -17,4 +17,7 @@ mod l_trim;
 mod r_pop;
 mod r_pop_lpush;
 mod r_pushx;
-mod r_push;
\ No newline at end of file
+mod r_push;
+
+pub(crate) use l_len::LLen;
+pub(crate) use l_push::LPush;
@@ -1,7 +1,10 @@
-use bytes::Buf;
+use bytes::Bytes;
+use tokio::time::Instant;
 
-use crate::db::SharedDb;
+use crate::cluster::redirect;
+use crate::db::{SharedDb, Store};
 use crate::frame::{Frame, FrameError, FrameIter};
+use crate::slowlog::ClientInfo;
 use crate::RedisResult;
 
 mod string;
@@ -14,12 +17,27 @@ mod list;
 mod set;
 mod sorted_set;
 mod pub_sub;
+mod cluster;
 mod command;
 mod key;
+mod replication;
+mod server;
+mod transaction;
 mod unknown;
 
 use ping::Ping;
-use crate::cmd::string::{DecrBy, MultiGet};
+use crate::cmd::cluster::ClusterCommand;
+use crate::cmd::hash::{HLen, HSet};
+use crate::cmd::key::{Dump, Migrate, Object, Restore, Scan, Sort, Type, Unlink};
+use crate::cmd::list::{LLen, LPush};
+use crate::cmd::pub_sub::{PubSubInfo, Publish, Subscribe, Unsubscribe};
+pub(crate) use crate::cmd::replication::{Psync, ReplConf, ReplicaOf, Wait};
+pub(crate) use crate::cmd::server::ClientCommand;
+use crate::cmd::server::{BgRewriteAof, ConfigCommand, DbSize, Flush, Info, LastSave, Memory, Save, SlowLogCommand};
+use crate::cmd::set::SCard;
+use crate::cmd::sorted_set::{ZAdd, ZCard};
+use crate::cmd::string::{DecrBy, MultiGet, StrLen};
+use crate::cmd::transaction::Watch;
 use crate::cmd::unknown::Unknown;
 use crate::codec::RedisFrame;
 
@@ -32,23 +50,240 @@ pub(crate) enum Cmd {
     Get(Get),
     MGet(MultiGet),
     Del(Del),
+    Unlink(Unlink),
+    Scan(Scan),
+    Type(Type),
+    Object(Object),
+    DbSize(DbSize),
+    StrLen(StrLen),
+    LLen(LLen),
+    SCard(SCard),
+    ZCard(ZCard),
+    HLen(HLen),
+    HSet(HSet),
+    LPush(LPush),
+    ZAdd(ZAdd),
+    Flush(Flush),
+    Save(Save),
+    LastSave(LastSave),
+    BgRewriteAof(BgRewriteAof),
+    Info(Info),
+    Memory(Memory),
+    Config(ConfigCommand),
+    SlowLog(SlowLogCommand),
+    Client(ClientCommand),
+    Cluster(ClusterCommand),
+    Asking,
+    Sort(Sort),
+    Dump(Dump),
+    Restore(Restore),
+    Migrate(Migrate),
     DecrBy(DecrBy),
     APPEND(Append),
     Ping(Ping),
+    Multi,
+    Exec,
+    Discard,
+    Watch(Watch),
+    Unwatch,
+    /// close the connection once the reply is written
+    Quit,
+    /// back to the state of a new connection
+    Reset,
+    Publish(Publish),
+    PubSub(PubSubInfo),
+    Subscribe(Subscribe),
+    PSubscribe(Subscribe),
+    Unsubscribe(Unsubscribe),
+    PUnsubscribe(Unsubscribe),
+    SSubscribe(Subscribe),
+    SUnsubscribe(Unsubscribe),
+    ReplicaOf(ReplicaOf),
+    ReplConf(ReplConf),
+    Psync(Psync),
+    Wait(Wait),
     UnKnown(Unknown),
 }
 
 impl Cmd {
-    pub(crate) async fn execute(self, db: &SharedDb) -> RedisResult<Frame> {
+    /// run a request of a client. In cluster mode it is redirected instead when its keys live
+    /// on another node, under the same lock so that a migration cannot move them in between.
+    /// `asking` is set when the client sent ASKING right before
+    pub(crate) async fn execute(self, db: &SharedDb, argv: Vec<Bytes>, asking: bool, client: &ClientInfo) -> RedisResult<Frame> {
+        let mut store = db.lock();
+        if let Some(redirect) = redirect(&store, &self.keys(), asking) {
+            return Ok(redirect);
+        }
+        self.call_from(&mut store, argv, client)
+    }
+
+    /// run the command and log it to the AOF if it changed the keyspace, counting it
+    /// for INFO commandstats. `argv` is the request the command was parsed from
+    pub(crate) fn call(self, store: &mut Store, argv: Vec<Bytes>) -> RedisResult<Frame> {
+        self.call_from(store, argv, &ClientInfo::default())
+    }
+
+    /// `call` for a request of `client`, which is what the slow log shows when it is slow
+    pub(crate) fn call_from(self, store: &mut Store, argv: Vec<Bytes>, client: &ClientInfo) -> RedisResult<Frame> {
+        let name = argv.first().map(|name| String::from_utf8_lossy(name).to_lowercase()).unwrap_or_default();
+        let refused = if self.is_write() && store.replication.is_read_only() {
+            Some("READONLY You can't write against a read only replica.".to_string())
+        } else if let Some(e) = store.aof_write_error().filter(|_| self.is_write()) {
+            Some(format!("MISCONF Errors writing to the AOF file: {}", e))
+        } else if !store.evict() && self.is_denyoom() {
+            Some("OOM command not allowed when used memory > 'maxmemory'.".to_string())
+        } else {
+            None
+        };
+        if let Some(error) = refused {
+            store.stats.rejected(&name, &error);
+            return Ok(Frame::Error(error));
+        }
+
+        let unknown = matches!(self, Cmd::UnKnown(_));
+        let propagate = self.propagate(&argv);
+        let write_count = store.write_count();
+        let start = Instant::now();
+        let reply = self.apply(store);
+        let duration = start.elapsed();
+        if let Some(argv) = propagate {
+            if store.write_count() != write_count {
+                store.propagate(&argv);
+            }
+        }
+        if !unknown {
+            store.slowlog.record(&store.config, &argv, duration, client);
+        }
+
+        let error = match &reply {
+            Ok(Frame::Error(e)) => Some(e.as_str()),
+            Ok(_) => None,
+            Err(_) => Some("ERR"),
+        };
+        match error {
+            // 未知命令不计入 commandstats，只算一次错误
+            Some(error) if unknown => store.stats.error(error),
+            _ => store.stats.command(&name, duration, error),
+        }
+        reply
+    }
+
+    /// commands handled by the session of the connection rather than run against the store
+    pub(crate) fn is_connection_level(&self) -> bool {
+        matches!(self, Cmd::Multi | Cmd::Exec | Cmd::Discard | Cmd::Watch(_) | Cmd::Unwatch | Cmd::Quit | Cmd::Reset
+            | Cmd::Subscribe(_) | Cmd::PSubscribe(_) | Cmd::Unsubscribe(_) | Cmd::PUnsubscribe(_)
+            | Cmd::SSubscribe(_) | Cmd::SUnsubscribe(_)
+            | Cmd::ReplicaOf(_) | Cmd::ReplConf(_) | Cmd::Psync(_) | Cmd::Wait(_) | Cmd::Asking
+            | Cmd::Migrate(_) | Cmd::Client(_))
+    }
+
+    /// what has to be written to the AOF and sent to replicas for this command,
+    /// `None` for commands that never write
+    fn propagate(&self, argv: &[Bytes]) -> Option<Vec<Bytes>> {
         match self {
-            Cmd::Set(set) => set.execute(&db).await,
-            Cmd::Get(get) => get.execute(&db).await,
-            Cmd::MGet(multi_get) => multi_get.execute(&db).await,
-            Cmd::DecrBy(decr_by) => decr_by.execute(&db).await,
-            Cmd::Ping(ping) => ping.execute().await,
-            Cmd::Del(del) => del.execute(&db).await,
-            Cmd::APPEND(append) => append.execute(&db).await,
-            Cmd::UnKnown(unknown) => unknown.execute().await,
+            Cmd::Set(set) => Some(set.propagate()),
+            Cmd::Restore(restore) => Some(restore.propagate()),
+            _ if self.is_write() => Some(argv.to_vec()),
+            _ => None,
+        }
+    }
+
+    /// the keys of the request, where a cluster looks for the node serving it
+    pub(crate) fn keys(&self) -> Vec<Bytes> {
+        let keys = match self {
+            Cmd::Get(get) => vec![get.key()],
+            Cmd::Set(set) => vec![set.key()],
+            Cmd::Dump(dump) => vec![dump.key()],
+            Cmd::Restore(restore) => vec![restore.key()],
+            Cmd::APPEND(append) => vec![append.key()],
+            Cmd::DecrBy(decr_by) => vec![decr_by.key()],
+            Cmd::Type(key_type) => vec![key_type.key()],
+            Cmd::StrLen(str_len) => vec![str_len.key()],
+            Cmd::LLen(l_len) => vec![l_len.key()],
+            Cmd::SCard(s_card) => vec![s_card.key()],
+            Cmd::ZCard(z_card) => vec![z_card.key()],
+            Cmd::HLen(h_len) => vec![h_len.key()],
+            Cmd::HSet(h_set) => vec![h_set.key()],
+            Cmd::LPush(l_push) => vec![l_push.key()],
+            Cmd::ZAdd(z_add) => vec![z_add.key()],
+            Cmd::Object(object) => vec![object.key()],
+            Cmd::Memory(Memory::Usage { key, .. }) => vec![key.as_str()],
+            Cmd::MGet(multi_get) => multi_get.keys().iter().map(String::as_str).collect(),
+            Cmd::Del(del) => del.keys().iter().map(String::as_str).collect(),
+            Cmd::Unlink(unlink) => unlink.keys().iter().map(String::as_str).collect(),
+            Cmd::Migrate(migrate) => migrate.keys().iter().map(String::as_str).collect(),
+            Cmd::Sort(sort) => sort.keys(),
+            _ => vec![],
+        };
+        keys.into_iter().map(|key| Bytes::from(key.to_string())).collect()
+    }
+
+    /// commands that may change the keyspace, refused by read only replicas
+    fn is_write(&self) -> bool {
+        match self {
+            Cmd::Sort(sort) => sort.is_store(),
+            Cmd::Set(_) | Cmd::Restore(_) | Cmd::Del(_) | Cmd::Unlink(_) | Cmd::Flush(_)
+            | Cmd::DecrBy(_) | Cmd::APPEND(_) | Cmd::HSet(_) | Cmd::LPush(_) | Cmd::ZAdd(_) => true,
+            _ => false,
+        }
+    }
+
+    /// writes that may use more memory, refused once `maxmemory` is reached and nothing can be evicted
+    fn is_denyoom(&self) -> bool {
+        match self {
+            Cmd::Sort(sort) => sort.is_store(),
+            Cmd::Set(_) | Cmd::Restore(_) | Cmd::DecrBy(_) | Cmd::APPEND(_)
+            | Cmd::HSet(_) | Cmd::LPush(_) | Cmd::ZAdd(_) => true,
+            _ => false,
+        }
+    }
+
+    /// run the command against an already locked store, so that `EXEC` can run a whole
+    /// transaction under a single lock
+    pub(crate) fn apply(self, store: &mut Store) -> RedisResult<Frame> {
+        match self {
+            Cmd::Set(set) => set.execute(store),
+            Cmd::Get(get) => get.execute(store),
+            Cmd::MGet(multi_get) => multi_get.execute(store),
+            Cmd::DecrBy(decr_by) => decr_by.execute(store),
+            Cmd::Ping(ping) => ping.execute(),
+            Cmd::Del(del) => del.execute(store),
+            Cmd::Unlink(unlink) => unlink.execute(store),
+            Cmd::Scan(scan) => scan.execute(store),
+            Cmd::Type(key_type) => key_type.execute(store),
+            Cmd::Object(object) => object.execute(store),
+            Cmd::DbSize(db_size) => db_size.execute(store),
+            Cmd::StrLen(str_len) => str_len.execute(store),
+            Cmd::LLen(l_len) => l_len.execute(store),
+            Cmd::SCard(s_card) => s_card.execute(store),
+            Cmd::ZCard(z_card) => z_card.execute(store),
+            Cmd::HLen(h_len) => h_len.execute(store),
+            Cmd::HSet(h_set) => h_set.execute(store),
+            Cmd::LPush(l_push) => l_push.execute(store),
+            Cmd::ZAdd(z_add) => z_add.execute(store),
+            Cmd::Flush(flush) => flush.execute(store),
+            Cmd::Save(save) => save.execute(store),
+            Cmd::LastSave(last_save) => last_save.execute(store),
+            Cmd::BgRewriteAof(rewrite) => rewrite.execute(store),
+            Cmd::Info(info) => info.execute(store),
+            Cmd::Memory(memory) => memory.execute(store),
+            Cmd::Config(config) => config.execute(store),
+            Cmd::SlowLog(slowlog) => slowlog.execute(store),
+            Cmd::Cluster(cluster) => cluster.execute(store),
+            Cmd::Sort(sort) => sort.execute(store),
+            Cmd::Dump(dump) => dump.execute(store),
+            Cmd::Restore(restore) => restore.execute(store),
+            Cmd::APPEND(append) => append.execute(store),
+            Cmd::Publish(publish) => publish.execute(store),
+            Cmd::PubSub(pub_sub) => pub_sub.execute(store),
+            Cmd::UnKnown(unknown) => unknown.execute(),
+            // connection level commands are handled by the session before reaching the store
+            Cmd::Multi | Cmd::Exec | Cmd::Discard | Cmd::Watch(_) | Cmd::Unwatch | Cmd::Quit | Cmd::Reset
+            | Cmd::Subscribe(_) | Cmd::PSubscribe(_) | Cmd::Unsubscribe(_) | Cmd::PUnsubscribe(_)
+            | Cmd::SSubscribe(_) | Cmd::SUnsubscribe(_)
+            | Cmd::ReplicaOf(_) | Cmd::ReplConf(_) | Cmd::Psync(_) | Cmd::Wait(_) | Cmd::Asking
+            | Cmd::Migrate(_) | Cmd::Client(_) =>
+                Ok(Frame::Error("ERR command not allowed here".to_string())),
         }
     }
 }
@@ -64,18 +299,72 @@ impl TryFrom<Frame> for Cmd {
 
         let mut frame_iter = FrameIter::new(frames);
 
-        let command = match frame_iter.next_string()?.to_uppercase().as_str() {
+        let name = frame_iter.next_string()?;
+        let command = match name.to_uppercase().as_str() {
             "GET" =>
                 Ok(Cmd::Get(Get::parse_frames(&mut frame_iter)?)),
             "MGET" => Ok(Cmd::MGet(MultiGet::parse_frames(&mut frame_iter)?)),
             "SET" => Ok(Cmd::Set(Set::parse_frames(&mut frame_iter)?)),
             "DEL" => Ok(Cmd::Del(Del::parse_frames(&mut frame_iter)?)),
+            "UNLINK" => Ok(Cmd::Unlink(Unlink::parse_frames(&mut frame_iter)?)),
+            "SCAN" => Ok(Cmd::Scan(Scan::parse_frames(&mut frame_iter)?)),
+            "TYPE" => Ok(Cmd::Type(Type::parse_frames(&mut frame_iter)?)),
+            "OBJECT" => Ok(Cmd::Object(Object::parse_frames(&mut frame_iter)?)),
+            "DBSIZE" => Ok(Cmd::DbSize(DbSize)),
+            "STRLEN" => Ok(Cmd::StrLen(StrLen::parse_frames(&mut frame_iter)?)),
+            "LLEN" => Ok(Cmd::LLen(LLen::parse_frames(&mut frame_iter)?)),
+            "SCARD" => Ok(Cmd::SCard(SCard::parse_frames(&mut frame_iter)?)),
+            "ZCARD" => Ok(Cmd::ZCard(ZCard::parse_frames(&mut frame_iter)?)),
+            "HLEN" => Ok(Cmd::HLen(HLen::parse_frames(&mut frame_iter)?)),
+            "HSET" => Ok(Cmd::HSet(HSet::parse_frames(&mut frame_iter)?)),
+            "LPUSH" => Ok(Cmd::LPush(LPush::parse_frames(&mut frame_iter)?)),
+            "ZADD" => Ok(Cmd::ZAdd(ZAdd::parse_frames(&mut frame_iter)?)),
+            "FLUSHDB" | "FLUSHALL" => Ok(Cmd::Flush(Flush::parse_frames(&mut frame_iter)?)),
+            "SAVE" => Ok(Cmd::Save(Save::parse_frames(&mut frame_iter, false)?)),
+            "BGSAVE" => Ok(Cmd::Save(Save::parse_frames(&mut frame_iter, true)?)),
+            "LASTSAVE" => Ok(Cmd::LastSave(LastSave)),
+            "BGREWRITEAOF" => Ok(Cmd::BgRewriteAof(BgRewriteAof)),
+            "INFO" => Ok(Cmd::Info(Info::parse_frames(&mut frame_iter)?)),
+            "MEMORY" => Ok(Cmd::Memory(Memory::parse_frames(&mut frame_iter)?)),
+            "CONFIG" => Ok(Cmd::Config(ConfigCommand::parse_frames(&mut frame_iter)?)),
+            "CLIENT" => Ok(Cmd::Client(ClientCommand::parse_frames(&mut frame_iter)?)),
+            "SLOWLOG" => Ok(Cmd::SlowLog(SlowLogCommand::parse_frames(&mut frame_iter)?)),
+            "CLUSTER" => Ok(Cmd::Cluster(ClusterCommand::parse_frames(&mut frame_iter)?)),
+            "ASKING" => Ok(Cmd::Asking),
+            "REPLICAOF" | "SLAVEOF" => Ok(Cmd::ReplicaOf(ReplicaOf::parse_frames(&mut frame_iter)?)),
+            "REPLCONF" => Ok(Cmd::ReplConf(ReplConf::parse_frames(&mut frame_iter)?)),
+            "PSYNC" => Ok(Cmd::Psync(Psync::parse_frames(&mut frame_iter)?)),
+            "WAIT" => Ok(Cmd::Wait(Wait::parse_frames(&mut frame_iter)?)),
+            "SORT" => Ok(Cmd::Sort(Sort::parse_frames(&mut frame_iter, false)?)),
+            "SORT_RO" => Ok(Cmd::Sort(Sort::parse_frames(&mut frame_iter, true)?)),
+            "DUMP" => Ok(Cmd::Dump(Dump::parse_frames(&mut frame_iter)?)),
+            // RESTORE-ASKING is sent by MIGRATE in cluster mode, it implies ASKING
+            "RESTORE" | "RESTORE-ASKING" => Ok(Cmd::Restore(Restore::parse_frames(&mut frame_iter)?)),
+            "MIGRATE" => Ok(Cmd::Migrate(Migrate::parse_frames(&mut frame_iter)?)),
             "APPEND" => Ok(Cmd::APPEND(Append::parse_frames(&mut frame_iter)?)),
             "PING" => Ok(Cmd::Ping(Ping)),
-            "DECRBY" | "DECR" => Ok(Cmd::DecrBy(DecrBy::parse_frames(&mut frame_iter, true)?)),
-            "INCRBY" | "INCR" => Ok(Cmd::DecrBy(DecrBy::parse_frames(&mut frame_iter, false)?)),
-            other =>
-                Ok(Cmd::UnKnown(Unknown::new(other.to_string()))),
+            "MULTI" => Ok(Cmd::Multi),
+            "EXEC" => Ok(Cmd::Exec),
+            "DISCARD" => Ok(Cmd::Discard),
+            "WATCH" => Ok(Cmd::Watch(Watch::parse_frames(&mut frame_iter)?)),
+            "UNWATCH" => Ok(Cmd::Unwatch),
+            "QUIT" => Ok(Cmd::Quit),
+            "RESET" => Ok(Cmd::Reset),
+            "PUBLISH" => Ok(Cmd::Publish(Publish::parse_frames(&mut frame_iter, false)?)),
+            "SPUBLISH" => Ok(Cmd::Publish(Publish::parse_frames(&mut frame_iter, true)?)),
+            "PUBSUB" => Ok(Cmd::PubSub(PubSubInfo::parse_frames(&mut frame_iter)?)),
+            "SUBSCRIBE" => Ok(Cmd::Subscribe(Subscribe::parse_frames(&mut frame_iter)?)),
+            "PSUBSCRIBE" => Ok(Cmd::PSubscribe(Subscribe::parse_frames(&mut frame_iter)?)),
+            "UNSUBSCRIBE" => Ok(Cmd::Unsubscribe(Unsubscribe::parse_frames(&mut frame_iter)?)),
+            "PUNSUBSCRIBE" => Ok(Cmd::PUnsubscribe(Unsubscribe::parse_frames(&mut frame_iter)?)),
+            "SSUBSCRIBE" => Ok(Cmd::SSubscribe(Subscribe::parse_frames(&mut frame_iter)?)),
+            "SUNSUBSCRIBE" => Ok(Cmd::SUnsubscribe(Unsubscribe::parse_frames(&mut frame_iter)?)),
+            "DECR" => Ok(Cmd::DecrBy(DecrBy::parse_frames(&mut frame_iter, "decr")?)),
+            "DECRBY" => Ok(Cmd::DecrBy(DecrBy::parse_frames(&mut frame_iter, "decrby")?)),
+            "INCR" => Ok(Cmd::DecrBy(DecrBy::parse_frames(&mut frame_iter, "incr")?)),
+            "INCRBY" => Ok(Cmd::DecrBy(DecrBy::parse_frames(&mut frame_iter, "incrby")?)),
+            // 未知命令的参数不用解析
+            _ => return Ok(Cmd::UnKnown(Unknown::new(name.clone()))),
         };
 
         frame_iter.finish()?;
@@ -113,4 +402,11 @@ mod test {
 
         println!();
     }
+
+    #[test]
+    fn unknown_command_test() {
+        let frame = Frame::Array(vec![Frame::Simple("NoSuch".to_string()), Frame::Simple("arg".to_string())]);
+        let Ok(Cmd::UnKnown(unknown)) = Cmd::try_from(frame) else { panic!("an unknown command parses") };
+        assert!(matches!(unknown.execute(), Ok(Frame::Error(e)) if e == "ERR unknown command 'NoSuch'"));
+    }
 }
\ No newline at end of file
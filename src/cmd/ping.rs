@@ -6,7 +6,7 @@ use crate::RedisResult;
 pub(crate) struct Ping;
 
 impl Ping {
-    pub(crate) async fn execute(self) -> RedisResult<Frame> {
+    pub(crate) fn execute(self) -> RedisResult<Frame> {
         Ok(Frame::Bulk(Bytes::from("pong".as_bytes())))
     }
 }
\ No newline at end of file
@@ -1,2 +1,9 @@
 mod publish;
+mod pubsub;
 mod subscribe;
+mod unsubscribe;
+
+pub(crate) use publish::Publish;
+pub(crate) use pubsub::PubSubInfo;
+pub(crate) use subscribe::Subscribe;
+pub(crate) use unsubscribe::Unsubscribe;
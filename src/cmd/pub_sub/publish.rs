@@ -0,0 +1,34 @@
+use bytes::Bytes;
+
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/publish/
+/// Syntax: PUBLISH channel message and SPUBLISH shardchannel message
+/// Returns the number of clients that received the message, pattern subscribers included.
+/// `SPUBLISH` only reaches the subscribers of the shard channel
+#[derive(Debug)]
+pub(crate) struct Publish {
+    channel: Bytes,
+    message: Bytes,
+    sharded: bool,
+}
+
+impl Publish {
+    pub(crate) fn parse_frames(iter: &mut FrameIter, sharded: bool) -> Result<Self, FrameError> {
+        let channel = iter.next_bytes()?;
+        let message = iter.next_bytes()?;
+
+        Ok(Self { channel, message, sharded })
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let receivers = if self.sharded {
+            store.pub_sub.spublish(&self.channel, &self.message)
+        } else {
+            store.pub_sub.publish(&self.channel, &self.message)
+        };
+        Ok(Frame::Integer(receivers as i64))
+    }
+}
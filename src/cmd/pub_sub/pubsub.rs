@@ -0,0 +1,76 @@
+use bytes::Bytes;
+
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/pubsub/
+/// Syntax:
+/// - PUBSUB CHANNELS [pattern]: channels with at least one subscriber
+/// - PUBSUB NUMSUB [channel [channel ...]]: subscriber count of each channel
+/// - PUBSUB NUMPAT: number of subscribed patterns
+/// - PUBSUB SHARDCHANNELS [pattern]: shard channels with at least one subscriber
+/// - PUBSUB SHARDNUMSUB [shardchannel [shardchannel ...]]: subscriber count of each shard channel
+#[derive(Debug)]
+pub(crate) enum PubSubInfo {
+    Channels(Option<Bytes>),
+    NumSub(Vec<Bytes>),
+    NumPat,
+    ShardChannels(Option<Bytes>),
+    ShardNumSub(Vec<Bytes>),
+}
+
+impl PubSubInfo {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let info = match iter.next_string()?.to_uppercase().as_str() {
+            "CHANNELS" => PubSubInfo::Channels(next_pattern(iter)?),
+            "NUMSUB" => PubSubInfo::NumSub(remaining_channels(iter)?),
+            "NUMPAT" => PubSubInfo::NumPat,
+            "SHARDCHANNELS" => PubSubInfo::ShardChannels(next_pattern(iter)?),
+            "SHARDNUMSUB" => PubSubInfo::ShardNumSub(remaining_channels(iter)?),
+            other => return Err(format!("ERR unknown subcommand '{}'. Try PUBSUB HELP.", other).into()),
+        };
+
+        Ok(info)
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let pub_sub = &store.pub_sub;
+        let frame = match self {
+            PubSubInfo::Channels(pattern) => Frame::Array(pub_sub.channels(pattern.as_deref())
+                .into_iter()
+                .map(Frame::Bulk)
+                .collect()),
+            PubSubInfo::NumSub(channels) => Frame::Array(channels.into_iter()
+                .flat_map(|channel| {
+                    let count = pub_sub.numsub(&channel);
+                    [Frame::Bulk(channel), Frame::Integer(count as i64)]
+                })
+                .collect()),
+            PubSubInfo::NumPat => Frame::Integer(pub_sub.numpat() as i64),
+            PubSubInfo::ShardChannels(pattern) => Frame::Array(pub_sub.shard_channels(pattern.as_deref())
+                .into_iter()
+                .map(Frame::Bulk)
+                .collect()),
+            PubSubInfo::ShardNumSub(channels) => Frame::Array(channels.into_iter()
+                .flat_map(|channel| {
+                    let count = pub_sub.shard_numsub(&channel);
+                    [Frame::Bulk(channel), Frame::Integer(count as i64)]
+                })
+                .collect()),
+        };
+        Ok(frame)
+    }
+}
+
+fn next_pattern(iter: &mut FrameIter) -> Result<Option<Bytes>, FrameError> {
+    if iter.has_remaining() { Ok(Some(iter.next_bytes()?)) } else { Ok(None) }
+}
+
+fn remaining_channels(iter: &mut FrameIter) -> Result<Vec<Bytes>, FrameError> {
+    let mut channels = vec![];
+    while iter.has_remaining() {
+        channels.push(iter.next_bytes()?);
+    }
+    Ok(channels)
+}
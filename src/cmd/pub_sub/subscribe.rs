@@ -0,0 +1,23 @@
+use bytes::Bytes;
+
+use crate::frame::{FrameError, FrameIter};
+
+/// https://redis.io/commands/subscribe/
+/// Syntax: SUBSCRIBE channel [channel ...], PSUBSCRIBE pattern [pattern ...]
+/// and SSUBSCRIBE shardchannel [shardchannel ...]
+/// Puts the connection in subscriber mode, it is handled by the session
+#[derive(Debug)]
+pub(crate) struct Subscribe {
+    pub(crate) channels: Vec<Bytes>,
+}
+
+impl Subscribe {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let mut channels = vec![iter.next_bytes()?];
+        while iter.has_remaining() {
+            channels.push(iter.next_bytes()?);
+        }
+
+        Ok(Self { channels })
+    }
+}
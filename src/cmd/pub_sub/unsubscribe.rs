@@ -0,0 +1,23 @@
+use bytes::Bytes;
+
+use crate::frame::{FrameError, FrameIter};
+
+/// https://redis.io/commands/unsubscribe/
+/// Syntax: UNSUBSCRIBE [channel [channel ...]], PUNSUBSCRIBE [pattern [pattern ...]]
+/// and SUNSUBSCRIBE [shardchannel [shardchannel ...]]
+/// Without arguments the connection leaves every channel, pattern or shard channel
+#[derive(Debug)]
+pub(crate) struct Unsubscribe {
+    pub(crate) channels: Vec<Bytes>,
+}
+
+impl Unsubscribe {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let mut channels = vec![];
+        while iter.has_remaining() {
+            channels.push(iter.next_bytes()?);
+        }
+
+        Ok(Self { channels })
+    }
+}
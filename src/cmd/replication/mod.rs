@@ -0,0 +1,9 @@
+mod psync;
+mod replconf;
+mod replicaof;
+mod wait;
+
+pub(crate) use psync::Psync;
+pub(crate) use replconf::ReplConf;
+pub(crate) use replicaof::ReplicaOf;
+pub(crate) use wait::Wait;
@@ -0,0 +1,22 @@
+use crate::frame::{FrameError, FrameIter};
+
+/// https://redis.io/commands/psync/
+/// Syntax: PSYNC replicationid offset
+///
+/// Sent by a replica to start replicating: `?` and `-1` ask for a full resync, the
+/// replication ID and next offset it has ask to continue from there if possible.
+/// The connection turns into a replication link, see [`crate::replication`]
+#[derive(Debug)]
+pub(crate) struct Psync {
+    pub(crate) replid: String,
+    pub(crate) offset: i64,
+}
+
+impl Psync {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let replid = iter.next_string()?;
+        let offset = iter.next_int().map_err(|_| FrameError::from("ERR value is not an integer or out of range"))?;
+
+        Ok(Self { replid, offset })
+    }
+}
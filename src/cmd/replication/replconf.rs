@@ -0,0 +1,25 @@
+use crate::frame::{FrameError, FrameIter};
+
+/// https://redis.io/commands/replconf/
+/// Syntax: REPLCONF option value [option value ...]
+/// - listening-port port: the port the replica listens on, shown by `INFO replication`
+/// - capa capability: what the replica supports, such as `psync2`
+/// - ACK offset: how far the replica got in the replication stream, never replied to
+/// - GETACK *: ask a replica for an ACK, sent by the master on the replication stream
+#[derive(Debug)]
+pub(crate) struct ReplConf {
+    pub(crate) options: Vec<(String, String)>,
+}
+
+impl ReplConf {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let mut options = vec![];
+        while iter.has_remaining() {
+            let option = iter.next_string()?.to_lowercase();
+            let value = iter.next_string().map_err(|_| FrameError::from("ERR syntax error"))?;
+            options.push((option, value));
+        }
+
+        Ok(Self { options })
+    }
+}
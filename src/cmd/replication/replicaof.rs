@@ -0,0 +1,25 @@
+use crate::frame::{FrameError, FrameIter};
+
+/// https://redis.io/commands/replicaof/
+/// Syntax: REPLICAOF host port | NO ONE
+///
+/// Make the server a replica of another one, or with `NO ONE` turn a replica into a master
+/// that keeps its dataset. `SLAVEOF` is the same command
+#[derive(Debug)]
+pub(crate) struct ReplicaOf {
+    /// `None` for NO ONE
+    pub(crate) master: Option<(String, u16)>,
+}
+
+impl ReplicaOf {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let host = iter.next_string()?;
+        let port = iter.next_string()?;
+        if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+            return Ok(Self { master: None });
+        }
+
+        let port = port.parse::<u16>().map_err(|_| FrameError::from("ERR Invalid master port"))?;
+        Ok(Self { master: Some((host, port)) })
+    }
+}
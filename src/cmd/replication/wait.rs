@@ -0,0 +1,26 @@
+use crate::frame::{FrameError, FrameIter};
+
+/// https://redis.io/commands/wait/
+/// Syntax: WAIT numreplicas timeout
+///
+/// Block until the writes made so far were acknowledged by at least
+/// `numreplicas` replicas, or `timeout` milliseconds passed, 0 waiting forever.
+/// Replies with how many replicas acknowledged them
+#[derive(Debug)]
+pub(crate) struct Wait {
+    pub(crate) replicas: usize,
+    pub(crate) timeout: u64,
+}
+
+impl Wait {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        const MSG: &str = "ERR value is not an integer or out of range";
+        let replicas = iter.next_int().map_err(|_| FrameError::from(MSG))?;
+        let timeout = iter.next_int().map_err(|_| FrameError::from(MSG))?;
+        if timeout < 0 {
+            return Err("ERR timeout is negative".into());
+        }
+
+        Ok(Self { replicas: replicas.max(0) as usize, timeout: timeout as u64 })
+    }
+}
@@ -0,0 +1,34 @@
+use crate::frame::{FrameError, FrameIter};
+
+/// https://redis.io/commands/client-setname/
+/// Syntax:
+/// - CLIENT SETNAME name: name the connection, SLOWLOG shows it with its commands.
+///   An empty name removes it
+/// - CLIENT GETNAME: the name of the connection, nil without one
+///
+/// Handled by the session of the connection
+#[derive(Debug)]
+pub(crate) enum ClientCommand {
+    SetName(String),
+    GetName,
+}
+
+impl ClientCommand {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let subcommand = iter.next_string()?.to_lowercase();
+        let command = match subcommand.as_str() {
+            "setname" => {
+                let name = iter.next_string()?;
+                // 和 Redis 一样，名字里不能有空格和控制字符
+                if !name.chars().all(|c| ('!'..='~').contains(&c)) {
+                    return Err("ERR Client names cannot contain spaces, newlines or special characters.".into());
+                }
+                ClientCommand::SetName(name)
+            }
+            "getname" => ClientCommand::GetName,
+            other => return Err(format!("ERR unknown subcommand '{}'. Try CLIENT HELP.", other).into()),
+        };
+
+        Ok(command)
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::HashSet;
+
+use bytes::Bytes;
+
+use crate::config::params;
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/config-get/
+/// Syntax:
+/// - CONFIG GET parameter [parameter ...]: the parameters matching the glob patterns and their values
+/// - CONFIG SET parameter value [parameter value ...]: change parameters of the running server.
+///   Either every value is valid and applied or none is
+/// - CONFIG REWRITE: write the current config to the config file the server started with
+/// - CONFIG RESETSTAT: reset the statistics reported by INFO
+#[derive(Debug)]
+pub(crate) enum ConfigCommand {
+    Get(Vec<String>),
+    Set(Vec<(String, String)>),
+    Rewrite,
+    ResetStat,
+}
+
+impl ConfigCommand {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let subcommand = iter.next_string()?.to_lowercase();
+        let arity_error = || FrameError::from(format!("ERR wrong number of arguments for 'config|{}' command", subcommand));
+        let command = match subcommand.as_str() {
+            "get" => {
+                let mut patterns = vec![iter.next_string().map_err(|_| arity_error())?];
+                while iter.has_remaining() {
+                    patterns.push(iter.next_string()?);
+                }
+                ConfigCommand::Get(patterns)
+            }
+            "set" => {
+                let mut params = vec![];
+                loop {
+                    let name = iter.next_string().map_err(|_| arity_error())?;
+                    let value = iter.next_string().map_err(|_| arity_error())?;
+                    params.push((name, value));
+                    if !iter.has_remaining() {
+                        break;
+                    }
+                }
+                ConfigCommand::Set(params)
+            }
+            "rewrite" => ConfigCommand::Rewrite,
+            "resetstat" => ConfigCommand::ResetStat,
+            other => return Err(format!("ERR unknown subcommand '{}'. Try CONFIG HELP.", other).into()),
+        };
+
+        Ok(command)
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let frame = match self {
+            ConfigCommand::Get(patterns) => {
+                // 多个模式匹配到同一个参数时只返回一次
+                let mut seen = HashSet::new();
+                let mut reply = vec![];
+                for pattern in patterns {
+                    for (name, value) in store.config.matching(&pattern) {
+                        if seen.insert(name) {
+                            reply.push(Frame::Bulk(Bytes::from(name)));
+                            reply.push(Frame::Bulk(Bytes::from(value)));
+                        }
+                    }
+                }
+                Frame::Array(reply)
+            }
+            ConfigCommand::Set(values) => match set(store, values) {
+                Ok(()) => Frame::ok(),
+                Err(e) => Frame::Error(e),
+            },
+            ConfigCommand::Rewrite => match store.config.config_file {
+                None => Frame::Error("ERR The server is running without a config file".to_string()),
+                Some(_) => match store.config.rewrite() {
+                    Ok(()) => Frame::ok(),
+                    Err(e) => Frame::Error(format!("ERR Rewriting config file: {}", e)),
+                },
+            },
+            ConfigCommand::ResetStat => {
+                store.reset_stats();
+                Frame::ok()
+            }
+        };
+        Ok(frame)
+    }
+}
+
+/// validate every value on a copy of the config before anything is applied
+fn set(store: &mut Store, values: Vec<(String, String)>) -> Result<(), String> {
+    let mut config = store.config.clone();
+    let mut names = HashSet::new();
+    for (name, value) in values {
+        let failed = |e: &str| format!("ERR CONFIG SET failed (possibly related to argument '{}') - {}", name, e);
+        let param = params::find(&name)
+            .ok_or_else(|| format!("ERR Unknown option or number of arguments for CONFIG SET - '{}'", name))?;
+        if !names.insert(param.name) {
+            return Err(failed("duplicate parameter"));
+        }
+        if !param.mutable {
+            return Err(failed("can't set immutable config"));
+        }
+        (param.set)(&mut config, &value).map_err(|e| failed(&e))?;
+    }
+    store.set_config(config).map_err(|e| format!("ERR CONFIG SET failed - {}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{AppendFsync, Config, MaxmemoryPolicy};
+    use crate::db::{run, test_config, test_db};
+    use crate::frame::Frame;
+
+    fn strings(frame: Frame) -> Vec<String> {
+        match frame {
+            Frame::Array(array) => array.into_iter().map(|frame| match frame {
+                Frame::Bulk(bytes) => String::from_utf8_lossy(&bytes).to_string(),
+                other => panic!("unexpected {:?}", other),
+            }).collect(),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn config_get_set_test() {
+        let db = test_db(test_config());
+        let mut store = db.lock();
+
+        assert_eq!(strings(run(&mut store, "CONFIG GET port")), vec!["port", "6379"]);
+        assert_eq!(strings(run(&mut store, "CONFIG GET maxmemory* port maxmemory")), vec![
+            "maxmemory", "0", "maxmemory-policy", "noeviction", "maxmemory-samples", "5", "port", "6379",
+        ]);
+        assert_eq!(strings(run(&mut store, "CONFIG GET slave-read-only")), vec!["replica-read-only", "yes"]);
+        assert!(strings(run(&mut store, "CONFIG GET nothing*")).is_empty());
+
+        assert!(matches!(run(&mut store, "CONFIG SET maxmemory 10mb maxmemory-policy allkeys-lru"), Frame::Simple(ok) if ok == "OK"));
+        assert_eq!(store.config.maxmemory, 10 * 1024 * 1024);
+        assert_eq!(store.config.maxmemory_policy, MaxmemoryPolicy::AllKeysLru);
+        assert!(matches!(run(&mut store, "CONFIG SET appendfsync always lfu-log-factor 20"), Frame::Simple(_)));
+        assert_eq!(store.config.appendfsync, AppendFsync::Always);
+        assert_eq!(store.config.lfu_log_factor, 20);
+
+        // nothing is applied when one of the values is refused
+        assert!(matches!(run(&mut store, "CONFIG SET maxmemory 1mb maxmemory-policy lru"),
+            Frame::Error(e) if e == "ERR CONFIG SET failed (possibly related to argument 'maxmemory-policy') - invalid maxmemory-policy 'lru'"));
+        assert_eq!(store.config.maxmemory, 10 * 1024 * 1024);
+        assert!(matches!(run(&mut store, "CONFIG SET port 7000"), Frame::Error(e) if e.ends_with("can't set immutable config")));
+        assert!(matches!(run(&mut store, "CONFIG SET maxmemory 1 maxmemory 2"), Frame::Error(e) if e.ends_with("duplicate parameter")));
+        assert!(matches!(run(&mut store, "CONFIG SET foo bar"), Frame::Error(e) if e.starts_with("ERR Unknown option")));
+        assert!(matches!(run(&mut store, "CONFIG SET maxmemory"), Frame::Error(e) if e.contains("wrong number of arguments")));
+        assert!(matches!(run(&mut store, "CONFIG REWRITE"), Frame::Error(e) if e.contains("without a config file")));
+
+        // a lower maxmemory evicts right away
+        for i in 0..100 {
+            run(&mut store, &format!("SET key{} {}", i, "x".repeat(100)));
+        }
+        run(&mut store, "CONFIG SET maxmemory 5kb");
+        assert!(store.used_memory() <= 5 * 1024);
+        assert!(store.evicted_keys() > 0);
+        assert!(matches!(run(&mut store, "CONFIG RESETSTAT"), Frame::Simple(_)));
+        assert_eq!(store.evicted_keys(), 0);
+    }
+
+    #[tokio::test]
+    async fn config_appendonly_test() {
+        let dir = std::env::temp_dir().join(format!("config-appendonly-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config { dir: dir.clone(), ..test_config() };
+        let db = test_db(config.clone());
+        run(&mut db.lock(), "SET before 1");
+        run(&mut db.lock(), "CONFIG SET appendonly yes");
+        run(&mut db.lock(), "SET after 2");
+        // the keys written before are saved in the background
+        while db.lock().aof_rewriting() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        db.sync_aof().unwrap();
+
+        // the AOF holds the keys written before it was turned on too
+        let restarted = test_db(Config { appendonly: true, ..config });
+        assert_eq!(restarted.load().unwrap(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
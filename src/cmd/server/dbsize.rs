@@ -0,0 +1,16 @@
+use crate::db::Store;
+use crate::frame::Frame;
+use crate::RedisResult;
+
+/// https://redis.io/commands/dbsize/
+/// Syntax: DBSIZE
+///
+/// The number of keys, including the expired ones that were not collected yet
+#[derive(Debug)]
+pub(crate) struct DbSize;
+
+impl DbSize {
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        Ok(Frame::Integer(store.key_count() as i64))
+    }
+}
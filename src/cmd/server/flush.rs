@@ -0,0 +1,36 @@
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/flushall/
+/// Syntax: FLUSHALL [ASYNC | SYNC] and FLUSHDB [ASYNC | SYNC]
+/// - ASYNC: the old keyspace is freed on a background thread
+/// - SYNC: the old keyspace is freed before replying
+///
+/// Without a modifier `lazyfree-lazy-user-flush` decides. There is a single database,
+/// so both commands do the same thing
+#[derive(Debug)]
+pub(crate) struct Flush {
+    lazy: Option<bool>,
+}
+
+impl Flush {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let mut lazy = None;
+        if iter.has_remaining() {
+            lazy = match iter.next_string()?.to_uppercase().as_str() {
+                "ASYNC" => Some(true),
+                "SYNC" => Some(false),
+                _ => return Err("ERR syntax error".into()),
+            };
+        }
+
+        Ok(Self { lazy })
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let lazy = self.lazy.unwrap_or(store.config.lazyfree_lazy_user_flush);
+        store.flush(lazy);
+        Ok(Frame::ok())
+    }
+}
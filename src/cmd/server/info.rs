@@ -0,0 +1,285 @@
+use std::fmt::Write;
+
+use bytes::Bytes;
+
+use crate::clock::unix_ms;
+use crate::cmd::server::memory::MemoryStats;
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::stats::{cpu_times, resident_memory};
+use crate::RedisResult;
+
+/// every section, in the order of Redis
+const SECTIONS: [&str; 12] = [
+    "server", "clients", "memory", "persistence", "stats", "replication", "cpu",
+    "commandstats", "errorstats", "latencystats", "cluster", "keyspace",
+];
+
+/// the sections left out unless asked for by name, `all` or `everything`
+const NOT_DEFAULT: [&str; 2] = ["commandstats", "latencystats"];
+
+/// the percentiles of `latency_percentiles_usec_<command>`
+const PERCENTILES: [f64; 3] = [50.0, 99.0, 99.9];
+
+/// https://redis.io/commands/info/
+/// Syntax: INFO [section [section ...]]
+/// - section: server, clients, memory, persistence, stats, replication, cpu, commandstats,
+///   errorstats, latencystats, cluster or keyspace. `default` for every section but
+///   commandstats and latencystats, `all` and `everything` for all of them
+///
+/// Sections are `# Name` followed by `field:value` lines, each ending with CRLF
+#[derive(Debug)]
+pub(crate) struct Info {
+    sections: Vec<String>,
+}
+
+impl Info {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let mut sections = vec![];
+        while iter.has_remaining() {
+            sections.push(iter.next_string()?.to_lowercase());
+        }
+
+        Ok(Self { sections })
+    }
+
+    fn wants(&self, section: &str) -> bool {
+        let default = self.sections.is_empty() || self.sections.iter().any(|name| name == "default");
+        self.sections.iter().any(|name| name == section || name == "all" || name == "everything")
+            || (default && !NOT_DEFAULT.contains(&section))
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let mut info = String::new();
+        for section in SECTIONS.into_iter().filter(|section| self.wants(section)) {
+            if !info.is_empty() {
+                info.push_str("\r\n");
+            }
+            match section {
+                "server" => server(store, &mut info),
+                "clients" => clients(store, &mut info),
+                "memory" => memory(store, &mut info),
+                "persistence" => persistence(store, &mut info),
+                "stats" => stats(store, &mut info),
+                "replication" => info.push_str(&store.replication.info()),
+                "cpu" => cpu(&mut info),
+                "commandstats" => command_stats(store, &mut info),
+                "errorstats" => error_stats(store, &mut info),
+                "latencystats" => latency_stats(store, &mut info),
+                "cluster" => {
+                    let _ = write!(info, "# Cluster\r\ncluster_enabled:{}\r\n", store.cluster.is_some() as u8);
+                }
+                _ => keyspace(store, &mut info),
+            }
+        }
+        Ok(Frame::Bulk(Bytes::from(info)))
+    }
+}
+
+fn server(store: &Store, info: &mut String) {
+    let uptime = store.stats.started.elapsed().as_secs();
+    let port = match store.replication.listening_port {
+        0 => store.config.port,
+        port => port,
+    };
+    let executable = std::env::current_exe().map(|path| path.display().to_string()).unwrap_or_default();
+    let config_file = store.config.config_file.as_ref().map(|path| path.display().to_string()).unwrap_or_default();
+
+    info.push_str("# Server\r\n");
+    let _ = write!(info, "redis_version:{}\r\n", env!("CARGO_PKG_VERSION"));
+    let _ = write!(info, "redis_mode:{}\r\n", if store.cluster.is_some() { "cluster" } else { "standalone" });
+    let _ = write!(info, "os:{} {}\r\narch_bits:{}\r\n", std::env::consts::OS, std::env::consts::ARCH, usize::BITS);
+    let _ = write!(info, "process_id:{}\r\nrun_id:{}\r\ntcp_port:{}\r\n", std::process::id(), store.stats.run_id, port);
+    let _ = write!(info, "server_time_usec:{}\r\n", unix_ms() * 1000);
+    let _ = write!(info, "uptime_in_seconds:{}\r\nuptime_in_days:{}\r\n", uptime, uptime / 86400);
+    let _ = write!(info, "executable:{}\r\nconfig_file:{}\r\n", executable, config_file);
+}
+
+fn clients(store: &Store, info: &mut String) {
+    let (pubsub_clients, _) = store.pub_sub.output_buffers();
+    info.push_str("# Clients\r\n");
+    let _ = write!(info, "connected_clients:{}\r\nmaxclients:{}\r\n", store.stats.connected_clients, store.config.maxclients);
+    let _ = write!(info, "pubsub_clients:{}\r\n", pubsub_clients);
+}
+
+fn memory(store: &Store, info: &mut String) {
+    let memory = MemoryStats::new(store);
+    let (_, client_buffers) = store.pub_sub.output_buffers();
+    let rss = resident_memory();
+    let used = store.used_memory();
+    let peak = store.peak_memory().max(used);
+    let maxmemory = store.config.maxmemory;
+
+    info.push_str("# Memory\r\n");
+    let _ = write!(info, "used_memory:{}\r\nused_memory_human:{}\r\n", used, human(used));
+    let _ = write!(info, "used_memory_rss:{}\r\nused_memory_rss_human:{}\r\n", rss, human(rss));
+    let _ = write!(info, "used_memory_peak:{}\r\nused_memory_peak_human:{}\r\n", peak, human(peak));
+    let _ = write!(info, "used_memory_peak_perc:{:.2}%\r\n", used as f64 * 100.0 / peak.max(1) as f64);
+    let _ = write!(info, "used_memory_overhead:{}\r\nused_memory_dataset:{}\r\n", memory.overhead(), memory.dataset());
+    let _ = write!(info, "maxmemory:{}\r\nmaxmemory_human:{}\r\nmaxmemory_policy:{}\r\n",
+                   maxmemory, human(maxmemory), store.config.maxmemory_policy);
+    let _ = write!(info, "mem_replication_backlog:{}\r\n", store.replication.backlog_memory());
+    let _ = write!(info, "mem_clients_slaves:{}\r\nmem_clients_normal:{}\r\n", store.replication.replica_buffers(), client_buffers);
+}
+
+fn persistence(store: &Store, info: &mut String) {
+    let save = &store.save_state;
+    info.push_str("# Persistence\r\nloading:0\r\n");
+    let _ = write!(info, "rdb_changes_since_last_save:{}\r\nrdb_bgsave_in_progress:{}\r\n",
+                   save.changes(), save.in_progress() as u8);
+    let _ = write!(info, "rdb_last_save_time:{}\r\nrdb_last_bgsave_status:{}\r\n",
+                   save.last_save(), if save.last_ok() { "ok" } else { "err" });
+    let _ = write!(info, "aof_enabled:{}\r\naof_rewrite_in_progress:{}\r\n",
+                   store.config.appendonly as u8, store.aof_rewriting() as u8);
+    let _ = write!(info, "aof_last_write_status:{}\r\n", if store.aof_write_error().is_some() { "err" } else { "ok" });
+}
+
+fn stats(store: &Store, info: &mut String) {
+    let stats = &store.stats;
+    info.push_str("# Stats\r\n");
+    let _ = write!(info, "total_connections_received:{}\r\ntotal_commands_processed:{}\r\n",
+                   stats.total_connections_received, stats.total_commands_processed);
+    let _ = write!(info, "instantaneous_ops_per_sec:{}\r\nrejected_connections:{}\r\n",
+                   stats.ops_per_sec(), stats.rejected_connections);
+    let _ = write!(info, "sync_full:{}\r\nsync_partial_ok:{}\r\nsync_partial_err:{}\r\n",
+                   store.replication.sync_full, store.replication.sync_partial_ok, store.replication.sync_partial_err);
+    let _ = write!(info, "expired_keys:{}\r\nevicted_keys:{}\r\n", stats.expired_keys, store.evicted_keys());
+    let _ = write!(info, "keyspace_hits:{}\r\nkeyspace_misses:{}\r\n", stats.keyspace_hits, stats.keyspace_misses);
+    let _ = write!(info, "pubsub_channels:{}\r\npubsub_patterns:{}\r\n",
+                   store.pub_sub.channels(None).len(), store.pub_sub.numpat());
+    let _ = write!(info, "total_error_replies:{}\r\n", stats.total_error_replies);
+}
+
+fn cpu(info: &mut String) {
+    let (user, sys) = cpu_times();
+    let _ = write!(info, "# CPU\r\nused_cpu_sys:{:.6}\r\nused_cpu_user:{:.6}\r\n", sys, user);
+}
+
+fn command_stats(store: &Store, info: &mut String) {
+    info.push_str("# Commandstats\r\n");
+    for (name, command) in &store.stats.commands {
+        let per_call = if command.calls == 0 { 0.0 } else { command.usec as f64 / command.calls as f64 };
+        let _ = write!(info, "cmdstat_{}:calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}\r\n",
+                       name, command.calls, command.usec, per_call, command.rejected_calls, command.failed_calls);
+    }
+}
+
+fn error_stats(store: &Store, info: &mut String) {
+    info.push_str("# Errorstats\r\n");
+    for (prefix, count) in &store.stats.errors {
+        let _ = write!(info, "errorstat_{}:count={}\r\n", prefix, count);
+    }
+}
+
+fn latency_stats(store: &Store, info: &mut String) {
+    info.push_str("# Latencystats\r\n");
+    for (name, command) in store.stats.commands.iter().filter(|(_, command)| command.latency.count() > 0) {
+        let percentiles: Vec<String> = PERCENTILES.iter()
+            .map(|p| format!("p{}={:.3}", p, command.latency.percentile(*p) as f64))
+            .collect();
+        let _ = write!(info, "latency_percentiles_usec_{}:{}\r\n", name, percentiles.join(","));
+    }
+}
+
+fn keyspace(store: &Store, info: &mut String) {
+    info.push_str("# Keyspace\r\n");
+    let keys = store.key_count();
+    if keys > 0 {
+        let (expires, avg_ttl) = store.expires();
+        let _ = write!(info, "db0:keys={},expires={},avg_ttl={}\r\n", keys, expires, avg_ttl);
+    }
+}
+
+/// bytes the way INFO shows them to people: `1.50K`, `20.00M`
+fn human(bytes: usize) -> String {
+    let units = ["B", "K", "M", "G", "T", "P"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < units.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{}B", bytes)
+    } else {
+        format!("{:.2}{}", value, units[unit])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::cmd::server::info::human;
+    use crate::db::{Store, run, test_config, test_db};
+    use crate::frame::Frame;
+
+    /// the sections of an INFO reply and their fields
+    fn info(store: &mut Store, args: &str) -> HashMap<String, HashMap<String, String>> {
+        let Frame::Bulk(info) = run(store, args) else { panic!("INFO replies with a bulk string") };
+        let mut sections = HashMap::new();
+        let mut section = String::new();
+        for line in String::from_utf8_lossy(&info).split("\r\n") {
+            if let Some(name) = line.strip_prefix("# ") {
+                section = name.to_lowercase();
+                sections.insert(section.clone(), HashMap::new());
+            } else if let Some((field, value)) = line.split_once(':') {
+                sections.get_mut(&section).unwrap().insert(field.to_string(), value.to_string());
+            }
+        }
+        sections
+    }
+
+    #[tokio::test]
+    async fn info_test() {
+        let db = test_db(test_config());
+        let mut store = db.lock();
+
+        run(&mut store, "SET a 1");
+        run(&mut store, "SET b 2 EX 100");
+        run(&mut store, "GET a");
+        run(&mut store, "GET missing");
+        run(&mut store, "LPUSH a x");
+        run(&mut store, "NOSUCHCOMMAND");
+
+        let sections = info(&mut store, "INFO");
+        let mut names: Vec<&str> = sections.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, vec!["clients", "cluster", "cpu", "errorstats", "keyspace", "memory", "persistence", "replication", "server", "stats"]);
+        assert_eq!(sections["stats"]["keyspace_hits"], "1");
+        assert_eq!(sections["stats"]["keyspace_misses"], "1");
+        assert_eq!(sections["stats"]["total_commands_processed"], "5");
+        assert_eq!(sections["stats"]["total_error_replies"], "2");
+        assert_eq!(sections["errorstats"]["errorstat_WRONGTYPE"], "count=1");
+        assert_eq!(sections["errorstats"]["errorstat_ERR"], "count=1");
+        assert!(sections["keyspace"]["db0"].starts_with("keys=2,expires=1,avg_ttl="));
+        assert_eq!(sections["server"]["redis_mode"], "standalone");
+
+        let sections = info(&mut store, "INFO commandstats LATENCYSTATS");
+        assert_eq!(sections.len(), 2);
+        assert!(sections["commandstats"]["cmdstat_set"].starts_with("calls=2,usec="));
+        assert!(sections["commandstats"]["cmdstat_lpush"].ends_with("rejected_calls=0,failed_calls=1"));
+        assert!(!sections["commandstats"].contains_key("cmdstat_nosuchcommand"));
+        assert!(sections["latencystats"]["latency_percentiles_usec_get"].starts_with("p50="));
+
+        // a write refused over maxmemory is a rejected call
+        store.config.maxmemory = 1;
+        assert!(matches!(run(&mut store, "SET c 3"), Frame::Error(e) if e.starts_with("OOM")));
+        let sections = info(&mut store, "INFO everything");
+        assert_eq!(sections.len(), 12);
+        assert!(sections["commandstats"]["cmdstat_set"].contains("rejected_calls=1"));
+        assert_eq!(sections["errorstats"]["errorstat_OOM"], "count=1");
+
+        run(&mut store, "CONFIG RESETSTAT");
+        let sections = info(&mut store, "INFO stats commandstats");
+        assert_eq!(sections["stats"]["total_commands_processed"], "1");
+        assert_eq!(sections["commandstats"].len(), 1);
+    }
+
+    #[test]
+    fn human_test() {
+        assert_eq!(human(100), "100B");
+        assert_eq!(human(1536), "1.50K");
+        assert_eq!(human(20 * 1024 * 1024), "20.00M");
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt::Write;
+
+use bytes::Bytes;
+
+use crate::config::MaxmemoryPolicy;
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// elements of a collection MEMORY USAGE looks at by default
+const DEFAULT_SAMPLES: usize = 5;
+
+/// below this much memory MEMORY DOCTOR has nothing meaningful to say
+const DOCTOR_MIN_MEMORY: usize = 5 * 1024 * 1024;
+
+/// https://redis.io/commands/memory-usage/
+/// Syntax:
+/// - MEMORY USAGE key [SAMPLES count]: the bytes a key and its value take. Collections are
+///   estimated from `count` of their elements, 5 by default, 0 for all of them
+/// - MEMORY STATS: where the memory goes, keyspace, bookkeeping, replication and client buffers
+/// - MEMORY DOCTOR: advice about the memory figures of MEMORY STATS
+///
+/// Sizes are estimated from the lengths and capacities of what is stored,
+/// not measured with the allocator
+#[derive(Debug)]
+pub(crate) enum Memory {
+    Usage { key: String, samples: usize },
+    Stats,
+    Doctor,
+}
+
+impl Memory {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let memory = match iter.next_string()?.to_uppercase().as_str() {
+            "USAGE" => {
+                let key = iter.next_string()?;
+                let mut samples = DEFAULT_SAMPLES;
+                if iter.has_remaining() {
+                    if !iter.next_string()?.eq_ignore_ascii_case("SAMPLES") {
+                        return Err("ERR syntax error".into());
+                    }
+                    let count = iter.next_int().map_err(|_| FrameError::from("ERR value is not an integer or out of range"))?;
+                    samples = usize::try_from(count).map_err(|_| FrameError::from("ERR syntax error"))?;
+                }
+                Memory::Usage { key, samples }
+            }
+            "STATS" => Memory::Stats,
+            "DOCTOR" => Memory::Doctor,
+            other => return Err(format!("ERR unknown subcommand '{}'. Try MEMORY HELP.", other).into()),
+        };
+
+        Ok(memory)
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let frame = match self {
+            Memory::Usage { key, samples } => match store.key_memory_usage(&key, samples) {
+                Some(bytes) => Frame::Integer(bytes as i64),
+                None => Frame::Null,
+            },
+            Memory::Stats => MemoryStats::new(store).to_frame(),
+            Memory::Doctor => Frame::Bulk(Bytes::from(MemoryStats::new(store).doctor())),
+        };
+        Ok(frame)
+    }
+}
+
+/// the figures of MEMORY STATS, in bytes
+#[derive(Debug, Default)]
+pub(super) struct MemoryStats {
+    peak: usize,
+    used: usize,
+    backlog: usize,
+    replicas: usize,
+    replica_buffers: usize,
+    clients: usize,
+    client_buffers: usize,
+    /// the main hash table and the expiry tree
+    main_overhead: usize,
+    expires_overhead: usize,
+    keys: usize,
+    maxmemory: usize,
+    policy: MaxmemoryPolicy,
+}
+
+impl MemoryStats {
+    pub(super) fn new(store: &Store) -> Self {
+        let (main_overhead, expires_overhead) = store.keyspace_overhead();
+        let (clients, client_buffers) = store.pub_sub.output_buffers();
+        Self {
+            peak: store.peak_memory(),
+            used: store.used_memory(),
+            backlog: store.replication.backlog_memory(),
+            replicas: store.replication.replica_count(),
+            replica_buffers: store.replication.replica_buffers(),
+            clients,
+            client_buffers,
+            main_overhead,
+            expires_overhead,
+            keys: store.key_count(),
+            maxmemory: store.config.maxmemory,
+            policy: store.config.maxmemory_policy,
+        }
+    }
+
+    pub(super) fn overhead(&self) -> usize {
+        self.main_overhead + self.expires_overhead + self.backlog + self.replica_buffers + self.client_buffers
+    }
+
+    /// the keyspace plus every buffer
+    fn total(&self) -> usize {
+        self.used + self.expires_overhead + self.backlog + self.replica_buffers + self.client_buffers
+    }
+
+    /// the names and values of the keys
+    pub(super) fn dataset(&self) -> usize {
+        self.used - self.main_overhead
+    }
+
+    fn to_frame(&self) -> Frame {
+        let total = self.total();
+        let percentage = |part: usize, whole: usize| if whole == 0 { 0.0 } else { part as f64 * 100.0 / whole as f64 };
+        let integer = |name: &'static str, value: usize| [Frame::Bulk(Bytes::from_static(name.as_bytes())), Frame::Integer(value as i64)];
+        let float = |name: &'static str, value: f64| [Frame::Bulk(Bytes::from_static(name.as_bytes())), Frame::Bulk(Bytes::from(format!("{:.4}", value)))];
+
+        let mut frames = vec![];
+        frames.extend(integer("peak.allocated", self.peak.max(total)));
+        frames.extend(integer("total.allocated", total));
+        frames.extend(integer("replication.backlog", self.backlog));
+        frames.extend(integer("clients.slaves", self.replica_buffers));
+        frames.extend(integer("clients.normal", self.client_buffers));
+        frames.push(Frame::Bulk(Bytes::from_static(b"db.0")));
+        frames.push(Frame::Array([
+            integer("overhead.hashtable.main", self.main_overhead),
+            integer("overhead.hashtable.expires", self.expires_overhead),
+        ].into_iter().flatten().collect()));
+        frames.extend(integer("overhead.total", self.overhead()));
+        frames.extend(integer("keys.count", self.keys));
+        frames.extend(integer("keys.bytes-per-key", total.checked_div(self.keys).unwrap_or(0)));
+        frames.extend(integer("dataset.bytes", self.dataset()));
+        frames.extend(float("dataset.percentage", percentage(self.dataset(), total)));
+        frames.extend(float("peak.percentage", percentage(self.used, self.peak)));
+        Frame::Array(frames)
+    }
+
+    /// what MEMORY DOCTOR has to say about these figures
+    fn doctor(&self) -> String {
+        if self.total() < DOCTOR_MIN_MEMORY {
+            return "Hi Sam, this instance is empty or is using very little memory, my issues detector can't be used \
+                in these conditions. Please, leave for your mission on Earth and fill it with some data. \
+                The new Sam and I will be back to our programming as soon as I finished rebooting.".to_string();
+        }
+
+        let mut issues = String::new();
+        if self.peak > self.used / 2 * 3 {
+            issues.push_str(" * Peak memory: In the past this instance used more than 150% the memory that is \
+                currently using. The memory freed since then is usually kept by the allocator and reused as soon \
+                as the instance is filled again, only a restart gives it back to the system.\n\n");
+        }
+        if self.clients > 0 && self.client_buffers / self.clients > 200 * 1024 {
+            issues.push_str(" * Big client buffers: The clients output buffers are in general large, the average \
+                is over 200KB. This is usually due to Pub/Sub subscribers that can't keep up with the messages \
+                they receive. Consider lowering their output buffer limit.\n\n");
+        }
+        if self.replicas > 0 && self.replica_buffers / self.replicas > 10 * 1024 * 1024 {
+            issues.push_str(" * Big replica buffers: The replica output buffers are in general large, the average \
+                is over 10MB. This happens when the master writes faster than the replicas or the network \
+                can follow.\n\n");
+        }
+        if self.maxmemory > 0 && self.used > self.maxmemory / 10 * 9 {
+            let consequence = if self.policy == MaxmemoryPolicy::NoEviction {
+                "with the noeviction policy writes will be refused with OOM errors once it is reached"
+            } else {
+                "keys are being evicted or will be soon"
+            };
+            let _ = write!(issues, " * Close to maxmemory: The keyspace uses more than 90% of maxmemory, {}. \
+                Consider raising maxmemory or removing keys.\n\n", consequence);
+        }
+
+        if issues.is_empty() {
+            "Hi Sam, I can't find any memory issue in your instance. I can only account for what occurs \
+                on this base.".to_string()
+        } else {
+            format!("Sam, I detected a few issues in this Redis instance memory implants:\n\n{}\
+                I'm here to keep you safe, Sam. I want to help you.\n", issues)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use crate::cmd::server::memory::MemoryStats;
+    use crate::cmd::Cmd;
+    use crate::config::MaxmemoryPolicy;
+    use crate::db::{run, test_config, test_db};
+    use crate::frame::Frame;
+
+    fn integer(frame: Frame) -> i64 {
+        match frame {
+            Frame::Integer(n) => n,
+            other => panic!("expected an integer, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn memory_usage_test() {
+        let db = test_db(test_config());
+        let mut store = db.lock();
+
+        assert!(matches!(run(&mut store, "MEMORY USAGE missing"), Frame::Null));
+        run(&mut store, "SET short 1");
+        run(&mut store, "SET longer 1234567890");
+        let short = integer(run(&mut store, "MEMORY USAGE short"));
+        let longer = integer(run(&mut store, "MEMORY USAGE longer"));
+        // one more byte of name counted twice, nine more bytes of value
+        assert_eq!(longer, short + 2 + 9);
+        run(&mut store, "SET short 1 EX 100");
+        assert!(integer(run(&mut store, "MEMORY USAGE short")) > short);
+
+        // the list is made of elements of the same size, sampling two is exact
+        let list: Vec<Bytes> = (0..100).map(|i| Bytes::from(format!("{:04}", i))).collect();
+        store.set_list("list", list);
+        let all = integer(run(&mut store, "MEMORY USAGE list SAMPLES 0"));
+        assert_eq!(integer(run(&mut store, "MEMORY USAGE list SAMPLES 2")), all);
+        assert!(all > 100 * 4);
+
+        let parse = |args: &str| Cmd::try_from(Frame::Array(args.split(' ')
+            .map(|arg| Frame::Bulk(Bytes::from(arg.to_string())))
+            .collect()));
+        assert!(parse("MEMORY USAGE list SAMPLES x").is_err());
+        assert!(parse("MEMORY USAGE list COUNT 1").is_err());
+        assert!(matches!(parse("MEMORY NOPE"), Err(e) if e.to_string().contains("MEMORY HELP")));
+    }
+
+    #[tokio::test]
+    async fn memory_stats_test() {
+        let db = test_db(test_config());
+        let mut store = db.lock();
+        run(&mut store, "SET a 1");
+        run(&mut store, "SET b 2 EX 100");
+
+        let Frame::Array(frames) = run(&mut store, "MEMORY STATS") else { panic!("MEMORY STATS is an array") };
+        let field = |name: &str| frames.iter()
+            .position(|frame| matches!(frame, Frame::Bulk(b) if b == name))
+            .map(|at| &frames[at + 1]);
+        assert!(matches!(field("keys.count"), Some(Frame::Integer(2))));
+        assert!(matches!(field("total.allocated"), Some(Frame::Integer(n)) if *n as usize >= store.used_memory()));
+        assert!(matches!(field("db.0"), Some(Frame::Array(db)) if db.len() == 4));
+
+        let reply = run(&mut store, "MEMORY DOCTOR");
+        assert!(matches!(reply, Frame::Bulk(b) if b.starts_with(b"Hi Sam, this instance is empty")));
+    }
+
+    #[test]
+    fn doctor_test() {
+        let healthy = MemoryStats { peak: 10 << 20, used: 10 << 20, keys: 1000, ..MemoryStats::default() };
+        assert!(healthy.doctor().starts_with("Hi Sam, I can't find any memory issue"));
+
+        let report = MemoryStats { peak: 40 << 20, maxmemory: 11 << 20, policy: MaxmemoryPolicy::NoEviction, ..healthy }.doctor();
+        assert!(report.contains("* Peak memory"));
+        assert!(report.contains("* Close to maxmemory"));
+        assert!(report.contains("OOM"));
+        assert!(!report.contains("* Big client buffers"));
+    }
+}
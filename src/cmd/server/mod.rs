@@ -0,0 +1,19 @@
+mod client;
+mod config;
+mod dbsize;
+mod flush;
+mod info;
+mod memory;
+mod rewrite_aof;
+mod save;
+mod slowlog;
+
+pub(crate) use client::ClientCommand;
+pub(crate) use config::ConfigCommand;
+pub(crate) use dbsize::DbSize;
+pub(crate) use flush::Flush;
+pub(crate) use info::Info;
+pub(crate) use memory::Memory;
+pub(crate) use rewrite_aof::BgRewriteAof;
+pub(crate) use save::{LastSave, Save};
+pub(crate) use slowlog::SlowLogCommand;
@@ -0,0 +1,20 @@
+use crate::db::Store;
+use crate::frame::Frame;
+use crate::RedisResult;
+
+/// https://redis.io/commands/bgrewriteaof/
+/// Syntax: BGREWRITEAOF
+///
+/// Rewrite the append only file as the smallest file that rebuilds the current keyspace.
+/// The old file keeps being written until the new one replaces it
+#[derive(Debug)]
+pub(crate) struct BgRewriteAof;
+
+impl BgRewriteAof {
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        match store.bgrewriteaof() {
+            Ok(()) => Ok(Frame::Simple("Background append only file rewriting started".to_string())),
+            Err(e) => Ok(Frame::Error(e.to_string())),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/save/
+/// Syntax: SAVE
+///
+/// https://redis.io/commands/bgsave/
+/// Syntax: BGSAVE [SCHEDULE]
+/// - SCHEDULE: if a background save is already running, start another one once it ends
+///   instead of replying with an error
+///
+/// SAVE writes the RDB file while holding the store, BGSAVE only copies the keyspace
+/// and writes it from another thread
+#[derive(Debug)]
+pub(crate) struct Save {
+    background: bool,
+    schedule: bool,
+}
+
+impl Save {
+    pub(crate) fn parse_frames(iter: &mut FrameIter, background: bool) -> Result<Self, FrameError> {
+        let mut schedule = false;
+        if background && iter.has_remaining() {
+            match iter.next_string()?.to_uppercase().as_str() {
+                "SCHEDULE" => schedule = true,
+                _ => return Err("ERR syntax error".into()),
+            }
+        }
+
+        Ok(Self { background, schedule })
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        if self.background {
+            if store.bgsave() {
+                return Ok(Frame::Simple("Background saving started".to_string()));
+            }
+            if self.schedule {
+                store.save_state.schedule();
+                return Ok(Frame::Simple("Background saving scheduled".to_string()));
+            }
+            return Ok(Frame::Error("ERR Background save already in progress".to_string()));
+        }
+
+        match store.save() {
+            Ok(()) => Ok(Frame::ok()),
+            Err(e) => Ok(Frame::Error(format!("ERR {}", e))),
+        }
+    }
+}
+
+/// https://redis.io/commands/lastsave/
+/// Syntax: LASTSAVE
+///
+/// The unix time in seconds of the last successful save
+#[derive(Debug)]
+pub(crate) struct LastSave;
+
+impl LastSave {
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        Ok(Frame::Integer(store.save_state.last_save() as i64))
+    }
+}
@@ -0,0 +1,125 @@
+use bytes::Bytes;
+
+use crate::db::Store;
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// entries SLOWLOG GET returns without a count
+const DEFAULT_COUNT: usize = 10;
+
+const HELP: [&str; 12] = [
+    "SLOWLOG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+    "GET [<count>]",
+    "    Return top <count> entries from the slowlog (default: 10, -1 mean all).",
+    "    Entries are made of:",
+    "    id, timestamp, time in microseconds, arguments array, client IP and port,",
+    "    client name",
+    "LEN",
+    "    Return the length of the slowlog.",
+    "RESET",
+    "    Reset the slowlog.",
+    "HELP",
+    "    Print this help.",
+];
+
+/// https://redis.io/commands/slowlog-get/
+/// Syntax:
+/// - SLOWLOG GET [count]: the `count` most recent entries, 10 by default and all of them for -1.
+///   Each is `[id, unix time, microseconds, [arg ...], client address, client name]`
+/// - SLOWLOG LEN: how many entries the log holds
+/// - SLOWLOG RESET: empty the log
+/// - SLOWLOG HELP
+#[derive(Debug)]
+pub(crate) enum SlowLogCommand {
+    Get(usize),
+    Len,
+    Reset,
+    Help,
+}
+
+impl SlowLogCommand {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let subcommand = iter.next_string()?.to_lowercase();
+        let command = match subcommand.as_str() {
+            "get" if iter.has_remaining() => {
+                let count = iter.next_string()?;
+                match count.parse::<i64>() {
+                    Ok(-1) => SlowLogCommand::Get(usize::MAX),
+                    Ok(count) if count >= 0 => SlowLogCommand::Get(count as usize),
+                    Ok(_) => return Err("ERR count should be greater than or equal to -1".into()),
+                    Err(_) => return Err("ERR value is not an integer or out of range".into()),
+                }
+            }
+            "get" => SlowLogCommand::Get(DEFAULT_COUNT),
+            "len" => SlowLogCommand::Len,
+            "reset" => SlowLogCommand::Reset,
+            "help" => SlowLogCommand::Help,
+            other => return Err(format!("ERR unknown subcommand '{}'. Try SLOWLOG HELP.", other).into()),
+        };
+
+        Ok(command)
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let frame = match self {
+            SlowLogCommand::Get(count) => Frame::Array(store.slowlog.entries(count)
+                .map(|entry| Frame::Array(vec![
+                    Frame::Integer(entry.id as i64),
+                    Frame::Integer(entry.time as i64),
+                    Frame::Integer(entry.usec as i64),
+                    Frame::Array(entry.args.iter().cloned().map(Frame::Bulk).collect()),
+                    Frame::Bulk(Bytes::from(entry.client.addr.clone())),
+                    Frame::Bulk(Bytes::from(entry.client.name.clone())),
+                ]))
+                .collect()),
+            SlowLogCommand::Len => Frame::Integer(store.slowlog.len() as i64),
+            SlowLogCommand::Reset => {
+                store.slowlog.reset();
+                Frame::ok()
+            }
+            SlowLogCommand::Help => Frame::Array(HELP.iter().map(|line| Frame::Simple(line.to_string())).collect()),
+        };
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::db::{run, run_from, test_config, test_db};
+    use crate::frame::Frame;
+    use crate::slowlog::ClientInfo;
+
+    #[tokio::test]
+    async fn slowlog_test() {
+        let db = test_db(test_config());
+        let mut store = db.lock();
+
+        // every command is slow enough at 0
+        run(&mut store, "CONFIG SET slowlog-log-slower-than 0");
+        run(&mut store, "SET a 1");
+        run(&mut store, "GET a");
+        let client = ClientInfo { addr: "127.0.0.1:50000".to_string(), name: "worker".to_string() };
+        assert!(matches!(run_from(&mut store, "SLOWLOG LEN", &client), Frame::Integer(3)));
+
+        let Frame::Array(entries) = run(&mut store, "SLOWLOG GET 2") else { panic!("SLOWLOG GET replies with an array") };
+        assert_eq!(entries.len(), 2);
+        let Frame::Array(newest) = &entries[0] else { panic!("an entry is an array") };
+        assert!(matches!(&newest[0], Frame::Integer(3)));
+        assert!(matches!(&newest[3], Frame::Array(args) if args.len() == 2));
+        assert!(matches!(&newest[4], Frame::Bulk(addr) if addr == "127.0.0.1:50000"));
+        assert!(matches!(&newest[5], Frame::Bulk(name) if name == "worker"));
+        assert!(matches!(run(&mut store, "SLOWLOG GET -1"), Frame::Array(entries) if entries.len() == 5));
+
+        // a lower max len drops the oldest entries right away
+        run(&mut store, "CONFIG SET slowlog-max-len 1");
+        assert!(matches!(run(&mut store, "SLOWLOG LEN"), Frame::Integer(1)));
+        run(&mut store, "CONFIG SET slowlog-log-slower-than -1");
+        assert!(matches!(run(&mut store, "SLOWLOG RESET"), Frame::Simple(_)));
+        run(&mut store, "GET a");
+        assert!(matches!(run(&mut store, "SLOWLOG LEN"), Frame::Integer(0)));
+
+        assert!(matches!(run(&mut store, "SLOWLOG GET -2"), Frame::Error(e) if e.contains("greater than or equal to -1")));
+        assert!(matches!(run(&mut store, "SLOWLOG NOPE"), Frame::Error(e) if e.contains("SLOWLOG HELP")));
+        assert!(matches!(run(&mut store, "SLOWLOG HELP"), Frame::Array(lines) if lines.len() == 12));
+    }
+}
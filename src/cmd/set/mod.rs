@@ -13,4 +13,6 @@ mod s_rand_member;
 mod s_rem;
 mod s_scan;
 mod s_union;
-mod s_union_store;
\ No newline at end of file
+mod s_union_store;
+
+pub(crate) use s_card::SCard;
@@ -0,0 +1,29 @@
+use crate::db::{RedisDataType, Store};
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/scard/
+/// Syntax: SCARD key
+///
+/// The number of members of the set stored at key, 0 if the key does not exist
+#[derive(Debug)]
+pub(crate) struct SCard(String);
+
+impl SCard {
+    pub(crate) fn key(&self) -> &str {
+        &self.0
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let key = iter.next_string()?;
+        Ok(Self(key))
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        match store.get_data(&self.0) {
+            Some(RedisDataType::Set(set)) => Ok(Frame::Integer(set.len() as i64)),
+            Some(_) => Ok(Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())),
+            None => Ok(Frame::Integer(0)),
+        }
+    }
+}
@@ -28,4 +28,7 @@ mod z_rev_rank;
 mod z_scan;
 mod z_score;
 mod z_union;
-mod z_union_store;
\ No newline at end of file
+mod z_union_store;
+
+pub(crate) use z_add::ZAdd;
+pub(crate) use z_card::ZCard;
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use crate::config::KeyspaceEvents;
+use crate::db::{RedisDataType, Resize, Store};
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/zadd/
+/// Syntax: ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]
+/// - XX: Only update elements that already exist. Don't add new elements
+/// - NX: Only add new elements. Don't update already existing elements
+/// - LT: Only update existing elements if the new score is less than the current score
+/// - GT: Only update existing elements if the new score is greater than the current score
+/// - CH: Count the changed elements in the reply, not only the added ones
+/// - INCR: Act like ZINCRBY, a single score and member, and reply with the new score
+#[derive(Debug)]
+pub(crate) struct ZAdd {
+    key: String,
+    /// Some(false) for NX, Some(true) for XX
+    exists: Option<bool>,
+    /// Some(true) for GT, Some(false) for LT
+    greater: Option<bool>,
+    changed: bool,
+    incr: bool,
+    members: Vec<(f64, Bytes)>,
+}
+
+impl ZAdd {
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let key = iter.next_string()?;
+        let mut zadd = Self { key, exists: None, greater: None, changed: false, incr: false, members: vec![] };
+
+        // 选项都在第一个分数之前
+        let mut arg = iter.next_bytes()?;
+        loop {
+            match String::from_utf8_lossy(&arg).to_uppercase().as_str() {
+                "NX" | "XX" => {
+                    let exists = Some(arg.eq_ignore_ascii_case(b"XX"));
+                    if zadd.exists.is_some_and(|other| Some(other) != exists) {
+                        return Err("ERR XX and NX options at the same time are not compatible".into());
+                    }
+                    zadd.exists = exists;
+                }
+                "GT" => zadd.greater = Some(true),
+                "LT" => zadd.greater = Some(false),
+                "CH" => zadd.changed = true,
+                "INCR" => zadd.incr = true,
+                _ => break,
+            }
+            arg = iter.next_bytes()?;
+        }
+        loop {
+            let member = iter.next_bytes().map_err(|_| FrameError::from("ERR syntax error"))?;
+            zadd.members.push((parse_score(&arg)?, member));
+            if !iter.has_remaining() {
+                break;
+            }
+            arg = iter.next_bytes()?;
+        }
+
+        if zadd.exists == Some(false) && zadd.greater.is_some() {
+            return Err("ERR GT, LT, and/or NX options at the same time are not compatible".into());
+        }
+        if zadd.incr && zadd.members.len() > 1 {
+            return Err("ERR INCR option supports a single increment-element pair".into());
+        }
+        Ok(zadd)
+    }
+
+    /// add or update the members in `set`, the names of the new members go to `resize`
+    fn apply(&self, set: &mut HashMap<Bytes, f64>, resize: &mut Resize) -> Result<Changes, &'static str> {
+        let mut changes = Changes::default();
+        for (score, member) in &self.members {
+            let score = match set.get(member).copied() {
+                Some(_) if self.exists == Some(false) => continue,
+                Some(current) => {
+                    let score = if self.incr { current + score } else { *score };
+                    if score.is_nan() {
+                        return Err("ERR resulting score is not a number (NaN)");
+                    }
+                    match self.greater {
+                        Some(true) if score <= current => continue,
+                        Some(false) if score >= current => continue,
+                        _ => {}
+                    }
+                    if score != current {
+                        set.insert(member.clone(), score);
+                        changes.updated += 1;
+                    }
+                    score
+                }
+                None if self.exists == Some(true) => continue,
+                None => {
+                    set.insert(member.clone(), *score);
+                    resize.add(member.len());
+                    changes.added += 1;
+                    *score
+                }
+            };
+            changes.score = Some(score);
+        }
+        Ok(changes)
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let changes = match store.get_data(&self.key) {
+            Some(RedisDataType::SortedSet(_)) => store.update_data(&self.key, |data, resize| match data {
+                RedisDataType::SortedSet(set) => self.apply(set, resize),
+                _ => Ok(Changes::default()),
+            }).unwrap_or(Ok(Changes::default())),
+            Some(_) => return Ok(Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())),
+            None => {
+                // 一个成员都没有加入时不创建 key
+                let mut set = HashMap::new();
+                let changes = self.apply(&mut set, &mut Resize::default());
+                if !set.is_empty() {
+                    store.set_data(&self.key, RedisDataType::SortedSet(set), None);
+                }
+                changes
+            }
+        };
+        let changes = match changes {
+            Ok(changes) => changes,
+            Err(e) => return Ok(Frame::Error(e.to_string())),
+        };
+
+        if changes.added + changes.updated > 0 {
+            store.notify(KeyspaceEvents::ZSET, if self.incr { "zincr" } else { "zadd" }, &self.key);
+        }
+        Ok(match changes.score {
+            _ if !self.incr && self.changed => Frame::Integer((changes.added + changes.updated) as i64),
+            _ if !self.incr => Frame::Integer(changes.added as i64),
+            Some(score) => Frame::Bulk(Bytes::from(score.to_string())),
+            None => Frame::Null,
+        })
+    }
+}
+
+/// what ZADD did to the sorted set
+#[derive(Debug, Default)]
+struct Changes {
+    added: usize,
+    updated: usize,
+    /// the score of the last member added or updated, the reply of INCR
+    score: Option<f64>,
+}
+
+fn parse_score(arg: &[u8]) -> Result<f64, FrameError> {
+    std::str::from_utf8(arg).ok()
+        .and_then(|score| score.parse::<f64>().ok())
+        .filter(|score| !score.is_nan())
+        .ok_or_else(|| "ERR value is not a valid float".into())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::db::{run, test_config, test_db};
+    use crate::frame::Frame;
+
+    #[tokio::test]
+    async fn zadd_test() {
+        let db = test_db(test_config());
+        let mut store = db.lock();
+
+        assert!(matches!(run(&mut store, "ZADD z XX 1 a"), Frame::Integer(0)));
+        assert!(matches!(run(&mut store, "TYPE z"), Frame::Simple(t) if t == "none"));
+        assert!(matches!(run(&mut store, "ZADD z 1 a 2 b"), Frame::Integer(2)));
+        assert!(matches!(run(&mut store, "ZADD z NX 5 a 3 c"), Frame::Integer(1)));
+        assert!(matches!(run(&mut store, "ZADD z GT CH 0 a 4 b"), Frame::Integer(1)));
+        assert!(matches!(run(&mut store, "ZADD z INCR 1.5 a"), Frame::Bulk(score) if score == "2.5"));
+        assert!(matches!(run(&mut store, "ZADD z LT INCR 1 a"), Frame::Null));
+        assert!(matches!(run(&mut store, "ZADD z INCR -inf a"), Frame::Bulk(score) if score == "-inf"));
+        assert!(matches!(run(&mut store, "ZADD z INCR +inf a"), Frame::Error(e) if e.contains("NaN")));
+        assert!(matches!(run(&mut store, "ZCARD z"), Frame::Integer(3)));
+
+        assert!(matches!(run(&mut store, "ZADD z NX XX 1 a"), Frame::Error(e) if e.contains("not compatible")));
+        assert!(matches!(run(&mut store, "ZADD z NX GT 1 a"), Frame::Error(e) if e.contains("not compatible")));
+        assert!(matches!(run(&mut store, "ZADD z INCR 1 a 2 b"), Frame::Error(e) if e.contains("single increment")));
+        assert!(matches!(run(&mut store, "ZADD z x a"), Frame::Error(e) if e == "ERR value is not a valid float"));
+        assert!(matches!(run(&mut store, "ZADD z 1 a 2"), Frame::Error(e) if e == "ERR syntax error"));
+    }
+}
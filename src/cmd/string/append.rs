@@ -1,5 +1,6 @@
 use bytes::{Bytes, BytesMut};
-use crate::db::{Db, SharedDb};
+use crate::config::KeyspaceEvents;
+use crate::db::{RedisDataType, Store};
 use crate::frame::{Frame, FrameError, FrameIter};
 use crate::RedisResult;
 
@@ -10,6 +11,10 @@ pub(crate) struct Append {
 }
 
 impl Append {
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
     pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
         let key = iter.next_string()?;
         let value = iter.next_bytes()?;
@@ -20,8 +25,10 @@ impl Append {
         })
     }
 
-    pub(crate) async fn execute(self, db: &SharedDb) -> RedisResult<Frame> {
-        let mut store = db.lock();
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        if store.peek_data(&self.key).is_some_and(|data| !matches!(data, RedisDataType::Bytes(_))) {
+            return Ok(Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()));
+        }
         if let Some(data) = store.get_bytes(self.key.clone()) {
 
             // 两个拼接
@@ -32,6 +39,7 @@ impl Append {
 
             let data = merged_data.freeze();
             store.update_bytes(&self.key, data);
+            store.notify(KeyspaceEvents::STRING, "append", &self.key);
         }
         Ok(Frame::ok())
     }
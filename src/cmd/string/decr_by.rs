@@ -1,5 +1,6 @@
 use bytes::Bytes;
-use crate::db::SharedDb;
+use crate::config::KeyspaceEvents;
+use crate::db::{RedisDataType, Store};
 use crate::frame::{Frame, FrameError, FrameIter};
 use crate::RedisResult;
 
@@ -7,42 +8,132 @@ use crate::RedisResult;
 pub(crate) struct DecrBy {
     key: String,
     decrement: i64,
+    /// the command that was parsed, in lower case: incr, incrby, decr or decrby
+    command: &'static str,
 }
 
 impl DecrBy {
-    pub(crate) fn parse_frames(iter: &mut FrameIter, positive: bool) -> Result<Self, FrameError> {
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter, command: &'static str) -> Result<Self, FrameError> {
         let key = iter.next_string()?;
 
         let mut decrement = 1;
         if (iter.has_remaining()) {
             decrement = iter.next_int()?;
         }
-        if !positive {
-            decrement = -decrement;
+        if command.starts_with("incr") {
+            decrement = decrement.checked_neg().ok_or_else(overflow)?;
         }
         Ok(Self {
             key,
             decrement,
+            command,
         })
     }
 
-    pub(crate) async fn execute(self, db: &SharedDb) -> RedisResult<Frame> {
-        let mut store = db.lock();
+    /// INCR and INCRBY publish `incrby`, DECR and DECRBY publish `decrby`, whatever the sign
+    /// of the increment
+    fn event(&self) -> &'static str {
+        if self.command.starts_with("incr") { "incrby" } else { "decrby" }
+    }
 
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        // get_bytes 对其他类型返回 None，不检查的话列表之类的值会被数字覆盖
+        if store.peek_data(&self.key).is_some_and(|data| !matches!(data, RedisDataType::Bytes(_))) {
+            return Ok(Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()));
+        }
         if let Some(data) = store.get_bytes(&self.key) {
-            let mut number = atoi::atoi::<i64>(&data).ok_or(0).unwrap();
-            number -= self.decrement;
+            let number = match atoi::atoi::<i64>(&data) {
+                Some(number) => number,
+                None => return Ok(Frame::Error("ERR value is not an integer or out of range".to_string())),
+            };
+            let Some(number) = number.checked_sub(self.decrement) else {
+                return Ok(overflow().into());
+            };
 
             // 转换成int操作后，更新
             store.update_bytes(&self.key, Bytes::from(number.to_string()));
+            store.notify(KeyspaceEvents::STRING, self.event(), &self.key);
             return Ok(Frame::Integer(number));
         } else {
-            let mut number = 0;
-            number -= self.decrement;
+            let Some(number) = 0i64.checked_sub(self.decrement) else {
+                return Ok(overflow().into());
+            };
             //新建一个，
             store.set_bytes(&self.key, Bytes::from(number.to_string()), None);
+            store.notify(KeyspaceEvents::STRING, self.event(), &self.key);
 
             return Ok(Frame::Integer(number));
         }
     }
-}
\ No newline at end of file
+}
+
+fn overflow() -> FrameError {
+    "ERR increment or decrement would overflow".into()
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use crate::codec::RedisFrame;
+    use crate::config::Config;
+    use crate::db::{run, test_config, test_db};
+    use crate::frame::Frame;
+    use crate::pub_sub::{Push, Subscriber};
+
+    #[tokio::test]
+    async fn event_test() {
+        let db = test_db(Config { notify_keyspace_events: "E$".parse().unwrap(), ..test_config() });
+        let (subscriber, mut receiver) = Subscriber::new(1, 0);
+        db.pub_sub().psubscribe(Bytes::from("__keyevent@0__:*"), &subscriber);
+
+        // the event follows the command, not the sign of the increment
+        for (args, event) in [("INCR n", "incrby"), ("INCRBY n -5", "incrby"), ("DECR n", "decrby"), ("DECRBY n -5", "decrby")] {
+            run(&mut db.lock(), args);
+            match receiver.recv().await {
+                Some(Push::Message(Frame::Array(message), _)) => {
+                    assert!(matches!(&message[2], Frame::Bulk(channel) if channel == &format!("__keyevent@0__:{}", event)), "{}", args)
+                }
+                other => panic!("unexpected push {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn overflow_test() {
+        let db = test_db(test_config());
+        let run = |args: &str| RedisFrame::from(run(&mut db.lock(), args));
+        let overflow = RedisFrame::Error("ERR increment or decrement would overflow".to_string());
+
+        run("SET n 1");
+        assert_eq!(run("INCRBY n 9223372036854775807"), overflow);
+        assert_eq!(run("DECRBY n -9223372036854775808"), overflow);
+        assert_eq!(run("INCRBY n -9223372036854775808"), overflow);
+        assert_eq!(run("GET n"), RedisFrame::Bulk(Bytes::from("1")));
+
+        run("SET n -9223372036854775808");
+        assert_eq!(run("DECR n"), overflow);
+        assert_eq!(run("DECRBY missing -9223372036854775808"), overflow);
+        assert_eq!(run("TYPE missing"), RedisFrame::Simple("none".to_string()));
+        assert_eq!(run("INCRBY n 9223372036854775807"), RedisFrame::Integer(-1));
+    }
+
+    #[tokio::test]
+    async fn wrong_type_test() {
+        let db = test_db(test_config());
+        let run = |args: &str| RedisFrame::from(run(&mut db.lock(), args));
+        let wrong_type = RedisFrame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string());
+
+        run("LPUSH list a");
+        run("HSET hash f v");
+        for args in ["INCR list", "DECRBY list 2", "GET hash", "APPEND hash x", "INCRBY hash 1"] {
+            assert_eq!(run(args), wrong_type, "{}", args);
+        }
+        assert_eq!(run("TYPE list"), RedisFrame::Simple("list".to_string()));
+        assert_eq!(run("TYPE hash"), RedisFrame::Simple("hash".to_string()));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::db::Db;
+use crate::db::Store;
 use crate::frame::{Frame, FrameError, FrameIter};
 use crate::RedisResult;
 
@@ -7,6 +7,10 @@ pub(crate) struct Del(Vec<String>);
 
 
 impl Del {
+    pub(crate) fn keys(&self) -> &[String] {
+        &self.0
+    }
+
     pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
         let mut vec = vec![];
         while let Ok(key) = iter.next_string() {
@@ -16,9 +20,8 @@ impl Del {
         Ok(Self(vec))
     }
 
-    pub(crate) async fn execute(self, db: &Db) -> RedisResult<Frame> {
-        let mut store = db.lock();
-        store.remove_vec(&self.0);
-        Ok(Frame::Simple("OK".to_string()))
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        let count = store.remove_vec(&self.0);
+        Ok(Frame::Integer(count as i64))
     }
 }
\ No newline at end of file
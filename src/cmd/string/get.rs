@@ -1,5 +1,5 @@
 use bytes::Bytes;
-use crate::db::SharedDb;
+use crate::db::{RedisDataType, Store};
 use crate::frame::{Frame, FrameError, FrameIter};
 use crate::RedisResult;
 
@@ -7,13 +7,19 @@ use crate::RedisResult;
 pub(crate) struct Get(String);
 
 impl Get {
+    pub(crate) fn key(&self) -> &str {
+        &self.0
+    }
+
     pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
         let key = iter.next_string()?;
         Ok(Self(key))
     }
 
-    pub(crate) async fn execute(self, db: &SharedDb) -> RedisResult<Frame> {
-        let mut shared_db = db.lock();
+    pub(crate) fn execute(self, shared_db: &mut Store) -> RedisResult<Frame> {
+        if shared_db.peek_data(&self.0).is_some_and(|data| !matches!(data, RedisDataType::Bytes(_))) {
+            return Ok(Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string()));
+        }
         if let Some(data) = shared_db.get_bytes(self.0.as_str()) {
             return Ok(Frame::Bulk(data));
         }
@@ -1,5 +1,5 @@
 use bytes::Bytes;
-use crate::db::SharedDb;
+use crate::db::Store;
 use crate::frame::{Frame, FrameError, FrameIter};
 use crate::RedisResult;
 
@@ -12,9 +12,7 @@ impl GetDel {
         Ok(Self(key))
     }
 
-    pub async fn execute(self, db: &SharedDb) -> RedisResult<Frame> {
-        let mut store = db.lock();
-
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
         let data = store.remove(&self.0);
         if let Some(data) = data {
             return Ok(Frame::Bulk(data));
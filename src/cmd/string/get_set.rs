@@ -1,5 +1,5 @@
 use bytes::Bytes;
-use crate::db::SharedDb;
+use crate::db::Store;
 use crate::frame::{Frame, FrameError, FrameIter};
 use crate::RedisResult;
 
@@ -19,8 +19,7 @@ impl GetAndSet {
         })
     }
 
-    pub(crate) async fn execute(self, db: &SharedDb) -> RedisResult<Frame> {
-        let mut shared_db = db.lock();
+    pub(crate) fn execute(self, shared_db: &mut Store) -> RedisResult<Frame> {
         // if let Some(data) = shared_db.get_bytes(self.0.as_str()) {
         //     shared_db.update_bytes(self.key, self.value);
         //     return Ok(Frame::Bulk(data));
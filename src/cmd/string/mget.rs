@@ -1,4 +1,4 @@
-use crate::db::SharedDb;
+use crate::db::Store;
 use crate::frame::{Frame, FrameError, FrameIter};
 use crate::RedisResult;
 
@@ -8,6 +8,10 @@ pub(crate) struct MultiGet {
 }
 
 impl MultiGet {
+    pub(crate) fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
     pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
         let mut keys = vec![];
 
@@ -20,9 +24,7 @@ impl MultiGet {
         })
     }
 
-    pub(crate) async fn execute(self, db: &SharedDb) -> RedisResult<Frame> {
-        let store = db.lock();
-
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
         let mut vec = vec![];
         for key in self.keys {
             if let Some(data) = store.get_bytes(key) {
@@ -10,6 +10,9 @@ mod set_nx;
 mod set_range;
 mod sub_str;
 mod str_len;
+
+pub(crate) use str_len::StrLen;
+
 mod get;
 
 pub(crate) use get::Get;
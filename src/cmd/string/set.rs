@@ -1,7 +1,7 @@
-use std::time::Duration;
 use bytes::Bytes;
-use tokio::time::Instant;
-use crate::db::SharedDb;
+use crate::clock::{unix_ms, unix_ms_to_instant};
+use crate::config::KeyspaceEvents;
+use crate::db::{RedisDataType, Store};
 use crate::frame::{Frame, FrameError, FrameIter};
 use crate::RedisResult;
 
@@ -16,24 +16,39 @@ use crate::RedisResult;
 /// - XX: Only set the key if it already exist
 /// - KEEPTTL: Retain the time to live associated with the key
 /// - GET: Return the old string stored at key, or nil if key did not exits. An error is returned and `SET` aborted if the value stored at key is not a string
+///
+/// Relative expiries are turned into an absolute unix time when parsed, so the command
+/// is written to the AOF with `PXAT` and replays to the same deadline
 #[derive(Debug)]
 pub(crate) struct Set {
     key: String,
     value: Bytes,
-    key_exists: bool,
+    /// Some(false) for NX, Some(true) for XX
+    key_exists: Option<bool>,
     get: bool,
-    expire_at: Option<Instant>,
+    expire: Option<Expire>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Expire {
+    /// unix time in milliseconds
+    At(u64),
+    KeepTtl,
 }
 
 
 impl Set {
+    pub(crate) fn key(&self) -> &str {
+        &self.key
+    }
+
     fn new(key: String, value: Bytes) -> Self {
         Self {
             key,
             value,
-            key_exists: false,
+            key_exists: None,
             get: false,
-            expire_at: None,
+            expire: None,
         }
     }
     pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
@@ -46,67 +61,90 @@ impl Set {
 
         let mut set = Self::new(key, value);
 
-        while let Ok(keyword) = iter.next_string() {
-            match keyword.to_uppercase().as_str() {
-                "EX" => {
-                    let secs = iter.next_int()?;
-
-                    let expire_at = Instant::now() + Duration::from_secs(secs as u64);
-                    set.expire_at = Some(expire_at);
-                }
-                "PX" => {
-                    let milli_secs = iter.next_int()?;
-
-                    let expire_at = Instant::now() + Duration::from_millis(milli_secs as u64);
-                    set.expire_at = Some(expire_at);
-                }
-                "EXAT" => {
-                    let expire_secs_timestamp = iter.next_int()? as u64;
-                }
-                "PXAT" => {
-                    let expire_millisecs_timestamp = iter.next_int()? as u64;
+        while iter.has_remaining() {
+            let keyword = iter.next_string()?.to_uppercase();
+            match keyword.as_str() {
+                "EX" | "PX" | "EXAT" | "PXAT" => {
+                    if set.expire.is_some() {
+                        return Err("ERR syntax error".into());
+                    }
+                    let time = iter.next_int().map_err(|_| FrameError::from("ERR value is not an integer or out of range"))?;
+                    let invalid = || FrameError::from("ERR invalid expire time in 'set' command");
+                    if time <= 0 {
+                        return Err(invalid());
+                    }
+                    let time = time as u64;
+                    // 过大的时间会溢出, 和 Redis 一样按非法的过期时间处理
+                    let at = match keyword.as_str() {
+                        "EX" => time.checked_mul(1000).and_then(|ms| ms.checked_add(unix_ms())),
+                        "PX" => time.checked_add(unix_ms()),
+                        "EXAT" => time.checked_mul(1000),
+                        _ => Some(time),
+                    };
+                    set.expire = Some(Expire::At(at.filter(|&at| at <= i64::MAX as u64).ok_or_else(invalid)?));
                 }
-                "NX" => {
-                    set.key_exists = false;
+                "KEEPTTL" => {
+                    if set.expire.is_some() {
+                        return Err("ERR syntax error".into());
+                    }
+                    set.expire = Some(Expire::KeepTtl);
                 }
-                "XX" => {
-                    set.key_exists = true;
+                "NX" | "XX" => {
+                    if set.key_exists.is_some() {
+                        return Err("ERR syntax error".into());
+                    }
+                    set.key_exists = Some(keyword == "XX");
                 }
                 "GET" => {
                     set.get = true;
                 }
-
-                "KEEPTTL" => {}
-                others => {
-                    return Err(format!("unhandle keyword:{}", others).into());
-                }
+                _ => return Err("ERR syntax error".into()),
             }
         }
 
+        Ok(set)
+    }
 
-        let expire = match iter.next_string() {
-            Ok(s) if s.to_uppercase() == "EX" => {
-                let secs = iter.next_int()?;
-                Some(Duration::from_secs(secs as u64))
-            }
-            Ok(s) if s.to_uppercase() == "PX" => {
-                let milli_secs = iter.next_int()?;
-                Some(Duration::from_millis(milli_secs as u64))
-            }
-            Ok(_) => return Err("currently `SET` only supports the expiration option".into()),
-            Err(FrameError::EndOfStream) => None,
-            Err(e) => return Err(e.into())
+    pub(crate) fn execute(self, shared: &mut Store) -> RedisResult<Frame> {
+        let old = match shared.get_data(&self.key) {
+            None => None,
+            Some(RedisDataType::Bytes(data)) => Some(data.clone()),
+            Some(_) if self.get => return Ok(Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())),
+            Some(_) => Some(Bytes::new()),
         };
+        let reply = |old: Option<Bytes>| if self.get { old.map_or(Frame::Null, Frame::Bulk) } else { Frame::ok() };
 
-        Ok(set)
-    }
-    pub async fn execute(self, db: &SharedDb) -> RedisResult<Frame> {
-        let mut shared = db.lock();
+        if let Some(key_exists) = self.key_exists {
+            if old.is_some() != key_exists {
+                return Ok(if self.get { reply(old) } else { Frame::Null });
+            }
+        }
 
+        let expire_at = match self.expire {
+            Some(Expire::At(at)) => Some(unix_ms_to_instant(at)),
+            Some(Expire::KeepTtl) => shared.expire_at(&self.key),
+            None => None,
+        };
+        shared.set_bytes(&self.key, self.value.clone(), expire_at);
+        shared.notify(KeyspaceEvents::STRING, "set", &self.key);
 
-        shared.set_bytes(self.key, self.value, None);
+        Ok(reply(old))
+    }
 
-        Ok(Frame::ok())
+    /// the command as it is written to the AOF: GET is dropped and expiries are absolute
+    pub(crate) fn propagate(&self) -> Vec<Bytes> {
+        let mut argv = vec![Bytes::from_static(b"SET"), Bytes::from(self.key.clone()), self.value.clone()];
+        match self.key_exists {
+            Some(false) => argv.push(Bytes::from_static(b"NX")),
+            Some(true) => argv.push(Bytes::from_static(b"XX")),
+            None => {}
+        }
+        match self.expire {
+            Some(Expire::At(at)) => argv.extend([Bytes::from_static(b"PXAT"), Bytes::from(at.to_string())]),
+            Some(Expire::KeepTtl) => argv.push(Bytes::from_static(b"KEEPTTL")),
+            None => {}
+        }
+        argv
     }
 }
 
@@ -114,17 +152,63 @@ impl Set {
 #[cfg(test)]
 mod test {
     use bytes::Bytes;
-    use tokio::sync::broadcast;
     use crate::cmd::string::Set;
-    use crate::db::{Db, SharedDb};
+    use crate::db::{SharedDb, test_config, test_db};
+    use crate::frame::{Frame, FrameIter};
 
     fn init_db() -> SharedDb {
-        let (sender, _) = broadcast::channel(1);
-        Db::new(sender.subscribe())
+        test_db(test_config())
+    }
+
+    fn parse(args: &str) -> Set {
+        let frames = args.split(' ').map(|arg| Frame::Bulk(Bytes::from(arg.to_string()))).collect();
+        Set::parse_frames(&mut FrameIter::new(frames)).unwrap()
     }
 
     #[tokio::test]
     async fn set_test() {
         let db = init_db();
+        let mut store = db.lock();
+
+        assert!(matches!(parse("foo 1 XX").execute(&mut store).unwrap(), Frame::Null));
+        assert!(matches!(parse("foo 1 NX GET").execute(&mut store).unwrap(), Frame::Null));
+        assert!(matches!(parse("foo 2 NX").execute(&mut store).unwrap(), Frame::Null));
+        assert!(matches!(parse("foo 3 GET EX 100").execute(&mut store).unwrap(), Frame::Bulk(old) if old == "1"));
+        assert!(store.expire_at("foo").is_some());
+
+        parse("foo 4 KEEPTTL").execute(&mut store).unwrap();
+        assert!(store.expire_at("foo").is_some());
+        parse("foo 5").execute(&mut store).unwrap();
+        assert!(store.expire_at("foo").is_none());
+        assert_eq!(store.get_bytes("foo"), Some(Bytes::from("5")));
+    }
+
+    #[test]
+    fn invalid_expire_test() {
+        let parse_err = |args: &str| {
+            let frames = args.split(' ').map(|arg| Frame::Bulk(Bytes::from(arg.to_string()))).collect();
+            Set::parse_frames(&mut FrameIter::new(frames)).unwrap_err().to_string()
+        };
+        for args in ["foo bar EX 0", "foo bar PX -1", "foo bar EX 9223372036854775807",
+            "foo bar EXAT 9223372036854775807", "foo bar PX 9223372036854775807"] {
+            assert_eq!(parse_err(args), "ERR invalid expire time in 'set' command", "{}", args);
+        }
+    }
+
+    #[tokio::test]
+    async fn far_expire_test() {
+        let db = init_db();
+        let mut store = db.lock();
+        parse("foo bar PXAT 9223372036854775807").execute(&mut store).unwrap();
+        parse("baz bar EX 9223372036854").execute(&mut store).unwrap();
+        assert_eq!(store.get_bytes("foo"), Some(Bytes::from("bar")));
+        assert_eq!(store.get_bytes("baz"), Some(Bytes::from("bar")));
+    }
+
+    #[test]
+    fn propagate_test() {
+        let argv = parse("foo bar EX 10 GET").propagate();
+        assert_eq!(argv[..4], [Bytes::from("SET"), Bytes::from("foo"), Bytes::from("bar"), Bytes::from("PXAT")]);
+        assert_eq!(argv.len(), 5);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,29 @@
+use crate::db::{RedisDataType, Store};
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::RedisResult;
+
+/// https://redis.io/commands/strlen/
+/// Syntax: STRLEN key
+///
+/// The length in bytes of the string stored at key, 0 if the key does not exist
+#[derive(Debug)]
+pub(crate) struct StrLen(String);
+
+impl StrLen {
+    pub(crate) fn key(&self) -> &str {
+        &self.0
+    }
+
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let key = iter.next_string()?;
+        Ok(Self(key))
+    }
+
+    pub(crate) fn execute(self, store: &mut Store) -> RedisResult<Frame> {
+        match store.get_data(&self.0) {
+            Some(RedisDataType::Bytes(value)) => Ok(Frame::Integer(value.len() as i64)),
+            Some(_) => Ok(Frame::Error("WRONGTYPE Operation against a key holding the wrong kind of value".to_string())),
+            None => Ok(Frame::Integer(0)),
+        }
+    }
+}
@@ -0,0 +1,3 @@
+mod watch;
+
+pub(crate) use watch::Watch;
@@ -0,0 +1,21 @@
+use crate::frame::{FrameError, FrameIter};
+
+/// https://redis.io/commands/watch/
+/// Syntax: WATCH key [key ...]
+/// Marks the keys for optimistic locking: the next `EXEC` of this connection is aborted
+/// if any of them was written, deleted or expired in the meantime
+#[derive(Debug)]
+pub(crate) struct Watch {
+    pub(crate) keys: Vec<String>,
+}
+
+impl Watch {
+    pub(crate) fn parse_frames(iter: &mut FrameIter) -> Result<Self, FrameError> {
+        let mut keys = vec![iter.next_string()?];
+        while iter.has_remaining() {
+            keys.push(iter.next_string()?);
+        }
+
+        Ok(Self { keys })
+    }
+}
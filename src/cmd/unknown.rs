@@ -12,7 +12,7 @@ impl Unknown {
             cmd
         }
     }
-    pub(crate) async fn execute(self) -> RedisResult<Frame> {
-        Ok(Frame::Error("Unknown Command".to_string()))
+    pub(crate) fn execute(self) -> RedisResult<Frame> {
+        Ok(Frame::Error(format!("ERR unknown command '{}'", self.cmd)))
     }
 }
\ No newline at end of file
@@ -1,8 +1,10 @@
-use std::io::ErrorKind;
+use std::io::{Cursor, ErrorKind};
 
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use tokio_util::codec::{Decoder, Encoder};
 
+use crate::frame::{check, FrameError};
+
 pub struct LineCodec;
 
 impl Decoder for LineCodec {
@@ -36,12 +38,16 @@ impl Encoder<String> for LineCodec {
 
 pub struct RedisCodec;
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum RedisFrame {
     Simple(String),
     Error(String),
     Integer(i64),
     Bulk(Bytes),
     Array(Vec<RedisFrame>),
+    Null,
+    /// `*-1`, the reply of an EXEC aborted by WATCH
+    NullArray,
 }
 
 impl Decoder for RedisCodec {
@@ -52,6 +58,22 @@ impl Decoder for RedisCodec {
         if !src.has_remaining() {
             return Ok(None);
         }
+
+        // 先检查是否收到了一个完整的frame, 不完整就等待更多的数据
+        let mut cursor = Cursor::new(&src[..]);
+        match check(&mut cursor) {
+            Ok(()) => {}
+            Err(FrameError::Incomplete) => return Ok(None),
+            Err(e) => return Err(std::io::Error::new(ErrorKind::InvalidData, e.to_string())),
+        }
+
+        Self::decode_frame(src).map(Some)
+    }
+}
+
+impl RedisCodec {
+    /// parse one frame that `check` already found to be complete
+    fn decode_frame(src: &mut BytesMut) -> Result<RedisFrame, std::io::Error> {
         let frame = match src.get_u8() {
             b'+' => {
                 RedisFrame::Simple(Self::get_line(src)?)
@@ -63,7 +85,11 @@ impl Decoder for RedisCodec {
                 RedisFrame::Integer(Self::get_decimal(src)?)
             }
             b'$' => {
-                let len = Self::get_decimal(src)? as usize;
+                let len = Self::get_decimal(src)?;
+                if len < 0 {
+                    return Ok(RedisFrame::Null);
+                }
+                let len = len as usize;
 
                 let slice = Bytes::copy_from_slice(&src.chunk()[..len]);
                 src.advance(len);
@@ -76,15 +102,14 @@ impl Decoder for RedisCodec {
                 RedisFrame::Bulk(slice)
             }
             b'*' => {
-                let len = Self::get_decimal(src)? as usize;
+                let len = Self::get_decimal(src)?;
+                if len < 0 {
+                    return Ok(RedisFrame::NullArray);
+                }
 
                 let mut vec = vec![];
                 for _ in 0..len {
-                    if let Ok(Some(frame)) = self.decode(src) {
-                        vec.push(frame);
-                    } else {
-                        return Err(std::io::Error::new(ErrorKind::InvalidData, "error"));
-                    }
+                    vec.push(Self::decode_frame(src)?);
                 }
 
                 RedisFrame::Array(vec)
@@ -93,7 +118,7 @@ impl Decoder for RedisCodec {
                 return Err(std::io::Error::new(ErrorKind::InvalidData, format!("redis command not find start with {} ", other)));
             }
         };
-        Ok(Some(frame))
+        Ok(frame)
     }
 }
 
@@ -108,7 +133,7 @@ impl Encoder<RedisFrame> for RedisCodec {
                 dst.put_slice("\r\n".as_bytes());
             }
             RedisFrame::Error(error) => {
-                dst.put_u8(b':');
+                dst.put_u8(b'-');
                 dst.put_slice(error.as_bytes());
                 dst.put_slice("\r\n".as_bytes());
             }
@@ -141,7 +166,12 @@ impl Encoder<RedisFrame> for RedisCodec {
                 for frame in array {
                     self.encode(frame, dst)?;
                 }
-                dst.put_slice("\r\n".as_bytes());
+            }
+            RedisFrame::Null => {
+                dst.put_slice("$-1\r\n".as_bytes());
+            }
+            RedisFrame::NullArray => {
+                dst.put_slice("*-1\r\n".as_bytes());
             }
         }
 
@@ -197,6 +227,33 @@ mod test {
         let bytes = codec.encode(frame, &mut stream);
 
         let frame2 = codec.decode(&mut stream).unwrap().unwrap();
+        assert!(matches!(frame2, RedisFrame::Array(ref array) if array.len() == 2));
+        assert!(stream.is_empty());
         println!();
     }
+
+    #[test]
+    fn decode_partial_test() {
+        let mut codec = RedisCodec;
+        let mut stream = BytesMut::from(&b"*2\r\n$3\r\nGET\r\n$3\r\nfo"[..]);
+
+        assert!(codec.decode(&mut stream).unwrap().is_none());
+
+        stream.extend_from_slice(b"o\r\n*1\r\n$4\r\nPING\r\n");
+        assert!(matches!(codec.decode(&mut stream).unwrap(), Some(RedisFrame::Array(ref array)) if array.len() == 2));
+        assert!(matches!(codec.decode(&mut stream).unwrap(), Some(RedisFrame::Array(ref array)) if array.len() == 1));
+        assert!(codec.decode(&mut stream).unwrap().is_none());
+    }
+
+    #[test]
+    fn null_test() {
+        let mut codec = RedisCodec;
+        let mut stream = BytesMut::new();
+        codec.encode(RedisFrame::Null, &mut stream).unwrap();
+        codec.encode(RedisFrame::NullArray, &mut stream).unwrap();
+        assert_eq!(&stream[..], b"$-1\r\n*-1\r\n");
+
+        assert_eq!(codec.decode(&mut stream).unwrap(), Some(RedisFrame::Null));
+        assert_eq!(codec.decode(&mut stream).unwrap(), Some(RedisFrame::NullArray));
+    }
 }
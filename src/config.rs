@@ -0,0 +1,402 @@
+use std::fmt::{Display, Formatter, Write};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::pattern::glob_match;
+
+mod file;
+pub(crate) mod params;
+
+/// Server tunables.
+///
+/// The names follow the matching `redis.conf` directives with `-` replaced by `_`.
+/// `Config::from_args` reads them from a config file and the command line, and CONFIG GET
+/// and SET see them through `Config::get` and `Config::set`
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// the addresses to listen on, only the first one for now. A leading `-` marks an address
+    /// that may be unavailable, `*` is every IPv4 address
+    pub bind: Vec<String>,
+    pub port: u16,
+    /// client connections open at once, any more are refused
+    pub maxclients: usize,
+    /// commands running for this many microseconds or more go to the slow log,
+    /// 0 logs every command and a negative value none
+    pub slowlog_log_slower_than: i64,
+    /// entries the slow log keeps, the oldest are dropped
+    pub slowlog_max_len: usize,
+    /// serve Prometheus metrics over HTTP at `/metrics` on this port of the first bind
+    /// address. Not a Redis directive, 0 disables it
+    pub metrics_port: u16,
+    /// free large values on a background thread when a user runs `DEL`
+    pub lazyfree_lazy_user_del: bool,
+    /// free large values on a background thread when a key expires
+    pub lazyfree_lazy_expire: bool,
+    /// free large values on a background thread when they are overwritten
+    pub lazyfree_lazy_server_del: bool,
+    /// make `FLUSHDB`/`FLUSHALL` without an explicit modifier behave like `ASYNC`
+    pub lazyfree_lazy_user_flush: bool,
+    /// bytes of pub/sub messages a subscriber may have waiting to be written
+    /// before it is disconnected, the hard limit of `client-output-buffer-limit pubsub`
+    pub client_output_buffer_limit_pubsub: usize,
+    /// which keyspace events are published, see [`KeyspaceEvents`]
+    pub notify_keyspace_events: KeyspaceEvents,
+    /// the working directory, where the RDB file is written
+    pub dir: PathBuf,
+    /// the name of the RDB file
+    pub dbfilename: String,
+    /// `save <seconds> <changes>` rules: BGSAVE once at least `changes` writes happened
+    /// and `seconds` passed since the last save. Empty disables automatic snapshots
+    pub save: Vec<(u64, u64)>,
+    /// log every write to the append only file and replay it at startup instead of the RDB file
+    pub appendonly: bool,
+    /// the prefix of the files the append only file is made of
+    pub appendfilename: String,
+    /// the directory in `dir` holding the files of the append only file and its manifest
+    pub appenddirname: String,
+    /// when the append only file is flushed to disk, see [`AppendFsync`]
+    pub appendfsync: AppendFsync,
+    /// load an append only file whose last command was cut short by a crash,
+    /// dropping that command, instead of refusing to start
+    pub aof_load_truncated: bool,
+    /// start even if the AOF manifest lists files that are missing or a file other than the
+    /// last one is truncated, dropping what cannot be read. Not a Redis directive, Redis
+    /// leaves this to `redis-check-aof --fix`
+    pub aof_load_repair: bool,
+    /// follow this master at startup, `replicaof <host> <port>`
+    pub replicaof: Option<(String, u16)>,
+    /// reject writes from clients while this server is a replica
+    pub replica_read_only: bool,
+    /// bytes of the replication stream kept for replicas that reconnect and resync partially
+    pub repl_backlog_size: usize,
+    /// serve only the hash slots this node owns and redirect clients for the others
+    pub cluster_enabled: bool,
+    /// where the node saves its ID and its view of the cluster, in `dir`
+    pub cluster_config_file: String,
+    /// milliseconds a node may not answer before it is flagged as failing
+    pub cluster_node_timeout: u64,
+    /// bytes the keyspace may take, as estimated by the server, before keys are evicted
+    /// or writes refused. 0 for no limit
+    pub maxmemory: usize,
+    /// which keys are evicted once `maxmemory` is reached, see [`MaxmemoryPolicy`]
+    pub maxmemory_policy: MaxmemoryPolicy,
+    /// keys sampled for each eviction. More gets closer to a true LRU or LFU but costs more
+    pub maxmemory_samples: usize,
+    /// how many hits it takes to saturate the LFU counter of a key, higher is slower
+    pub lfu_log_factor: u32,
+    /// minutes after which the LFU counter of a key that was not accessed is decremented
+    pub lfu_decay_time: u64,
+    /// free evicted values on a background thread
+    pub lazyfree_lazy_eviction: bool,
+    /// the file the config was read from, which CONFIG REWRITE writes. Not a directive
+    pub config_file: Option<PathBuf>,
+}
+
+impl Config {
+    /// where SAVE and BGSAVE write the snapshot and where it is loaded from at startup
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    /// the directory of the multi-part append only file
+    pub fn aof_dir(&self) -> PathBuf {
+        self.dir.join(&self.appenddirname)
+    }
+
+    /// the config of `server [/path/to/redis.conf] [--name arg ...]`, like redis-server:
+    /// the defaults, then the config file, then the options of the command line
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Config, String> {
+        let args: Vec<String> = args.into_iter().collect();
+        let mut loader = file::Loader::new();
+        let options = match args.split_first() {
+            Some((path, options)) if !path.starts_with("--") => {
+                loader.file(Path::new(path), 0)?;
+                loader.config.config_file = Some(PathBuf::from(path));
+                options
+            }
+            _ => &args[..],
+        };
+        loader.args(options)?;
+        Ok(loader.config)
+    }
+
+    /// the defaults, then the directives of the config file at `path`
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Config, String> {
+        Config::from_args([path.as_ref().display().to_string()])
+    }
+
+    /// the value of a parameter as CONFIG GET shows it, `None` for an unknown name
+    pub fn get(&self, name: &str) -> Option<String> {
+        params::find(name).map(|param| (param.get)(self))
+    }
+
+    /// the parameters whose name matches the glob `pattern`, with their values, by name
+    pub fn matching(&self, pattern: &str) -> Vec<(&'static str, String)> {
+        let pattern = pattern.to_lowercase();
+        let mut matching: Vec<_> = params::PARAMS.iter()
+            .filter(|param| glob_match(pattern.as_bytes(), param.name.as_bytes()))
+            .map(|param| (param.name, (param.get)(self)))
+            .collect();
+        // 不带通配符的旧名字也能查到
+        if matching.is_empty() {
+            matching.extend(params::find(&pattern).map(|param| (param.name, (param.get)(self))));
+        }
+        matching.sort_by_key(|(name, _)| *name);
+        matching
+    }
+
+    /// change a parameter, the way a config file line `name value` does. Whether a running
+    /// server can apply the change is up to CONFIG SET
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let param = params::find(name).ok_or_else(|| format!("unknown parameter '{}'", name))?;
+        (param.set)(self, value)
+    }
+
+    /// CONFIG REWRITE: write the current values to `config_file`, keeping its comments
+    pub fn rewrite(&self) -> io::Result<()> {
+        let path = self.config_file.as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "The server is running without a config file"))?;
+        file::rewrite(self, path)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: vec!["127.0.0.1".to_string()],
+            port: 6379,
+            maxclients: 10000,
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+            metrics_port: 0,
+            lazyfree_lazy_user_del: false,
+            lazyfree_lazy_expire: false,
+            lazyfree_lazy_server_del: false,
+            lazyfree_lazy_user_flush: false,
+            client_output_buffer_limit_pubsub: 32 * 1024 * 1024,
+            notify_keyspace_events: KeyspaceEvents::default(),
+            dir: PathBuf::from("."),
+            dbfilename: "dump.rdb".to_string(),
+            save: vec![(3600, 1), (300, 100), (60, 10000)],
+            appendonly: false,
+            appendfilename: "appendonly.aof".to_string(),
+            appenddirname: "appendonlydir".to_string(),
+            appendfsync: AppendFsync::EverySec,
+            aof_load_truncated: true,
+            aof_load_repair: false,
+            replicaof: None,
+            replica_read_only: true,
+            repl_backlog_size: 1024 * 1024,
+            cluster_enabled: false,
+            cluster_config_file: "nodes.conf".to_string(),
+            cluster_node_timeout: 15000,
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+            lazyfree_lazy_eviction: false,
+            config_file: None,
+        }
+    }
+}
+
+/// The `appendfsync` policy
+/// - always: fsync after every write, before the client gets its reply
+/// - everysec: fsync once per second, at most a second of writes is lost
+/// - no: leave it to the operating system
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AppendFsync {
+    Always,
+    #[default]
+    EverySec,
+    No,
+}
+
+impl FromStr for AppendFsync {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "always" => Ok(Self::Always),
+            "everysec" => Ok(Self::EverySec),
+            "no" => Ok(Self::No),
+            other => Err(format!("invalid appendfsync '{}'", other)),
+        }
+    }
+}
+
+impl Display for AppendFsync {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Always => "always",
+            Self::EverySec => "everysec",
+            Self::No => "no",
+        })
+    }
+}
+
+/// The `maxmemory-policy`, what is evicted once `maxmemory` is reached
+/// - noeviction: nothing, writes that would use more memory get an OOM error
+/// - allkeys-lru / volatile-lru: the least recently used keys
+/// - allkeys-lfu / volatile-lfu: the least frequently used keys
+/// - allkeys-random / volatile-random: keys at random
+/// - volatile-ttl: the keys closest to their expiry
+///
+/// The volatile policies only evict keys with a TTL and behave like noeviction when there are none
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MaxmemoryPolicy {
+    #[default]
+    NoEviction,
+    AllKeysLru,
+    VolatileLru,
+    AllKeysLfu,
+    VolatileLfu,
+    AllKeysRandom,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+impl MaxmemoryPolicy {
+    /// only keys with a TTL may be evicted
+    pub fn is_volatile(&self) -> bool {
+        matches!(self, Self::VolatileLru | Self::VolatileLfu | Self::VolatileRandom | Self::VolatileTtl)
+    }
+
+    /// keys are ranked by access frequency, OBJECT FREQ is meaningful
+    pub fn is_lfu(&self) -> bool {
+        matches!(self, Self::AllKeysLfu | Self::VolatileLfu)
+    }
+}
+
+impl FromStr for MaxmemoryPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "noeviction" => Ok(Self::NoEviction),
+            "allkeys-lru" => Ok(Self::AllKeysLru),
+            "volatile-lru" => Ok(Self::VolatileLru),
+            "allkeys-lfu" => Ok(Self::AllKeysLfu),
+            "volatile-lfu" => Ok(Self::VolatileLfu),
+            "allkeys-random" => Ok(Self::AllKeysRandom),
+            "volatile-random" => Ok(Self::VolatileRandom),
+            "volatile-ttl" => Ok(Self::VolatileTtl),
+            other => Err(format!("invalid maxmemory-policy '{}'", other)),
+        }
+    }
+}
+
+impl Display for MaxmemoryPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::NoEviction => "noeviction",
+            Self::AllKeysLru => "allkeys-lru",
+            Self::VolatileLru => "volatile-lru",
+            Self::AllKeysLfu => "allkeys-lfu",
+            Self::VolatileLfu => "volatile-lfu",
+            Self::AllKeysRandom => "allkeys-random",
+            Self::VolatileRandom => "volatile-random",
+            Self::VolatileTtl => "volatile-ttl",
+        })
+    }
+}
+
+/// a number of bytes the way `redis.conf` writes them: `1024`, `64k`, `100mb`, `2gb`.
+/// `k`, `m` and `g` are powers of 1000, `kb`, `mb` and `gb` powers of 1024
+pub fn parse_memory(s: &str) -> Result<usize, String> {
+    let lower = s.to_lowercase();
+    let split = lower.find(|c: char| !c.is_ascii_digit()).unwrap_or(lower.len());
+    let (digits, unit) = lower.split_at(split);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err(format!("invalid memory size '{}'", s)),
+    };
+    digits.parse::<usize>()
+        .ok()
+        .and_then(|value| value.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid memory size '{}'", s))
+}
+
+/// The `notify-keyspace-events` flags, written with the same letters as Redis:
+/// - K: keyspace events, published on `__keyspace@<db>__:<key>`
+/// - E: keyevent events, published on `__keyevent@<db>__:<event>`
+/// - g: generic commands such as DEL, EXPIRE, RENAME
+/// - $: string commands, l: list, s: set, h: hash, z: sorted set, t: stream
+/// - x: expired events, e: evicted events, m: key miss events, n: new key events
+/// - A: alias for "g$lshzxet"
+///
+/// Nothing is published unless K or E is set together with at least one class
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct KeyspaceEvents(u16);
+
+impl KeyspaceEvents {
+    pub const KEYSPACE: u16 = 1 << 0;
+    pub const KEYEVENT: u16 = 1 << 1;
+    pub const GENERIC: u16 = 1 << 2;
+    pub const STRING: u16 = 1 << 3;
+    pub const LIST: u16 = 1 << 4;
+    pub const SET: u16 = 1 << 5;
+    pub const HASH: u16 = 1 << 6;
+    pub const ZSET: u16 = 1 << 7;
+    pub const EXPIRED: u16 = 1 << 8;
+    pub const EVICTED: u16 = 1 << 9;
+    pub const STREAM: u16 = 1 << 10;
+    pub const KEY_MISS: u16 = 1 << 11;
+    pub const NEW: u16 = 1 << 12;
+    /// the classes covered by `A`
+    pub const ALL: u16 = Self::GENERIC | Self::STRING | Self::LIST | Self::SET | Self::HASH
+        | Self::ZSET | Self::EXPIRED | Self::EVICTED | Self::STREAM;
+
+    const LETTERS: [(char, u16); 13] = [
+        ('g', Self::GENERIC), ('$', Self::STRING), ('l', Self::LIST), ('s', Self::SET),
+        ('h', Self::HASH), ('z', Self::ZSET), ('x', Self::EXPIRED), ('e', Self::EVICTED),
+        ('t', Self::STREAM), ('K', Self::KEYSPACE), ('E', Self::KEYEVENT), ('m', Self::KEY_MISS),
+        ('n', Self::NEW),
+    ];
+
+    pub fn contains(&self, flags: u16) -> bool {
+        self.0 & flags != 0
+    }
+}
+
+impl FromStr for KeyspaceEvents {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut flags = 0;
+        for c in s.chars() {
+            flags |= match c {
+                'A' => Self::ALL,
+                c => Self::LETTERS.iter()
+                    .find(|(letter, _)| *letter == c)
+                    .map(|(_, flag)| *flag)
+                    .ok_or_else(|| format!("invalid notify-keyspace-events flag '{}'", c))?,
+            };
+        }
+        Ok(Self(flags))
+    }
+}
+
+impl Display for KeyspaceEvents {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut flags = self.0;
+        if flags & Self::ALL == Self::ALL {
+            f.write_char('A')?;
+            flags &= !Self::ALL;
+        }
+        for (letter, flag) in Self::LETTERS {
+            if flags & flag != 0 {
+                f.write_char(letter)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,292 @@
+//! The `redis.conf` format: one `name arg [arg ...]` directive per line, `#` comments,
+//! arguments quoted like in redis-cli. Also read from the command line, `--name arg ...`,
+//! and written back by CONFIG REWRITE
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::Path;
+
+use crate::config::params::{self, Param, PARAMS};
+use crate::config::Config;
+
+/// how deep `include` directives may nest
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// The directives read so far, applied on top of the defaults
+pub(super) struct Loader {
+    pub(super) config: Config,
+    /// the repeatable parameters already set, any other occurrence adds to them
+    seen: HashSet<&'static str>,
+}
+
+impl Loader {
+    pub(super) fn new() -> Self {
+        Self { config: Config::default(), seen: HashSet::new() }
+    }
+
+    /// apply every directive of a config file. Unknown directives are skipped with a warning,
+    /// so a `redis.conf` written for Redis loads as long as what it sets is supported here
+    pub(super) fn file(&mut self, path: &Path, depth: usize) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Can't open the config file '{}': {}", path.display(), e))?;
+
+        for (number, line) in text.lines().enumerate() {
+            let location = || format!("{}:{} '{}'", path.display(), number + 1, line.trim());
+            let Some(args) = split_line(line) else {
+                return Err(format!("{}: unbalanced quotes", location()));
+            };
+            let Some((name, args)) = args.split_first() else { continue };
+            if name.starts_with('#') {
+                continue;
+            }
+
+            if name.eq_ignore_ascii_case("include") {
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(format!("{}: too many nested includes", location()));
+                }
+                let [include] = args else {
+                    return Err(format!("{}: include needs a single path", location()));
+                };
+                self.file(Path::new(include), depth + 1)?;
+                continue;
+            }
+            match params::find(name) {
+                Some(param) => self.directive(param, args).map_err(|e| format!("{}: {}", location(), e))?,
+                None => eprintln!("Ignoring an unsupported directive at {}", location()),
+            }
+        }
+        Ok(())
+    }
+
+    /// apply `--name arg [arg ...]` options, where every argument up to the next `--` belongs
+    /// to the option before it. Unlike in a file, an unknown name is an error
+    pub(super) fn args(&mut self, args: &[String]) -> Result<(), String> {
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            let name = arg.strip_prefix("--").ok_or_else(|| format!("'{}' is not an option, options start with --", arg))?;
+            let param = params::find(name).ok_or_else(|| format!("unknown option '--{}'", name))?;
+            let mut values = vec![];
+            while let Some(value) = args.next_if(|value| !value.starts_with("--")) {
+                values.push(value.clone());
+            }
+            self.directive(param, &values).map_err(|e| format!("--{}: {}", name, e))?;
+        }
+        Ok(())
+    }
+
+    fn directive(&mut self, param: &'static Param, args: &[String]) -> Result<(), String> {
+        if args.is_empty() || (args.len() > 1 && !param.args) {
+            return Err("wrong number of arguments".to_string());
+        }
+        let mut value = args.join(" ");
+        // `save 3600 1` 后面再跟 `save 300 100` 是追加规则，不是替换
+        if param.repeatable && !self.seen.insert(param.name) {
+            value = format!("{} {}", (param.get)(&self.config), value);
+        }
+        (param.set)(&mut self.config, value.trim())
+    }
+}
+
+/// write the config back to the file it was loaded from. Comments, blank lines and the
+/// directives this server does not know are kept as they are. The first line of each known
+/// parameter gets its current value and its other lines are dropped, and the parameters
+/// changed from their default that the file did not mention are appended at the end
+pub(super) fn rewrite(config: &Config, path: &Path) -> io::Result<()> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let defaults = Config::default();
+
+    let mut written = HashSet::new();
+    let mut lines = vec![];
+    for line in text.lines() {
+        let param = split_line(line)
+            .and_then(|args| args.first().and_then(|name| params::find(name)));
+        match param {
+            None => lines.push(line.to_string()),
+            Some(param) if written.insert(param.name) => lines.extend(render(param, config, &defaults)),
+            Some(_) => {}
+        }
+    }
+
+    let changed: Vec<String> = PARAMS.iter()
+        .filter(|param| !written.contains(param.name) && (param.get)(config) != (param.get)(&defaults))
+        .filter_map(|param| render(param, config, &defaults))
+        .collect();
+    if !changed.is_empty() {
+        if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+            lines.push(String::new());
+        }
+        lines.push("# Generated by CONFIG REWRITE".to_string());
+        lines.extend(changed);
+    }
+
+    // 先写临时文件再改名，写到一半崩溃也不会留下残缺的配置
+    let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+    {
+        let mut file = fs::File::create(&tmp)?;
+        for line in &lines {
+            writeln!(file, "{}", line)?;
+        }
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+/// the line of a parameter in a config file, `None` when it is empty and empty is its default,
+/// like `replicaof` for a master
+fn render(param: &Param, config: &Config, defaults: &Config) -> Option<String> {
+    let value = (param.get)(config);
+    if value.is_empty() {
+        return (!(param.get)(defaults).is_empty()).then(|| format!("{} \"\"", param.name));
+    }
+    if param.args {
+        Some(format!("{} {}", param.name, value))
+    } else {
+        Some(format!("{} {}", param.name, quote(&value)))
+    }
+}
+
+/// `value` as a single argument, in double quotes when it would not read back as one
+fn quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '\\')) {
+        return value.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// split a line into its arguments. Double quoted arguments understand `\n`, `\r`, `\t`,
+/// `\\` and `\"`, single quoted ones `\'`. `None` for unbalanced quotes
+fn split_line(line: &str) -> Option<Vec<String>> {
+    let mut args = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(&first) = chars.peek() else { return Some(args) };
+
+        let mut arg = String::new();
+        match first {
+            '"' | '\'' => {
+                chars.next();
+                loop {
+                    match chars.next()? {
+                        '\\' if first == '"' => match chars.next()? {
+                            'n' => arg.push('\n'),
+                            'r' => arg.push('\r'),
+                            't' => arg.push('\t'),
+                            other => arg.push(other),
+                        },
+                        '\\' if chars.peek() == Some(&'\'') => arg.push(chars.next()?),
+                        c if c == first => break,
+                        c => arg.push(c),
+                    }
+                }
+                // 引号后面必须是空白或者行尾
+                if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                    return None;
+                }
+            }
+            _ => {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    arg.push(c);
+                }
+            }
+        }
+        args.push(arg);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::config::file::{quote, split_line};
+    use crate::config::{Config, MaxmemoryPolicy};
+
+    #[test]
+    fn split_line_test() {
+        assert_eq!(split_line("  save 3600 1  ").unwrap(), vec!["save", "3600", "1"]);
+        assert_eq!(split_line("save \"\"").unwrap(), vec!["save", ""]);
+        assert_eq!(split_line("dir \"/tmp/with space\" 'it\\'s'").unwrap(), vec!["dir", "/tmp/with space", "it's"]);
+        assert_eq!(split_line(&format!("dir {}", quote("a \"b\"\\c"))).unwrap(), vec!["dir", "a \"b\"\\c"]);
+        assert!(split_line("dir \"unbalanced").is_none());
+        assert!(split_line("dir \"a\"b").is_none());
+        assert!(split_line("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn load_test() {
+        let dir = std::env::temp_dir().join(format!("config-load-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("redis.conf");
+        let included = dir.join("included.conf");
+        fs::write(&included, "maxmemory-samples 10\n").unwrap();
+        fs::write(&path, format!("# a comment\n\nport 7000\nbind 0.0.0.0 -::1\nSAVE 900 1\nsave 300 10\n\
+            maxmemory 1gb\nslave-read-only no\ntcp-backlog 511\ninclude {}\n\
+            client-output-buffer-limit normal 0 0 0\nclient-output-buffer-limit pubsub 8mb 2mb 60\n", included.display())).unwrap();
+
+        let args = [path.display().to_string(), "--maxmemory-policy".to_string(), "allkeys-lru".to_string(),
+            "--replicaof".to_string(), "10.0.0.1".to_string(), "6380".to_string()];
+        let config = Config::from_args(args).unwrap();
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.bind, vec!["0.0.0.0", "-::1"]);
+        assert_eq!(config.save, vec![(900, 1), (300, 10)]);
+        assert_eq!(config.maxmemory, 1024 * 1024 * 1024);
+        assert!(!config.replica_read_only);
+        assert_eq!(config.maxmemory_samples, 10);
+        assert_eq!(config.client_output_buffer_limit_pubsub, 8 * 1024 * 1024);
+        assert_eq!(config.maxmemory_policy, MaxmemoryPolicy::AllKeysLru);
+        assert_eq!(config.replicaof, Some(("10.0.0.1".to_string(), 6380)));
+        assert_eq!(config.config_file, Some(path.clone()));
+
+        let e = Config::from_args(["--port".to_string(), "x".to_string()]).unwrap_err();
+        assert_eq!(e, "--port: argument couldn't be parsed into an integer");
+        assert!(Config::from_args(["--prot".to_string(), "1".to_string()]).is_err());
+        fs::write(&path, "port 7000\nmaxmemory-policy lru\n").unwrap();
+        assert!(Config::from_file(&path).unwrap_err().contains(":2 'maxmemory-policy lru'"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rewrite_test() {
+        let dir = std::env::temp_dir().join(format!("config-rewrite-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("redis.conf");
+        fs::write(&path, "# the port\nport 7000\n\n# snapshots\nsave 900 1\nsave 300 10\ntcp-backlog 511\n").unwrap();
+
+        let mut config = Config::from_file(&path).unwrap();
+        config.set("port", "7001").unwrap();
+        config.set("save", "").unwrap();
+        config.set("maxmemory", "100mb").unwrap();
+        config.set("dbfilename", "my dump.rdb").unwrap();
+        config.rewrite().unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text, "# the port\nport 7001\n\n# snapshots\nsave \"\"\ntcp-backlog 511\n\n\
+            # Generated by CONFIG REWRITE\ndbfilename \"my dump.rdb\"\nmaxmemory 104857600\n");
+        let reloaded = Config::from_file(&path).unwrap();
+        assert!(reloaded.save.is_empty());
+        assert_eq!(reloaded.dbfilename, "my dump.rdb");
+        assert_eq!(reloaded.maxmemory, 100 * 1024 * 1024);
+
+        // rewriting again changes nothing
+        reloaded.rewrite().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), text);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
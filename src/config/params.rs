@@ -0,0 +1,399 @@
+//! Every parameter of `Config`, by its `redis.conf` name, with how it is read and written as text
+
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::config::{parse_memory, Config};
+
+/// A parameter of the config file, of the command line and of CONFIG GET/SET
+pub(crate) struct Param {
+    pub(crate) name: &'static str,
+    /// CONFIG SET may change it while the server runs
+    pub(crate) mutable: bool,
+    /// the value is made of several arguments, like `save 3600 1 300 100`, written unquoted
+    pub(crate) args: bool,
+    /// repeated directives in a config file add up instead of replacing each other
+    pub(crate) repeatable: bool,
+    pub(crate) get: fn(&Config) -> String,
+    pub(crate) set: fn(&mut Config, &str) -> Result<(), String>,
+}
+
+/// the old names Redis still accepts
+const ALIASES: [(&str, &str); 2] = [
+    ("slaveof", "replicaof"),
+    ("slave-read-only", "replica-read-only"),
+];
+
+pub(crate) const PARAMS: &[Param] = &[
+    Param {
+        name: "bind",
+        mutable: false,
+        args: true,
+        repeatable: false,
+        get: |config| config.bind.join(" "),
+        set: |config, value| {
+            let addrs: Vec<String> = value.split_whitespace().map(str::to_string).collect();
+            if addrs.is_empty() {
+                return Err("at least one address is needed".to_string());
+            }
+            config.bind = addrs;
+            Ok(())
+        },
+    },
+    Param {
+        name: "port",
+        mutable: false,
+        args: false,
+        repeatable: false,
+        get: |config| config.port.to_string(),
+        set: |config, value| number(value).map(|port| config.port = port),
+    },
+    Param {
+        name: "maxclients",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.maxclients.to_string(),
+        set: |config, value| match number(value)? {
+            0 => Err("argument must be between 1 and 4294967295 inclusive".to_string()),
+            clients => {
+                config.maxclients = clients;
+                Ok(())
+            }
+        },
+    },
+    Param {
+        name: "slowlog-log-slower-than",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.slowlog_log_slower_than.to_string(),
+        set: |config, value| number(value).map(|usec| config.slowlog_log_slower_than = usec),
+    },
+    Param {
+        name: "slowlog-max-len",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.slowlog_max_len.to_string(),
+        set: |config, value| number(value).map(|len| config.slowlog_max_len = len),
+    },
+    Param {
+        name: "metrics-port",
+        mutable: false,
+        args: false,
+        repeatable: false,
+        get: |config| config.metrics_port.to_string(),
+        set: |config, value| number(value).map(|port| config.metrics_port = port),
+    },
+    Param {
+        name: "dir",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.dir.display().to_string(),
+        set: |config, value| {
+            let dir = PathBuf::from(value);
+            if !dir.is_dir() {
+                return Err(format!("No such directory '{}'", value));
+            }
+            config.dir = dir;
+            Ok(())
+        },
+    },
+    Param {
+        name: "dbfilename",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.dbfilename.clone(),
+        set: |config, value| file_name(value).map(|name| config.dbfilename = name),
+    },
+    Param {
+        name: "save",
+        mutable: true,
+        args: true,
+        repeatable: true,
+        get: |config| config.save.iter()
+            .map(|(seconds, changes)| format!("{} {}", seconds, changes))
+            .collect::<Vec<_>>()
+            .join(" "),
+        set: |config, value| {
+            let numbers = value.split_whitespace().map(number).collect::<Result<Vec<u64>, _>>()?;
+            if !numbers.len().is_multiple_of(2) {
+                return Err("Invalid save parameters".to_string());
+            }
+            config.save = numbers.chunks(2).map(|rule| (rule[0], rule[1])).collect();
+            Ok(())
+        },
+    },
+    Param {
+        name: "appendonly",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| yes_no(config.appendonly),
+        set: |config, value| bool_value(value).map(|on| config.appendonly = on),
+    },
+    Param {
+        name: "appendfilename",
+        mutable: false,
+        args: false,
+        repeatable: false,
+        get: |config| config.appendfilename.clone(),
+        set: |config, value| file_name(value).map(|name| config.appendfilename = name),
+    },
+    Param {
+        name: "appenddirname",
+        mutable: false,
+        args: false,
+        repeatable: false,
+        get: |config| config.appenddirname.clone(),
+        set: |config, value| file_name(value).map(|name| config.appenddirname = name),
+    },
+    Param {
+        name: "appendfsync",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.appendfsync.to_string(),
+        set: |config, value| value.parse().map(|fsync| config.appendfsync = fsync),
+    },
+    Param {
+        name: "aof-load-truncated",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| yes_no(config.aof_load_truncated),
+        set: |config, value| bool_value(value).map(|on| config.aof_load_truncated = on),
+    },
+    Param {
+        name: "aof-load-repair",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| yes_no(config.aof_load_repair),
+        set: |config, value| bool_value(value).map(|on| config.aof_load_repair = on),
+    },
+    Param {
+        name: "replicaof",
+        // REPLICAOF changes it at runtime
+        mutable: false,
+        args: true,
+        repeatable: false,
+        get: |config| config.replicaof.as_ref()
+            .map(|(host, port)| format!("{} {}", host, port))
+            .unwrap_or_default(),
+        set: |config, value| {
+            let args: Vec<&str> = value.split_whitespace().collect();
+            config.replicaof = match args[..] {
+                [] => None,
+                [host, port] if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") => None,
+                [host, port] => Some((host.to_string(), number(port)?)),
+                _ => return Err("replicaof needs a host and a port".to_string()),
+            };
+            Ok(())
+        },
+    },
+    Param {
+        name: "replica-read-only",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| yes_no(config.replica_read_only),
+        set: |config, value| bool_value(value).map(|on| config.replica_read_only = on),
+    },
+    Param {
+        name: "repl-backlog-size",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.repl_backlog_size.to_string(),
+        set: |config, value| {
+            let size = parse_memory(value)?;
+            if size == 0 {
+                return Err("argument must be greater than 0".to_string());
+            }
+            config.repl_backlog_size = size;
+            Ok(())
+        },
+    },
+    Param {
+        name: "cluster-enabled",
+        mutable: false,
+        args: false,
+        repeatable: false,
+        get: |config| yes_no(config.cluster_enabled),
+        set: |config, value| bool_value(value).map(|on| config.cluster_enabled = on),
+    },
+    Param {
+        name: "cluster-config-file",
+        mutable: false,
+        args: false,
+        repeatable: false,
+        get: |config| config.cluster_config_file.clone(),
+        set: |config, value| file_name(value).map(|name| config.cluster_config_file = name),
+    },
+    Param {
+        name: "cluster-node-timeout",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.cluster_node_timeout.to_string(),
+        set: |config, value| number(value).map(|timeout| config.cluster_node_timeout = timeout),
+    },
+    Param {
+        name: "maxmemory",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.maxmemory.to_string(),
+        set: |config, value| parse_memory(value).map(|bytes| config.maxmemory = bytes),
+    },
+    Param {
+        name: "maxmemory-policy",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.maxmemory_policy.to_string(),
+        set: |config, value| value.parse().map(|policy| config.maxmemory_policy = policy),
+    },
+    Param {
+        name: "maxmemory-samples",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.maxmemory_samples.to_string(),
+        set: |config, value| match number(value)? {
+            0 => Err("argument must be between 1 and 64 inclusive".to_string()),
+            samples if samples > 64 => Err("argument must be between 1 and 64 inclusive".to_string()),
+            samples => {
+                config.maxmemory_samples = samples;
+                Ok(())
+            }
+        },
+    },
+    Param {
+        name: "lfu-log-factor",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.lfu_log_factor.to_string(),
+        set: |config, value| number(value).map(|factor| config.lfu_log_factor = factor),
+    },
+    Param {
+        name: "lfu-decay-time",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.lfu_decay_time.to_string(),
+        set: |config, value| number(value).map(|minutes| config.lfu_decay_time = minutes),
+    },
+    Param {
+        name: "lazyfree-lazy-eviction",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| yes_no(config.lazyfree_lazy_eviction),
+        set: |config, value| bool_value(value).map(|on| config.lazyfree_lazy_eviction = on),
+    },
+    Param {
+        name: "lazyfree-lazy-expire",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| yes_no(config.lazyfree_lazy_expire),
+        set: |config, value| bool_value(value).map(|on| config.lazyfree_lazy_expire = on),
+    },
+    Param {
+        name: "lazyfree-lazy-server-del",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| yes_no(config.lazyfree_lazy_server_del),
+        set: |config, value| bool_value(value).map(|on| config.lazyfree_lazy_server_del = on),
+    },
+    Param {
+        name: "lazyfree-lazy-user-del",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| yes_no(config.lazyfree_lazy_user_del),
+        set: |config, value| bool_value(value).map(|on| config.lazyfree_lazy_user_del = on),
+    },
+    Param {
+        name: "lazyfree-lazy-user-flush",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| yes_no(config.lazyfree_lazy_user_flush),
+        set: |config, value| bool_value(value).map(|on| config.lazyfree_lazy_user_flush = on),
+    },
+    Param {
+        name: "notify-keyspace-events",
+        mutable: true,
+        args: false,
+        repeatable: false,
+        get: |config| config.notify_keyspace_events.to_string(),
+        set: |config, value| value.parse().map(|events| config.notify_keyspace_events = events),
+    },
+    Param {
+        name: "client-output-buffer-limit",
+        // 只有新连接才会用到新的限制
+        mutable: true,
+        args: true,
+        repeatable: true,
+        get: |config| format!("pubsub {} 0 0", config.client_output_buffer_limit_pubsub),
+        set: |config, value| {
+            let args: Vec<&str> = value.split_whitespace().collect();
+            if args.is_empty() || !args.len().is_multiple_of(4) {
+                return Err("Wrong number of arguments in buffer limit configuration.".to_string());
+            }
+            // 只有 pubsub 的硬限制有实现，normal 和 replica 的限制被接受但不生效
+            for limit in args.chunks(4) {
+                let hard = parse_memory(limit[1])?;
+                parse_memory(limit[2])?;
+                number::<u64>(limit[3])?;
+                match limit[0].to_lowercase().as_str() {
+                    "pubsub" => config.client_output_buffer_limit_pubsub = hard,
+                    "normal" | "replica" | "slave" => {}
+                    other => return Err(format!("Invalid client class specified in buffer limit configuration: '{}'", other)),
+                }
+            }
+            Ok(())
+        },
+    },
+];
+
+/// the parameter named `name`, or one of its aliases, case insensitive
+pub(crate) fn find(name: &str) -> Option<&'static Param> {
+    let name = name.to_lowercase();
+    let name = ALIASES.iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name.as_str(), |(_, name)| name);
+    PARAMS.iter().find(|param| param.name == name)
+}
+
+fn yes_no(on: bool) -> String {
+    if on { "yes" } else { "no" }.to_string()
+}
+
+fn bool_value(value: &str) -> Result<bool, String> {
+    match value.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err("argument must be 'yes' or 'no'".to_string()),
+    }
+}
+
+fn number<T: FromStr>(value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| "argument couldn't be parsed into an integer".to_string())
+}
+
+/// a file name in `dir`, not a path
+fn file_name(value: &str) -> Result<String, String> {
+    if value.is_empty() || value.contains('/') {
+        return Err("must be a file name, not a path".to_string());
+    }
+    Ok(value.to_string())
+}
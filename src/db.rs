@@ -1,109 +1,800 @@
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
 use std::collections::{BTreeSet, HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
 
 use bytes::Bytes;
 use tokio::time;
 use tokio::sync::broadcast::Receiver;
 use tokio::sync::Notify;
 use tokio::time::Instant;
+use crate::aof::{self, Aof};
+use crate::clock::{instant_to_unix_ms, unix_ms, unix_ms_to_instant, unix_secs};
+use crate::cluster::{self, Cluster};
+use crate::cmd::Cmd;
+use crate::config::{Config, KeyspaceEvents};
+use crate::frame::encode_command;
+use crate::pub_sub::PubSub;
+use crate::rdb::{self, SaveState, SnapshotEntry};
+use crate::replication::Replication;
+use crate::slot::key_hash_slot;
+use crate::slowlog::SlowLog;
+use crate::stats::Stats;
 use crate::RedisResult;
 
+mod evict;
+
+/// the background expiry wakes up at least this often, so keys set after it went to sleep
+/// still expire close to their deadline
+const ACTIVE_EXPIRE_PERIOD: Duration = Duration::from_millis(100);
+
+/// how often the command rate is sampled for INFO
+const OPS_SAMPLE_PERIOD: Duration = Duration::from_millis(100);
+
+/// how often the `save` rules are checked
+const AUTO_SAVE_PERIOD: Duration = Duration::from_secs(1);
+
+/// seconds to wait before retrying an automatic save that failed
+const BGSAVE_RETRY_DELAY: u64 = 5;
+
+/// what a key costs on top of its name and value: its slot in the hash table with the
+/// control byte, and the copy of the name kept in `Store::keys` for sampling
+const ENTRY_OVERHEAD: usize = size_of::<(String, Entry)>() + 1 + size_of::<String>();
+
+/// what a TTL costs on top of the copy of the key name, in the `expirations` tree
+const EXPIRE_OVERHEAD: usize = size_of::<(Instant, String)>();
+
+/// values made of more elements than this are dropped on the rayon pool
+/// instead of under the store lock when lazy freeing is enabled
+const LAZYFREE_THRESHOLD: usize = 64;
+
 pub(crate) type SharedDb = Arc<Db>;
 
 #[derive(Debug)]
 pub(crate) struct Db {
     shared: Mutex<Store>,
     background_task: Notify,
+    next_client_id: AtomicU64,
+    pub_sub: Arc<PubSub>,
     notify_shutdown: Receiver<()>,
 }
 
 #[derive(Debug)]
 pub(crate) struct Store {
     entries: HashMap<String, Entry>,
+    /// every key once, each entry knows its position, so eviction can sample keys at random
+    keys: Vec<String>,
+    /// estimated bytes taken by the keyspace, compared against `maxmemory`
+    used_memory: usize,
+    /// the most `used_memory` ever was
+    peak_memory: usize,
+    /// keys removed to stay under `maxmemory` since startup
+    evicted_keys: u64,
+    /// xorshift state, for sampling and the LFU counters
+    rng: Cell<u64>,
     expirations: BTreeSet<(Instant, String)>,
+    /// key -> ids of the clients that WATCH it
+    watched_keys: HashMap<String, HashSet<u64>>,
+    /// clients whose watched keys were touched since they ran WATCH
+    dirty_clients: HashSet<u64>,
+    pub(crate) pub_sub: Arc<PubSub>,
+    pub(crate) config: Config,
+    /// changes since the last snapshot and the state of the running BGSAVE
+    pub(crate) save_state: Arc<SaveState>,
+    /// writes since startup, never reset. A command whose run moved it changed the keyspace
+    write_count: u64,
+    /// the append only file, when `appendonly` is on
+    aof: Option<Aof>,
+    /// replication ID, offset, backlog and the replicas or the master of this server
+    pub(crate) replication: Replication,
+    /// the slots and nodes of the cluster, when `cluster-enabled` is on
+    pub(crate) cluster: Option<Cluster>,
+    transaction: Transaction,
+    /// the counters of INFO
+    pub(crate) stats: Stats,
+    pub(crate) slowlog: SlowLog,
+}
+
+/// MULTI is only propagated once a transaction actually writes something
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Transaction {
+    None,
+    Pending,
+    Open,
 }
 
 #[derive(Debug)]
 struct Entry {
     data: RedisDataType,
     expire_at: Option<Instant>,
+    /// position of the key in `Store::keys`
+    index: usize,
+    /// unix time in milliseconds of the last access, for the LRU policies
+    lru: Cell<u64>,
+    /// minute of the last decrement in the upper bits, a logarithmic access counter
+    /// in the low 8 bits, for the LFU policies
+    lfu: Cell<u32>,
+    /// `data.memory_usage()`, kept up to date by the writes so that it is never measured again
+    memory: usize,
 }
 
-#[derive(Debug)]
-enum RedisDataType {
+#[derive(Debug, Clone)]
+pub(crate) enum RedisDataType {
     Bytes(Bytes),
     List(Vec<Bytes>),
     Set(HashSet<Bytes>),
-    SortedSet(BTreeSet<Bytes>),
+    /// member -> score
+    SortedSet(HashMap<Bytes, f64>),
     HASH(HashMap<String, Bytes>),
     BITMAP,
 }
 
+impl RedisDataType {
+    /// roughly how many allocations have to be released to drop this value
+    fn free_effort(&self) -> usize {
+        match self {
+            RedisDataType::Bytes(_) | RedisDataType::BITMAP => 1,
+            RedisDataType::List(list) => list.len(),
+            RedisDataType::Set(set) => set.len(),
+            RedisDataType::SortedSet(set) => set.len(),
+            RedisDataType::HASH(hash) => hash.len(),
+        }
+    }
+
+    /// the name TYPE replies with
+    pub(crate) fn type_name(&self) -> &'static str {
+        match self {
+            RedisDataType::Bytes(_) | RedisDataType::BITMAP => "string",
+            RedisDataType::List(_) => "list",
+            RedisDataType::Set(_) => "set",
+            RedisDataType::SortedSet(_) => "zset",
+            RedisDataType::HASH(_) => "hash",
+        }
+    }
+
+    /// bytes the value takes on the heap: the payloads plus the slots of its container
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.sampled_memory_usage(0)
+    }
+
+    /// like `memory_usage`, but the payload of collections is extrapolated from their first
+    /// `samples` elements. 0 samples every element
+    pub(crate) fn sampled_memory_usage(&self, samples: usize) -> usize {
+        self.container_usage() + match self {
+            RedisDataType::Bytes(bytes) => bytes.len(),
+            RedisDataType::List(list) => sampled(list.iter(), samples, Bytes::len),
+            RedisDataType::Set(set) => sampled(set.iter(), samples, Bytes::len),
+            RedisDataType::SortedSet(set) => sampled(set.keys(), samples, Bytes::len),
+            RedisDataType::HASH(hash) => sampled(hash.iter(), samples, |(field, value)| field.len() + value.len()),
+            RedisDataType::BITMAP => 0,
+        }
+    }
+
+    /// the slots of the container alone, without walking the elements
+    fn container_usage(&self) -> usize {
+        match self {
+            RedisDataType::Bytes(_) | RedisDataType::BITMAP => 0,
+            RedisDataType::List(list) => list.capacity() * size_of::<Bytes>(),
+            RedisDataType::Set(set) => set.capacity() * (size_of::<Bytes>() + 1),
+            RedisDataType::SortedSet(set) => set.capacity() * (size_of::<(Bytes, f64)>() + 1),
+            RedisDataType::HASH(hash) => hash.capacity() * (size_of::<(String, Bytes)>() + 1),
+        }
+    }
+}
+
+/// the payload bytes a write added to and removed from a collection in `Store::update_data`,
+/// so that its size follows the write instead of being measured again
+#[derive(Debug, Default)]
+pub(crate) struct Resize {
+    added: usize,
+    removed: usize,
+}
+
+impl Resize {
+    pub(crate) fn add(&mut self, bytes: usize) {
+        self.added += bytes;
+    }
+
+    pub(crate) fn remove(&mut self, bytes: usize) {
+        self.removed += bytes;
+    }
+}
+
+/// the sum of `size` over the elements, or over the first `samples` of them scaled to all of them
+fn sampled<I: ExactSizeIterator>(elements: I, samples: usize, size: impl Fn(I::Item) -> usize) -> usize {
+    let len = elements.len();
+    if samples == 0 || samples >= len {
+        return elements.map(size).sum();
+    }
+    elements.take(samples).map(size).sum::<usize>() * len / samples
+}
+
+/// what a key and its value count for in `used_memory`. The name is stored twice,
+/// as the key of `entries` and in `keys`
+fn entry_memory(key: &str, entry: &Entry) -> usize {
+    ENTRY_OVERHEAD + 2 * key.len() + entry.memory
+}
+
+/// drop a value that was detached from the keyspace.
+/// When `lazy` is set, big collections are handed to the rayon pool so the caller
+/// does not pay for the deallocation while holding the store lock
+fn free_value(data: RedisDataType, lazy: bool) {
+    if lazy && data.free_effort() > LAZYFREE_THRESHOLD {
+        rayon::spawn(move || drop(data));
+    }
+}
+
 impl Store {
-    fn new() -> Self {
+    fn new(config: Config, pub_sub: Arc<PubSub>) -> Self {
+        let replication = Replication::new(&config);
+        let cluster = config.cluster_enabled.then(|| Cluster::new(&config));
         Self {
             entries: HashMap::new(),
+            keys: vec![],
+            used_memory: 0,
+            peak_memory: 0,
+            evicted_keys: 0,
+            rng: Cell::new(RandomState::new().build_hasher().finish() | 1),
             expirations: BTreeSet::new(),
+            watched_keys: HashMap::new(),
+            dirty_clients: HashSet::new(),
+            pub_sub,
+            config,
+            save_state: Arc::new(SaveState::new()),
+            write_count: 0,
+            aof: None,
+            replication,
+            cluster,
+            transaction: Transaction::None,
+            stats: Stats::new(),
+            slowlog: SlowLog::new(),
+        }
+    }
+
+    /// register a WATCH of `key` by a client.
+    /// Returns whether the key was alive, so that a later expiry of it can be told apart
+    /// from a key that had already expired when it was watched
+    pub(crate) fn watch(&mut self, client_id: u64, key: &str) -> bool {
+        self.watched_keys.entry(key.to_string()).or_default().insert(client_id);
+        self.get_data(key).is_some()
+    }
+
+    /// forget every WATCH of a client, after EXEC, DISCARD, UNWATCH or when it disconnects
+    pub(crate) fn unwatch(&mut self, client_id: u64, keys: &[(String, bool)]) {
+        for (key, _) in keys {
+            if let Some(clients) = self.watched_keys.get_mut(key) {
+                clients.remove(&client_id);
+                if clients.is_empty() {
+                    self.watched_keys.remove(key);
+                }
+            }
+        }
+        self.dirty_clients.remove(&client_id);
+    }
+
+    /// whether a watched key changed since WATCH. Keys that were alive when watched and
+    /// have logically expired since count as changed even before they are purged
+    pub(crate) fn is_dirty(&self, client_id: u64, keys: &[(String, bool)]) -> bool {
+        self.dirty_clients.contains(&client_id)
+            || keys.iter().any(|(key, alive)| *alive && self.entries.get(key).is_some_and(Entry::is_expired))
+    }
+
+    /// called from every write path so that transactions watching the key get aborted
+    pub(crate) fn touch(&mut self, key: &str) {
+        self.save_state.dirty(1);
+        self.write_count += 1;
+        if let Some(clients) = self.watched_keys.get(key) {
+            self.dirty_clients.extend(clients.iter().copied());
+        }
+    }
+
+    fn touch_all(&mut self) {
+        self.save_state.dirty(self.entries.len().max(1) as u64);
+        self.write_count += 1;
+        for clients in self.watched_keys.values() {
+            self.dirty_clients.extend(clients.iter().copied());
         }
     }
 
     pub(crate) fn set_bytes(&mut self, key: impl ToString, value: Bytes, expire_at: Option<Instant>) -> Option<Bytes> {
         let key = key.to_string();
+        self.touch(&key);
 
-        let prev_entry = self.entries.insert(key.clone(), (value, expire_at).into());
+        let prev_entry = self.insert_entry(key.clone(), (value, expire_at).into());
 
         // 如果有超时时间，则将该时间存储进来
         if let Some(expire_at) = expire_at {
             self.expirations.insert((expire_at, key.clone()));
         }
 
+        if prev_entry.as_ref().is_none_or(Entry::is_expired) {
+            self.notify(KeyspaceEvents::NEW, "new", &key);
+        }
+
         if let Some(data) = prev_entry {
             if let Some(expire_at) = data.expire_at {
                 // 删掉
                 self.expirations.remove(&(expire_at, key));
             }
 
-            if let RedisDataType::Bytes(data) = data.data {
-                return Some(data);
+            match data.data {
+                RedisDataType::Bytes(data) => return Some(data),
+                other => free_value(other, self.config.lazyfree_lazy_server_del),
             }
         }
 
         None
     }
 
-    pub(crate) fn get_bytes(&self, key: impl ToString) -> Option<Bytes> {
+    pub(crate) fn get_bytes(&mut self, key: impl ToString) -> Option<Bytes> {
         let key = key.to_string();
 
-        if let Some(entry) = self.entries.get(&key) {
-            //判断时间,时间过期了，则不能再继续了
-            if let Some(expire_at) = entry.expire_at {
-                if expire_at < Instant::now() {
-                    return None;
+        //判断时间,时间过期了，则不能再继续了
+        if self.expire_if_needed(&key) {
+            self.stats.keyspace_misses += 1;
+            return None;
+        }
+
+        let Some(entry) = self.entries.get(&key) else {
+            self.stats.keyspace_misses += 1;
+            return None;
+        };
+        self.stats.keyspace_hits += 1;
+        self.record_access(entry);
+        match &entry.data {
+            RedisDataType::Bytes(data) => Some(Bytes::copy_from_slice(data)),
+            _ => None,
+        }
+    }
+
+    /// lazy expiry: delete the key if its time is up and publish the `expired` event.
+    /// Returns true if the key was expired
+    pub(crate) fn expire_if_needed(&mut self, key: &str) -> bool {
+        if !self.entries.get(key).is_some_and(Entry::is_expired) {
+            return false;
+        }
+        self.expire(key);
+        true
+    }
+
+    /// delete a key whose time is up. The deletion is logged as a DEL, so that replaying the
+    /// AOF does not depend on when it is replayed. Replicas wait for the DEL of their master
+    /// instead, their stream has to stay the one of the master
+    fn expire(&mut self, key: &str) {
+        self.delete(key, self.config.lazyfree_lazy_expire);
+        self.stats.expired_keys += 1;
+        self.notify(KeyspaceEvents::EXPIRED, "expired", key);
+        if !self.replication.is_replica() {
+            self.propagate(&[Bytes::from_static(b"DEL"), Bytes::from(key.to_string())]);
+        }
+    }
+
+    pub(crate) fn write_count(&self) -> u64 {
+        self.write_count
+    }
+
+    /// log a write command to the AOF, if it is on, and send it to the replicas
+    pub(crate) fn propagate(&mut self, argv: &[Bytes]) {
+        if self.transaction == Transaction::Pending {
+            self.transaction = Transaction::Open;
+            self.propagate_raw(encode_command(&[Bytes::from_static(b"MULTI")]), true);
+        }
+        self.propagate_raw(encode_command(argv), true);
+    }
+
+    /// propagate an already encoded command. Replicas forward what their master sends as is,
+    /// and only the write commands in it go to the AOF
+    pub(crate) fn propagate_raw(&mut self, data: Bytes, to_aof: bool) {
+        if to_aof {
+            if let Some(aof) = self.aof.as_mut() {
+                if let Err(e) = aof.append(&data) {
+                    eprintln!("Error writing to the AOF file: {}", e);
                 }
             }
-            if let RedisDataType::Bytes(data) = &entry.data {
-                return Some(Bytes::copy_from_slice(data));
+        }
+        self.replication.feed(data);
+    }
+
+    /// the writes of one EXEC are propagated as a MULTI/EXEC block, so that a crash or a
+    /// replica never sees half a transaction
+    pub(crate) fn begin_transaction(&mut self) {
+        self.transaction = Transaction::Pending;
+    }
+
+    pub(crate) fn end_transaction(&mut self) {
+        let open = self.transaction == Transaction::Open;
+        self.transaction = Transaction::None;
+        if open {
+            self.propagate_raw(encode_command(&[Bytes::from_static(b"EXEC")]), true);
+        }
+    }
+
+    /// BGREWRITEAOF: compact the AOF into the current keyspace, written from another thread
+    pub(crate) fn bgrewriteaof(&mut self) -> Result<(), &'static str> {
+        let entries = self.snapshot();
+        match self.aof.as_mut() {
+            None => Err("ERR Background append only file rewriting requires appendonly to be enabled"),
+            Some(aof) if aof.rewrite_in_progress() => Err("ERR Background append only file rewriting already in progress"),
+            Some(aof) => aof.start_rewrite(entries).map_err(|e| {
+                eprintln!("Can't start the AOF rewrite: {}", e);
+                "ERR Can't rewrite append only file in background"
+            }),
+        }
+    }
+
+    /// CONFIG SET: switch to `config` and apply what changed to the running server.
+    /// Turning `appendonly` on starts an AOF whose base, the keyspace, is written in the background
+    pub(crate) fn set_config(&mut self, config: Config) -> std::io::Result<()> {
+        if config.appendonly && self.aof.is_none() {
+            let snapshot = self.snapshot();
+            self.aof = Some(Aof::create_in_background(&config.aof_dir(), &config.appendfilename, config.appendfsync, snapshot)?);
+        } else if !config.appendonly {
+            if let Some(mut aof) = self.aof.take() {
+                aof.sync()?;
             }
         }
+        if let Some(aof) = self.aof.as_mut() {
+            aof.set_fsync(config.appendfsync);
+        }
+        self.replication.configure(&config);
+        self.slowlog.truncate(config.slowlog_max_len);
+        if let Some(cluster) = self.cluster.as_mut() {
+            cluster.configure(&config);
+        }
+        self.config = config;
+        // maxmemory 调低之后马上淘汰，不等下一个写命令
+        self.evict();
+        Ok(())
+    }
 
-        None
+    /// CONFIG RESETSTAT: start the statistics of INFO over
+    pub(crate) fn reset_stats(&mut self) {
+        self.evicted_keys = 0;
+        self.peak_memory = self.used_memory;
+        self.replication.reset_stats();
+        self.stats.reset();
     }
+
+    /// publish a keyspace notification for `key` if the `class` of the event is enabled
+    /// in `notify-keyspace-events`
+    pub(crate) fn notify(&self, class: u16, event: &str, key: &str) {
+        let flags = self.config.notify_keyspace_events;
+        if !flags.contains(class) {
+            return;
+        }
+
+        if flags.contains(KeyspaceEvents::KEYSPACE) {
+            let channel = Bytes::from(format!("__keyspace@0__:{}", key));
+            self.pub_sub.publish(&channel, &Bytes::from(event.to_string()));
+        }
+        if flags.contains(KeyspaceEvents::KEYEVENT) {
+            let channel = Bytes::from(format!("__keyevent@0__:{}", event));
+            self.pub_sub.publish(&channel, &Bytes::from(key.to_string()));
+        }
+    }
+
     pub(crate) fn update_bytes(&mut self, key: impl ToString, value: Bytes) {
         let key = key.to_string();
+        self.touch(&key);
 
         if let Some(entry) = self.entries.get_mut(&key) {
-            entry.data = RedisDataType::Bytes(value);
+            let value = RedisDataType::Bytes(value);
+            let memory = value.memory_usage();
+            self.used_memory = self.used_memory + memory - entry.memory;
+            self.peak_memory = self.peak_memory.max(self.used_memory);
+            entry.data = value;
+            entry.memory = memory;
+        }
+    }
+
+    /// borrow the value stored at key, whatever its type, unless it has expired
+    pub(crate) fn get_data(&self, key: &str) -> Option<&RedisDataType> {
+        self.entries.get(key)
+            .filter(|entry| !entry.is_expired())
+            .inspect(|entry| self.record_access(entry))
+            .map(|entry| &entry.data)
+    }
+
+    /// like `get_data`, without counting as an access of the key
+    pub(crate) fn peek_data(&self, key: &str) -> Option<&RedisDataType> {
+        self.entries.get(key)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| &entry.data)
+    }
+
+    /// SCAN: the live keys among the `count` positions of `keys` before `cursor`, with their
+    /// values, and the cursor to continue from, 0 once every key was visited.
+    /// Keys are visited from the end: a removal moves the last key into the hole, so the moved
+    /// key either is still to be visited or already was, no key present all along is missed
+    pub(crate) fn scan(&self, cursor: usize, count: usize) -> (usize, Vec<(&String, &RedisDataType)>) {
+        let end = if cursor == 0 { self.keys.len() } else { cursor.min(self.keys.len()) };
+        let start = end.saturating_sub(count.max(1));
+        let found = self.keys[start..end].iter()
+            .rev()
+            .filter_map(|key| self.entries.get(key)
+                .filter(|entry| !entry.is_expired())
+                .map(|entry| (key, &entry.data)))
+            .collect();
+        (start, found)
+    }
+
+    /// when the key expires, `None` for keys without a TTL or that do not exist
+    pub(crate) fn expire_at(&self, key: &str) -> Option<Instant> {
+        self.entries.get(key)
+            .filter(|entry| !entry.is_expired())
+            .and_then(|entry| entry.expire_at)
+    }
+
+    /// replace whatever is stored at key with a value of any type
+    pub(crate) fn set_data(&mut self, key: impl ToString, data: RedisDataType, expire_at: Option<Instant>) {
+        let key = key.to_string();
+        self.touch(&key);
+
+        if let Some(when) = expire_at {
+            self.expirations.insert((when, key.clone()));
+        }
+        let prev = self.insert_entry(key.clone(), Entry::new(data, expire_at));
+        if prev.as_ref().is_none_or(Entry::is_expired) {
+            self.notify(KeyspaceEvents::NEW, "new", &key);
+        }
+        if let Some(prev) = prev {
+            if let Some(when) = prev.expire_at {
+                if Some(when) != expire_at {
+                    self.expirations.remove(&(when, key));
+                }
+            }
+            free_value(prev.data, self.config.lazyfree_lazy_server_del);
+        }
+    }
+
+    /// replace whatever is stored at key with a list. An empty list removes the key
+    pub(crate) fn set_list(&mut self, key: impl ToString, list: Vec<Bytes>) {
+        let key = key.to_string();
+        let lazy = self.config.lazyfree_lazy_server_del;
+        self.touch(&key);
+
+        if list.is_empty() {
+            self.delete(&key, lazy);
+            return;
+        }
+
+        let prev = self.insert_entry(key.clone(), (list, None).into());
+        if prev.as_ref().is_none_or(Entry::is_expired) {
+            self.notify(KeyspaceEvents::NEW, "new", &key);
+        }
+        if let Some(prev) = prev {
+            if let Some(when) = prev.expire_at {
+                self.expirations.remove(&(when, key));
+            }
+            free_value(prev.data, lazy);
+        }
+    }
+
+    /// change the value stored at key in place, keeping its TTL. `update` reports the bytes of
+    /// the elements it adds and removes in the `Resize`, the container is measured again.
+    /// `None` if the key does not exist
+    pub(crate) fn update_data<R>(&mut self, key: &str, update: impl FnOnce(&mut RedisDataType, &mut Resize) -> R) -> Option<R> {
+        if self.expire_if_needed(key) {
+            return None;
+        }
+        self.record_access(self.entries.get(key)?);
+        self.touch(key);
+
+        let entry = self.entries.get_mut(key)?;
+        let container = entry.data.container_usage();
+        let mut resize = Resize::default();
+        let result = update(&mut entry.data, &mut resize);
+        let memory = entry.memory + entry.data.container_usage() + resize.added - container - resize.removed;
+        self.used_memory = self.used_memory + memory - entry.memory;
+        self.peak_memory = self.peak_memory.max(self.used_memory);
+        entry.memory = memory;
+        Some(result)
+    }
+
+    /// delete the keys and return how many of them existed
+    pub(crate) fn remove_vec(&mut self, keys: &[String]) -> usize {
+        let lazy = self.config.lazyfree_lazy_user_del;
+        keys.iter().filter(|key| self.user_delete(key, lazy)).count()
+    }
+
+    /// delete the keys, always freeing big values in the background, and return how many existed
+    pub(crate) fn unlink_vec(&mut self, keys: &[String]) -> usize {
+        keys.iter().filter(|key| self.user_delete(key, true)).count()
+    }
+
+    /// a deletion asked for by a client, which publishes the `del` event
+    fn user_delete(&mut self, key: &str, lazy: bool) -> bool {
+        let deleted = self.delete(key, lazy);
+        if deleted {
+            self.notify(KeyspaceEvents::GENERIC, "del", key);
         }
+        deleted
     }
 
-    pub(crate) fn remove_vec(&mut self, keys: &Vec<String>) {
-        for key in keys {
-            self.remove(key);
+    /// detach the key from the keyspace and free its value, lazily if asked to.
+    /// Returns false if the key did not exist or had already expired
+    pub(crate) fn delete(&mut self, key: &str, lazy: bool) -> bool {
+        match self.remove_entry(key) {
+            Some(entry) => {
+                self.touch(key);
+                let alive = !entry.is_expired();
+                if let Some(when) = entry.expire_at {
+                    self.expirations.remove(&(when, key.to_string()));
+                }
+                free_value(entry.data, lazy);
+                alive
+            }
+            None => false,
+        }
+    }
+
+    /// remove every key. With `lazy` the old keyspace is swapped out and dropped on the rayon pool
+    pub(crate) fn flush(&mut self, lazy: bool) {
+        self.touch_all();
+        let entries = std::mem::take(&mut self.entries);
+        let keys = std::mem::take(&mut self.keys);
+        let expirations = std::mem::take(&mut self.expirations);
+        self.used_memory = 0;
+
+        if lazy {
+            rayon::spawn(move || drop((entries, keys, expirations)));
         }
     }
 
+    /// the live keys that hash to `slot`
+    pub(crate) fn keys_in_slot(&self, slot: u16) -> impl Iterator<Item = &String> {
+        self.entries.iter()
+            .filter(move |(key, entry)| !entry.is_expired() && key_hash_slot(key.as_bytes()) == slot)
+            .map(|(key, _)| key)
+    }
+
+    /// a point in time copy of the keyspace for SAVE and BGSAVE.
+    /// Values are cloned, which for strings only bumps the reference count of their `Bytes`
+    pub(crate) fn snapshot(&self) -> Vec<SnapshotEntry> {
+        self.entries.iter()
+            .filter(|(_, entry)| !entry.is_expired())
+            .map(|(key, entry)| (key.clone(), entry.data.clone(), entry.expire_at.map(instant_to_unix_ms)))
+            .collect()
+    }
+
+    /// put back keys loaded from a snapshot, skipping the ones that expired in the meantime.
+    /// Returns how many keys were loaded
+    pub(crate) fn restore(&mut self, entries: Vec<SnapshotEntry>) -> usize {
+        let now = unix_ms();
+        let mut loaded = 0;
+        for (key, data, expire_at) in entries {
+            if expire_at.is_some_and(|when| when <= now) {
+                continue;
+            }
+            let expire_at = expire_at.map(unix_ms_to_instant);
+            if let Some(when) = expire_at {
+                self.expirations.insert((when, key.clone()));
+            }
+            self.insert_entry(key, Entry::new(data, expire_at));
+            loaded += 1;
+        }
+        loaded
+    }
+
+    /// SAVE: write the snapshot from the calling thread, blocking every client meanwhile
+    pub(crate) fn save(&mut self) -> std::io::Result<()> {
+        // both would write the same temp file
+        if self.save_state.in_progress() {
+            return Err(std::io::Error::other("Background save already in progress"));
+        }
+        let entries = self.snapshot();
+        let dirty = self.save_state.changes();
+        let result = rdb::save(&self.config.rdb_path(), &entries);
+        self.save_state.finished(result.is_ok(), dirty);
+        result
+    }
+
+    /// BGSAVE: take the snapshot under the lock and write it from another thread.
+    /// Returns false if a background save is already running
+    pub(crate) fn bgsave(&mut self) -> bool {
+        if !self.save_state.start() {
+            return false;
+        }
+        let entries = self.snapshot();
+        let dirty = self.save_state.changes();
+        let path = self.config.rdb_path();
+        let state = self.save_state.clone();
+
+        std::thread::spawn(move || {
+            let result = rdb::save(&path, &entries);
+            if let Err(e) = &result {
+                eprintln!("Background saving error: {}", e);
+            }
+            state.finished(result.is_ok(), dirty);
+        });
+        true
+    }
+
+    /// MEMORY USAGE: the bytes of a key with its value and TTL, without counting as an access
+    pub(crate) fn key_memory_usage(&self, key: &str, samples: usize) -> Option<usize> {
+        let entry = self.entries.get(key).filter(|entry| !entry.is_expired())?;
+        let ttl = entry.expire_at.map_or(0, |_| EXPIRE_OVERHEAD + key.len());
+        Some(ENTRY_OVERHEAD + 2 * key.len() + entry.data.sampled_memory_usage(samples) + ttl)
+    }
+
+    /// the most the keyspace ever took
+    pub(crate) fn peak_memory(&self) -> usize {
+        self.peak_memory
+    }
+
+    /// number of keys, including expired ones not collected yet
+    pub(crate) fn key_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// how many keys have a TTL and their average time left in milliseconds, for INFO keyspace
+    pub(crate) fn expires(&self) -> (usize, u64) {
+        let now = Instant::now();
+        let total: u128 = self.expirations.iter()
+            .map(|(when, _)| when.saturating_duration_since(now).as_millis())
+            .sum();
+        let count = self.expirations.len();
+        (count, total.checked_div(count as u128).unwrap_or(0) as u64)
+    }
+
+    /// the append only file is being compacted by BGREWRITEAOF
+    pub(crate) fn aof_rewriting(&self) -> bool {
+        self.aof.as_ref().is_some_and(Aof::rewrite_in_progress)
+    }
+
+    /// why the append only file can't be written, writes are refused until it can again
+    pub(crate) fn aof_write_error(&self) -> Option<&str> {
+        self.aof.as_ref()?.write_error()
+    }
+
+    /// bytes taken by the bookkeeping of the keys rather than by their names and values:
+    /// the main hash table and the tree of expiries
+    pub(crate) fn keyspace_overhead(&self) -> (usize, usize) {
+        let expires = self.expirations.iter().map(|(_, key)| EXPIRE_OVERHEAD + key.len()).sum();
+        (self.entries.len() * ENTRY_OVERHEAD, expires)
+    }
+
+    /// add or replace an entry, keeping `keys` and `used_memory` in step.
+    /// A replaced key keeps its slot in `keys` and its LFU counter
+    fn insert_entry(&mut self, key: String, mut entry: Entry) -> Option<Entry> {
+        self.used_memory += entry_memory(&key, &entry);
+        self.peak_memory = self.peak_memory.max(self.used_memory);
+        match self.entries.get_mut(&key) {
+            Some(prev) => {
+                entry.index = prev.index;
+                entry.lfu.set(prev.lfu.get());
+                let prev = std::mem::replace(prev, entry);
+                self.used_memory -= entry_memory(&key, &prev);
+                Some(prev)
+            }
+            None => {
+                entry.index = self.keys.len();
+                self.keys.push(key.clone());
+                self.entries.insert(key, entry);
+                None
+            }
+        }
+    }
+
+    /// take an entry out of the keyspace, the last key of `keys` moves into its slot
+    fn remove_entry(&mut self, key: &str) -> Option<Entry> {
+        let entry = self.entries.remove(key)?;
+        self.used_memory -= entry_memory(key, &entry);
+        self.keys.swap_remove(entry.index);
+        if let Some(moved) = self.keys.get(entry.index) {
+            if let Some(moved) = self.entries.get_mut(moved) {
+                moved.index = entry.index;
+            }
+        }
+        Some(entry)
+    }
+
     pub(crate) fn remove(&mut self, key: &str) -> Option<Bytes> {
-        let prev = self.entries.remove(key);
+        self.touch(key);
+        let prev = self.remove_entry(key);
         //去掉在expiration中对应的信息
         if let Some(prev) = prev {
             if let Some(when) = prev.expire_at {
@@ -118,17 +809,89 @@ impl Store {
 }
 
 impl Db {
-    pub(crate) fn new(notify_shutdown: Receiver<()>) -> SharedDb {
-        let db = Arc::new(Self { shared: Mutex::new(Store::new()), background_task: Notify::new(), notify_shutdown });
+    pub(crate) fn new(notify_shutdown: Receiver<()>, config: Config) -> SharedDb {
+        let pub_sub = Arc::new(PubSub::default());
+        let db = Arc::new(Self {
+            shared: Mutex::new(Store::new(config, pub_sub.clone())),
+            background_task: Notify::new(),
+            next_client_id: AtomicU64::new(1),
+            pub_sub,
+            notify_shutdown,
+        });
 
         tokio::spawn(purge_expired_tasks(db.clone()));
+        tokio::spawn(auto_save_task(db.clone()));
+        tokio::spawn(aof_task(db.clone()));
+        tokio::spawn(stats_task(db.clone()));
+        if db.lock().cluster.is_some() {
+            tokio::spawn(cluster::cluster_task(db.clone()));
+        }
         db
     }
 
+    /// load the data saved by a previous run. With `appendonly` the AOF is replayed and then
+    /// kept open for appending, otherwise the RDB file is loaded.
+    /// Returns how many keys there are afterwards
+    pub(crate) fn load(&self) -> std::io::Result<usize> {
+        let mut store = self.lock();
+        if let Some(cluster) = store.cluster.as_mut() {
+            cluster.load()?;
+        }
+        if !store.config.appendonly {
+            let entries = rdb::load(&store.config.rdb_path())?.unwrap_or_default();
+            return Ok(store.restore(entries));
+        }
+
+        let config = store.config.clone();
+        let dir = config.aof_dir();
+        // an AOF from before the multi-part layout is upgraded in place
+        let legacy = config.dir.join(&config.appendfilename);
+        match aof::load(&dir, &config.appendfilename, &legacy, config.aof_load_truncated, config.aof_load_repair)? {
+            Some(contents) => {
+                store.restore(contents.preamble);
+                // the AOF is not open yet, so replaying logs nothing
+                for frame in contents.commands {
+                    match Cmd::try_from(frame) {
+                        Ok(cmd) => {
+                            let _ = cmd.apply(&mut store);
+                        }
+                        Err(e) => eprintln!("Skipping an invalid command in the AOF: {}", e),
+                    }
+                }
+                store.aof = Some(Aof::open(&dir, &config.appendfilename, config.appendfsync)?);
+            }
+            None => {
+                // turning appendonly on for an existing dataset: start the AOF from the RDB file
+                let entries = rdb::load(&config.rdb_path())?.unwrap_or_default();
+                store.restore(entries);
+                let snapshot = store.snapshot();
+                store.aof = Some(Aof::create(&dir, &config.appendfilename, config.appendfsync, &snapshot)?);
+            }
+        }
+        Ok(store.entries.len())
+    }
+
+    /// flush the AOF to disk, at shutdown
+    pub(crate) fn sync_aof(&self) -> std::io::Result<()> {
+        match self.lock().aof.as_mut() {
+            Some(aof) => aof.sync(),
+            None => Ok(()),
+        }
+    }
 
     pub(crate) fn lock(&self) -> MutexGuard<Store> {
         self.shared.lock().unwrap()
     }
+
+    /// every connection gets its own id, used to track its WATCHed keys
+    pub(crate) fn next_client_id(&self) -> u64 {
+        self.next_client_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// the pub/sub hub, reachable without taking the store lock
+    pub(crate) fn pub_sub(&self) -> &PubSub {
+        &self.pub_sub
+    }
     // 获取key信息
 
     /// purge all expired keys and return the Instant at which the next
@@ -146,61 +909,317 @@ impl Db {
                 return Some(when);
             }
             //已经过期了，可以去掉了
-            shared.entries.remove(key);
+            let key = key.clone();
             shared.expirations.remove(&(when, key.clone()));
+            if shared.entries.get(&key).is_some_and(Entry::is_expired) {
+                shared.expire(&key);
+            }
         }
         None
     }
 }
 
-impl Entry {}
+impl Entry {
+    fn new(data: RedisDataType, expire_at: Option<Instant>) -> Self {
+        Self {
+            memory: data.memory_usage(),
+            data,
+            expire_at,
+            index: 0,
+            lru: Cell::new(unix_ms()),
+            lfu: Cell::new(evict::lfu_init()),
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        matches!(self.expire_at, Some(expire_at) if expire_at < Instant::now())
+    }
+}
 
 impl From<(Bytes, Option<Instant>)> for Entry {
     fn from(value: (Bytes, Option<Instant>)) -> Self {
-        Self {
-            data: RedisDataType::Bytes(value.0),
-            expire_at: value.1,
-        }
+        Self::new(RedisDataType::Bytes(value.0), value.1)
     }
 }
 
 impl From<(Vec<Bytes>, Option<Instant>)> for Entry {
     fn from(value: (Vec<Bytes>, Option<Instant>)) -> Self {
-        Self {
-            data: RedisDataType::List(value.0),
-            expire_at: value.1,
-        }
+        Self::new(RedisDataType::List(value.0), value.1)
     }
 }
 
-
 //作用于后台，异步处理过期的key数据
 async fn purge_expired_tasks(db: SharedDb) {
     loop {
 
-        //获取到过期的keys
-        if let Some(when) = db.purge_expired_keys() {
-            //睡眠，直到那个时间为止
-            time::sleep_until(when);
+        //获取到过期的keys, 删除掉, 并拿到下一个过期的时间
+        let next = db.purge_expired_keys();
+
+        // 新设置的key可能会更早过期，所以最多只睡眠一个周期
+        let deadline = Instant::now() + ACTIVE_EXPIRE_PERIOD;
+        let when = next.map_or(deadline, |when| when.min(deadline));
+
+        //睡眠，直到那个时间为止, 或者被其他任务叫醒
+        tokio::select! {
+            _ = time::sleep_until(when) => {}
+            _ = db.background_task.notified() => {}
         }
+    }
+}
+
+/// BGSAVE when one of the `save <seconds> <changes>` rules is met
+async fn auto_save_task(db: SharedDb) {
+    let mut interval = time::interval(AUTO_SAVE_PERIOD);
+    loop {
+        interval.tick().await;
+
+        let mut store = db.lock();
+        let state = store.save_state.clone();
+        if state.in_progress() {
+            continue;
+        }
+
+        let now = unix_secs();
+        let since_save = now.saturating_sub(state.last_save());
+        let changes = state.changes();
+        // 失败之后等一会再试，不要每秒都去写
+        let retry = state.last_ok() || now.saturating_sub(state.last_attempt()) >= BGSAVE_RETRY_DELAY;
+        let rule_met = store.config.save.iter()
+            .any(|&(seconds, min_changes)| changes >= min_changes && since_save >= seconds);
+
+        if (rule_met && retry) || state.take_scheduled() {
+            store.bgsave();
+        }
+    }
+}
+
+/// sample the command rate for `instantaneous_ops_per_sec`
+async fn stats_task(db: SharedDb) {
+    let mut interval = time::interval(OPS_SAMPLE_PERIOD);
+    loop {
+        interval.tick().await;
+        db.lock().stats.sample_ops();
+    }
+}
+
+/// fsync the AOF every second under `appendfsync everysec` and finish AOF rewrites
+async fn aof_task(db: SharedDb) {
+    let mut interval = time::interval(ACTIVE_EXPIRE_PERIOD);
+    loop {
+        interval.tick().await;
+
+        let file = db.lock().aof.as_mut().and_then(Aof::poll);
+        // 刷盘不能持有锁
+        if let Some(file) = file {
+            if let Ok(Err(e)) = tokio::task::spawn_blocking(move || file.sync_data()).await {
+                eprintln!("Error syncing the AOF file: {}", e);
+                if let Some(aof) = db.lock().aof.as_mut() {
+                    aof.fsync_failed(&e);
+                }
+            }
+        }
+    }
+}
+
+/// the config of test databases: no `save` rules and no AOF, so that nothing is written to
+/// the working directory unless a test asks for it
+#[cfg(test)]
+pub(crate) fn test_config() -> Config {
+    Config { save: vec![], appendonly: false, ..Config::default() }
+}
+
+/// a `Db` for tests, `config` is usually built on `test_config()`
+#[cfg(test)]
+pub(crate) fn test_db(config: Config) -> SharedDb {
+    let (sender, _) = tokio::sync::broadcast::channel(1);
+    Db::new(sender.subscribe(), config)
+}
 
-        //等待其他任务，把自己叫醒
-        db.background_task.notified().await;
+/// run a request against `store` and return its reply, `args` being the words of the request.
+/// A request that does not parse gets the error reply a client would get
+#[cfg(test)]
+pub(crate) fn run(store: &mut Store, args: &str) -> crate::frame::Frame {
+    run_from(store, args, &crate::slowlog::ClientInfo::default())
+}
 
-        //找到所有时间小于单签时间的任务，并将它删除掉
+/// `run` for a request of `client`
+#[cfg(test)]
+pub(crate) fn run_from(store: &mut Store, args: &str, client: &crate::slowlog::ClientInfo) -> crate::frame::Frame {
+    use crate::frame::Frame;
 
-        //然后通知后台进行删除掉
+    let argv: Vec<Bytes> = args.split(' ').map(|arg| Bytes::from(arg.to_string())).collect();
+    let frame = Frame::Array(argv.iter().cloned().map(Frame::Bulk).collect());
+    match Cmd::try_from(frame) {
+        Ok(cmd) => cmd.call_from(store, argv, client).unwrap(),
+        Err(e) => e.into(),
     }
 }
 
 #[cfg(test)]
 mod test {
-    use tokio::sync::broadcast;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::time::Instant;
 
-    use crate::db::{Db, SharedDb};
+    use crate::config::Config;
+    use crate::db::{RedisDataType, SharedDb, run, test_config, test_db};
+    use crate::frame::Frame;
+    use crate::pub_sub::{Push, Subscriber};
 
     fn init_db() -> SharedDb {
-        let (sender, _) = broadcast::channel(1);
-        Db::new(sender.subscribe())
+        test_db(test_config())
+    }
+
+    #[tokio::test]
+    async fn unlink_test() {
+        let db = init_db();
+        let mut store = db.lock();
+
+        let list: Vec<Bytes> = (0..1000).map(|i| Bytes::from(i.to_string())).collect();
+        store.insert_entry("big".to_string(), (list, None).into());
+        store.set_bytes("small", Bytes::from("v"), None);
+
+        let keys = vec!["big".to_string(), "small".to_string(), "missing".to_string()];
+        assert_eq!(store.unlink_vec(&keys), 2);
+        assert!(store.entries.is_empty());
+        assert_eq!(store.get_bytes("small"), None);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn expired_event_test() {
+        let db = test_db(Config { notify_keyspace_events: "Ex".parse().unwrap(), ..test_config() });
+
+        let (subscriber, mut receiver) = Subscriber::new(1, 0);
+        db.pub_sub().subscribe(Bytes::from("__keyevent@0__:expired"), &subscriber);
+
+        {
+            let mut store = db.lock();
+            let expired = Instant::now() - Duration::from_millis(1);
+            store.set_bytes("lazy", Bytes::from("v"), Some(expired));
+            store.set_bytes("active", Bytes::from("v"), Some(Instant::now() + Duration::from_millis(20)));
+
+            // lazy expiry on access
+            assert_eq!(store.get_bytes("lazy"), None);
+        }
+        match receiver.recv().await {
+            Some(Push::Message(Frame::Array(message), _)) => assert!(matches!(&message[2], Frame::Bulk(key) if key == "lazy")),
+            other => panic!("unexpected push {:?}", other),
+        }
+
+        // active expiry from the background task
+        match tokio::time::timeout(Duration::from_secs(1), receiver.recv()).await {
+            Ok(Some(Push::Message(Frame::Array(message), _))) => assert!(matches!(&message[2], Frame::Bulk(key) if key == "active")),
+            other => panic!("unexpected push {:?}", other),
+        }
+        assert!(db.lock().entries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn flush_test() {
+        let db = init_db();
+        let mut store = db.lock();
+
+        for i in 0..100 {
+            store.set_bytes(i, Bytes::from("v"), None);
+        }
+        store.flush(true);
+
+        assert!(store.entries.is_empty());
+        assert!(store.expirations.is_empty());
+        assert_eq!(store.get_bytes(1), None);
+    }
+
+    #[tokio::test]
+    async fn save_load_test() {
+        let dir = std::env::temp_dir().join(format!("mini-redis-rdb-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config { dir: dir.clone(), ..test_config() };
+
+        let db = test_db(config.clone());
+        {
+            let mut store = db.lock();
+            store.set_bytes("string", Bytes::from("v"), Some(Instant::now() + Duration::from_secs(100)));
+            store.set_list("list", vec![Bytes::from("a"), Bytes::from("b")]);
+            store.set_bytes("gone", Bytes::from("v"), Some(Instant::now() - Duration::from_millis(1)));
+            assert!(store.save_state.changes() > 0);
+
+            store.save().unwrap();
+            assert_eq!(store.save_state.changes(), 0);
+        }
+
+        let restarted = test_db(config);
+        assert_eq!(restarted.load().unwrap(), 2);
+        let mut store = restarted.lock();
+        assert_eq!(store.get_bytes("string"), Some(Bytes::from("v")));
+        assert!(store.entries["string"].expire_at.is_some());
+        assert!(matches!(store.get_data("list"), Some(RedisDataType::List(list)) if list.len() == 2));
+        assert!(store.get_data("gone").is_none());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn aof_replay_test() {
+        let dir = std::env::temp_dir().join(format!("mini-redis-aof-replay-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = Config { dir: dir.clone(), appendonly: true, ..test_config() };
+
+        let db = test_db(config.clone());
+        assert_eq!(db.load().unwrap(), 0);
+        {
+            let mut store = db.lock();
+            run(&mut store, "SET counter 10 EX 100");
+            run(&mut store, "INCR counter");
+            run(&mut store, "SET gone 1");
+            run(&mut store, "DEL gone");
+            run(&mut store, "GET counter");
+        }
+        db.sync_aof().unwrap();
+
+        let incr = config.aof_dir().join("appendonly.aof.1.incr.aof");
+        let log = String::from_utf8_lossy(&std::fs::read(&incr).unwrap()).to_string();
+        assert!(log.contains("PXAT"));
+        assert!(!log.contains("GET"));
+
+        // the rewrite starts a new base and incremental file and drops the old ones
+        db.lock().bgrewriteaof().unwrap();
+        run(&mut db.lock(), "SET during 1");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        run(&mut db.lock(), "DEL during");
+        assert!(!incr.exists());
+        assert!(config.aof_dir().join("appendonly.aof.2.base.rdb").exists());
+        assert!(config.aof_dir().join("appendonly.aof.2.incr.aof").exists());
+
+        let restarted = test_db(config);
+        assert_eq!(restarted.load().unwrap(), 1);
+        let mut store = restarted.lock();
+        assert_eq!(store.get_bytes("counter"), Some(Bytes::from("11")));
+        assert!(store.expire_at("counter").is_some());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn aof_error_test() {
+        let dir = std::env::temp_dir().join(format!("mini-redis-aof-error-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = test_db(Config { dir: dir.clone(), appendonly: true, ..test_config() });
+        db.load().unwrap();
+        let mut store = db.lock();
+
+        // writes are refused until the file can be written again, reads still work
+        store.aof.as_mut().unwrap().fsync_failed(&std::io::Error::other("No space left on device"));
+        assert!(matches!(run(&mut store, "SET a 1"), Frame::Error(e) if e == "MISCONF Errors writing to the AOF file: No space left on device"));
+        assert!(matches!(run(&mut store, "GET a"), Frame::Null));
+        assert!(matches!(run(&mut store, "INFO persistence"), Frame::Bulk(info) if info.windows(25).any(|line| line == b"aof_last_write_status:err")));
+        assert_eq!(store.stats.commands["set"].rejected_calls, 1);
+
+        store.aof.as_mut().unwrap().poll();
+        assert!(store.aof_write_error().is_none());
+        assert!(matches!(run(&mut store, "SET a 1"), Frame::Simple(_)));
+        drop(store);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
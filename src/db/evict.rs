@@ -0,0 +1,361 @@
+//! `maxmemory` and the eviction policies.
+//!
+//! Like Redis the eviction is approximate: instead of keeping every key ordered by last access
+//! or frequency, a few keys are sampled and the best candidates kept in a small pool sorted by
+//! how much they deserve to go. The pool survives the samplings of one eviction run, so each
+//! sampling refines the candidates left by the previous one.
+//!
+//! Each entry carries its last access time for LRU and a logarithmic counter for LFU:
+//! the counter grows with a probability that shrinks as it grows, so 8 bits are enough to tell
+//! a few hits from millions, and it is decremented once every `lfu-decay-time` minutes
+//! without access, so formerly hot keys cool down
+
+use std::time::Duration;
+
+use bytes::Bytes;
+
+use crate::clock::{unix_ms, unix_secs};
+use crate::config::{KeyspaceEvents, MaxmemoryPolicy};
+use crate::db::{Entry, Store};
+
+/// candidates kept between two samplings
+const EVICTION_POOL_SIZE: usize = 16;
+
+/// the counter of new keys, so that they are not evicted before they had a chance to be used
+const LFU_INIT_VAL: u32 = 5;
+
+/// the LFU clock: minutes, wrapping at 16 bits
+fn lfu_minutes() -> u32 {
+    ((unix_secs() / 60) & 0xffff) as u32
+}
+
+/// the LFU field of a new entry
+pub(super) fn lfu_init() -> u32 {
+    (lfu_minutes() << 8) | LFU_INIT_VAL
+}
+
+/// minutes since `ldt`, the LFU clock wraps around every ~45 days
+fn lfu_elapsed(ldt: u32) -> u64 {
+    let now = lfu_minutes();
+    if now >= ldt {
+        (now - ldt) as u64
+    } else {
+        (65535 - ldt + now) as u64
+    }
+}
+
+/// the counter of an LFU field, decremented once per `decay_time` minutes since its last decrement
+fn lfu_decayed(lfu: u32, decay_time: u64) -> u32 {
+    let counter = lfu & 0xff;
+    let periods = lfu_elapsed(lfu >> 8).checked_div(decay_time).unwrap_or(0);
+    counter.saturating_sub(periods.min(255) as u32)
+}
+
+/// increment the counter with a probability of 1 / ((counter - LFU_INIT_VAL) * factor + 1).
+/// `random` is uniform in [0, 1)
+fn lfu_log_incr(counter: u32, factor: u32, random: f64) -> u32 {
+    if counter >= 255 {
+        return 255;
+    }
+    let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    if random < 1.0 / (base * factor as f64 + 1.0) {
+        counter + 1
+    } else {
+        counter
+    }
+}
+
+impl Store {
+    /// xorshift64, good enough to pick samples and roll the LFU dice
+    fn random(&self) -> u64 {
+        let mut x = self.rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.rng.set(x);
+        x
+    }
+
+    /// a key was read or written: refresh its LRU clock and bump its LFU counter
+    pub(super) fn record_access(&self, entry: &Entry) {
+        entry.lru.set(unix_ms());
+        let counter = lfu_decayed(entry.lfu.get(), self.config.lfu_decay_time);
+        let random = (self.random() >> 11) as f64 / (1u64 << 53) as f64;
+        let counter = lfu_log_incr(counter, self.config.lfu_log_factor, random);
+        entry.lfu.set((lfu_minutes() << 8) | counter);
+    }
+
+    /// set the idle time in seconds or the LFU counter of a key, for RESTORE
+    pub(crate) fn set_access(&self, key: &str, idle_time: Option<u64>, freq: Option<u32>) {
+        if let Some(entry) = self.entries.get(key) {
+            if let Some(seconds) = idle_time {
+                entry.lru.set(unix_ms().saturating_sub(seconds.saturating_mul(1000)));
+            }
+            if let Some(freq) = freq {
+                entry.lfu.set((lfu_minutes() << 8) | freq.min(255));
+            }
+        }
+    }
+
+    /// OBJECT FREQ: the LFU counter of a key, as decayed by now
+    pub(crate) fn object_freq(&self, key: &str) -> Option<u32> {
+        let entry = self.entries.get(key).filter(|entry| !entry.is_expired())?;
+        Some(lfu_decayed(entry.lfu.get(), self.config.lfu_decay_time))
+    }
+
+    /// OBJECT IDLETIME: seconds since the key was last accessed
+    pub(crate) fn object_idletime(&self, key: &str) -> Option<u64> {
+        let entry = self.entries.get(key).filter(|entry| !entry.is_expired())?;
+        Some(unix_ms().saturating_sub(entry.lru.get()) / 1000)
+    }
+
+    /// estimated bytes taken by the keyspace
+    pub(crate) fn used_memory(&self) -> usize {
+        self.used_memory
+    }
+
+    /// keys evicted since startup
+    pub(crate) fn evicted_keys(&self) -> u64 {
+        self.evicted_keys
+    }
+
+    /// evict keys until the keyspace fits in `maxmemory` again.
+    /// Returns false if it still does not fit, because of `noeviction` or because the policy
+    /// found nothing it may evict. Replicas leave eviction to their master, whose DELs they get
+    pub(crate) fn evict(&mut self) -> bool {
+        let maxmemory = self.config.maxmemory;
+        if maxmemory == 0 || self.replication.is_replica() {
+            return true;
+        }
+
+        let mut pool = Vec::with_capacity(EVICTION_POOL_SIZE + 1);
+        while self.used_memory > maxmemory {
+            match self.pick_victim(&mut pool) {
+                Some(key) => self.evict_key(&key),
+                None => return false,
+            }
+        }
+        true
+    }
+
+    fn pick_victim(&self, pool: &mut Vec<(u64, String)>) -> Option<String> {
+        let policy = self.config.maxmemory_policy;
+        match policy {
+            MaxmemoryPolicy::NoEviction => None,
+            MaxmemoryPolicy::AllKeysRandom => self.sample_keys(1).pop(),
+            MaxmemoryPolicy::VolatileRandom => self.sample_volatile(1).pop(),
+            // expiries are already kept in order, no need to sample
+            MaxmemoryPolicy::VolatileTtl => self.expirations.first().map(|(_, key)| key.clone()),
+            _ => loop {
+                let samples = if policy.is_volatile() {
+                    self.sample_volatile(self.config.maxmemory_samples)
+                } else {
+                    self.sample_keys(self.config.maxmemory_samples)
+                };
+                for key in samples {
+                    if let Some(score) = self.eviction_score(&key, policy) {
+                        pool_insert(pool, score, key);
+                    }
+                }
+                // the best candidate is last, earlier evictions may have removed some of them
+                while let Some((_, key)) = pool.pop() {
+                    if self.entries.contains_key(&key) {
+                        return Some(key);
+                    }
+                }
+                if self.keys.is_empty() || (policy.is_volatile() && self.expirations.is_empty()) {
+                    return None;
+                }
+            },
+        }
+    }
+
+    /// the higher the score, the sooner the key goes
+    fn eviction_score(&self, key: &str, policy: MaxmemoryPolicy) -> Option<u64> {
+        let entry = self.entries.get(key)?;
+        match policy {
+            MaxmemoryPolicy::AllKeysLru | MaxmemoryPolicy::VolatileLru => Some(unix_ms().saturating_sub(entry.lru.get())),
+            MaxmemoryPolicy::AllKeysLfu | MaxmemoryPolicy::VolatileLfu =>
+                Some(255 - lfu_decayed(entry.lfu.get(), self.config.lfu_decay_time) as u64),
+            _ => None,
+        }
+    }
+
+    /// `count` keys picked at random, possibly the same one twice
+    fn sample_keys(&self, count: usize) -> Vec<String> {
+        if self.keys.is_empty() {
+            return vec![];
+        }
+        (0..count)
+            .map(|_| self.keys[(self.random() % self.keys.len() as u64) as usize].clone())
+            .collect()
+    }
+
+    /// `count` keys with a TTL: the first expiry at or after a random point between the
+    /// soonest and the latest expiry. Keys after a long gap come up more often, which is
+    /// fine for an approximation
+    fn sample_volatile(&self, count: usize) -> Vec<String> {
+        let (Some((first, _)), Some((last, _))) = (self.expirations.first(), self.expirations.last()) else {
+            return vec![];
+        };
+        let span = last.duration_since(*first).as_nanos() as u64;
+        (0..count)
+            .filter_map(|_| {
+                let at = *first + Duration::from_nanos(self.random() % span.max(1));
+                self.expirations.range((at, String::new())..).next().map(|(_, key)| key.clone())
+            })
+            .collect()
+    }
+
+    /// remove a key to make room, it is logged as a DEL like an expiry
+    fn evict_key(&mut self, key: &str) {
+        self.delete(key, self.config.lazyfree_lazy_eviction);
+        self.evicted_keys += 1;
+        self.notify(KeyspaceEvents::EVICTED, "evicted", key);
+        self.propagate(&[Bytes::from_static(b"DEL"), Bytes::from(key.to_string())]);
+    }
+}
+
+/// keep the pool sorted by score, dropping the worst candidate when it is full
+fn pool_insert(pool: &mut Vec<(u64, String)>, score: u64, key: String) {
+    if pool.iter().any(|(_, k)| *k == key) {
+        return;
+    }
+    if pool.len() >= EVICTION_POOL_SIZE && score <= pool[0].0 {
+        return;
+    }
+    let at = pool.partition_point(|(s, _)| *s < score);
+    pool.insert(at, (score, key));
+    if pool.len() > EVICTION_POOL_SIZE {
+        pool.remove(0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{Config, MaxmemoryPolicy};
+    use crate::db::{ENTRY_OVERHEAD, SharedDb, Store, run, test_config, test_db};
+    use crate::db::evict::{lfu_decayed, lfu_log_incr, LFU_INIT_VAL};
+    use crate::frame::Frame;
+
+    fn init_db(maxmemory: usize, maxmemory_policy: MaxmemoryPolicy) -> SharedDb {
+        test_db(Config { maxmemory, maxmemory_policy, ..test_config() })
+    }
+
+    #[test]
+    fn lfu_counter_test() {
+        // new keys always count their first hits, a saturated counter stays put
+        assert_eq!(lfu_log_incr(LFU_INIT_VAL, 10, 0.99), LFU_INIT_VAL + 1);
+        assert_eq!(lfu_log_incr(LFU_INIT_VAL + 10, 10, 0.5), LFU_INIT_VAL + 10);
+        assert_eq!(lfu_log_incr(LFU_INIT_VAL + 10, 10, 0.001), LFU_INIT_VAL + 11);
+        assert_eq!(lfu_log_incr(255, 10, 0.0), 255);
+
+        // the clock wraps at 16 bits, 10 minutes ago with a decay time of 1 minute
+        let now = ((crate::clock::unix_secs() / 60) & 0xffff) as u32;
+        let ten_minutes_ago = (now + 65535 - 10) % 65535;
+        assert_eq!(lfu_decayed((ten_minutes_ago << 8) | 30, 1), 20);
+        assert_eq!(lfu_decayed((ten_minutes_ago << 8) | 30, 0), 30);
+        assert_eq!(lfu_decayed((ten_minutes_ago << 8) | 3, 1), 0);
+    }
+
+    #[tokio::test]
+    async fn allkeys_lru_test() {
+        let db = init_db(0, MaxmemoryPolicy::AllKeysLru);
+        let mut store = db.lock();
+        for i in 0..20 {
+            run(&mut store, &format!("SET key:{:02} 0123456789", i));
+        }
+        // the first 10 keys have been idle for a while, the last 10 were just used
+        for i in 0..20 {
+            let idle = if i < 10 { 100 } else { 0 };
+            store.set_access(&format!("key:{:02}", i), Some(idle), None);
+        }
+
+        store.config.maxmemory = store.used_memory() / 2;
+        store.config.maxmemory_samples = 20;
+        // any command makes room before it runs
+        assert!(matches!(run(&mut store, "GET key:10"), Frame::Bulk(_)));
+        assert!(store.used_memory() <= store.config.maxmemory);
+        assert_eq!(store.evicted_keys(), 10);
+        for i in 0..10 {
+            assert!(store.get_data(&format!("key:{:02}", i)).is_none());
+        }
+        for i in 10..20 {
+            assert!(store.get_data(&format!("key:{:02}", i)).is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn noeviction_test() {
+        let db = init_db(0, MaxmemoryPolicy::NoEviction);
+        let mut store = db.lock();
+        run(&mut store, "SET a 1");
+        store.config.maxmemory = 1;
+
+        let reply = run(&mut store, "SET b 2");
+        assert!(matches!(reply, Frame::Error(e) if e.starts_with("OOM")));
+        assert!(store.get_data("b").is_none());
+        // reads and deletions still work, and free the memory
+        assert!(matches!(run(&mut store, "GET a"), Frame::Bulk(_)));
+        assert!(matches!(run(&mut store, "DEL a"), Frame::Integer(1)));
+        assert_eq!(store.used_memory(), 0);
+        assert_eq!(store.evicted_keys(), 0);
+    }
+
+    #[tokio::test]
+    async fn volatile_ttl_test() {
+        let db = init_db(0, MaxmemoryPolicy::VolatileTtl);
+        let mut store = db.lock();
+        run(&mut store, "SET persistent 1");
+        run(&mut store, "SET soon 1 EX 10");
+        run(&mut store, "SET later 1 EX 1000");
+        store.config.maxmemory = store.used_memory() - 1;
+
+        assert!(matches!(run(&mut store, "GET persistent"), Frame::Bulk(_)));
+        assert!(store.get_data("soon").is_none());
+        assert!(store.get_data("later").is_some());
+
+        // only keys without a TTL are left, writes are refused
+        run(&mut store, "DEL later");
+        store.config.maxmemory = 1;
+        let reply = run(&mut store, "SET other 1");
+        assert!(matches!(reply, Frame::Error(e) if e.starts_with("OOM")));
+        assert!(store.get_data("persistent").is_some());
+    }
+
+    #[tokio::test]
+    async fn used_memory_test() {
+        let db = init_db(0, MaxmemoryPolicy::NoEviction);
+        let mut store = db.lock();
+        run(&mut store, "SET a 1");
+        let one = store.used_memory();
+        assert!(one > 0);
+        run(&mut store, "SET b 2 EX 100");
+        run(&mut store, "SET a 1234567890");
+        assert_eq!(store.used_memory(), one * 2 + 9);
+        run(&mut store, "APPEND b 345");
+        run(&mut store, "DEL a");
+        run(&mut store, "UNLINK b");
+        assert_eq!(store.used_memory(), 0);
+        assert!(store.keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn collection_memory_test() {
+        let db = init_db(0, MaxmemoryPolicy::NoEviction);
+        let mut store = db.lock();
+        let measured = |store: &Store| store.entries.iter()
+            .map(|(key, entry)| ENTRY_OVERHEAD + 2 * key.len() + entry.data.memory_usage())
+            .sum::<usize>();
+
+        // the writes that change a collection in place only report what they add and remove
+        for i in 0..50 {
+            run(&mut store, &format!("HSET hash field{} {}", i % 20, "v".repeat(i)));
+            run(&mut store, &format!("LPUSH list {}", "e".repeat(i)));
+            run(&mut store, &format!("ZADD zset {} member{}", i, i % 30));
+            assert_eq!(store.used_memory(), measured(&store));
+        }
+        run(&mut store, "DEL hash list zset");
+        assert_eq!(store.used_memory(), 0);
+    }
+}
@@ -0,0 +1,191 @@
+//! The engine of the server without the network: commands are parsed and run in process,
+//! against the same keyspace, expiry, eviction and persistence as over TCP.
+//!
+//! `Embedded::execute` runs one command at a time and is enough for most uses. Commands that
+//! keep state on the connection, MULTI/EXEC, WATCH and the subscriptions, need an
+//! `EmbeddedSession`, the in-process counterpart of a client connection.
+//!
+//! Like `Server`, an `Embedded` has to be created inside a tokio runtime, which runs the
+//! active expiry and the automatic saves in the background.
+//!
+//! Unlike `Server`, an engine keeps everything in memory unless asked otherwise: persistence
+//! is turned on by passing `save` rules or `appendonly` to `Embedded::with_config`
+
+use bytes::Bytes;
+use tokio::sync::broadcast;
+
+use crate::cmd::Cmd;
+use crate::codec::RedisFrame;
+use crate::config::Config;
+use crate::db::{Db, SharedDb};
+use crate::frame::Frame;
+use crate::pub_sub::Push;
+use crate::session::Session;
+use crate::RedisResult;
+
+/// A keyspace and its command executor, in process
+pub struct Embedded {
+    db: SharedDb,
+    /// the sending half of the shutdown channel the db listens to, held like `Server` does
+    _shutdown: broadcast::Sender<()>,
+}
+
+/// The state a client connection would have, for MULTI/EXEC, WATCH and Pub/Sub
+pub struct EmbeddedSession {
+    session: Session,
+}
+
+impl Embedded {
+    /// an engine that never touches the disk, see `in_memory_config`
+    pub fn new() -> Self {
+        Self::with_config(Self::in_memory_config())
+    }
+
+    /// the defaults of the server without its `save` rules, and with `appendonly` off.
+    /// The base to build on for an engine that persists some of its data:
+    /// `Config { dir, save: vec![(3600, 1)], ..Embedded::in_memory_config() }`
+    pub fn in_memory_config() -> Config {
+        Config { save: vec![], appendonly: false, ..Config::default() }
+    }
+
+    /// an engine with the given config, which persists to `dir` as its `save` rules
+    /// and `appendonly` say
+    pub fn with_config(config: Config) -> Self {
+        let (shutdown, _) = broadcast::channel(1);
+        let db = Db::new(shutdown.subscribe(), config);
+        Self { db, _shutdown: shutdown }
+    }
+
+    /// load what a previous run persisted, see `Server::load`. Returns how many keys were loaded
+    pub fn load(&self) -> RedisResult<usize> {
+        Ok(self.db.load()?)
+    }
+
+    /// run one command, `args` being the command name followed by its arguments, and return
+    /// its reply. Errors are replies too, like over TCP. Commands that only make sense on a
+    /// connection, such as MULTI or SUBSCRIBE, are refused: they need a `session`
+    pub fn execute(&self, args: Vec<Bytes>) -> RedisFrame {
+        let frame = Frame::Array(args.iter().cloned().map(Frame::Bulk).collect());
+        let reply = match Cmd::try_from(frame) {
+            Ok(cmd) => cmd.call(&mut self.db.lock(), args).unwrap_or_else(|e| Frame::Error(format!("ERR {}", e))),
+            Err(e) => e.into(),
+        };
+        reply.into()
+    }
+
+    /// a new session, as if a client connected
+    pub fn session(&self) -> EmbeddedSession {
+        EmbeddedSession { session: Session::new(self.db.clone()) }
+    }
+}
+
+impl Default for Embedded {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EmbeddedSession {
+    /// run one command in this session. Most commands have exactly one reply,
+    /// SUBSCRIBE and friends reply once per channel
+    pub async fn execute(&mut self, args: Vec<Bytes>) -> Vec<RedisFrame> {
+        let frame = Frame::Array(args.into_iter().map(Frame::Bulk).collect());
+        self.session.handle(frame).await.into_iter().map(RedisFrame::from).collect()
+    }
+
+    /// the next message published on a channel this session subscribed to.
+    /// `None` if the session was disconnected for not reading its messages fast enough
+    pub async fn next_message(&mut self) -> Option<RedisFrame> {
+        match self.session.next_push().await? {
+            Push::Message(message, size) => {
+                self.session.written(size);
+                Some(message.into())
+            }
+            Push::Disconnect => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use crate::codec::RedisFrame;
+    use crate::config::Config;
+    use crate::embedded::Embedded;
+
+    fn args(line: &str) -> Vec<Bytes> {
+        line.split(' ').map(|arg| Bytes::from(arg.to_string())).collect()
+    }
+
+    fn engine() -> Embedded {
+        Embedded::new()
+    }
+
+    #[tokio::test]
+    async fn execute_test() {
+        let engine = engine();
+        assert_eq!(engine.execute(args("SET key value")), RedisFrame::Simple("OK".to_string()));
+        assert_eq!(engine.execute(args("GET key")), RedisFrame::Bulk(Bytes::from("value")));
+        assert_eq!(engine.execute(args("INCR counter")), RedisFrame::Integer(1));
+        assert_eq!(engine.execute(args("HSET hash a 1 b 2")), RedisFrame::Integer(2));
+        assert_eq!(engine.execute(args("TYPE hash")), RedisFrame::Simple("hash".to_string()));
+        assert_eq!(engine.execute(args("DBSIZE")), RedisFrame::Integer(3));
+
+        assert_eq!(engine.execute(args("INCR key")), RedisFrame::Error("ERR value is not an integer or out of range".to_string()));
+        assert!(matches!(engine.execute(args("LPUSH hash x")), RedisFrame::Error(e) if e.starts_with("WRONGTYPE")));
+        assert!(matches!(engine.execute(args("SET key")), RedisFrame::Error(_)));
+        assert!(matches!(engine.execute(args("MULTI")), RedisFrame::Error(_)));
+
+        // the background expiry runs too
+        engine.execute(args("SET short lived PX 20"));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(engine.execute(args("DBSIZE")), RedisFrame::Integer(3));
+    }
+
+    #[tokio::test]
+    async fn session_test() {
+        let engine = engine();
+        let mut session = engine.session();
+        session.execute(args("MULTI")).await;
+        assert_eq!(session.execute(args("INCR a")).await, vec![RedisFrame::Simple("QUEUED".to_string())]);
+        session.execute(args("INCR a")).await;
+        let replies = session.execute(args("EXEC")).await;
+        assert_eq!(replies, vec![RedisFrame::Array(vec![RedisFrame::Integer(1), RedisFrame::Integer(2)])]);
+
+        // a key changed outside of the session aborts its transaction
+        session.execute(args("WATCH a")).await;
+        engine.execute(args("SET a 0"));
+        session.execute(args("MULTI")).await;
+        session.execute(args("INCR a")).await;
+        assert_eq!(session.execute(args("EXEC")).await, vec![RedisFrame::NullArray]);
+
+        let mut subscriber = engine.session();
+        assert_eq!(subscriber.execute(args("SUBSCRIBE news weather")).await.len(), 2);
+        assert_eq!(engine.execute(args("PUBLISH news hello")), RedisFrame::Integer(1));
+        let message = subscriber.next_message().await.unwrap();
+        assert_eq!(message, RedisFrame::Array(vec![
+            RedisFrame::Bulk(Bytes::from("message")),
+            RedisFrame::Bulk(Bytes::from("news")),
+            RedisFrame::Bulk(Bytes::from("hello")),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn persistence_test() {
+        // nothing is saved unless asked for
+        let config = engine().db.lock().config.clone();
+        assert!(config.save.is_empty() && !config.appendonly);
+
+        let dir = std::env::temp_dir().join(format!("embedded-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let engine = Embedded::with_config(Config { dir: dir.clone(), ..Embedded::in_memory_config() });
+        engine.execute(args("SET key value"));
+        assert_eq!(engine.execute(args("SAVE")), RedisFrame::Simple("OK".to_string()));
+        let restarted = Embedded::with_config(Config { dir: dir.clone(), ..Embedded::in_memory_config() });
+        assert_eq!(restarted.load().unwrap(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
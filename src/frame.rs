@@ -6,6 +6,8 @@ use std::vec;
 
 use bytes::{Buf, BufMut, Bytes};
 
+use crate::codec::RedisFrame;
+
 #[derive(Debug)]
 pub(crate) enum Frame {
     Simple(String),
@@ -13,6 +15,9 @@ pub(crate) enum Frame {
     Integer(i64),
     Bulk(Bytes),
     Array(Vec<Frame>),
+    Null,
+    /// `*-1`, the reply of an EXEC aborted by WATCH
+    NullArray,
 }
 
 #[derive(Debug, PartialEq)]
@@ -21,6 +26,8 @@ pub enum FrameError {
     Incomplete,
 
     EndOfStream,
+
+    Other(String),
 }
 
 #[derive(Debug)]
@@ -101,6 +108,12 @@ impl Frame {
                     vec.put_slice(protocol.chunk());
                 }
             }
+            Frame::Null => {
+                vec.put_slice(b"$-1\r\n");
+            }
+            Frame::NullArray => {
+                vec.put_slice(b"*-1\r\n");
+            }
         }
 
         //这个末尾都要统一加的
@@ -114,7 +127,55 @@ impl Frame {
         Frame::Simple("OK".to_string())
     }
     pub(crate) fn nil() -> Frame {
-        Frame::Integer(-1)
+        Frame::Null
+    }
+}
+
+/// a command as RESP, the way it is written to the AOF and the replication stream
+pub(crate) fn encode_command(argv: &[Bytes]) -> Bytes {
+    let frame = Frame::Array(argv.iter().cloned().map(Frame::Bulk).collect());
+    frame.to_protocol().unwrap_or_default()
+}
+
+impl From<RedisFrame> for Frame {
+    fn from(value: RedisFrame) -> Self {
+        match value {
+            RedisFrame::Simple(simple) => Frame::Simple(simple),
+            RedisFrame::Error(error) => Frame::Error(error),
+            RedisFrame::Integer(integer) => Frame::Integer(integer),
+            RedisFrame::Bulk(data) => Frame::Bulk(data),
+            RedisFrame::Array(array) => Frame::Array(array.into_iter().map(Frame::from).collect()),
+            RedisFrame::Null => Frame::Null,
+            RedisFrame::NullArray => Frame::NullArray,
+        }
+    }
+}
+
+impl From<Frame> for RedisFrame {
+    fn from(value: Frame) -> Self {
+        match value {
+            Frame::Simple(simple) => RedisFrame::Simple(simple),
+            Frame::Error(error) => RedisFrame::Error(error),
+            Frame::Integer(integer) => RedisFrame::Integer(integer),
+            Frame::Bulk(data) => RedisFrame::Bulk(data),
+            Frame::Array(array) => RedisFrame::Array(array.into_iter().map(RedisFrame::from).collect()),
+            Frame::Null => RedisFrame::Null,
+            Frame::NullArray => RedisFrame::NullArray,
+        }
+    }
+}
+
+/// a command that failed to parse is answered with an error reply.
+/// Messages that already carry an error code such as `ERR` or `WRONGTYPE` are kept as they are
+impl From<FrameError> for Frame {
+    fn from(value: FrameError) -> Self {
+        match value {
+            FrameError::Other(msg) if msg.split(' ').next().is_some_and(|code| !code.is_empty() && code.chars().all(|c| c.is_ascii_uppercase())) =>
+                Frame::Error(msg),
+            FrameError::Other(msg) => Frame::Error(format!("ERR {}", msg)),
+            FrameError::EndOfStream => Frame::Error("ERR wrong number of arguments for command".to_string()),
+            FrameError::Incomplete => Frame::Error("ERR Protocol error: incomplete frame".to_string()),
+        }
     }
 }
 
@@ -184,7 +245,7 @@ impl FrameIter {
     }
 }
 
-fn check(cursor: &mut Cursor<&[u8]>) -> Result<(), FrameError> {
+pub(crate) fn check(cursor: &mut Cursor<&[u8]>) -> Result<(), FrameError> {
     if !cursor.has_remaining() {
         return Err(FrameError::Incomplete);
     }
@@ -201,14 +262,18 @@ fn check(cursor: &mut Cursor<&[u8]>) -> Result<(), FrameError> {
         b'$' => {
 
             //先获得数量
-            let n = get_decimal(cursor)? as usize;
+            let n = get_decimal(cursor)?;
+            if n < 0 {
+                return Ok(());
+            }
+            let n = n as usize;
 
             //然后忽略字符
             let _ = skip(cursor, n + 2)?;
             return Ok(());
         }
         b'*' => {
-            let n = get_decimal(cursor)? as usize;
+            let n = get_decimal(cursor)?;
 
             for _ in 0..n {
                 check(cursor)?;
@@ -232,7 +297,11 @@ fn parse(cursor: &mut Cursor<&[u8]>) -> Result<Frame, FrameError> {
             Ok(Frame::Integer(get_decimal(cursor)?))
         }
         b'$' => {
-            let len = get_decimal(cursor)? as usize;
+            let len = get_decimal(cursor)?;
+            if len < 0 {
+                return Ok(Frame::Null);
+            }
+            let len = len as usize;
 
             let data = Bytes::copy_from_slice(&cursor.chunk()[..len]);
 
@@ -241,6 +310,9 @@ fn parse(cursor: &mut Cursor<&[u8]>) -> Result<Frame, FrameError> {
         }
         b'*' => {
             let len = get_decimal(cursor)?;
+            if len < 0 {
+                return Ok(Frame::NullArray);
+            }
 
             let mut vec = vec![];
 
@@ -310,7 +382,7 @@ impl From<FromUtf8Error> for FrameError {
 
 impl From<String> for FrameError {
     fn from(value: String) -> Self {
-        "error".into()
+        FrameError::Other(value)
     }
 }
 
@@ -325,6 +397,7 @@ impl Display for FrameError {
         match self {
             FrameError::Incomplete => { std::fmt::Display::fmt("stream ended early", f) }
             FrameError::EndOfStream => { std::fmt::Display::fmt("attempt to extract a value failed", f) }
+            FrameError::Other(msg) => { std::fmt::Display::fmt(msg, f) }
         }
     }
 }
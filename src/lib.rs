@@ -5,7 +5,23 @@ mod frame;
 mod connection;
 mod cmd;
 mod db;
+mod session;
+mod pattern;
+mod pub_sub;
+mod slot;
+mod clock;
+mod cluster;
+mod rdb;
+mod replication;
+mod stats;
+mod metrics;
+mod slowlog;
+pub mod aof;
+pub mod client;
 pub mod codec;
+pub mod config;
+pub mod embedded;
+pub mod sentinel;
 
 
 pub type RedisResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
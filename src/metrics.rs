@@ -0,0 +1,268 @@
+//! `GET /metrics` in the Prometheus text format, on its own port when `metrics-port` is set.
+//! The values come from the counters behind INFO, read under the store lock at each scrape
+
+use std::fmt::{Display, Write as _};
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::select;
+use tokio::sync::broadcast::Receiver;
+use tokio::time::timeout;
+
+use crate::db::{SharedDb, Store};
+use crate::stats::{cpu_times, resident_memory};
+
+/// the upper bounds of the latency histogram buckets, in microseconds
+const LATENCY_BUCKETS: [u64; 16] = [
+    10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000,
+];
+
+/// a request whose headers are larger than this is refused
+const MAX_REQUEST: usize = 8 * 1024;
+
+/// time a scraper has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// answer scrapes on `listener` until the server shuts down
+pub(crate) async fn serve(listener: TcpListener, db: SharedDb, mut notify_shutdown: Receiver<()>) {
+    loop {
+        select! {
+            Ok((socket, _)) = listener.accept() => {
+                let db = db.clone();
+                tokio::spawn(async move {
+                    let _ = respond(socket, db).await;
+                });
+            }
+            _ = notify_shutdown.recv() => break,
+        }
+    }
+}
+
+/// one request per connection, closed after the response
+async fn respond(mut socket: TcpStream, db: SharedDb) -> io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    // 只需要请求行，读到头部结束为止
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = match timeout(REQUEST_TIMEOUT, socket.read(&mut buf)).await {
+            Ok(n) => n?,
+            Err(_) => return Ok(()),
+        };
+        if n == 0 {
+            return Ok(());
+        }
+        request.extend_from_slice(&buf[..n]);
+        if request.len() > MAX_REQUEST {
+            return write(&mut socket, "431 Request Header Fields Too Large", "").await;
+        }
+    }
+
+    let request = String::from_utf8_lossy(&request);
+    let mut parts = request.lines().next().unwrap_or_default().split(' ');
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+
+    match (method, path) {
+        ("GET", "/metrics") => {
+            let body = render(&db.lock());
+            write(&mut socket, "200 OK", &body).await
+        }
+        (_, "/metrics") => write(&mut socket, "405 Method Not Allowed", "").await,
+        _ => write(&mut socket, "404 Not Found", "").await,
+    }
+}
+
+async fn write(socket: &mut TcpStream, status: &str, body: &str) -> io::Result<()> {
+    let head = format!("HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+        Content-Length: {}\r\nConnection: close\r\n\r\n", status, body.len());
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body.as_bytes()).await?;
+    socket.shutdown().await
+}
+
+/// the text exposition format: a `# HELP` and a `# TYPE` line for each metric, then its samples
+#[derive(Default)]
+struct Metrics {
+    text: String,
+}
+
+impl Metrics {
+    fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = write!(self.text, "# HELP redis_{} {}\n# TYPE redis_{} {}\n", name, help, name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        let _ = write!(self.text, "redis_{}", name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels.iter()
+                .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+                .collect();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+
+    /// a metric with a single sample and no labels
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: impl Display) {
+        self.family(name, kind, help);
+        self.sample(name, &[], value);
+    }
+}
+
+/// a label value with `\`, `"` and new lines escaped
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// every metric, from the same numbers as INFO
+fn render(store: &Store) -> String {
+    let stats = &store.stats;
+    let mut metrics = Metrics::default();
+
+    metrics.single("uptime_seconds", "gauge", "Seconds since the server started.", stats.started.elapsed().as_secs());
+    let (user, sys) = cpu_times();
+    metrics.single("cpu_user_seconds_total", "counter", "CPU time spent in user mode.", user);
+    metrics.single("cpu_sys_seconds_total", "counter", "CPU time spent in system mode.", sys);
+
+    metrics.single("connected_clients", "gauge", "Client connections open now.", stats.connected_clients);
+    metrics.single("max_clients", "gauge", "The maxclients limit.", store.config.maxclients);
+    metrics.single("connections_received_total", "counter", "Connections accepted.", stats.total_connections_received);
+    metrics.single("rejected_connections_total", "counter", "Connections refused because of maxclients.", stats.rejected_connections);
+
+    metrics.single("commands_processed_total", "counter", "Commands run.", stats.total_commands_processed);
+    metrics.single("instantaneous_ops_per_sec", "gauge", "Commands per second over the last seconds.", stats.ops_per_sec());
+    metrics.family("commands_total", "counter", "Calls of each command.");
+    for (name, command) in &stats.commands {
+        metrics.sample("commands_total", &[("cmd", name)], command.calls);
+    }
+    metrics.family("commands_rejected_calls_total", "counter", "Calls refused before running, by command.");
+    for (name, command) in &stats.commands {
+        metrics.sample("commands_rejected_calls_total", &[("cmd", name)], command.rejected_calls);
+    }
+    metrics.family("commands_failed_calls_total", "counter", "Calls that replied with an error, by command.");
+    for (name, command) in &stats.commands {
+        metrics.sample("commands_failed_calls_total", &[("cmd", name)], command.failed_calls);
+    }
+    metrics.family("command_duration_seconds", "histogram", "Time spent running each command.");
+    for (name, command) in stats.commands.iter().filter(|(_, command)| command.latency.count() > 0) {
+        for usec in LATENCY_BUCKETS {
+            let le = (usec as f64 / 1_000_000.0).to_string();
+            metrics.sample("command_duration_seconds_bucket", &[("cmd", name), ("le", &le)], command.latency.count_below(usec));
+        }
+        metrics.sample("command_duration_seconds_bucket", &[("cmd", name), ("le", "+Inf")], command.latency.count());
+        metrics.sample("command_duration_seconds_sum", &[("cmd", name)], command.usec as f64 / 1_000_000.0);
+        metrics.sample("command_duration_seconds_count", &[("cmd", name)], command.latency.count());
+    }
+    metrics.family("errors_total", "counter", "Error replies, by error prefix.");
+    for (prefix, count) in &stats.errors {
+        metrics.sample("errors_total", &[("err", prefix)], count);
+    }
+
+    let used = store.used_memory();
+    metrics.single("memory_used_bytes", "gauge", "Memory used by keys and values.", used);
+    metrics.single("memory_used_rss_bytes", "gauge", "Memory of the process resident in RAM.", resident_memory());
+    metrics.single("memory_used_peak_bytes", "gauge", "The most memory used so far.", store.peak_memory().max(used));
+    metrics.single("memory_max_bytes", "gauge", "The maxmemory limit, 0 for none.", store.config.maxmemory);
+
+    let (expires, avg_ttl) = store.expires();
+    metrics.family("db_keys", "gauge", "Keys in the database.");
+    metrics.sample("db_keys", &[("db", "db0")], store.key_count());
+    metrics.family("db_keys_expiring", "gauge", "Keys with a TTL in the database.");
+    metrics.sample("db_keys_expiring", &[("db", "db0")], expires);
+    metrics.family("db_avg_ttl_seconds", "gauge", "Average TTL of the keys with one.");
+    metrics.sample("db_avg_ttl_seconds", &[("db", "db0")], avg_ttl as f64 / 1000.0);
+    metrics.single("keyspace_hits_total", "counter", "Lookups of existing keys.", stats.keyspace_hits);
+    metrics.single("keyspace_misses_total", "counter", "Lookups of missing keys.", stats.keyspace_misses);
+    metrics.single("expired_keys_total", "counter", "Keys deleted because their TTL was over.", stats.expired_keys);
+    metrics.single("evicted_keys_total", "counter", "Keys evicted because of maxmemory.", store.evicted_keys());
+
+    let replication = &store.replication;
+    metrics.single("master_repl_offset", "gauge", "Bytes of the replication stream so far.", replication.offset());
+    metrics.single("connected_slaves", "gauge", "Replicas connected to this server.", replication.replica_count());
+    let lags = replication.replica_lags();
+    metrics.family("connected_slave_lag_seconds", "gauge", "Seconds since each replica last acknowledged the stream.");
+    for (ip, port, _, seconds) in &lags {
+        metrics.sample("connected_slave_lag_seconds", &[("slave_ip", ip), ("slave_port", &port.to_string())], seconds);
+    }
+    metrics.family("connected_slave_lag_bytes", "gauge", "Bytes of the stream each replica did not acknowledge yet.");
+    for (ip, port, bytes, _) in &lags {
+        metrics.sample("connected_slave_lag_bytes", &[("slave_ip", ip), ("slave_port", &port.to_string())], bytes);
+    }
+    if let Some((up, last_io)) = replication.master_link() {
+        metrics.single("master_link_up", "gauge", "Whether the link to the master is up.", up as u8);
+        metrics.single("master_last_io_seconds_ago", "gauge", "Seconds since the master last sent something.", last_io);
+    }
+
+    metrics.text
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::broadcast;
+
+    use crate::db::{run, test_config, test_db};
+    use crate::metrics::{render, serve};
+
+    async fn get(port: u16, request: &str) -> String {
+        let mut socket = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn render_test() {
+        let db = test_db(test_config());
+        let mut store = db.lock();
+        run(&mut store, "SET a 1 EX 100");
+        run(&mut store, "GET a");
+        run(&mut store, "LPUSH a x");
+
+        let text = render(&store);
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines.contains(&"# TYPE redis_commands_total counter"));
+        assert!(lines.contains(&"redis_commands_total{cmd=\"get\"} 1"));
+        assert!(lines.contains(&"redis_commands_failed_calls_total{cmd=\"lpush\"} 1"));
+        assert!(lines.contains(&"redis_command_duration_seconds_bucket{cmd=\"set\",le=\"+Inf\"} 1"));
+        assert!(lines.contains(&"redis_command_duration_seconds_count{cmd=\"set\"} 1"));
+        assert!(lines.contains(&"redis_errors_total{err=\"WRONGTYPE\"} 1"));
+        assert!(lines.contains(&"redis_db_keys{db=\"db0\"} 1"));
+        assert!(lines.contains(&"redis_db_keys_expiring{db=\"db0\"} 1"));
+        assert!(lines.contains(&"redis_keyspace_hits_total 1"));
+        assert!(lines.contains(&"redis_connected_slaves 0"));
+        assert!(!text.contains("redis_master_link_up"));
+
+        // the buckets of a histogram only grow
+        let buckets: Vec<u64> = lines.iter()
+            .filter(|line| line.starts_with("redis_command_duration_seconds_bucket{cmd=\"get\""))
+            .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(buckets.len(), 17);
+        assert!(buckets.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[tokio::test]
+    async fn serve_test() {
+        let (sender, _) = broadcast::channel(1);
+        let db = test_db(test_config());
+        db.lock().stats.connected_clients = 2;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(serve(listener, db, sender.subscribe()));
+
+        let response = get(port, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}", body.len())));
+        assert!(body.contains("\nredis_connected_clients 2\n"));
+
+        assert!(get(port, "GET / HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 404"));
+        assert!(get(port, "POST /metrics HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 405"));
+    }
+}
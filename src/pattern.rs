@@ -0,0 +1,120 @@
+/// Redis style glob matching, used by PSUBSCRIBE, PUBSUB CHANNELS and friends.
+/// Supports `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` to escape the next character
+pub(crate) fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // where to resume after the last `*` if the rest does not match
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        if p < pattern.len() {
+            match pattern[p] {
+                b'*' => {
+                    backtrack = Some((p, s));
+                    p += 1;
+                    continue;
+                }
+                b'?' => {
+                    p += 1;
+                    s += 1;
+                    continue;
+                }
+                b'[' => match match_class(pattern, p, string[s]) {
+                    Some((true, next)) => {
+                        p = next;
+                        s += 1;
+                        continue;
+                    }
+                    Some((false, _)) => {}
+                    // unterminated class, the `[` is a literal
+                    None if string[s] == b'[' => {
+                        p += 1;
+                        s += 1;
+                        continue;
+                    }
+                    None => {}
+                },
+                b'\\' if p + 1 < pattern.len() => {
+                    if pattern[p + 1] == string[s] {
+                        p += 2;
+                        s += 1;
+                        continue;
+                    }
+                }
+                c => {
+                    if c == string[s] {
+                        p += 1;
+                        s += 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        // mismatch: let the last `*` swallow one more character
+        match backtrack {
+            Some((star, matched)) => {
+                p = star + 1;
+                s = matched + 1;
+                backtrack = Some((star, matched + 1));
+            }
+            None => return false,
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// match `c` against the `[...]` class starting at `start`.
+/// Returns whether it matched and the index right after the class, `None` if the class
+/// is not closed
+fn match_class(pattern: &[u8], start: usize, c: u8) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negate = pattern.get(i) == Some(&b'^');
+    if negate {
+        i += 1;
+    }
+
+    let mut matched = false;
+    while i < pattern.len() && pattern[i] != b']' {
+        if pattern[i] == b'\\' && i + 1 < pattern.len() {
+            matched |= pattern[i + 1] == c;
+            i += 2;
+        } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']' {
+            let (low, high) = (pattern[i].min(pattern[i + 2]), pattern[i].max(pattern[i + 2]));
+            matched |= low <= c && c <= high;
+            i += 3;
+        } else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+    }
+
+    if i >= pattern.len() {
+        return None;
+    }
+    Some((matched != negate, i + 1))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pattern::glob_match;
+
+    #[test]
+    fn glob_match_test() {
+        assert!(glob_match(b"*", b"anything"));
+        assert!(glob_match(b"news.*", b"news.art"));
+        assert!(!glob_match(b"news.*", b"new.art"));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-c]llo", b"hbllo"));
+        assert!(glob_match(b"a\\*b", b"a*b"));
+        assert!(!glob_match(b"a\\*b", b"axb"));
+        assert!(glob_match(b"*:*:end", b"x:y:z:end"));
+        assert!(!glob_match(b"", b"x"));
+        // an unterminated class is a literal `[`
+        assert!(glob_match(b"a[", b"a["));
+        assert!(glob_match(b"a[b*", b"a[bc"));
+        assert!(!glob_match(b"a[", b"ab"));
+    }
+}
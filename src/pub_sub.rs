@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use tokio::sync::mpsc;
+
+use crate::frame::Frame;
+use crate::pattern::glob_match;
+use crate::slot::key_hash_slot;
+
+/// The pub/sub hub shared by every connection.
+///
+/// Subscribers are fed through an unbounded channel, but each one counts the bytes it has
+/// not written to its socket yet. A subscriber that falls more than its limit behind is
+/// dropped from the hub and told to disconnect, instead of buffering forever
+#[derive(Debug, Default)]
+pub(crate) struct PubSub {
+    inner: Mutex<Subscriptions>,
+}
+
+#[derive(Debug, Default)]
+struct Subscriptions {
+    channels: HashMap<Bytes, HashMap<u64, Subscriber>>,
+    patterns: HashMap<Bytes, HashMap<u64, Subscriber>>,
+    /// shard channels grouped by the hash slot they belong to
+    shard_channels: BTreeMap<u16, HashMap<Bytes, HashMap<u64, Subscriber>>>,
+}
+
+/// what the hub sends to a subscribed connection
+#[derive(Debug)]
+pub(crate) enum Push {
+    /// a message frame and the bytes it accounts for in the output buffer
+    Message(Frame, usize),
+    /// the output buffer limit was hit, the connection has to be closed
+    Disconnect,
+}
+
+/// the sending half of a connection's message queue
+#[derive(Debug, Clone)]
+pub(crate) struct Subscriber {
+    id: u64,
+    sender: mpsc::UnboundedSender<Push>,
+    pending: Arc<AtomicUsize>,
+    limit: usize,
+}
+
+impl Subscriber {
+    pub(crate) fn new(id: u64, limit: usize) -> (Self, mpsc::UnboundedReceiver<Push>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let subscriber = Self {
+            id,
+            sender,
+            pending: Arc::new(AtomicUsize::new(0)),
+            limit,
+        };
+        (subscriber, receiver)
+    }
+
+    /// the connection wrote `size` bytes of messages to its socket
+    pub(crate) fn written(&self, size: usize) {
+        self.pending.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    /// queue a message, returns false if the subscriber is over its limit or gone
+    fn push(&self, frame: Frame, size: usize) -> bool {
+        let pending = self.pending.fetch_add(size, Ordering::Relaxed) + size;
+        if self.limit > 0 && pending > self.limit {
+            let _ = self.sender.send(Push::Disconnect);
+            return false;
+        }
+        self.sender.send(Push::Message(frame, size)).is_ok()
+    }
+}
+
+impl PubSub {
+    pub(crate) fn subscribe(&self, channel: Bytes, subscriber: &Subscriber) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.channels.entry(channel).or_default().insert(subscriber.id, subscriber.clone());
+    }
+
+    pub(crate) fn unsubscribe(&self, channel: &Bytes, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        remove_from(&mut inner.channels, channel, id);
+    }
+
+    pub(crate) fn psubscribe(&self, pattern: Bytes, subscriber: &Subscriber) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.patterns.entry(pattern).or_default().insert(subscriber.id, subscriber.clone());
+    }
+
+    pub(crate) fn punsubscribe(&self, pattern: &Bytes, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        remove_from(&mut inner.patterns, pattern, id);
+    }
+
+    pub(crate) fn ssubscribe(&self, channel: Bytes, subscriber: &Subscriber) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.shard_channels.entry(key_hash_slot(&channel)).or_default()
+            .entry(channel).or_default()
+            .insert(subscriber.id, subscriber.clone());
+    }
+
+    pub(crate) fn sunsubscribe(&self, channel: &Bytes, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        let slot = key_hash_slot(channel);
+        if let Some(channels) = inner.shard_channels.get_mut(&slot) {
+            remove_from(channels, channel, id);
+            if channels.is_empty() {
+                inner.shard_channels.remove(&slot);
+            }
+        }
+    }
+
+    /// deliver a message to the subscribers of a shard channel, patterns never match these
+    pub(crate) fn spublish(&self, channel: &Bytes, message: &Bytes) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let mut receivers = 0;
+        let mut overflowed = vec![];
+
+        let subscribers = inner.shard_channels.get(&key_hash_slot(channel))
+            .and_then(|channels| channels.get(channel));
+        if let Some(subscribers) = subscribers {
+            let size = channel.len() + message.len() + 32;
+            for subscriber in subscribers.values() {
+                let frame = Frame::Array(vec![
+                    Frame::Bulk(Bytes::from_static(b"smessage")),
+                    Frame::Bulk(channel.clone()),
+                    Frame::Bulk(message.clone()),
+                ]);
+                if subscriber.push(frame, size) {
+                    receivers += 1;
+                } else {
+                    overflowed.push(subscriber.id);
+                }
+            }
+        }
+
+        for id in overflowed {
+            inner.remove_client(id);
+        }
+        receivers
+    }
+
+    /// deliver a message to channel and pattern subscribers, returns how many received it
+    pub(crate) fn publish(&self, channel: &Bytes, message: &Bytes) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let mut receivers = 0;
+        let mut overflowed = vec![];
+
+        if let Some(subscribers) = inner.channels.get(channel) {
+            let size = channel.len() + message.len() + 32;
+            for subscriber in subscribers.values() {
+                let frame = Frame::Array(vec![
+                    Frame::Bulk(Bytes::from_static(b"message")),
+                    Frame::Bulk(channel.clone()),
+                    Frame::Bulk(message.clone()),
+                ]);
+                if subscriber.push(frame, size) {
+                    receivers += 1;
+                } else {
+                    overflowed.push(subscriber.id);
+                }
+            }
+        }
+
+        for (pattern, subscribers) in &inner.patterns {
+            if !glob_match(pattern, channel) {
+                continue;
+            }
+            let size = pattern.len() + channel.len() + message.len() + 48;
+            for subscriber in subscribers.values() {
+                let frame = Frame::Array(vec![
+                    Frame::Bulk(Bytes::from_static(b"pmessage")),
+                    Frame::Bulk(pattern.clone()),
+                    Frame::Bulk(channel.clone()),
+                    Frame::Bulk(message.clone()),
+                ]);
+                if subscriber.push(frame, size) {
+                    receivers += 1;
+                } else {
+                    overflowed.push(subscriber.id);
+                }
+            }
+        }
+
+        for id in overflowed {
+            inner.remove_client(id);
+        }
+        receivers
+    }
+
+    /// PUBSUB CHANNELS: channels with at least one subscriber, optionally filtered by a glob
+    pub(crate) fn channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        let inner = self.inner.lock().unwrap();
+        inner.channels.keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect()
+    }
+
+    /// PUBSUB NUMSUB: number of subscribers of a channel, patterns not included
+    pub(crate) fn numsub(&self, channel: &Bytes) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner.channels.get(channel).map_or(0, HashMap::len)
+    }
+
+    /// PUBSUB SHARDCHANNELS: shard channels with at least one subscriber
+    pub(crate) fn shard_channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        let inner = self.inner.lock().unwrap();
+        inner.shard_channels.values()
+            .flat_map(HashMap::keys)
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect()
+    }
+
+    /// PUBSUB SHARDNUMSUB: number of subscribers of a shard channel
+    pub(crate) fn shard_numsub(&self, channel: &Bytes) -> usize {
+        let inner = self.inner.lock().unwrap();
+        inner.shard_channels.get(&key_hash_slot(channel))
+            .and_then(|channels| channels.get(channel))
+            .map_or(0, HashMap::len)
+    }
+
+    /// PUBSUB NUMPAT: number of distinct subscribed patterns
+    pub(crate) fn numpat(&self) -> usize {
+        self.inner.lock().unwrap().patterns.len()
+    }
+
+    /// how many subscribed clients there are and the bytes of messages they did not write yet
+    pub(crate) fn output_buffers(&self) -> (usize, usize) {
+        let inner = self.inner.lock().unwrap();
+        let mut pending = HashMap::new();
+        let shard_maps = inner.shard_channels.values();
+        for map in [&inner.channels, &inner.patterns].into_iter().chain(shard_maps) {
+            for subscriber in map.values().flat_map(HashMap::values) {
+                pending.insert(subscriber.id, subscriber.pending.load(Ordering::Relaxed));
+            }
+        }
+        (pending.len(), pending.values().sum())
+    }
+
+    /// drop every subscription of a client, when it disconnects
+    pub(crate) fn remove_client(&self, id: u64) {
+        self.inner.lock().unwrap().remove_client(id);
+    }
+}
+
+impl Subscriptions {
+    fn remove_client(&mut self, id: u64) {
+        let shard_maps = self.shard_channels.values_mut();
+        for map in [&mut self.channels, &mut self.patterns].into_iter().chain(shard_maps) {
+            map.retain(|_, subscribers| {
+                subscribers.remove(&id);
+                !subscribers.is_empty()
+            });
+        }
+        self.shard_channels.retain(|_, channels| !channels.is_empty());
+    }
+}
+
+fn remove_from(map: &mut HashMap<Bytes, HashMap<u64, Subscriber>>, name: &Bytes, id: u64) {
+    if let Some(subscribers) = map.get_mut(name) {
+        subscribers.remove(&id);
+        if subscribers.is_empty() {
+            map.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use crate::pub_sub::{PubSub, Push, Subscriber};
+
+    #[test]
+    fn publish_test() {
+        let hub = PubSub::default();
+        let (first, mut first_rx) = Subscriber::new(1, 0);
+        let (second, mut second_rx) = Subscriber::new(2, 0);
+
+        hub.subscribe(Bytes::from("news"), &first);
+        hub.psubscribe(Bytes::from("n*"), &second);
+
+        assert_eq!(hub.publish(&Bytes::from("news"), &Bytes::from("hi")), 2);
+        assert_eq!(hub.publish(&Bytes::from("other"), &Bytes::from("hi")), 0);
+        assert!(matches!(first_rx.try_recv(), Ok(Push::Message(..))));
+        assert!(matches!(second_rx.try_recv(), Ok(Push::Message(..))));
+
+        assert_eq!(hub.numsub(&Bytes::from("news")), 1);
+        assert_eq!(hub.numpat(), 1);
+        assert_eq!(hub.channels(Some(b"ne*")), vec![Bytes::from("news")]);
+
+        hub.remove_client(1);
+        assert_eq!(hub.numsub(&Bytes::from("news")), 0);
+    }
+
+    #[test]
+    fn spublish_test() {
+        let hub = PubSub::default();
+        let (subscriber, mut receiver) = Subscriber::new(1, 0);
+        hub.ssubscribe(Bytes::from("{user}.orders"), &subscriber);
+        hub.psubscribe(Bytes::from("*"), &subscriber);
+
+        // pattern subscriptions never see sharded messages
+        assert_eq!(hub.spublish(&Bytes::from("{user}.orders"), &Bytes::from("hi")), 1);
+        assert!(matches!(receiver.try_recv(), Ok(Push::Message(..))));
+        assert!(receiver.try_recv().is_err());
+
+        assert_eq!(hub.shard_numsub(&Bytes::from("{user}.orders")), 1);
+        assert_eq!(hub.shard_channels(None), vec![Bytes::from("{user}.orders")]);
+        assert!(hub.channels(None).is_empty());
+
+        hub.sunsubscribe(&Bytes::from("{user}.orders"), 1);
+        assert_eq!(hub.shard_numsub(&Bytes::from("{user}.orders")), 0);
+    }
+
+    #[test]
+    fn slow_subscriber_test() {
+        let hub = PubSub::default();
+        let (subscriber, mut receiver) = Subscriber::new(1, 100);
+        hub.subscribe(Bytes::from("news"), &subscriber);
+
+        let message = Bytes::from(vec![b'x'; 40]);
+        assert_eq!(hub.publish(&Bytes::from("news"), &message), 1);
+        // nothing was written to the socket, the second message goes over the limit
+        assert_eq!(hub.publish(&Bytes::from("news"), &message), 0);
+        assert_eq!(hub.numsub(&Bytes::from("news")), 0);
+
+        assert!(matches!(receiver.try_recv(), Ok(Push::Message(..))));
+        assert!(matches!(receiver.try_recv(), Ok(Push::Disconnect)));
+    }
+}
@@ -0,0 +1,847 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use bytes::Bytes;
+
+use crate::clock::unix_secs;
+use crate::db::RedisDataType;
+
+/// One key of a snapshot: name, value and absolute expiry in unix milliseconds
+pub(crate) type SnapshotEntry = (String, RedisDataType, Option<u64>);
+
+/// Bookkeeping shared between the store and a background save:
+/// how many changes are not in a snapshot yet, and when and how the last save went
+#[derive(Debug)]
+pub(crate) struct SaveState {
+    dirty: AtomicU64,
+    last_save: AtomicU64,
+    last_attempt: AtomicU64,
+    last_ok: AtomicBool,
+    in_progress: AtomicBool,
+    /// BGSAVE SCHEDULE was asked while another save was running
+    scheduled: AtomicBool,
+}
+
+impl SaveState {
+    pub(crate) fn new() -> Self {
+        Self {
+            dirty: AtomicU64::new(0),
+            last_save: AtomicU64::new(unix_secs()),
+            last_attempt: AtomicU64::new(0),
+            last_ok: AtomicBool::new(true),
+            in_progress: AtomicBool::new(false),
+            scheduled: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn dirty(&self, changes: u64) {
+        self.dirty.fetch_add(changes, Ordering::Relaxed);
+    }
+
+    /// changes since the last successful save
+    pub(crate) fn changes(&self) -> u64 {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    /// unix time in seconds of the last successful save, what LASTSAVE returns
+    pub(crate) fn last_save(&self) -> u64 {
+        self.last_save.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn last_attempt(&self) -> u64 {
+        self.last_attempt.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn last_ok(&self) -> bool {
+        self.last_ok.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn in_progress(&self) -> bool {
+        self.in_progress.load(Ordering::Acquire)
+    }
+
+    /// mark a background save as running, false if one already is
+    pub(crate) fn start(&self) -> bool {
+        self.in_progress.compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire).is_ok()
+    }
+
+    pub(crate) fn schedule(&self) {
+        self.scheduled.store(true, Ordering::Relaxed);
+    }
+
+    pub(crate) fn take_scheduled(&self) -> bool {
+        self.scheduled.swap(false, Ordering::Relaxed)
+    }
+
+    /// a save of a snapshot taken when there were `dirty` changes has ended.
+    /// Changes made while it was being written stay counted
+    pub(crate) fn finished(&self, ok: bool, dirty: u64) {
+        let now = unix_secs();
+        self.last_attempt.store(now, Ordering::Relaxed);
+        self.last_ok.store(ok, Ordering::Relaxed);
+        if ok {
+            self.dirty.fetch_sub(dirty, Ordering::Relaxed);
+            self.last_save.store(now, Ordering::Relaxed);
+        }
+        self.in_progress.store(false, Ordering::Release);
+    }
+}
+
+/// The version written in the header. Version 9 is the last one that allows the plain
+/// (non listpack) encodings written here, and every newer Redis still loads it
+pub(crate) const RDB_VERSION: u16 = 9;
+
+// value types
+const TYPE_STRING: u8 = 0;
+const TYPE_LIST: u8 = 1;
+const TYPE_SET: u8 = 2;
+const TYPE_ZSET: u8 = 3;
+const TYPE_HASH: u8 = 4;
+const TYPE_ZSET_2: u8 = 5;
+const TYPE_HASH_ZIPLIST: u8 = 13;
+const TYPE_LIST_ZIPLIST: u8 = 10;
+const TYPE_SET_INTSET: u8 = 11;
+const TYPE_ZSET_ZIPLIST: u8 = 12;
+const TYPE_LIST_QUICKLIST: u8 = 14;
+const TYPE_HASH_LISTPACK: u8 = 16;
+const TYPE_ZSET_LISTPACK: u8 = 17;
+const TYPE_LIST_QUICKLIST_2: u8 = 18;
+const TYPE_SET_LISTPACK: u8 = 20;
+
+// opcodes
+const OPCODE_FUNCTION2: u8 = 245;
+const OPCODE_MODULE_AUX: u8 = 247;
+const OPCODE_IDLE: u8 = 248;
+const OPCODE_FREQ: u8 = 249;
+const OPCODE_AUX: u8 = 250;
+const OPCODE_RESIZEDB: u8 = 251;
+const OPCODE_EXPIRETIME_MS: u8 = 252;
+const OPCODE_EXPIRETIME: u8 = 253;
+const OPCODE_SELECTDB: u8 = 254;
+const OPCODE_EOF: u8 = 255;
+
+// special string encodings
+const ENC_INT8: u8 = 0;
+const ENC_INT16: u8 = 1;
+const ENC_INT32: u8 = 2;
+const ENC_LZF: u8 = 3;
+
+/// serialize a snapshot as an RDB file
+pub(crate) fn encode(entries: &[SnapshotEntry]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(64 + entries.len() * 32);
+    buf.extend_from_slice(format!("REDIS{:04}", RDB_VERSION).as_bytes());
+
+    write_aux(&mut buf, "redis-ver", "7.0.0");
+    write_aux(&mut buf, "redis-bits", "64");
+    write_aux(&mut buf, "ctime", &unix_secs().to_string());
+
+    buf.push(OPCODE_SELECTDB);
+    write_len(&mut buf, 0);
+    buf.push(OPCODE_RESIZEDB);
+    write_len(&mut buf, entries.len() as u64);
+    write_len(&mut buf, entries.iter().filter(|(_, _, expire)| expire.is_some()).count() as u64);
+
+    for (key, data, expire_at) in entries {
+        if let Some(expire_at) = expire_at {
+            buf.push(OPCODE_EXPIRETIME_MS);
+            buf.extend_from_slice(&expire_at.to_le_bytes());
+        }
+        buf.push(value_type(data));
+        write_string(&mut buf, key.as_bytes());
+        write_value(&mut buf, data);
+    }
+
+    buf.push(OPCODE_EOF);
+    let checksum = crc64(0, &buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+/// write the snapshot to `path` through a temp file, so a crash never leaves a half written dump
+pub(crate) fn save(path: &Path, entries: &[SnapshotEntry]) -> io::Result<()> {
+    let data = encode(entries);
+    let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+    {
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp, path)
+}
+
+/// read an RDB file, `Ok(None)` if it does not exist
+pub(crate) fn load(path: &Path) -> io::Result<Option<Vec<SnapshotEntry>>> {
+    match fs::read(path) {
+        Ok(data) => decode(&data).map(Some),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// parse an RDB file produced by this server or by Redis
+pub(crate) fn decode(data: &[u8]) -> io::Result<Vec<SnapshotEntry>> {
+    decode_prefix(data).map(|(entries, _)| entries)
+}
+
+/// parse an RDB file at the start of `data`, like the preamble of an append only file.
+/// Returns the keys and how many bytes the RDB part took
+pub(crate) fn decode_prefix(data: &[u8]) -> io::Result<(Vec<SnapshotEntry>, usize)> {
+    let mut reader = Reader::new(data);
+
+    let magic = reader.take(9)?;
+    if &magic[..5] != b"REDIS" {
+        return Err(invalid("wrong signature trying to load DB from file"));
+    }
+    let version: u16 = std::str::from_utf8(&magic[5..]).ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| invalid("invalid RDB version"))?;
+    if version > 12 {
+        return Err(invalid(&format!("can't handle RDB format version {}", version)));
+    }
+
+    let mut entries = vec![];
+    let mut expire_at = None;
+    loop {
+        let opcode = reader.u8()?;
+        match opcode {
+            OPCODE_EXPIRETIME_MS => expire_at = Some(u64::from_le_bytes(reader.array()?)),
+            OPCODE_EXPIRETIME => expire_at = Some(u32::from_le_bytes(reader.array()?) as u64 * 1000),
+            OPCODE_FREQ => {
+                reader.u8()?;
+            }
+            OPCODE_IDLE => {
+                reader.len()?;
+            }
+            OPCODE_SELECTDB => {
+                reader.len()?;
+            }
+            OPCODE_RESIZEDB => {
+                reader.len()?;
+                reader.len()?;
+            }
+            OPCODE_AUX => {
+                reader.string()?;
+                reader.string()?;
+            }
+            OPCODE_MODULE_AUX | OPCODE_FUNCTION2 => return Err(invalid("modules and functions are not supported")),
+            OPCODE_EOF => {
+                // version 5 and later end with a checksum, zero means it was disabled
+                if version >= 5 && reader.remaining() >= 8 {
+                    let expected = u64::from_le_bytes(reader.array()?);
+                    let actual = crc64(0, &data[..reader.pos - 8]);
+                    if expected != 0 && expected != actual {
+                        return Err(invalid("wrong RDB checksum"));
+                    }
+                }
+                return Ok((entries, reader.pos));
+            }
+            value_type => {
+                let key = String::from_utf8_lossy(&reader.string()?).to_string();
+                let data = read_value(&mut reader, value_type)?;
+                entries.push((key, data, expire_at.take()));
+            }
+        }
+    }
+}
+
+/// the RDB type byte a value is written with. Bitmaps are strings in Redis,
+/// the empty `BITMAP` placeholder is written as an empty one
+pub(crate) fn value_type(data: &RedisDataType) -> u8 {
+    match data {
+        RedisDataType::Bytes(_) | RedisDataType::BITMAP => TYPE_STRING,
+        RedisDataType::List(_) => TYPE_LIST,
+        RedisDataType::Set(_) => TYPE_SET,
+        RedisDataType::SortedSet(_) => TYPE_ZSET_2,
+        RedisDataType::HASH(_) => TYPE_HASH,
+    }
+}
+
+/// The DUMP payload of a value: its type and body as in an RDB file, then the RDB version
+/// (2 bytes) and the CRC64 of everything before (8 bytes), both little endian
+pub(crate) fn dump(data: &RedisDataType) -> Vec<u8> {
+    let mut buf = vec![value_type(data)];
+    write_value(&mut buf, data);
+    buf.extend_from_slice(&RDB_VERSION.to_le_bytes());
+    let checksum = crc64(0, &buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+/// read back a DUMP payload, from this server or from Redis up to RDB version 12.
+/// A wrong version or checksum is an `InvalidInput` error, a bad body `InvalidData`
+pub(crate) fn restore(payload: &[u8]) -> io::Result<RedisDataType> {
+    let wrong = || io::Error::new(ErrorKind::InvalidInput, "DUMP payload version or checksum are wrong");
+    if payload.len() < 11 {
+        return Err(wrong());
+    }
+    let (body, trailer) = payload.split_at(payload.len() - 10);
+    let version = u16::from_le_bytes([trailer[0], trailer[1]]);
+    let checksum = u64::from_le_bytes(trailer[2..].try_into().unwrap());
+    // 和 Redis 不同, 不接受关闭了校验和(值为 0)的 payload, 它可能来自任何客户端
+    if version > 12 || checksum != crc64(0, &payload[..payload.len() - 8]) {
+        return Err(wrong());
+    }
+
+    let mut reader = Reader::new(body);
+    let value_type = reader.u8()?;
+    let data = read_value(&mut reader, value_type)
+        .map_err(|_| invalid("Bad data format"))?;
+    if reader.remaining() != 0 {
+        return Err(invalid("Bad data format"));
+    }
+    Ok(data)
+}
+
+/// write the body of a value, without its type byte
+pub(crate) fn write_value(buf: &mut Vec<u8>, data: &RedisDataType) {
+    match data {
+        RedisDataType::Bytes(data) => write_string(buf, data),
+        RedisDataType::List(list) => {
+            write_len(buf, list.len() as u64);
+            for element in list {
+                write_string(buf, element);
+            }
+        }
+        RedisDataType::Set(set) => {
+            write_len(buf, set.len() as u64);
+            for member in set {
+                write_string(buf, member);
+            }
+        }
+        RedisDataType::SortedSet(zset) => {
+            write_len(buf, zset.len() as u64);
+            for (member, score) in zset {
+                write_string(buf, member);
+                buf.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        RedisDataType::HASH(hash) => {
+            write_len(buf, hash.len() as u64);
+            for (field, value) in hash {
+                write_string(buf, field.as_bytes());
+                write_string(buf, value);
+            }
+        }
+        RedisDataType::BITMAP => write_string(buf, b""),
+    }
+}
+
+/// read the body of a value of the given RDB type
+pub(crate) fn read_value(reader: &mut Reader, value_type: u8) -> io::Result<RedisDataType> {
+    let data = match value_type {
+        TYPE_STRING => RedisDataType::Bytes(Bytes::from(reader.string()?)),
+        TYPE_LIST => {
+            let len = reader.count()?;
+            let mut list = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                list.push(Bytes::from(reader.string()?));
+            }
+            RedisDataType::List(list)
+        }
+        TYPE_SET => {
+            let len = reader.count()?;
+            let mut set = HashSet::with_capacity(len.min(1024));
+            for _ in 0..len {
+                set.insert(Bytes::from(reader.string()?));
+            }
+            RedisDataType::Set(set)
+        }
+        TYPE_ZSET | TYPE_ZSET_2 => {
+            let len = reader.count()?;
+            let mut zset = HashMap::with_capacity(len.min(1024));
+            for _ in 0..len {
+                let member = Bytes::from(reader.string()?);
+                let score = if value_type == TYPE_ZSET_2 {
+                    f64::from_le_bytes(reader.array()?)
+                } else {
+                    reader.text_double()?
+                };
+                zset.insert(member, score);
+            }
+            RedisDataType::SortedSet(zset)
+        }
+        TYPE_HASH => {
+            let len = reader.count()?;
+            let mut hash = HashMap::with_capacity(len.min(1024));
+            for _ in 0..len {
+                let field = String::from_utf8_lossy(&reader.string()?).to_string();
+                hash.insert(field, Bytes::from(reader.string()?));
+            }
+            RedisDataType::HASH(hash)
+        }
+        TYPE_LIST_ZIPLIST => RedisDataType::List(ziplist_entries(&reader.string()?)?),
+        TYPE_LIST_QUICKLIST => {
+            let mut list = vec![];
+            for _ in 0..reader.count()? {
+                list.extend(ziplist_entries(&reader.string()?)?);
+            }
+            RedisDataType::List(list)
+        }
+        TYPE_LIST_QUICKLIST_2 => {
+            let mut list = vec![];
+            for _ in 0..reader.count()? {
+                // 1 is a plain node holding one element, 2 a packed listpack
+                let container = reader.len()?;
+                let node = reader.string()?;
+                if container == 1 {
+                    list.push(Bytes::from(node));
+                } else {
+                    list.extend(listpack_entries(&node)?);
+                }
+            }
+            RedisDataType::List(list)
+        }
+        TYPE_SET_INTSET => RedisDataType::Set(intset_entries(&reader.string()?)?.into_iter().collect()),
+        TYPE_SET_LISTPACK => RedisDataType::Set(listpack_entries(&reader.string()?)?.into_iter().collect()),
+        TYPE_ZSET_ZIPLIST | TYPE_ZSET_LISTPACK => {
+            let blob = reader.string()?;
+            let entries = if value_type == TYPE_ZSET_ZIPLIST { ziplist_entries(&blob)? } else { listpack_entries(&blob)? };
+            let mut zset = HashMap::new();
+            for pair in entries.chunks(2) {
+                let [member, score] = pair else { return Err(invalid("odd sorted set listpack")) };
+                zset.insert(member.clone(), parse_double(score)?);
+            }
+            RedisDataType::SortedSet(zset)
+        }
+        TYPE_HASH_ZIPLIST | TYPE_HASH_LISTPACK => {
+            let blob = reader.string()?;
+            let entries = if value_type == TYPE_HASH_ZIPLIST { ziplist_entries(&blob)? } else { listpack_entries(&blob)? };
+            let mut hash = HashMap::new();
+            for pair in entries.chunks(2) {
+                let [field, value] = pair else { return Err(invalid("odd hash listpack")) };
+                hash.insert(String::from_utf8_lossy(field).to_string(), value.clone());
+            }
+            RedisDataType::HASH(hash)
+        }
+        other => return Err(invalid(&format!("unsupported RDB value type {}", other))),
+    };
+    Ok(data)
+}
+
+fn write_aux(buf: &mut Vec<u8>, key: &str, value: &str) {
+    buf.push(OPCODE_AUX);
+    write_string(buf, key.as_bytes());
+    write_string(buf, value.as_bytes());
+}
+
+pub(crate) fn write_len(buf: &mut Vec<u8>, len: u64) {
+    if len < 1 << 6 {
+        buf.push(len as u8);
+    } else if len < 1 << 14 {
+        buf.push(0x40 | (len >> 8) as u8);
+        buf.push(len as u8);
+    } else if len <= u32::MAX as u64 {
+        buf.push(0x80);
+        buf.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        buf.push(0x81);
+        buf.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+/// strings that are small integers are stored in their integer encoding, like Redis does
+pub(crate) fn write_string(buf: &mut Vec<u8>, data: &[u8]) {
+    if data.len() <= 11 {
+        if let Some(value) = std::str::from_utf8(data).ok().and_then(|s| s.parse::<i64>().ok()) {
+            // only if it round trips, "007" has to stay a string
+            if value.to_string().as_bytes() == data {
+                if let Ok(value) = i8::try_from(value) {
+                    buf.extend_from_slice(&[0xC0 | ENC_INT8, value as u8]);
+                    return;
+                } else if let Ok(value) = i16::try_from(value) {
+                    buf.push(0xC0 | ENC_INT16);
+                    buf.extend_from_slice(&value.to_le_bytes());
+                    return;
+                } else if let Ok(value) = i32::try_from(value) {
+                    buf.push(0xC0 | ENC_INT32);
+                    buf.extend_from_slice(&value.to_le_bytes());
+                    return;
+                }
+            }
+        }
+    }
+    write_len(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+fn parse_double(data: &[u8]) -> io::Result<f64> {
+    std::str::from_utf8(data).ok()
+        .and_then(|s| match s {
+            "inf" | "+inf" => Some(f64::INFINITY),
+            "-inf" => Some(f64::NEG_INFINITY),
+            s => s.parse().ok(),
+        })
+        .ok_or_else(|| invalid("invalid double"))
+}
+
+/// a cursor over RDB data
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.remaining() < n {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "unexpected end of RDB data"));
+        }
+        let slice = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    pub(crate) fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    /// a length, or the special encoding marker of a string as `Err(Ok(encoding))`
+    fn len_or_encoding(&mut self) -> io::Result<Result<u64, u8>> {
+        let first = self.u8()?;
+        let len = match first >> 6 {
+            0 => (first & 0x3F) as u64,
+            1 => (((first & 0x3F) as u64) << 8) | self.u8()? as u64,
+            2 => match first {
+                0x80 => u32::from_be_bytes(self.array()?) as u64,
+                0x81 => u64::from_be_bytes(self.array()?),
+                _ => return Err(invalid("unknown length encoding")),
+            },
+            _ => return Ok(Err(first & 0x3F)),
+        };
+        Ok(Ok(len))
+    }
+
+    pub(crate) fn len(&mut self) -> io::Result<u64> {
+        self.len_or_encoding()?.map_err(|_| invalid("unexpected string encoding"))
+    }
+
+    /// the number of elements of a collection, every element takes at least one byte so a count
+    /// past the end of the data is corrupt
+    fn count(&mut self) -> io::Result<usize> {
+        let len = self.len()?;
+        if len > self.remaining() as u64 {
+            return Err(invalid("collection length past the end of the data"));
+        }
+        Ok(len as usize)
+    }
+
+    pub(crate) fn string(&mut self) -> io::Result<Vec<u8>> {
+        match self.len_or_encoding()? {
+            Ok(len) => Ok(self.take(len as usize)?.to_vec()),
+            Err(ENC_INT8) => Ok((self.u8()? as i8).to_string().into_bytes()),
+            Err(ENC_INT16) => Ok(i16::from_le_bytes(self.array()?).to_string().into_bytes()),
+            Err(ENC_INT32) => Ok(i32::from_le_bytes(self.array()?).to_string().into_bytes()),
+            Err(ENC_LZF) => {
+                let compressed_len = self.len()? as usize;
+                let len = self.len()? as usize;
+                lzf_decompress(self.take(compressed_len)?, len)
+            }
+            Err(other) => Err(invalid(&format!("unknown string encoding {}", other))),
+        }
+    }
+
+    /// the old text encoding of doubles, with special lengths for nan and infinities
+    fn text_double(&mut self) -> io::Result<f64> {
+        match self.u8()? {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            len => parse_double(self.take(len as usize)?),
+        }
+    }
+}
+
+/// the decompressed length comes from the data, so the buffer only grows with what the input
+/// actually produces and never past `len`
+fn lzf_decompress(input: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(len.min(input.len() * 2));
+    let mut i = 0;
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+        if ctrl < 32 {
+            // a run of ctrl + 1 literal bytes
+            let run = ctrl + 1;
+            if output.len() + run > len {
+                return Err(invalid("corrupt LZF data"));
+            }
+            let literal = input.get(i..i + run).ok_or_else(|| invalid("corrupt LZF data"))?;
+            output.extend_from_slice(literal);
+            i += run;
+        } else {
+            // a back reference
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i).ok_or_else(|| invalid("corrupt LZF data"))? as usize;
+                i += 1;
+            }
+            let low = *input.get(i).ok_or_else(|| invalid("corrupt LZF data"))? as usize;
+            i += 1;
+            let offset = ((ctrl & 0x1F) << 8) + low + 1;
+            if offset > output.len() || output.len() + run + 2 > len {
+                return Err(invalid("corrupt LZF data"));
+            }
+            let start = output.len() - offset;
+            for k in 0..run + 2 {
+                output.push(output[start + k]);
+            }
+        }
+    }
+    if output.len() != len {
+        return Err(invalid("corrupt LZF data"));
+    }
+    Ok(output)
+}
+
+fn ziplist_entries(blob: &[u8]) -> io::Result<Vec<Bytes>> {
+    let mut reader = Reader::new(blob);
+    reader.take(8)?;
+    reader.take(2)?;
+
+    let mut entries = vec![];
+    loop {
+        let first = reader.u8()?;
+        if first == 0xFF {
+            return Ok(entries);
+        }
+        // previous entry length, 1 or 5 bytes
+        if first == 0xFE {
+            reader.take(4)?;
+        }
+
+        let encoding = reader.u8()?;
+        let entry = match encoding >> 6 {
+            0 => reader.take((encoding & 0x3F) as usize)?.to_vec(),
+            1 => {
+                let len = (((encoding & 0x3F) as usize) << 8) | reader.u8()? as usize;
+                reader.take(len)?.to_vec()
+            }
+            2 => {
+                let len = u32::from_be_bytes(reader.array()?) as usize;
+                reader.take(len)?.to_vec()
+            }
+            _ => {
+                let value: i64 = match encoding {
+                    0xC0 => i16::from_le_bytes(reader.array()?) as i64,
+                    0xD0 => i32::from_le_bytes(reader.array()?) as i64,
+                    0xE0 => i64::from_le_bytes(reader.array()?),
+                    0xF0 => {
+                        let [a, b, c] = reader.array()?;
+                        (i32::from_le_bytes([0, a, b, c]) >> 8) as i64
+                    }
+                    0xFE => reader.u8()? as i8 as i64,
+                    0xF1..=0xFD => (encoding & 0x0F) as i64 - 1,
+                    _ => return Err(invalid("unknown ziplist encoding")),
+                };
+                value.to_string().into_bytes()
+            }
+        };
+        entries.push(Bytes::from(entry));
+    }
+}
+
+fn listpack_entries(blob: &[u8]) -> io::Result<Vec<Bytes>> {
+    let mut reader = Reader::new(blob);
+    reader.take(6)?;
+
+    let mut entries = vec![];
+    loop {
+        let start = reader.pos;
+        let encoding = reader.u8()?;
+        if encoding == 0xFF {
+            return Ok(entries);
+        }
+
+        let entry = if encoding & 0x80 == 0 {
+            (encoding & 0x7F).to_string().into_bytes()
+        } else if encoding & 0xC0 == 0x80 {
+            reader.take((encoding & 0x3F) as usize)?.to_vec()
+        } else if encoding & 0xE0 == 0xC0 {
+            let value = (((encoding & 0x1F) as u16) << 8) | reader.u8()? as u16;
+            // 13 bit two's complement
+            let value = if value >= 1 << 12 { value as i64 - (1 << 13) } else { value as i64 };
+            value.to_string().into_bytes()
+        } else if encoding & 0xF0 == 0xE0 {
+            let len = (((encoding & 0x0F) as usize) << 8) | reader.u8()? as usize;
+            reader.take(len)?.to_vec()
+        } else {
+            match encoding {
+                0xF0 => {
+                    let len = u32::from_le_bytes(reader.array()?) as usize;
+                    reader.take(len)?.to_vec()
+                }
+                0xF1 => i16::from_le_bytes(reader.array()?).to_string().into_bytes(),
+                0xF2 => {
+                    let [a, b, c] = reader.array()?;
+                    (i32::from_le_bytes([0, a, b, c]) >> 8).to_string().into_bytes()
+                }
+                0xF3 => i32::from_le_bytes(reader.array()?).to_string().into_bytes(),
+                0xF4 => i64::from_le_bytes(reader.array()?).to_string().into_bytes(),
+                _ => return Err(invalid("unknown listpack encoding")),
+            }
+        };
+
+        // skip the backlen, which encodes the size of the entry just read
+        let size = reader.pos - start;
+        let backlen = match size {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        reader.take(backlen)?;
+        entries.push(Bytes::from(entry));
+    }
+}
+
+fn intset_entries(blob: &[u8]) -> io::Result<Vec<Bytes>> {
+    let mut reader = Reader::new(blob);
+    let width = u32::from_le_bytes(reader.array()?) as usize;
+    let len = u32::from_le_bytes(reader.array()?);
+
+    let mut entries = vec![];
+    for _ in 0..len {
+        let value = match width {
+            2 => i16::from_le_bytes(reader.array()?) as i64,
+            4 => i32::from_le_bytes(reader.array()?) as i64,
+            8 => i64::from_le_bytes(reader.array()?),
+            _ => return Err(invalid("unknown intset encoding")),
+        };
+        entries.push(Bytes::from(value.to_string()));
+    }
+    Ok(entries)
+}
+
+/// CRC64 Jones, the checksum of RDB files and DUMP payloads
+/// (reflected, polynomial 0xad93d23594c935a9, no final xor)
+pub(crate) fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    const POLY: u64 = 0x95ac9329ac4bc9b5; // 0xad93d23594c935a9 bit reversed
+    for &byte in data {
+        crc ^= byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ POLY } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{HashMap, HashSet};
+
+    use bytes::Bytes;
+
+    use crate::db::RedisDataType;
+    use crate::rdb::{crc64, decode, dump, encode, listpack_entries, lzf_decompress, restore, ziplist_entries};
+
+    #[test]
+    fn crc64_test() {
+        // reference value from the Redis source
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6d914c4b8d9ca);
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let list = vec![Bytes::from("a"), Bytes::from("12"), Bytes::from("-70000"), Bytes::from("007")];
+        let set: HashSet<Bytes> = [Bytes::from("x"), Bytes::from("y")].into_iter().collect();
+        let zset: HashMap<Bytes, f64> = [(Bytes::from("m"), 1.5), (Bytes::from("n"), f64::INFINITY)].into_iter().collect();
+        let hash: HashMap<String, Bytes> = [("field".to_string(), Bytes::from(vec![b'v'; 100]))].into_iter().collect();
+
+        let entries = vec![
+            ("string".to_string(), RedisDataType::Bytes(Bytes::from("value")), Some(1_900_000_000_000)),
+            ("list".to_string(), RedisDataType::List(list.clone()), None),
+            ("set".to_string(), RedisDataType::Set(set.clone()), None),
+            ("zset".to_string(), RedisDataType::SortedSet(zset.clone()), None),
+            ("hash".to_string(), RedisDataType::HASH(hash.clone()), None),
+        ];
+
+        let data = encode(&entries);
+        assert_eq!(&data[..9], b"REDIS0009");
+
+        let loaded = decode(&data).unwrap();
+        assert_eq!(loaded.len(), 5);
+        assert!(matches!(&loaded[0], (key, RedisDataType::Bytes(v), Some(1_900_000_000_000)) if key == "string" && v == "value"));
+        assert!(matches!(&loaded[1].1, RedisDataType::List(l) if *l == list));
+        assert!(matches!(&loaded[2].1, RedisDataType::Set(s) if *s == set));
+        assert!(matches!(&loaded[3].1, RedisDataType::SortedSet(z) if *z == zset));
+        assert!(matches!(&loaded[4].1, RedisDataType::HASH(h) if *h == hash));
+
+        // a flipped bit is caught by the checksum
+        let mut corrupted = data.clone();
+        corrupted[20] ^= 1;
+        assert!(decode(&corrupted).is_err());
+    }
+
+    #[test]
+    fn dump_test() {
+        let value = RedisDataType::List(vec![Bytes::from("a"), Bytes::from("1")]);
+        let payload = dump(&value);
+        assert!(matches!(restore(&payload).unwrap(), RedisDataType::List(list) if list.len() == 2));
+
+        // RDB version 11 from Redis 7
+        let mut redis = b"\x00\x03bar\x0b\x00".to_vec();
+        redis.extend_from_slice(&crc64(0, &redis).to_le_bytes());
+        assert!(matches!(restore(&redis), Ok(RedisDataType::Bytes(bar)) if bar == "bar"));
+
+        // a disabled (zero) checksum is not trusted
+        let unchecked = b"\x00\x03bar\x0b\x00\x00\x00\x00\x00\x00\x00\x00\x00";
+        assert_eq!(restore(unchecked).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+
+        // lengths past the end of the payload are refused before anything is allocated
+        let mut huge_list = b"\x01\x81\x00\x00\x00\x00\xff\xff\xff\xff\x0b\x00".to_vec();
+        huge_list.extend_from_slice(&crc64(0, &huge_list).to_le_bytes());
+        assert_eq!(restore(&huge_list).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+        let mut corrupted = payload.clone();
+        corrupted[1] ^= 1;
+        assert_eq!(restore(&corrupted).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn compact_encodings_test() {
+        // ziplist with "a", 5 (immediate int) and 300 (int16)
+        let ziplist = [
+            0, 0, 0, 0, 0, 0, 0, 0, 3, 0,
+            0x00, 0x01, b'a',
+            0x03, 0xF6,
+            0x02, 0xC0, 0x2C, 0x01,
+            0xFF,
+        ];
+        assert_eq!(ziplist_entries(&ziplist).unwrap(), vec![Bytes::from("a"), Bytes::from("5"), Bytes::from("300")]);
+
+        // listpack with "ab", 7 and -1 (13 bit int)
+        let listpack = [
+            0, 0, 0, 0, 3, 0,
+            0x82, b'a', b'b', 3,
+            0x07, 1,
+            0xDF, 0xFF, 2,
+            0xFF,
+        ];
+        assert_eq!(listpack_entries(&listpack).unwrap(), vec![Bytes::from("ab"), Bytes::from("7"), Bytes::from("-1")]);
+
+        // "aaaaaa": one literal then a back reference of length 5
+        assert_eq!(lzf_decompress(&[0x00, b'a', 0x60, 0x00], 6).unwrap(), b"aaaaaa".to_vec());
+        // a decompressed length that the data does not match
+        assert!(lzf_decompress(&[0x00, b'a', 0x60, 0x00], 5).is_err());
+        assert!(lzf_decompress(&[0x00, b'a'], usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,809 @@
+//! Master/replica replication, following the PSYNC2 protocol of Redis:
+//! - the master keeps a replication ID and an offset, the number of bytes of write
+//!   commands it produced, and the last `repl-backlog-size` bytes of them in a circular backlog
+//! - a replica sends `PSYNC <replid> <offset>`. If the master knows that history and still has
+//!   the bytes from `offset` on, it answers `+CONTINUE` and sends them (partial resync),
+//!   otherwise `+FULLRESYNC <replid> <offset>` followed by an RDB snapshot
+//! - afterwards the master forwards every write command, and the replica acknowledges
+//!   how far it got with `REPLCONF ACK <offset>`, which is what WAIT counts
+//!
+//! A replica forwards the stream it gets, byte for byte, to its own replicas and keeps the
+//! replication ID of its master, so that when it is promoted its replicas can continue with it
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Cursor;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::AbortHandle;
+use tokio::{select, time};
+use tokio_util::codec::{Framed, FramedRead};
+
+use crate::clock::{unix_ms, unix_secs};
+use crate::cmd::Cmd;
+use crate::codec::RedisCodec;
+use crate::config::Config;
+use crate::db::{SharedDb, Store};
+use crate::frame::{check, encode_command, Frame, FrameError};
+use crate::rdb::{self, SnapshotEntry};
+use crate::RedisResult;
+
+/// how often a replica tells its master how far it got
+const REPLICA_ACK_PERIOD: Duration = Duration::from_secs(1);
+
+/// wait before connecting again to a master that could not be reached
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// the replication ID of a history that does not exist
+const NO_REPLID: &str = "0000000000000000000000000000000000000000";
+
+/// The replication state of a server, master and replica side
+#[derive(Debug)]
+pub(crate) struct Replication {
+    /// the ID of the history this server serves
+    replid: String,
+    /// the ID of the history it served before it was promoted, valid up to `second_replid_offset`
+    replid2: String,
+    second_replid_offset: i64,
+    /// bytes of the replication stream produced, or received from the master, so far
+    offset: u64,
+    backlog: Backlog,
+    /// the connected replicas, by id
+    replicas: BTreeMap<u64, ReplicaHandle>,
+    next_replica_id: u64,
+    /// the master, when this server is a replica
+    master: Option<MasterLink>,
+    /// the port this server listens on, announced to its master with `REPLCONF listening-port`
+    pub(crate) listening_port: u16,
+    /// reject writes from clients while this server is a replica
+    read_only: bool,
+    /// full resyncs served
+    pub(crate) sync_full: u64,
+    /// partial resyncs served
+    pub(crate) sync_partial_ok: u64,
+    /// PSYNC requests that could not continue and got a full resync instead
+    pub(crate) sync_partial_err: u64,
+}
+
+/// the last bytes of the replication stream, for replicas that reconnect
+#[derive(Debug)]
+struct Backlog {
+    data: VecDeque<u8>,
+    size: usize,
+}
+
+/// a replica connected to this server
+#[derive(Debug)]
+struct ReplicaHandle {
+    ip: String,
+    port: u16,
+    /// the write commands still to send to it
+    sender: UnboundedSender<Bytes>,
+    /// the snapshot of a full resync was sent
+    online: bool,
+    /// the offset it acknowledged last, and when
+    ack_offset: u64,
+    ack_time: u64,
+}
+
+/// the connection to the master of a replica
+#[derive(Debug)]
+struct MasterLink {
+    host: String,
+    port: u16,
+    state: LinkState,
+    /// unix time of the last data received from the master
+    last_io: u64,
+    /// when the link went down, unix time
+    down_since: u64,
+    task: AbortHandle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LinkState {
+    Connecting,
+    /// waiting for the snapshot of a full resync
+    Sync,
+    Connected,
+}
+
+/// how a master answers a PSYNC
+#[derive(Debug)]
+pub(crate) enum SyncStart {
+    /// `+FULLRESYNC replid offset` and a snapshot of the keyspace
+    Full { replid: String, offset: u64, entries: Vec<SnapshotEntry> },
+    /// `+CONTINUE replid` and the missing part of the stream
+    Partial { replid: String, backlog: Vec<u8> },
+}
+
+impl Backlog {
+    fn new(size: usize) -> Self {
+        Self { data: VecDeque::new(), size }
+    }
+
+    fn feed(&mut self, data: &[u8]) {
+        self.data.extend(data);
+        if self.data.len() > self.size {
+            let excess = self.data.len() - self.size;
+            self.data.drain(..excess);
+        }
+    }
+
+    /// the bytes from replication offset `from` up to `offset`, the offset of the last byte
+    /// fed. `None` when they are not all in the backlog anymore
+    fn since(&self, from: u64, offset: u64) -> Option<Vec<u8>> {
+        let first = offset + 1 - self.data.len() as u64;
+        if from < first || from > offset + 1 {
+            return None;
+        }
+        Some(self.data.range((from - first) as usize..).copied().collect())
+    }
+
+    fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    /// keep at most `size` bytes, the most recent ones
+    fn resize(&mut self, size: usize) {
+        self.size = size;
+        self.feed(&[]);
+    }
+}
+
+impl Replication {
+    pub(crate) fn new(config: &Config) -> Self {
+        Self {
+            replid: random_id(),
+            replid2: NO_REPLID.to_string(),
+            second_replid_offset: -1,
+            offset: 0,
+            backlog: Backlog::new(config.repl_backlog_size),
+            replicas: BTreeMap::new(),
+            next_replica_id: 1,
+            master: None,
+            listening_port: 0,
+            read_only: config.replica_read_only,
+            sync_full: 0,
+            sync_partial_ok: 0,
+            sync_partial_err: 0,
+        }
+    }
+
+    /// apply a CONFIG SET of `replica-read-only` or `repl-backlog-size`
+    pub(crate) fn configure(&mut self, config: &Config) {
+        self.read_only = config.replica_read_only;
+        self.backlog.resize(config.repl_backlog_size);
+    }
+
+    /// CONFIG RESETSTAT
+    pub(crate) fn reset_stats(&mut self) {
+        self.sync_full = 0;
+        self.sync_partial_ok = 0;
+        self.sync_partial_err = 0;
+    }
+
+    pub(crate) fn is_replica(&self) -> bool {
+        self.master.is_some()
+    }
+
+    /// clients may not write to a replica unless `replica-read-only` is off
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.is_replica() && self.read_only
+    }
+
+    pub(crate) fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub(crate) fn has_replicas(&self) -> bool {
+        !self.replicas.is_empty()
+    }
+
+    /// append an encoded command to the replication stream
+    pub(crate) fn feed(&mut self, data: Bytes) {
+        self.offset += data.len() as u64;
+        self.backlog.feed(&data);
+        // a replica whose connection is gone is dropped
+        self.replicas.retain(|_, replica| replica.sender.send(data.clone()).is_ok());
+    }
+
+    /// bytes held by the backlog
+    pub(crate) fn backlog_memory(&self) -> usize {
+        self.backlog.data.len()
+    }
+
+    /// bytes of the stream sent to replicas that they did not acknowledge yet,
+    /// an estimate of what sits in their output buffers
+    pub(crate) fn replica_buffers(&self) -> usize {
+        self.replicas.values()
+            .filter(|replica| replica.online && replica.ack_offset > 0)
+            .map(|replica| self.offset.saturating_sub(replica.ack_offset) as usize)
+            .sum()
+    }
+
+    /// number of connected replicas
+    pub(crate) fn replica_count(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// how far behind each replica is: its address, the bytes of the stream it did not
+    /// acknowledge and the seconds since its last acknowledgement
+    pub(crate) fn replica_lags(&self) -> Vec<(String, u16, u64, u64)> {
+        let now = unix_ms();
+        self.replicas.values()
+            .map(|replica| (replica.ip.clone(), replica.port, self.offset.saturating_sub(replica.ack_offset),
+                            now.saturating_sub(replica.ack_time) / 1000))
+            .collect()
+    }
+
+    /// when this server is a replica, whether its link to the master is up and the seconds
+    /// since it last received something from it
+    pub(crate) fn master_link(&self) -> Option<(bool, u64)> {
+        self.master.as_ref()
+            .map(|link| (link.state == LinkState::Connected, unix_secs().saturating_sub(link.last_io)))
+    }
+
+    /// how many replicas acknowledged the stream up to `offset`
+    pub(crate) fn acked(&self, offset: u64) -> usize {
+        self.replicas.values().filter(|replica| replica.ack_offset >= offset).count()
+    }
+
+    /// the part of the stream a replica asking for `PSYNC replid offset` misses,
+    /// if it can continue from there
+    fn continue_from(&self, replid: &str, offset: i64) -> Option<Vec<u8>> {
+        if offset < 0 {
+            return None;
+        }
+        let known = replid == self.replid || (replid == self.replid2 && offset <= self.second_replid_offset);
+        if !known {
+            return None;
+        }
+        self.backlog.since(offset as u64, self.offset)
+    }
+
+    /// start a new history, when a replica is promoted. Replicas that followed the old one
+    /// can still continue up to where it ended
+    fn shift_replid(&mut self) {
+        self.replid2 = std::mem::replace(&mut self.replid, random_id());
+        self.second_replid_offset = self.offset as i64 + 1;
+    }
+
+    /// a full resync with the master: take over its history. Our own replicas have to sync again
+    fn full_synced(&mut self, replid: String, offset: u64) {
+        self.replid = replid;
+        self.replid2 = NO_REPLID.to_string();
+        self.second_replid_offset = -1;
+        self.offset = offset;
+        self.backlog.clear();
+        self.replicas.clear();
+    }
+
+    /// `+CONTINUE <replid>`: the master may have been promoted since we last followed it
+    fn continued(&mut self, replid: &str) {
+        if replid != self.replid {
+            self.replid2 = std::mem::replace(&mut self.replid, replid.to_string());
+            self.second_replid_offset = self.offset as i64 + 1;
+            self.replicas.clear();
+        }
+    }
+
+    fn link_state(&mut self, state: LinkState) {
+        if let Some(link) = self.master.as_mut() {
+            if link.state == LinkState::Connected && state != LinkState::Connected {
+                link.down_since = unix_secs();
+            }
+            link.state = state;
+            link.last_io = unix_secs();
+        }
+    }
+
+    fn master_io(&mut self) {
+        if let Some(link) = self.master.as_mut() {
+            link.last_io = unix_secs();
+        }
+    }
+
+    fn ack(&mut self, id: u64, offset: u64) {
+        if let Some(replica) = self.replicas.get_mut(&id) {
+            replica.ack_offset = offset;
+            replica.ack_time = unix_ms();
+        }
+    }
+
+    /// the `# Replication` section of INFO
+    pub(crate) fn info(&self) -> String {
+        let mut info = String::from("# Replication\r\n");
+        let _ = write!(info, "role:{}\r\n", if self.is_replica() { "slave" } else { "master" });
+
+        if let Some(link) = &self.master {
+            let now = unix_secs();
+            let up = link.state == LinkState::Connected;
+            let _ = write!(info, "master_host:{}\r\nmaster_port:{}\r\n", link.host, link.port);
+            let _ = write!(info, "master_link_status:{}\r\n", if up { "up" } else { "down" });
+            let _ = write!(info, "master_last_io_seconds_ago:{}\r\n", if up { now.saturating_sub(link.last_io) as i64 } else { -1 });
+            let _ = write!(info, "master_sync_in_progress:{}\r\n", (link.state == LinkState::Sync) as u8);
+            let _ = write!(info, "slave_read_repl_offset:{}\r\nslave_repl_offset:{}\r\n", self.offset, self.offset);
+            if !up {
+                let _ = write!(info, "master_link_down_since_seconds:{}\r\n", now.saturating_sub(link.down_since));
+            }
+            let _ = write!(info, "slave_priority:100\r\nslave_read_only:{}\r\nreplica_announced:1\r\n", self.read_only as u8);
+        }
+
+        let _ = write!(info, "connected_slaves:{}\r\n", self.replicas.len());
+        let now = unix_ms();
+        for (i, replica) in self.replicas.values().enumerate() {
+            let _ = write!(info, "slave{}:ip={},port={},state={},offset={},lag={}\r\n",
+                           i, replica.ip, replica.port, if replica.online { "online" } else { "wait_bgsave" },
+                           replica.ack_offset, now.saturating_sub(replica.ack_time) / 1000);
+        }
+
+        let _ = write!(info, "master_failover_state:no-failover\r\n");
+        let _ = write!(info, "master_replid:{}\r\nmaster_replid2:{}\r\n", self.replid, self.replid2);
+        let _ = write!(info, "master_repl_offset:{}\r\nsecond_repl_offset:{}\r\n", self.offset, self.second_replid_offset);
+        let _ = write!(info, "repl_backlog_active:1\r\nrepl_backlog_size:{}\r\n", self.backlog.size);
+        let _ = write!(info, "repl_backlog_first_byte_offset:{}\r\n", self.offset + 1 - self.backlog.data.len() as u64);
+        let _ = write!(info, "repl_backlog_histlen:{}\r\n", self.backlog.data.len());
+        info
+    }
+}
+
+/// 40 random hex characters, the format of replication IDs and cluster node IDs
+pub(crate) fn random_id() -> String {
+    let state = RandomState::new();
+    let mut replid = String::with_capacity(48);
+    for i in 0..3u64 {
+        let mut hasher = state.build_hasher();
+        hasher.write_u64(i);
+        hasher.write_u64(unix_ms());
+        let _ = write!(replid, "{:016x}", hasher.finish());
+    }
+    replid.truncate(40);
+    replid
+}
+
+/// REPLICAOF host port, or REPLICAOF NO ONE when `master` is `None`
+pub(crate) fn replicaof(db: &SharedDb, master: Option<(String, u16)>) -> Frame {
+    let mut store = db.lock();
+    // CONFIG GET 和 CONFIG REWRITE 看到的是当前的 master
+    store.config.replicaof = master.clone();
+    let replication = &mut store.replication;
+
+    let (host, port) = match master {
+        None => {
+            if let Some(link) = replication.master.take() {
+                link.task.abort();
+                replication.shift_replid();
+            }
+            return Frame::ok();
+        }
+        Some(master) => master,
+    };
+
+    if replication.master.as_ref().is_some_and(|link| link.host == host && link.port == port) {
+        return Frame::Simple("OK Already connected to specified master".to_string());
+    }
+    if let Some(link) = replication.master.take() {
+        link.task.abort();
+    }
+    let task = tokio::spawn(replica_task(db.clone(), host.clone(), port)).abort_handle();
+    replication.master = Some(MasterLink {
+        host,
+        port,
+        state: LinkState::Connecting,
+        last_io: unix_secs(),
+        down_since: unix_secs(),
+        task,
+    });
+    Frame::ok()
+}
+
+/// register a replica that sent `PSYNC replid offset` and decide how it syncs.
+/// The snapshot of a full resync is taken under the same lock as the registration,
+/// so the replica gets every write that follows it and nothing before
+fn attach_replica(store: &mut Store, ip: String, port: u16, replid: &str, offset: i64)
+                  -> (u64, SyncStart, UnboundedReceiver<Bytes>) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let replication = &mut store.replication;
+    let id = replication.next_replica_id;
+    replication.next_replica_id += 1;
+
+    let partial = replication.continue_from(replid, offset);
+    if partial.is_some() {
+        replication.sync_partial_ok += 1;
+    } else {
+        // "?" is a replica that never synced, it does not count as a failed partial resync
+        if replid != "?" {
+            replication.sync_partial_err += 1;
+        }
+        replication.sync_full += 1;
+    }
+    replication.replicas.insert(id, ReplicaHandle {
+        ip,
+        port,
+        sender,
+        online: partial.is_some(),
+        ack_offset: 0,
+        ack_time: unix_ms(),
+    });
+
+    let replid = replication.replid.clone();
+    let master_offset = replication.offset;
+    let start = match partial {
+        Some(backlog) => SyncStart::Partial { replid, backlog },
+        None => SyncStart::Full { replid, offset: master_offset, entries: store.snapshot() },
+    };
+    (id, start, receiver)
+}
+
+/// serve a replica on the connection it sent PSYNC on, until it goes away
+pub(crate) async fn serve_replica(framed: Framed<TcpStream, RedisCodec>, db: SharedDb,
+                                  replid: String, offset: i64, port: u16) -> RedisResult<()> {
+    let parts = framed.into_parts();
+    let ip = parts.io.peer_addr()?.ip().to_string();
+    let (read, mut write) = parts.io.into_split();
+    // whatever the replica sent after PSYNC is already buffered
+    let mut requests = FramedRead::new(read, RedisCodec);
+    requests.read_buffer_mut().extend_from_slice(&parts.read_buf);
+
+    let (id, start, mut stream) = attach_replica(&mut db.lock(), ip, port, &replid, offset);
+    let result = feed_replica(&db, id, start, &mut write, &mut requests, &mut stream).await;
+    db.lock().replication.replicas.remove(&id);
+    result
+}
+
+async fn feed_replica(db: &SharedDb, id: u64, start: SyncStart, write: &mut OwnedWriteHalf,
+                      requests: &mut FramedRead<OwnedReadHalf, RedisCodec>,
+                      stream: &mut UnboundedReceiver<Bytes>) -> RedisResult<()> {
+    match start {
+        SyncStart::Full { replid, offset, entries } => {
+            write.write_all(format!("+FULLRESYNC {} {}\r\n", replid, offset).as_bytes()).await?;
+            let rdb = tokio::task::spawn_blocking(move || rdb::encode(&entries)).await?;
+            write.write_all(format!("${}\r\n", rdb.len()).as_bytes()).await?;
+            write.write_all(&rdb).await?;
+        }
+        SyncStart::Partial { replid, backlog } => {
+            write.write_all(format!("+CONTINUE {}\r\n", replid).as_bytes()).await?;
+            write.write_all(&backlog).await?;
+        }
+    }
+    if let Some(replica) = db.lock().replication.replicas.get_mut(&id) {
+        replica.online = true;
+    }
+
+    loop {
+        select! {
+            data = stream.recv() => match data {
+                Some(data) => write.write_all(&data).await?,
+                // dropped by the master, after a resync of its own
+                None => return Ok(()),
+            },
+            request = requests.next() => match request {
+                Some(Ok(frame)) => {
+                    if let Some(offset) = ack_offset(&frame.into()) {
+                        db.lock().replication.ack(id, offset);
+                    }
+                }
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+/// the offset of a `REPLCONF ACK <offset>`
+fn ack_offset(frame: &Frame) -> Option<u64> {
+    let argv = match frame {
+        Frame::Array(array) => array,
+        _ => return None,
+    };
+    let arg = |i: usize| match argv.get(i) {
+        Some(Frame::Bulk(data)) => Some(String::from_utf8_lossy(data).to_string()),
+        Some(Frame::Simple(data)) => Some(data.clone()),
+        _ => None,
+    };
+    if !arg(0)?.eq_ignore_ascii_case("REPLCONF") || !arg(1)?.eq_ignore_ascii_case("ACK") {
+        return None;
+    }
+    arg(2)?.parse().ok()
+}
+
+/// follow a master until REPLICAOF changes, reconnecting whenever the link breaks
+async fn replica_task(db: SharedDb, host: String, port: u16) {
+    loop {
+        if let Err(e) = sync_with_master(&db, &host, port).await {
+            eprintln!("Lost the connection with master {}:{}: {}", host, port, e);
+        }
+        db.lock().replication.link_state(LinkState::Connecting);
+        time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn sync_with_master(db: &SharedDb, host: &str, port: u16) -> RedisResult<()> {
+    let (read, mut write) = TcpStream::connect((host, port)).await?.into_split();
+    let mut reader = BufReader::new(read);
+
+    // 先握手，再用自己的replid和offset尝试部分同步
+    let (listening_port, replid, offset) = {
+        let store = db.lock();
+        let replication = &store.replication;
+        (replication.listening_port, replication.replid.clone(), replication.offset + 1)
+    };
+    handshake(&mut reader, &mut write, &["PING"]).await?;
+    handshake(&mut reader, &mut write, &["REPLCONF", "listening-port", &listening_port.to_string()]).await?;
+    handshake(&mut reader, &mut write, &["REPLCONF", "capa", "psync2"]).await?;
+    send(&mut write, &["PSYNC", &replid, &offset.to_string()]).await?;
+    db.lock().replication.link_state(LinkState::Sync);
+
+    let reply = read_line(&mut reader).await?;
+    let mut words = reply.split(' ');
+    match words.next() {
+        Some("+FULLRESYNC") => {
+            let replid = words.next().ok_or("bad FULLRESYNC reply")?.to_string();
+            let offset = words.next().and_then(|offset| offset.parse().ok()).ok_or("bad FULLRESYNC reply")?;
+            let data = read_rdb(&mut reader).await?;
+            let entries = tokio::task::spawn_blocking(move || rdb::decode(&data)).await??;
+
+            let mut store = db.lock();
+            store.flush(true);
+            store.restore(entries);
+            store.replication.full_synced(replid, offset);
+            // the AOF has to start again from the new dataset
+            if store.config.appendonly {
+                if let Err(e) = store.bgrewriteaof() {
+                    eprintln!("Can't rewrite the AOF after the sync with master: {}", e);
+                }
+            }
+        }
+        Some("+CONTINUE") => {
+            if let Some(replid) = words.next() {
+                db.lock().replication.continued(replid);
+            }
+        }
+        _ => return Err(format!("unexpected reply to PSYNC: {}", reply).into()),
+    }
+    db.lock().replication.link_state(LinkState::Connected);
+
+    follow_master(db, reader, write).await
+}
+
+/// apply the write commands streamed by the master, forwarding them to the AOF and our replicas
+async fn follow_master(db: &SharedDb, mut reader: BufReader<OwnedReadHalf>, mut write: OwnedWriteHalf) -> RedisResult<()> {
+    let mut buffer = BytesMut::with_capacity(4096);
+    let mut ack = time::interval(REPLICA_ACK_PERIOD);
+    // the commands of a MULTI are applied together when its EXEC arrives
+    let mut transaction: Option<Vec<(Frame, Bytes)>> = None;
+
+    loop {
+        while let Some(data) = next_command(&mut buffer)? {
+            let frame = Frame::parse_protocol(&data)?;
+            match command_name(&frame).as_str() {
+                "REPLCONF" => {
+                    // GETACK: report the offset before this command, the one WAIT waits for
+                    let offset = db.lock().replication.offset;
+                    send(&mut write, &["REPLCONF", "ACK", &offset.to_string()]).await?;
+                    db.lock().propagate_raw(data, false);
+                }
+                "MULTI" => transaction = Some(vec![(frame, data)]),
+                "EXEC" => {
+                    let mut queued = transaction.take().unwrap_or_default();
+                    queued.push((frame, data));
+                    let mut store = db.lock();
+                    for (frame, data) in queued {
+                        apply_from_master(&mut store, frame, data);
+                    }
+                }
+                _ => match transaction.as_mut() {
+                    Some(queued) => queued.push((frame, data)),
+                    None => apply_from_master(&mut db.lock(), frame, data),
+                },
+            }
+        }
+
+        select! {
+            read = reader.read_buf(&mut buffer) => {
+                if read? == 0 {
+                    return Err("connection closed by master".into());
+                }
+                db.lock().replication.master_io();
+            }
+            _ = ack.tick() => {
+                let offset = db.lock().replication.offset;
+                send(&mut write, &["REPLCONF", "ACK", &offset.to_string()]).await?;
+            }
+        }
+    }
+}
+
+fn apply_from_master(store: &mut Store, frame: Frame, data: Bytes) {
+    match command_name(&frame).as_str() {
+        "PING" => store.propagate_raw(data, false),
+        "MULTI" | "EXEC" => store.propagate_raw(data, true),
+        _ => {
+            match Cmd::try_from(frame) {
+                Ok(cmd) => {
+                    if let Ok(Frame::Error(e)) = cmd.apply(store) {
+                        eprintln!("Error applying a command from the master: {}", e);
+                    }
+                }
+                Err(e) => eprintln!("Invalid command from the master: {}", e),
+            }
+            store.propagate_raw(data, true);
+        }
+    }
+}
+
+/// split the next complete command off the stream
+fn next_command(buffer: &mut BytesMut) -> RedisResult<Option<Bytes>> {
+    let mut cursor = Cursor::new(&buffer[..]);
+    match check(&mut cursor) {
+        Ok(()) => {
+            let len = cursor.position() as usize;
+            Ok(Some(buffer.split_to(len).freeze()))
+        }
+        Err(FrameError::Incomplete) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn command_name(frame: &Frame) -> String {
+    match frame {
+        Frame::Array(array) => match array.first() {
+            Some(Frame::Bulk(name)) => String::from_utf8_lossy(name).to_uppercase(),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+async fn send(write: &mut OwnedWriteHalf, argv: &[&str]) -> RedisResult<()> {
+    let argv: Vec<Bytes> = argv.iter().map(|arg| Bytes::from(arg.to_string())).collect();
+    write.write_all(&encode_command(&argv)).await?;
+    Ok(())
+}
+
+/// send a command of the handshake and check that it did not fail
+async fn handshake(reader: &mut BufReader<OwnedReadHalf>, write: &mut OwnedWriteHalf, argv: &[&str]) -> RedisResult<()> {
+    send(write, argv).await?;
+    let reply = read_line(reader).await?;
+    if let Some(error) = reply.strip_prefix('-') {
+        return Err(format!("{} failed: {}", argv[0], error).into());
+    }
+    // a bulk reply, such as the pong of this server
+    if reply.starts_with('$') && reply != "$-1" {
+        read_line(reader).await?;
+    }
+    Ok(())
+}
+
+/// the next line of a reply without its CRLF. Empty lines are keepalives and skipped
+async fn read_line(reader: &mut BufReader<OwnedReadHalf>) -> RedisResult<String> {
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err("connection closed by master".into());
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if !line.is_empty() {
+            return Ok(line.to_string());
+        }
+    }
+}
+
+/// the snapshot of a full resync: `$<len>\r\n` and the RDB file, without a trailing CRLF
+async fn read_rdb(reader: &mut BufReader<OwnedReadHalf>) -> RedisResult<Vec<u8>> {
+    let line = read_line(reader).await?;
+    let len = line.strip_prefix('$')
+        .and_then(|len| len.parse::<usize>().ok())
+        .ok_or_else(|| format!("bad snapshot header from master: {}", line))?;
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use tokio::net::TcpListener;
+    use tokio::sync::broadcast;
+
+    use crate::config::Config;
+    use crate::db::{SharedDb, run, test_config, test_db};
+    use crate::frame::Frame;
+    use crate::replication::{replicaof, Backlog, Replication};
+    use crate::server::process;
+    use crate::session::Session;
+
+    /// wait until `key` shows up on the replica
+    async fn replicated(db: &SharedDb, key: &str) -> Bytes {
+        for _ in 0..300 {
+            if let Some(value) = db.lock().get_bytes(key) {
+                return value;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{} was not replicated", key);
+    }
+
+    #[test]
+    fn backlog_test() {
+        let mut backlog = Backlog::new(4);
+        backlog.feed(b"abc");
+        assert_eq!(backlog.since(1, 3), Some(b"abc".to_vec()));
+        assert_eq!(backlog.since(4, 3), Some(vec![]));
+        assert_eq!(backlog.since(5, 3), None);
+
+        // the oldest bytes fall off
+        backlog.feed(b"def");
+        assert_eq!(backlog.since(3, 6), Some(b"cdef".to_vec()));
+        assert_eq!(backlog.since(2, 6), None);
+    }
+
+    #[test]
+    fn continue_test() {
+        let mut replication = Replication::new(&Config::default());
+        replication.feed(Bytes::from_static(b"*1\r\n$4\r\nPING\r\n"));
+        let replid = replication.replid.clone();
+
+        assert_eq!(replication.continue_from(&replid, 1).map(|data| data.len()), Some(14));
+        assert_eq!(replication.continue_from(&replid, 15), Some(vec![]));
+        assert!(replication.continue_from("?", -1).is_none());
+
+        // after a promotion the old history can be continued up to where it ended
+        replication.shift_replid();
+        replication.feed(Bytes::from_static(b"*1\r\n$4\r\nPING\r\n"));
+        assert!(replication.continue_from(&replid, 15).is_some());
+        assert!(replication.continue_from(&replid, 16).is_none());
+    }
+
+    #[tokio::test]
+    async fn replication_test() {
+        let (sender, _) = broadcast::channel(1);
+        let master = test_db(test_config());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        {
+            let master = master.clone();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    tokio::spawn(process(socket, master.clone(), sender.subscribe()));
+                }
+            });
+        }
+        run(&mut master.lock(), "SET before 1");
+
+        // full resync, then the stream
+        let replica = test_db(test_config());
+        replicaof(&replica, Some(("127.0.0.1".to_string(), port)));
+        assert_eq!(replicated(&replica, "before").await, "1");
+        run(&mut master.lock(), "SET after 2");
+        assert_eq!(replicated(&replica, "after").await, "2");
+
+        assert!(matches!(run(&mut replica.lock(), "SET nope 1"), Frame::Error(e) if e.starts_with("READONLY")));
+        assert!(replica.lock().replication.info().contains("master_link_status:up"));
+
+        let mut session = Session::new(master.clone());
+        let reply = session.handle(Frame::Array(vec![
+            Frame::Bulk(Bytes::from("WAIT")), Frame::Bulk(Bytes::from("1")), Frame::Bulk(Bytes::from("2000")),
+        ])).await;
+        assert!(matches!(reply[..], [Frame::Integer(1)]));
+
+        // a broken link continues where it stopped
+        master.lock().replication.replicas.clear();
+        run(&mut master.lock(), "SET later 3");
+        assert_eq!(replicated(&replica, "later").await, "3");
+        let store = master.lock();
+        assert_eq!((store.replication.sync_full, store.replication.sync_partial_ok), (1, 1));
+        assert_eq!(replica.lock().replication.offset(), store.replication.offset());
+    }
+}
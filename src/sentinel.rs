@@ -0,0 +1,1086 @@
+//! Sentinel: a supervisor that watches masters and their replicas and fails a master over
+//! to one of its replicas when it goes away, like `redis-sentinel`.
+//!
+//! Every instance is sent a PING each second and an INFO every ten seconds, which is how
+//! the replicas of a master are found. A master that did not answer for `down-after-milliseconds`
+//! is subjectively down (`s_down`). The sentinels watching it find each other through hello
+//! messages published on `__sentinel__:hello` of every instance, and ask each other with
+//! `SENTINEL is-master-down-by-addr`. Once `quorum` of them agree the master is objectively
+//! down (`o_down`) and one of them is elected leader for a new epoch by a majority. The leader
+//! promotes the best replica with `REPLICAOF NO ONE`, points the other replicas to it and
+//! announces the new configuration in its hello messages, so the other sentinels switch too.
+//!
+//! Like the cluster there is no dedicated bus, everything goes over plain client connections
+
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Write as _;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{self, Instant};
+use tokio::{select, signal};
+use tokio_util::codec::Framed;
+
+use crate::clock::unix_ms;
+use crate::codec::{RedisCodec, RedisFrame};
+use crate::frame::{Frame, FrameError, FrameIter};
+use crate::replication::random_id;
+use crate::RedisResult;
+
+/// how often the state of every master is looked at
+const TICK_PERIOD: Duration = Duration::from_millis(100);
+const PING_PERIOD: Duration = Duration::from_secs(1);
+const INFO_PERIOD: Duration = Duration::from_secs(10);
+/// INFO is sent more often while the master is down or failing over
+const INFO_PERIOD_FAST: Duration = Duration::from_secs(1);
+const HELLO_PERIOD: Duration = Duration::from_secs(2);
+/// how often the other sentinels are asked whether they also see the master down
+const ASK_PERIOD: Duration = Duration::from_secs(1);
+/// an answer of another sentinel older than this is not counted
+const ASK_VALIDITY: Duration = Duration::from_secs(5);
+/// how long a single request to an instance or another sentinel may take
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+/// a replica that follows the wrong master is reconfigured once the configuration
+/// did not change for that long, so every sentinel had the time to hear about it
+const RECONF_GRACE: Duration = Duration::from_secs(8);
+/// at most this long is waited before starting a failover, so that the sentinels
+/// that see the master down at the same time do not all ask for votes at once
+const MAX_DESYNC: Duration = Duration::from_millis(1000);
+const HELLO_CHANNEL: &str = "__sentinel__:hello";
+
+/// A master to monitor, `sentinel monitor <name> <host> <port> <quorum>` in `sentinel.conf`
+#[derive(Debug, Clone)]
+pub struct MasterConfig {
+    pub name: String,
+    pub host: String,
+    pub port: u16,
+    /// how many sentinels have to agree the master is down before it is failed over
+    pub quorum: usize,
+    /// how long an instance may not answer PING before it is considered down
+    pub down_after: Duration,
+    /// how long a failover may take, and twice that between two attempts on the same master
+    pub failover_timeout: Duration,
+}
+
+impl MasterConfig {
+    pub fn new(name: impl ToString, host: impl ToString, port: u16, quorum: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            host: host.to_string(),
+            port,
+            quorum,
+            down_after: Duration::from_secs(30),
+            failover_timeout: Duration::from_secs(180),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SentinelConfig {
+    /// the address other sentinels reach this one at, announced in the hello messages
+    pub announce_ip: String,
+    pub masters: Vec<MasterConfig>,
+}
+
+impl Default for SentinelConfig {
+    fn default() -> Self {
+        Self { announce_ip: "127.0.0.1".to_string(), masters: vec![] }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Role {
+    Unknown,
+    Master,
+    Replica,
+}
+
+/// a monitored master or replica, as seen by this sentinel
+#[derive(Debug)]
+struct Instance {
+    host: String,
+    port: u16,
+    /// the last time it answered PING
+    last_ok: Instant,
+    last_ping: Option<Instant>,
+    /// when the oldest PING still without an answer was sent
+    ping_since: Option<Instant>,
+    last_info: Option<Instant>,
+    /// a PING or an INFO is waiting for an answer
+    ping_pending: bool,
+    info_pending: bool,
+    /// what its last INFO said
+    role: Role,
+    master_addr: Option<(String, u16)>,
+    master_link_up: bool,
+    offset: u64,
+    priority: u64,
+    /// the last time it was told which master to follow
+    last_reconf: Option<Instant>,
+}
+
+impl Instance {
+    fn new(host: String, port: u16) -> Self {
+        Self {
+            host,
+            port,
+            last_ok: Instant::now(),
+            last_ping: None,
+            ping_since: None,
+            last_info: None,
+            ping_pending: false,
+            info_pending: false,
+            role: Role::Unknown,
+            master_addr: None,
+            master_link_up: false,
+            offset: 0,
+            priority: 100,
+            last_reconf: None,
+        }
+    }
+
+    fn addr(&self) -> (String, u16) {
+        (self.host.clone(), self.port)
+    }
+
+    /// like Redis the time is counted from the oldest unanswered PING,
+    /// so the period of the PINGs does not add to it
+    fn is_sdown(&self, down_after: Duration, now: Instant) -> bool {
+        self.ping_since.is_some_and(|since| now.saturating_duration_since(since) > down_after)
+    }
+}
+
+/// another sentinel watching the same master
+#[derive(Debug)]
+struct Peer {
+    ip: String,
+    port: u16,
+    runid: String,
+    last_hello: Instant,
+    ask_pending: bool,
+    /// the last time it answered that the master is down
+    down_reply: Option<Instant>,
+    /// who it voted for, and in which epoch
+    leader: Option<String>,
+    leader_epoch: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FailoverState {
+    /// asking the other sentinels for their votes
+    WaitStart,
+    /// `REPLICAOF NO ONE` was sent to the promoted replica
+    WaitPromotion,
+}
+
+#[derive(Debug)]
+struct Failover {
+    epoch: u64,
+    state: FailoverState,
+    /// when the state was entered
+    since: Instant,
+    promoted: Option<(String, u16)>,
+}
+
+#[derive(Debug)]
+struct Master {
+    name: String,
+    quorum: usize,
+    down_after: Duration,
+    failover_timeout: Duration,
+    instance: Instance,
+    replicas: BTreeMap<(String, u16), Instance>,
+    /// the other sentinels, by run ID
+    sentinels: BTreeMap<String, Peer>,
+    /// the epoch of the failover that produced the current master
+    config_epoch: u64,
+    config_changed: Instant,
+    /// the vote of this sentinel, and in which epoch
+    leader: Option<String>,
+    leader_epoch: u64,
+    /// since when it is objectively down, and how long to wait from there before failing over
+    odown_since: Option<(Instant, Duration)>,
+    failover: Option<Failover>,
+    /// the last failover attempt, ours or one we voted for
+    failover_start: Option<Instant>,
+    last_hello: Option<Instant>,
+    last_ask: Option<Instant>,
+    /// instances a hello subscriber was started for
+    subscribed: HashSet<(String, u16)>,
+}
+
+impl Master {
+    fn new(config: MasterConfig) -> Self {
+        Self {
+            name: config.name,
+            quorum: config.quorum,
+            down_after: config.down_after,
+            failover_timeout: config.failover_timeout,
+            instance: Instance::new(config.host, config.port),
+            replicas: BTreeMap::new(),
+            sentinels: BTreeMap::new(),
+            config_epoch: 0,
+            config_changed: Instant::now(),
+            leader: None,
+            leader_epoch: 0,
+            odown_since: None,
+            failover: None,
+            failover_start: None,
+            last_hello: None,
+            last_ask: None,
+            subscribed: HashSet::new(),
+        }
+    }
+
+    fn instance_mut(&mut self, addr: &(String, u16)) -> Option<&mut Instance> {
+        if self.instance.host == addr.0 && self.instance.port == addr.1 {
+            return Some(&mut self.instance);
+        }
+        self.replicas.get_mut(addr)
+    }
+
+    fn is_sdown(&self, now: Instant) -> bool {
+        self.instance.is_sdown(self.down_after, now)
+    }
+
+    /// the sentinels that see the master down, this one included
+    fn down_votes(&self, now: Instant) -> usize {
+        let others = self.sentinels.values()
+            .filter(|peer| peer.down_reply.is_some_and(|at| now.saturating_duration_since(at) < ASK_VALIDITY))
+            .count();
+        others + 1
+    }
+
+    fn flags(&self, now: Instant) -> String {
+        let mut flags = String::from("master");
+        if self.is_sdown(now) {
+            flags.push_str(",s_down");
+        }
+        if self.odown_since.is_some() {
+            flags.push_str(",o_down");
+        }
+        if self.failover.is_some() {
+            flags.push_str(",failover_in_progress");
+        }
+        flags
+    }
+
+    /// the sentinel that won the votes of `epoch`, if it won a majority of them
+    fn leader_of(&self, epoch: u64) -> Option<String> {
+        let mut votes: BTreeMap<&str, usize> = BTreeMap::new();
+        let ours = self.leader.as_deref().filter(|_| self.leader_epoch == epoch);
+        let theirs = self.sentinels.values()
+            .filter(|peer| peer.leader_epoch == epoch)
+            .filter_map(|peer| peer.leader.as_deref());
+        for leader in ours.into_iter().chain(theirs) {
+            *votes.entry(leader).or_default() += 1;
+        }
+
+        let voters = self.sentinels.len() + 1;
+        let needed = self.quorum.max(voters / 2 + 1);
+        votes.into_iter()
+            .max_by_key(|(_, count)| *count)
+            .filter(|(_, count)| *count >= needed)
+            .map(|(leader, _)| leader.to_string())
+    }
+
+    /// the replica to promote: reachable, recently heard of, not excluded by
+    /// `replica-priority 0`, the lowest priority and then the most data first
+    fn select_replica(&self, now: Instant) -> Option<(String, u16)> {
+        self.replicas.values()
+            .filter(|replica| replica.role == Role::Replica && !replica.is_sdown(self.down_after, now))
+            .filter(|replica| replica.last_info.is_some_and(|at| now.saturating_duration_since(at) < INFO_PERIOD_FAST * 5))
+            .filter(|replica| replica.priority != 0)
+            .min_by_key(|replica| (replica.priority, std::cmp::Reverse(replica.offset), replica.addr()))
+            .map(Instance::addr)
+    }
+
+    /// make the replica at `addr` the master, the old master is kept as a replica
+    /// so that it is turned into one when it comes back
+    fn switch(&mut self, addr: (String, u16), now: Instant) {
+        println!("+switch-master {} {} {} {} {}", self.name, self.instance.host, self.instance.port, addr.0, addr.1);
+        let promoted = self.replicas.remove(&addr).unwrap_or_else(|| Instance::new(addr.0, addr.1));
+        let old = std::mem::replace(&mut self.instance, promoted);
+        let mut old = Instance::new(old.host, old.port);
+        // 旧master还没有回来，不要马上把它当成在线
+        old.ping_since = now.checked_sub(self.down_after + PING_PERIOD);
+        self.replicas.insert(old.addr(), old);
+        self.config_changed = now;
+        self.odown_since = None;
+        self.failover = None;
+    }
+}
+
+struct State {
+    myid: String,
+    ip: String,
+    port: u16,
+    current_epoch: u64,
+    masters: BTreeMap<String, Master>,
+}
+
+type SharedState = Arc<Mutex<State>>;
+
+fn lock(state: &SharedState) -> MutexGuard<'_, State> {
+    state.lock().unwrap()
+}
+
+impl State {
+    /// the hello message about `master`:
+    /// `ip,port,runid,current_epoch,master_name,master_ip,master_port,master_config_epoch`
+    fn hello(&self, master: &Master) -> String {
+        format!("{},{},{},{},{},{},{},{}", self.ip, self.port, self.myid, self.current_epoch,
+                master.name, master.instance.host, master.instance.port, master.config_epoch)
+    }
+
+    fn master_by_addr(&mut self, ip: &str, port: u16) -> Option<&mut Master> {
+        self.masters.values_mut().find(|master| master.instance.host == ip && master.instance.port == port)
+    }
+
+    /// vote for `runid` as the leader of `epoch`, unless this sentinel already voted in that
+    /// epoch. Returns the vote this sentinel holds for the master
+    fn vote(&mut self, name: &str, epoch: u64, runid: &str) -> (Option<String>, u64) {
+        self.current_epoch = self.current_epoch.max(epoch);
+        let Some(master) = self.masters.get_mut(name) else { return (None, 0) };
+        if master.leader_epoch < epoch && self.current_epoch <= epoch {
+            master.leader = Some(runid.to_string());
+            master.leader_epoch = self.current_epoch;
+            println!("+vote-for-leader {} {}", runid, self.current_epoch);
+            // 投给了别人，就不要马上自己再发起故障转移
+            if runid != self.myid {
+                master.failover_start = Some(Instant::now() + jitter(MAX_DESYNC));
+            }
+        }
+        (master.leader.clone(), master.leader_epoch)
+    }
+
+    /// a hello message from another sentinel, introducing itself and its view of a master
+    fn on_hello(&mut self, message: &str) {
+        let fields: Vec<&str> = message.split(',').collect();
+        let [ip, port, runid, epoch, name, master_ip, master_port, master_epoch] = fields[..] else { return };
+        let (Ok(port), Ok(epoch), Ok(master_port), Ok(master_epoch)) =
+            (port.parse::<u16>(), epoch.parse::<u64>(), master_port.parse::<u16>(), master_epoch.parse::<u64>()) else { return };
+        if runid == self.myid {
+            return;
+        }
+
+        self.current_epoch = self.current_epoch.max(epoch);
+        let Some(master) = self.masters.get_mut(name) else { return };
+
+        // a sentinel that restarted comes back with a new run ID at the same address
+        master.sentinels.retain(|id, peer| id == runid || peer.ip != ip || peer.port != port);
+        let peer = master.sentinels.entry(runid.to_string()).or_insert_with(|| {
+            println!("+sentinel sentinel {} {} {} @ {}", runid, ip, port, name);
+            Peer {
+                ip: ip.to_string(),
+                port,
+                runid: runid.to_string(),
+                last_hello: Instant::now(),
+                ask_pending: false,
+                down_reply: None,
+                leader: None,
+                leader_epoch: 0,
+            }
+        });
+        peer.last_hello = Instant::now();
+
+        // the configuration with the highest epoch wins
+        if master_epoch > master.config_epoch {
+            master.config_epoch = master_epoch;
+            if master.instance.host != master_ip || master.instance.port != master_port {
+                master.switch((master_ip.to_string(), master_port), Instant::now());
+            }
+        }
+    }
+
+    fn on_ping(&mut self, name: &str, addr: &(String, u16), reply: RedisResult<Frame>) {
+        let Some(instance) = self.masters.get_mut(name).and_then(|master| master.instance_mut(addr)) else { return };
+        instance.ping_pending = false;
+        // a loading instance is alive too
+        let alive = match reply {
+            Ok(Frame::Simple(_) | Frame::Bulk(_)) => true,
+            Err(e) => e.to_string().starts_with("LOADING") || e.to_string().starts_with("MASTERDOWN"),
+            Ok(_) => false,
+        };
+        if alive {
+            instance.last_ok = Instant::now();
+            instance.ping_since = None;
+        }
+    }
+
+    fn on_info(&mut self, name: &str, addr: &(String, u16), reply: RedisResult<Frame>) {
+        let Some(master) = self.masters.get_mut(name) else { return };
+        let is_master = master.instance.addr() == *addr;
+        let Some(instance) = master.instance_mut(addr) else { return };
+        instance.info_pending = false;
+        let info = match reply {
+            Ok(Frame::Bulk(info)) => parse_info(&String::from_utf8_lossy(&info)),
+            _ => return,
+        };
+
+        instance.last_info = Some(Instant::now());
+        instance.role = info.role;
+        instance.master_addr = info.master_addr;
+        instance.master_link_up = info.master_link_up;
+        instance.offset = info.offset;
+        instance.priority = info.priority;
+
+        // the replicas of the master are found in its INFO
+        if is_master {
+            for (ip, port) in info.replicas {
+                if !master.replicas.contains_key(&(ip.clone(), port)) {
+                    println!("+slave slave {}:{} {} {} @ {} {} {}", ip, port, ip, port, name, master.instance.host, master.instance.port);
+                    master.replicas.insert((ip.clone(), port), Instance::new(ip, port));
+                }
+            }
+        }
+    }
+
+    fn on_ask(&mut self, name: &str, runid: &str, reply: RedisResult<Frame>) {
+        let Some(peer) = self.masters.get_mut(name).and_then(|master| master.sentinels.get_mut(runid)) else { return };
+        peer.ask_pending = false;
+        let Ok(Frame::Array(reply)) = reply else { return };
+        if let [Frame::Integer(down), Frame::Bulk(leader), Frame::Integer(leader_epoch)] = &reply[..] {
+            peer.down_reply = (*down == 1).then(Instant::now);
+            if &leader[..] != b"*" {
+                peer.leader = Some(String::from_utf8_lossy(leader).to_string());
+                peer.leader_epoch = *leader_epoch as u64;
+            }
+        }
+    }
+
+    /// everything that is due for one master: checking its instances, the hello messages,
+    /// the down detection and the steps of a failover
+    fn tick(&mut self, shared: &SharedState, name: &str) {
+        let now = Instant::now();
+        let hello = match self.masters.get(name) {
+            Some(master) => self.hello(master),
+            None => return,
+        };
+        let State { myid, current_epoch, masters, .. } = self;
+        let master = masters.get_mut(name).unwrap();
+        let sdown = master.is_sdown(now);
+        let info_period = if sdown || master.failover.is_some() { INFO_PERIOD_FAST } else { INFO_PERIOD };
+
+        let publish_hello = master.last_hello.is_none_or(|at| now - at >= HELLO_PERIOD);
+        if publish_hello {
+            master.last_hello = Some(now);
+        }
+        let instances = std::iter::once(&mut master.instance).chain(master.replicas.values_mut());
+        for instance in instances {
+            let addr = instance.addr();
+            if !instance.ping_pending && instance.last_ping.is_none_or(|at| now - at >= PING_PERIOD) {
+                instance.ping_pending = true;
+                instance.last_ping = Some(now);
+                instance.ping_since.get_or_insert(now);
+                let (shared, name, addr) = (shared.clone(), name.to_string(), addr.clone());
+                tokio::spawn(async move {
+                    let reply = command(&addr, &["PING"]).await;
+                    lock(&shared).on_ping(&name, &addr, reply);
+                });
+            }
+            if !instance.info_pending && instance.last_info.is_none_or(|at| now - at >= info_period) {
+                instance.info_pending = true;
+                let (shared, name, addr) = (shared.clone(), name.to_string(), addr.clone());
+                tokio::spawn(async move {
+                    let reply = command(&addr, &["INFO", "replication"]).await;
+                    lock(&shared).on_info(&name, &addr, reply);
+                });
+            }
+            if publish_hello {
+                let hello = hello.clone();
+                let addr = addr.clone();
+                tokio::spawn(async move {
+                    let _ = command(&addr, &["PUBLISH", HELLO_CHANNEL, &hello]).await;
+                });
+            }
+            if master.subscribed.insert(addr.clone()) {
+                tokio::spawn(subscribe(shared.clone(), addr));
+            }
+        }
+
+        // ask the other sentinels whether they see the master down too, and for their vote
+        // once we are trying to fail it over
+        if !sdown {
+            master.odown_since = None;
+            for peer in master.sentinels.values_mut() {
+                peer.down_reply = None;
+            }
+        } else if master.last_ask.is_none_or(|at| now - at >= ASK_PERIOD) {
+            master.last_ask = Some(now);
+            let runid = match &master.failover {
+                Some(failover) if failover.state == FailoverState::WaitStart => myid.clone(),
+                _ => "*".to_string(),
+            };
+            for peer in master.sentinels.values_mut().filter(|peer| !peer.ask_pending) {
+                peer.ask_pending = true;
+                let argv = [
+                    "SENTINEL".to_string(), "is-master-down-by-addr".to_string(),
+                    master.instance.host.clone(), master.instance.port.to_string(),
+                    current_epoch.to_string(), runid.clone(),
+                ];
+                let (shared, name) = (shared.clone(), name.to_string());
+                let (addr, peer_id) = ((peer.ip.clone(), peer.port), peer.runid.clone());
+                tokio::spawn(async move {
+                    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+                    let reply = command(&addr, &argv).await;
+                    lock(&shared).on_ask(&name, &peer_id, reply);
+                });
+            }
+        }
+
+        if sdown && master.odown_since.is_none() && master.down_votes(now) >= master.quorum {
+            println!("+odown master {} {} {} #quorum {}/{}",
+                     name, master.instance.host, master.instance.port, master.down_votes(now), master.quorum);
+            master.odown_since = Some((now, jitter(MAX_DESYNC)));
+        }
+
+        let can_start = master.failover.is_none()
+            && master.odown_since.is_some_and(|(since, delay)| now - since >= delay)
+            && master.failover_start.is_none_or(|at| now.saturating_duration_since(at) >= master.failover_timeout * 2);
+        if can_start {
+            *current_epoch += 1;
+            println!("+try-failover master {} {} {} epoch {}", name, master.instance.host, master.instance.port, current_epoch);
+            master.failover = Some(Failover { epoch: *current_epoch, state: FailoverState::WaitStart, since: now, promoted: None });
+            master.failover_start = Some(now);
+            // 马上去拉票
+            master.last_ask = None;
+            let (epoch, myid) = (*current_epoch, myid.clone());
+            self.vote(name, epoch, &myid);
+            return;
+        }
+
+        self.failover(name, now);
+        self.reconfigure_replicas(name, now);
+    }
+
+    /// advance the failover of `name`, if there is one
+    fn failover(&mut self, name: &str, now: Instant) {
+        let myid = self.myid.clone();
+        let master = self.masters.get_mut(name).unwrap();
+        let Some(failover) = master.failover.as_mut() else { return };
+        let election_timeout = master.failover_timeout.min(Duration::from_secs(10));
+
+        match failover.state {
+            FailoverState::WaitStart => {
+                let epoch = failover.epoch;
+                let since = failover.since;
+                if master.leader_of(epoch).as_deref() != Some(myid.as_str()) {
+                    if now - since > election_timeout {
+                        println!("-failover-abort-not-elected master {} epoch {}", name, epoch);
+                        master.failover = None;
+                    }
+                    return;
+                }
+                let Some(promoted) = master.select_replica(now) else {
+                    if now - since > master.failover_timeout {
+                        println!("-failover-abort-no-good-slave master {}", name);
+                        master.failover = None;
+                    }
+                    return;
+                };
+                println!("+elected-leader master {} epoch {}", name, epoch);
+                println!("+promoted-slave slave {}:{} @ {}", promoted.0, promoted.1, name);
+                let failover = master.failover.as_mut().unwrap();
+                failover.state = FailoverState::WaitPromotion;
+                failover.since = now;
+                failover.promoted = Some(promoted.clone());
+                tokio::spawn(async move {
+                    let _ = command(&promoted, &["REPLICAOF", "NO", "ONE"]).await;
+                });
+            }
+            FailoverState::WaitPromotion => {
+                let promoted = failover.promoted.clone().unwrap();
+                let (epoch, since) = (failover.epoch, failover.since);
+                let done = master.replicas.get(&promoted)
+                    .is_some_and(|replica| replica.role == Role::Master && replica.last_info.is_some_and(|at| at > since));
+                if !done {
+                    if now - since > master.failover_timeout {
+                        println!("-failover-abort-slave-timeout master {}", name);
+                        master.failover = None;
+                    }
+                    return;
+                }
+
+                // the other replicas follow the new master, then the new configuration is announced
+                master.config_epoch = epoch;
+                for replica in master.replicas.values_mut().filter(|replica| replica.addr() != promoted) {
+                    replica.last_reconf = Some(now);
+                    let (addr, port) = (replica.addr(), promoted.1.to_string());
+                    let host = promoted.0.clone();
+                    tokio::spawn(async move {
+                        let _ = command(&addr, &["REPLICAOF", &host, &port]).await;
+                    });
+                }
+                master.switch(promoted, now);
+                master.last_hello = None;
+            }
+        }
+    }
+
+    /// point replicas that follow another master, or came back as masters, to the current master
+    fn reconfigure_replicas(&mut self, name: &str, now: Instant) {
+        let master = self.masters.get_mut(name).unwrap();
+        if master.is_sdown(now) || master.failover.is_some() || now - master.config_changed < RECONF_GRACE {
+            return;
+        }
+        let target = master.instance.addr();
+        for replica in master.replicas.values_mut() {
+            let fresh = replica.last_info.is_some_and(|at| now.saturating_duration_since(at) < INFO_PERIOD);
+            let wrong = match replica.role {
+                Role::Master => true,
+                Role::Replica => replica.master_addr.as_ref() != Some(&target),
+                Role::Unknown => false,
+            };
+            if !fresh || !wrong || replica.last_reconf.is_some_and(|at| now - at < RECONF_GRACE) {
+                continue;
+            }
+            println!("+convert-to-slave slave {}:{} @ {}", replica.host, replica.port, name);
+            replica.last_reconf = Some(now);
+            let (addr, host, port) = (replica.addr(), target.0.clone(), target.1.to_string());
+            tokio::spawn(async move {
+                let _ = command(&addr, &["REPLICAOF", &host, &port]).await;
+            });
+        }
+    }
+
+    /// `SENTINEL is-master-down-by-addr`: whether the master is down here,
+    /// and the vote of this sentinel if `runid` asks for it
+    fn is_master_down(&mut self, ip: &str, port: u16, epoch: u64, runid: &str) -> Frame {
+        let now = Instant::now();
+        let Some(master) = self.master_by_addr(ip, port) else {
+            return Frame::Array(vec![Frame::Integer(0), Frame::Bulk(Bytes::from_static(b"*")), Frame::Integer(0)]);
+        };
+        let down = master.is_sdown(now) as i64;
+        let name = master.name.clone();
+        let (leader, leader_epoch) = if runid == "*" { (None, 0) } else { self.vote(&name, epoch, runid) };
+        Frame::Array(vec![
+            Frame::Integer(down),
+            Frame::Bulk(Bytes::from(leader.unwrap_or_else(|| "*".to_string()))),
+            Frame::Integer(leader_epoch as i64),
+        ])
+    }
+
+    fn info(&self) -> String {
+        let now = Instant::now();
+        let mut info = String::from("# Sentinel\r\n");
+        let _ = write!(info, "sentinel_masters:{}\r\n", self.masters.len());
+        let _ = write!(info, "sentinel_tilt:0\r\nsentinel_running_scripts:0\r\nsentinel_scripts_queue_length:0\r\n");
+        for (i, master) in self.masters.values().enumerate() {
+            let status = if master.odown_since.is_some() { "odown" } else if master.is_sdown(now) { "sdown" } else { "ok" };
+            let _ = write!(info, "master{}:name={},status={},address={}:{},slaves={},sentinels={}\r\n",
+                           i, master.name, status, master.instance.host, master.instance.port,
+                           master.replicas.len(), master.sentinels.len() + 1);
+        }
+        info
+    }
+}
+
+/// 0 to `max`, a different delay for every call
+fn jitter(max: Duration) -> Duration {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(unix_ms());
+    Duration::from_millis(hasher.finish() % max.as_millis().max(1) as u64)
+}
+
+/// what the sentinel needs from `INFO replication`
+#[derive(Debug, PartialEq)]
+struct InfoReply {
+    role: Role,
+    master_addr: Option<(String, u16)>,
+    master_link_up: bool,
+    offset: u64,
+    priority: u64,
+    replicas: Vec<(String, u16)>,
+}
+
+fn parse_info(info: &str) -> InfoReply {
+    let mut reply = InfoReply {
+        role: Role::Unknown,
+        master_addr: None,
+        master_link_up: false,
+        offset: 0,
+        priority: 100,
+        replicas: vec![],
+    };
+    let mut master_host = None;
+    let mut master_port = None;
+    for line in info.lines() {
+        let Some((field, value)) = line.split_once(':') else { continue };
+        match field {
+            "role" => reply.role = match value {
+                "master" => Role::Master,
+                "slave" => Role::Replica,
+                _ => Role::Unknown,
+            },
+            "master_host" => master_host = Some(value.to_string()),
+            "master_port" => master_port = value.parse().ok(),
+            "master_link_status" => reply.master_link_up = value == "up",
+            "slave_repl_offset" => reply.offset = value.parse().unwrap_or(0),
+            "master_repl_offset" if reply.role == Role::Master => reply.offset = value.parse().unwrap_or(0),
+            "slave_priority" | "replica_priority" => reply.priority = value.parse().unwrap_or(100),
+            // slave0:ip=127.0.0.1,port=6380,state=online,offset=0,lag=0
+            field if field.starts_with("slave") && field[5..].parse::<u32>().is_ok() => {
+                let mut ip = None;
+                let mut port = None;
+                for pair in value.split(',') {
+                    match pair.split_once('=') {
+                        Some(("ip", value)) => ip = Some(value.to_string()),
+                        Some(("port", value)) => port = value.parse().ok(),
+                        _ => {}
+                    }
+                }
+                if let (Some(ip), Some(port)) = (ip, port) {
+                    reply.replicas.push((ip, port));
+                }
+            }
+            _ => {}
+        }
+    }
+    reply.master_addr = master_host.zip(master_port);
+    reply
+}
+
+/// send one command on a new connection and wait for the reply
+async fn command(addr: &(String, u16), argv: &[&str]) -> RedisResult<Frame> {
+    let request = async {
+        let mut framed = Framed::new(TcpStream::connect((addr.0.as_str(), addr.1)).await?, RedisCodec);
+        request(&mut framed, argv).await
+    };
+    match time::timeout(REQUEST_TIMEOUT, request).await {
+        Ok(reply) => reply,
+        Err(_) => Err("timed out".into()),
+    }
+}
+
+async fn request(framed: &mut Framed<TcpStream, RedisCodec>, argv: &[&str]) -> RedisResult<Frame> {
+    let request = argv.iter().map(|arg| RedisFrame::Bulk(Bytes::from(arg.to_string()))).collect();
+    framed.send(RedisFrame::Array(request)).await?;
+    match framed.next().await {
+        Some(Ok(reply)) => match Frame::from(reply) {
+            Frame::Error(e) => Err(e.into()),
+            reply => Ok(reply),
+        },
+        Some(Err(e)) => Err(e.into()),
+        None => Err("connection closed".into()),
+    }
+}
+
+/// listen to the hello messages published on an instance, forever
+async fn subscribe(shared: SharedState, addr: (String, u16)) {
+    loop {
+        if let Ok(socket) = TcpStream::connect((addr.0.as_str(), addr.1)).await {
+            let mut framed = Framed::new(socket, RedisCodec);
+            if request(&mut framed, &["SUBSCRIBE", HELLO_CHANNEL]).await.is_ok() {
+                while let Some(Ok(frame)) = framed.next().await {
+                    if let Frame::Array(message) = Frame::from(frame) {
+                        if let [_, _, Frame::Bulk(payload)] = &message[..] {
+                            lock(&shared).on_hello(&String::from_utf8_lossy(payload));
+                        }
+                    }
+                }
+            }
+        }
+        time::sleep(PING_PERIOD).await;
+    }
+}
+
+async fn sentinel_task(shared: SharedState) {
+    let mut interval = time::interval(TICK_PERIOD);
+    loop {
+        interval.tick().await;
+        let mut state = lock(&shared);
+        let names: Vec<String> = state.masters.keys().cloned().collect();
+        for name in names {
+            state.tick(&shared, &name);
+        }
+    }
+}
+
+/// A sentinel process: serves the `SENTINEL` commands on its listener and supervises
+/// the configured masters
+pub struct Sentinel {
+    listener: TcpListener,
+    state: SharedState,
+}
+
+impl Sentinel {
+    pub fn new(listener: TcpListener, config: SentinelConfig) -> Self {
+        let port = listener.local_addr().map_or(0, |addr| addr.port());
+        let masters = config.masters.into_iter()
+            .map(|master| (master.name.clone(), Master::new(master)))
+            .collect();
+        let state = State { myid: random_id(), ip: config.announce_ip, port, current_epoch: 0, masters };
+        Self { listener, state: Arc::new(Mutex::new(state)) }
+    }
+
+    pub async fn run(self) -> RedisResult<()> {
+        let task = tokio::spawn(sentinel_task(self.state.clone()));
+        loop {
+            select! {
+                Ok((socket, _)) = self.listener.accept() => {
+                    tokio::spawn(serve(socket, self.state.clone()));
+                }
+                _ = signal::ctrl_c() => break,
+            }
+        }
+        task.abort();
+        Ok(())
+    }
+}
+
+async fn serve(socket: TcpStream, state: SharedState) {
+    let mut framed = Framed::new(socket, RedisCodec);
+    while let Some(Ok(frame)) = framed.next().await {
+        let reply = match Frame::from(frame) {
+            Frame::Array(frames) => execute(&state, &mut FrameIter::new(frames)).unwrap_or_else(Frame::from),
+            _ => Frame::Error("ERR invalid command".to_string()),
+        };
+        if framed.send(reply.into()).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn execute(state: &SharedState, iter: &mut FrameIter) -> Result<Frame, FrameError> {
+    let command = iter.next_string()?;
+    match command.to_uppercase().as_str() {
+        "PING" => Ok(Frame::Simple("PONG".to_string())),
+        "INFO" => Ok(Frame::Bulk(Bytes::from(lock(state).info()))),
+        "SENTINEL" => sentinel_command(&mut lock(state), iter),
+        _ => Ok(Frame::Error(format!("ERR unknown command '{}'", command))),
+    }
+}
+
+/// https://redis.io/docs/management/sentinel/#sentinel-commands
+/// Syntax: SENTINEL subcommand [argument ...]
+/// - MYID: the run ID of this sentinel
+/// - MASTERS / MASTER name: the state of the monitored masters
+/// - REPLICAS name / SLAVES name: the replicas of a master
+/// - SENTINELS name: the other sentinels watching a master
+/// - GET-MASTER-ADDR-BY-NAME name: the address of the current master
+/// - IS-MASTER-DOWN-BY-ADDR ip port current-epoch runid: asked by the other sentinels
+fn sentinel_command(state: &mut State, iter: &mut FrameIter) -> Result<Frame, FrameError> {
+    let now = Instant::now();
+    let subcommand = iter.next_string()?;
+    let reply = match subcommand.to_uppercase().as_str() {
+        "MYID" => Frame::Bulk(Bytes::from(state.myid.clone())),
+        "MASTERS" => Frame::Array(state.masters.values().map(|master| master_reply(master, now)).collect()),
+        "MASTER" => master_reply(find_master(state, &iter.next_string()?)?, now),
+        "REPLICAS" | "SLAVES" => {
+            let master = find_master(state, &iter.next_string()?)?;
+            Frame::Array(master.replicas.values().map(|replica| replica_reply(master, replica, now)).collect())
+        }
+        "SENTINELS" => {
+            let master = find_master(state, &iter.next_string()?)?;
+            Frame::Array(master.sentinels.values().map(|peer| peer_reply(peer, now)).collect())
+        }
+        "GET-MASTER-ADDR-BY-NAME" => match state.masters.get(&iter.next_string()?) {
+            Some(master) => Frame::Array(vec![
+                Frame::Bulk(Bytes::from(master.instance.host.clone())),
+                Frame::Bulk(Bytes::from(master.instance.port.to_string())),
+            ]),
+            None => Frame::Null,
+        },
+        "IS-MASTER-DOWN-BY-ADDR" => {
+            let ip = iter.next_string()?;
+            let port = iter.next_int()?;
+            let epoch = iter.next_int()?;
+            let runid = iter.next_string()?;
+            state.is_master_down(&ip, port as u16, epoch as u64, &runid)
+        }
+        _ => return Err(format!("ERR Unknown sentinel subcommand '{}'", subcommand).into()),
+    };
+    iter.finish()?;
+    Ok(reply)
+}
+
+fn find_master<'a>(state: &'a State, name: &str) -> Result<&'a Master, FrameError> {
+    state.masters.get(name).ok_or_else(|| "ERR No such master with that name".into())
+}
+
+/// a flat list of field names and values, the shape of the SENTINEL MASTER(S) replies
+fn fields(fields: Vec<(&str, String)>) -> Frame {
+    Frame::Array(fields.into_iter()
+        .flat_map(|(field, value)| [Frame::Bulk(Bytes::from(field.to_string())), Frame::Bulk(Bytes::from(value))])
+        .collect())
+}
+
+fn master_reply(master: &Master, now: Instant) -> Frame {
+    let failover_state = match master.failover.as_ref().map(|failover| failover.state) {
+        None => "none",
+        Some(FailoverState::WaitStart) => "wait_start",
+        Some(FailoverState::WaitPromotion) => "wait_promotion",
+    };
+    fields(vec![
+        ("name", master.name.clone()),
+        ("ip", master.instance.host.clone()),
+        ("port", master.instance.port.to_string()),
+        ("flags", master.flags(now)),
+        ("last-ok-ping-reply", (now - master.instance.last_ok).as_millis().to_string()),
+        ("num-slaves", master.replicas.len().to_string()),
+        ("num-other-sentinels", master.sentinels.len().to_string()),
+        ("quorum", master.quorum.to_string()),
+        ("down-after-milliseconds", master.down_after.as_millis().to_string()),
+        ("failover-timeout", master.failover_timeout.as_millis().to_string()),
+        ("config-epoch", master.config_epoch.to_string()),
+        ("failover-state", failover_state.to_string()),
+    ])
+}
+
+fn replica_reply(master: &Master, replica: &Instance, now: Instant) -> Frame {
+    let mut flags = String::from("slave");
+    if replica.is_sdown(master.down_after, now) {
+        flags.push_str(",s_down");
+    }
+    let (master_host, master_port) = replica.master_addr.clone().unwrap_or_else(|| ("?".to_string(), 0));
+    fields(vec![
+        ("name", format!("{}:{}", replica.host, replica.port)),
+        ("ip", replica.host.clone()),
+        ("port", replica.port.to_string()),
+        ("flags", flags),
+        ("last-ok-ping-reply", (now - replica.last_ok).as_millis().to_string()),
+        ("master-link-status", if replica.master_link_up { "ok" } else { "err" }.to_string()),
+        ("master-host", master_host),
+        ("master-port", master_port.to_string()),
+        ("slave-priority", replica.priority.to_string()),
+        ("slave-repl-offset", replica.offset.to_string()),
+    ])
+}
+
+fn peer_reply(peer: &Peer, now: Instant) -> Frame {
+    fields(vec![
+        ("name", peer.runid.clone()),
+        ("ip", peer.ip.clone()),
+        ("port", peer.port.to_string()),
+        ("runid", peer.runid.clone()),
+        ("flags", "sentinel".to_string()),
+        ("last-hello-message", (now - peer.last_hello).as_millis().to_string()),
+        ("voted-leader", peer.leader.clone().unwrap_or_else(|| "?".to_string())),
+        ("voted-leader-epoch", peer.leader_epoch.to_string()),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tokio::net::TcpListener;
+    use tokio::sync::broadcast;
+    use tokio::task::JoinHandle;
+
+    use crate::db::{SharedDb, test_config, test_db};
+    use crate::frame::Frame;
+    use crate::replication::replicaof;
+    use crate::sentinel::{command, parse_info, MasterConfig, Role, Sentinel, SentinelConfig};
+    use crate::server::process;
+
+    #[test]
+    fn parse_info_test() {
+        let info = parse_info("# Replication\r\nrole:master\r\nconnected_slaves:1\r\n\
+            slave0:ip=127.0.0.1,port=6380,state=online,offset=10,lag=0\r\nmaster_repl_offset:42\r\n");
+        assert_eq!(info.role, Role::Master);
+        assert_eq!(info.offset, 42);
+        assert_eq!(info.replicas, vec![("127.0.0.1".to_string(), 6380)]);
+
+        let info = parse_info("role:slave\r\nmaster_host:127.0.0.1\r\nmaster_port:6379\r\n\
+            master_link_status:up\r\nslave_repl_offset:7\r\nslave_priority:0\r\nmaster_repl_offset:7\r\n");
+        assert_eq!(info.role, Role::Replica);
+        assert_eq!(info.master_addr, Some(("127.0.0.1".to_string(), 6379)));
+        assert!(info.master_link_up);
+        assert_eq!((info.offset, info.priority), (7, 0));
+    }
+
+    /// serve `db` until the returned task is aborted and the sender fired
+    async fn serve(db: SharedDb) -> (u16, broadcast::Sender<()>, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        db.lock().replication.listening_port = port;
+        let (shutdown, _) = broadcast::channel(1);
+        let sender = shutdown.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                tokio::spawn(process(socket, db.clone(), shutdown.subscribe()));
+            }
+        });
+        (port, sender, task)
+    }
+
+    async fn master_addr(port: u16) -> Option<u16> {
+        let reply = command(&("127.0.0.1".to_string(), port), &["SENTINEL", "get-master-addr-by-name", "mymaster"]).await;
+        match reply.ok()? {
+            Frame::Array(addr) => match &addr[..] {
+                [_, Frame::Bulk(port)] => String::from_utf8_lossy(port).parse().ok(),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    async fn known(port: u16, what: &str) -> usize {
+        match command(&("127.0.0.1".to_string(), port), &["SENTINEL", what, "mymaster"]).await {
+            Ok(Frame::Array(items)) => items.len(),
+            _ => 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn failover_test() {
+        let master = test_db(test_config());
+        let replica = test_db(test_config());
+        let (master_port, master_shutdown, master_task) = serve(master).await;
+        let (replica_port, _replica_shutdown, _) = serve(replica.clone()).await;
+        replicaof(&replica, Some(("127.0.0.1".to_string(), master_port)));
+        // otherwise the first INFO of the master misses the replica, and the next one is 10s later
+        while !replica.lock().replication.info().contains("master_link_status:up") {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        let mut sentinels = vec![];
+        for _ in 0..3 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            sentinels.push(listener.local_addr().unwrap().port());
+            let mut master = MasterConfig::new("mymaster", "127.0.0.1", master_port, 2);
+            master.down_after = Duration::from_millis(500);
+            master.failover_timeout = Duration::from_secs(3);
+            let config = SentinelConfig { masters: vec![master], ..SentinelConfig::default() };
+            tokio::spawn(Sentinel::new(listener, config).run());
+        }
+
+        // the sentinels find the replica in the INFO of the master and each other in the hello messages
+        for _ in 0..100 {
+            let mut ready = true;
+            for &port in &sentinels {
+                ready &= known(port, "replicas").await == 1 && known(port, "sentinels").await == 2;
+            }
+            if ready {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(master_addr(sentinels[0]).await, Some(master_port));
+
+        // the master dies: its listener is closed and its connections dropped
+        master_task.abort();
+        let _ = master_shutdown.send(());
+
+        // a split vote is retried after twice the failover timeout
+        for _ in 0..300 {
+            let mut switched = true;
+            for &port in &sentinels {
+                switched &= master_addr(port).await == Some(replica_port);
+            }
+            if switched {
+                assert!(!replica.lock().replication.is_replica());
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("the sentinels did not fail the master over");
+    }
+}
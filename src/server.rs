@@ -1,52 +1,110 @@
+use futures::future::select_all;
 use futures::{SinkExt, StreamExt};
+use tokio::io::AsyncWriteExt;
 use tokio::{select, signal};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::broadcast;
 use tokio::sync::broadcast::Receiver;
 use tokio_util::codec::Framed;
 
-use crate::cmd::Cmd;
-use crate::codec::{LineCodec, RedisCodec, RedisFrame};
-use crate::connection::Connection;
+use crate::codec::{RedisCodec, RedisFrame};
+use crate::config::Config;
 use crate::db::{Db, SharedDb};
+use crate::frame::Frame;
+use crate::metrics;
+use crate::pub_sub::Push;
+use crate::replication;
 use crate::RedisResult;
+use crate::session::Session;
 
 #[derive(Debug)]
 pub struct Server {
-    listener: TcpListener,
+    /// one per `bind` address, the first one is the address of this node
+    listeners: Vec<TcpListener>,
+
+    config: Config,
+
+    db: SharedDb,
 
     notify_shutdown: broadcast::Sender<()>,
 }
 
 impl Server {
     pub fn new(listener: TcpListener) -> Self {
+        Self::with_config(listener, Config::default())
+    }
+
+    pub fn with_config(listener: TcpListener, config: Config) -> Self {
+        Self::with_listeners(vec![listener], config)
+    }
+
+    /// accept clients on every listener, like a server bound to several addresses.
+    /// The port of the first one is announced to the master and the cluster
+    pub fn with_listeners(listeners: Vec<TcpListener>, config: Config) -> Self {
+        assert!(!listeners.is_empty(), "a server needs at least one listener");
 
         // send shutdown to all active connections
         // broadcast channel for this purpose
         // a receiver is needed, the subscribe() method on ther sender is needed
         let (notify_shutdown, _) = broadcast::channel(1);
 
+        //启动数据库，并且传入一个命令接受功能，随时准备接收关闭信号的命令
+        let db = Db::new(notify_shutdown.subscribe(), config.clone());
+        // 副本通过REPLCONF告诉master自己的端口
+        if let Ok(addr) = listeners[0].local_addr() {
+            let mut store = db.lock();
+            store.replication.listening_port = addr.port();
+            // 集群里其他节点和客户端通过这个地址找到本节点
+            if let Some(cluster) = store.cluster.as_mut() {
+                let ip = if addr.ip().is_unspecified() { "127.0.0.1".to_string() } else { addr.ip().to_string() };
+                cluster.set_address(ip, addr.port());
+            }
+        }
+
         Self {
-            listener,
+            listeners,
+            config,
+            db,
             notify_shutdown,
         }
     }
 
+    /// load what a previous run persisted, before `run`: the append only file when
+    /// `appendonly` is on, the RDB snapshot at `dir`/`dbfilename` otherwise.
+    /// Missing files are not an error. Returns how many keys were loaded
+    pub fn load(&self) -> RedisResult<usize> {
+        Ok(self.db.load()?)
+    }
+
     //这里只能执行一次
     pub async fn run(self) -> RedisResult<()> {
-
-
-        //启动数据库，并且传入一个命令接受功能，随时准备接收关闭信号的命令
-        let shared_db = Db::new(self.notify_shutdown.subscribe());
+        let shared_db = self.db.clone();
+        if let Some(master) = self.config.replicaof.clone() {
+            replication::replicaof(&shared_db, Some(master));
+        }
+        // Prometheus 指标用单独的端口，和客户端监听同一个地址
+        if self.config.metrics_port != 0 {
+            let ip = self.listeners[0].local_addr()?.ip();
+            let listener = TcpListener::bind((ip, self.config.metrics_port)).await?;
+            tokio::spawn(metrics::serve(listener, shared_db.clone(), self.notify_shutdown.subscribe()));
+        }
         loop {
+            let incoming = select_all(self.listeners.iter().map(|listener| Box::pin(listener.accept())));
             select! {
 
-                Ok((socket,_)) = self.listener.accept() =>{
-                    let sharedDb = shared_db.clone();
+                (Ok((mut socket,_)), _, _) = incoming =>{
+                    let shared_db = shared_db.clone();
+                    if !accept(&shared_db) {
+                        tokio::spawn(async move {
+                            let _ = socket.write_all(b"-ERR max number of clients reached\r\n").await;
+                        });
+                        continue;
+                    }
 
                     let notify_shutdown = self.notify_shutdown.subscribe();
                     tokio::spawn(async move {
-                        let _ = process(socket, sharedDb, notify_shutdown).await;
+                        let _ = process(socket, shared_db.clone(), notify_shutdown).await;
+                        shared_db.lock().stats.connected_clients -= 1;
                     });
                 }
                 _= signal::ctrl_c()=>{
@@ -58,48 +116,85 @@ impl Server {
             }
         }
         drop(self.notify_shutdown);
+
+        shared_db.sync_aof()?;
+        // 和Redis一样，配置了save规则的话，退出前保存一次。规则可能被 CONFIG SET 改过
+        let mut store = shared_db.lock();
+        if !store.config.save.is_empty() {
+            store.save()?;
+        }
         Ok(())
     }
 }
 
-async fn process(socket: TcpStream, mut db: SharedDb, mut notify_shutdown: Receiver<()>) -> RedisResult<()> {
+/// count a new connection, false if `maxclients` are already connected and it has to be refused
+fn accept(db: &SharedDb) -> bool {
+    let mut store = db.lock();
+    if store.stats.connected_clients >= store.config.maxclients as u64 {
+        store.stats.rejected_connections += 1;
+        return false;
+    }
+    store.stats.connected_clients += 1;
+    store.stats.total_connections_received += 1;
+    true
+}
+
+pub(crate) async fn process(socket: TcpStream, db: SharedDb, mut notify_shutdown: Receiver<()>) -> RedisResult<()> {
 
+    let addr = socket.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
     // 将stream信息转换成编码
     let mut framed = Framed::new(socket, RedisCodec);
 
-    loop {
-        if let Some(Ok(RedisFrame::Array(arr))) = framed.next().await {
-
-            // 生成command
-
-
+    // 每个连接都有自己的状态，比如事务和WATCH的key
+    let mut session = Session::new(db.clone());
+    session.set_addr(addr);
 
+    loop {
+        let frame = select! {
+            frame = framed.next() => frame,
+            // 订阅的频道有新消息
+            Some(push) = session.next_push() => {
+                match push {
+                    Push::Message(message, size) => {
+                        framed.send(message.into()).await?;
+                        session.written(size);
+                        continue;
+                    }
+                    // 消费太慢，输出缓冲区超过了限制
+                    Push::Disconnect => break,
+                }
+            }
+            _ = notify_shutdown.recv() => {
+                println!("任务结束，连接即将关闭");
+                break;
+            }
+        };
+
+        let responses = match frame {
+            Some(Ok(frame @ RedisFrame::Array(_))) => session.handle(frame.into()).await,
+            Some(Ok(_)) => vec![Frame::Error("ERR invalid command".to_string())],
+            Some(Err(e)) => {
+                framed.send(RedisFrame::Error(format!("ERR Protocol error: {}", e))).await?;
+                break;
+            }
+            // 客户端关闭了连接
+            None => break,
+        };
 
-        } else {
-            let errorFrame = RedisFrame::Error(" invalid command".to_string());
+        for response in responses {
+            framed.send(response.into()).await?;
+        }
+        if session.is_quitting() {
+            break;
+        }
 
-            framed.send(errorFrame).await.unwrap();
+        // PSYNC之后这个连接就变成了复制链路
+        if let Some((psync, port)) = session.take_psync() {
+            drop(session);
+            return replication::serve_replica(framed, db, psync.replid, psync.offset, port).await;
         }
     }
 
-
-    // //读取二进制信息，将内容转换成Frame信息
-    // let mut connection = Connection::new(socket);
-    //
-    // loop {
-    //     select! {
-    //         result=handle(&mut connection,&mut db) => {
-    //
-    //             println!("result");
-    //         }
-    //         _=notify_shutdown.recv()=>{
-    //             println!("任务结束，连接即将关闭");
-    //             break;
-    //         }
-    //     }
-    // }
-
-
     Ok(())
 }
 
@@ -117,4 +212,26 @@ async fn process(socket: TcpStream, mut db: SharedDb, mut notify_shutdown: Recei
 //     Ok(())
 // }
 
-
+#[cfg(test)]
+mod test {
+    use tokio::net::TcpListener;
+
+    use crate::client::Client;
+    use crate::config::Config;
+    use crate::server::Server;
+
+    #[tokio::test]
+    async fn listeners_test() {
+        let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ports = [first.local_addr().unwrap().port(), second.local_addr().unwrap().port()];
+        let config = Config { save: vec![], dir: std::env::temp_dir(), ..Config::default() };
+        tokio::spawn(Server::with_listeners(vec![first, second], config).run());
+
+        // both addresses serve the same keyspace
+        let mut a = Client::connect(("127.0.0.1", ports[0])).await.unwrap();
+        let mut b = Client::connect(("127.0.0.1", ports[1])).await.unwrap();
+        a.set("key", "value").await.unwrap();
+        assert_eq!(b.get("key").await.unwrap().as_deref(), Some(&b"value"[..]));
+    }
+}
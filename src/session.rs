@@ -0,0 +1,695 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::time::{self, Instant};
+
+use crate::cluster;
+use crate::cmd::{ClientCommand, Cmd, Psync, Wait};
+use crate::db::SharedDb;
+use crate::frame::{encode_command, Frame};
+use crate::pub_sub::{Push, Subscriber};
+use crate::replication;
+use crate::slot::key_hash_slot;
+use crate::slowlog::ClientInfo;
+
+/// the only commands a connection may send once it subscribed to something
+const SUBSCRIBER_COMMANDS: [&str; 9] = [
+    "subscribe", "psubscribe", "ssubscribe", "unsubscribe", "punsubscribe", "sunsubscribe", "ping", "quit", "reset",
+];
+
+/// how often WAIT checks the acknowledgements of the replicas
+const WAIT_POLL_PERIOD: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Subscription {
+    Channel,
+    Pattern,
+    Shard,
+}
+
+impl Subscription {
+    fn subscribe_reply(self) -> &'static str {
+        match self {
+            Subscription::Channel => "subscribe",
+            Subscription::Pattern => "psubscribe",
+            Subscription::Shard => "ssubscribe",
+        }
+    }
+
+    fn unsubscribe_reply(self) -> &'static str {
+        match self {
+            Subscription::Channel => "unsubscribe",
+            Subscription::Pattern => "punsubscribe",
+            Subscription::Shard => "sunsubscribe",
+        }
+    }
+}
+
+/// Per-connection state: the queued `MULTI` transaction, the `WATCH`ed keys,
+/// the pub/sub subscriptions and what a replica announced before its PSYNC
+pub(crate) struct Session {
+    id: u64,
+    db: SharedDb,
+    /// commands queued since MULTI with the requests they came from, `None` outside of a transaction
+    queued: Option<Vec<(Cmd, Vec<Bytes>)>>,
+    /// a command failed to queue, EXEC has to discard the transaction
+    queue_failed: bool,
+    /// watched keys and whether each of them was alive when watched
+    watched: Vec<(String, bool)>,
+    subscriber: Subscriber,
+    messages: UnboundedReceiver<Push>,
+    channels: HashSet<Bytes>,
+    patterns: HashSet<Bytes>,
+    shard_channels: HashSet<Bytes>,
+    /// `REPLCONF listening-port` of a replica
+    listening_port: u16,
+    /// the PSYNC that turns this connection into a replication link
+    psync: Option<Psync>,
+    /// ASKING was sent, the next command may use a slot this node is importing
+    asking: bool,
+    /// the address and CLIENT SETNAME name of the connection
+    client: ClientInfo,
+    /// QUIT was sent, the connection closes after its reply
+    quit: bool,
+}
+
+impl Session {
+    pub(crate) fn new(db: SharedDb) -> Self {
+        let id = db.next_client_id();
+        let limit = db.lock().config.client_output_buffer_limit_pubsub;
+        let (subscriber, messages) = Subscriber::new(id, limit);
+
+        Self {
+            id,
+            db,
+            queued: None,
+            queue_failed: false,
+            watched: vec![],
+            subscriber,
+            messages,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            shard_channels: HashSet::new(),
+            listening_port: 0,
+            psync: None,
+            asking: false,
+            client: ClientInfo::default(),
+            quit: false,
+        }
+    }
+
+    /// the `ip:port` of the peer, for SLOWLOG
+    pub(crate) fn set_addr(&mut self, addr: String) {
+        self.client.addr = addr;
+    }
+
+    /// handle one request. Most commands have exactly one reply,
+    /// SUBSCRIBE and friends reply once per channel
+    pub(crate) async fn handle(&mut self, frame: Frame) -> Vec<Frame> {
+        let name = command_name(&frame);
+        if self.is_subscriber() && !SUBSCRIBER_COMMANDS.contains(&name.as_str()) {
+            let error = format!(
+                "ERR Can't execute '{}': only (P|S)SUBSCRIBE / (P|S)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                name);
+            self.db.lock().stats.rejected(&name, &error);
+            return vec![Frame::Error(error)];
+        }
+
+        let argv = argv(&frame);
+        // ASKING only holds for the command right after it
+        let asking = std::mem::take(&mut self.asking) || name == "restore-asking";
+        let cmd = match Cmd::try_from(frame) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                // a syntax error while queueing aborts the whole transaction at EXEC
+                if self.queued.is_some() {
+                    self.queue_failed = true;
+                }
+                let reply: Frame = e.into();
+                if let Frame::Error(error) = &reply {
+                    self.db.lock().stats.error(error);
+                }
+                return vec![reply];
+            }
+        };
+
+        // 存储层的命令在 Cmd::call 里计时，MIGRATE 在 Migrate::call 里，其他连接层的在这里
+        if !cmd.is_connection_level() || matches!(cmd, Cmd::Migrate(_)) {
+            return self.dispatch(cmd, argv, asking).await;
+        }
+        let start = Instant::now();
+        let replies = self.dispatch(cmd, argv, asking).await;
+        let error = replies.iter().find_map(|reply| match reply {
+            Frame::Error(error) => Some(error.as_str()),
+            _ => None,
+        });
+        self.db.lock().stats.command(&name, start.elapsed(), error);
+        replies
+    }
+
+    async fn dispatch(&mut self, cmd: Cmd, argv: Vec<Bytes>, asking: bool) -> Vec<Frame> {
+        let reply = match cmd {
+            Cmd::Multi => self.multi(),
+            Cmd::Exec => self.exec(asking),
+            Cmd::Discard => self.discard(),
+            Cmd::Watch(watch) => self.watch(watch.keys, asking),
+            Cmd::Unwatch => {
+                self.unwatch();
+                Frame::ok()
+            }
+            Cmd::Quit => {
+                self.quit = true;
+                Frame::ok()
+            }
+            Cmd::Reset => self.reset(),
+            Cmd::Subscribe(_) | Cmd::PSubscribe(_) | Cmd::Unsubscribe(_) | Cmd::PUnsubscribe(_)
+            | Cmd::SSubscribe(_) | Cmd::SUnsubscribe(_)
+            | Cmd::ReplicaOf(_) | Cmd::ReplConf(_) | Cmd::Psync(_) | Cmd::Wait(_)
+            | Cmd::Migrate(_) | Cmd::Client(_) if self.queued.is_some() => {
+                self.queue_failed = true;
+                Frame::Error("ERR Command not allowed inside a transaction".to_string())
+            }
+            Cmd::Subscribe(subscribe) => return self.subscribe(subscribe.channels, Subscription::Channel),
+            Cmd::PSubscribe(subscribe) => return self.subscribe(subscribe.channels, Subscription::Pattern),
+            Cmd::SSubscribe(subscribe) => return self.subscribe(subscribe.channels, Subscription::Shard),
+            Cmd::Unsubscribe(unsubscribe) => return self.unsubscribe(unsubscribe.channels, Subscription::Channel),
+            Cmd::PUnsubscribe(unsubscribe) => return self.unsubscribe(unsubscribe.channels, Subscription::Pattern),
+            Cmd::SUnsubscribe(unsubscribe) => return self.unsubscribe(unsubscribe.channels, Subscription::Shard),
+            Cmd::ReplicaOf(replica_of) => replication::replicaof(&self.db, replica_of.master),
+            Cmd::ReplConf(conf) => match self.replconf(conf.options) {
+                Some(reply) => reply,
+                None => return vec![],
+            },
+            // the reply is the start of the replication stream, sent by the caller
+            Cmd::Psync(psync) => {
+                self.psync = Some(psync);
+                return vec![];
+            }
+            Cmd::Wait(wait) => self.wait(wait).await,
+            Cmd::Migrate(migrate) => migrate.execute(&self.db, argv, asking, self.client.clone()).await,
+            Cmd::Asking if self.db.lock().cluster.is_none() =>
+                Frame::Error("ERR This instance has cluster support disabled".to_string()),
+            Cmd::Asking => {
+                self.asking = true;
+                Frame::ok()
+            }
+            Cmd::Client(ClientCommand::SetName(name)) => {
+                self.client.name = name;
+                Frame::ok()
+            }
+            Cmd::Client(ClientCommand::GetName) if self.client.name.is_empty() => Frame::Null,
+            Cmd::Client(ClientCommand::GetName) => Frame::Bulk(Bytes::from(self.client.name.clone())),
+            // in subscriber mode PING answers with a message shaped reply
+            Cmd::Ping(_) if self.is_subscriber() => Frame::Array(vec![
+                Frame::Bulk(Bytes::from_static(b"pong")),
+                Frame::Bulk(Bytes::new()),
+            ]),
+            Cmd::UnKnown(unknown) if self.queued.is_some() => {
+                self.queue_failed = true;
+                unknown.execute().unwrap_or_else(error_frame)
+            }
+            cmd if self.queued.is_some() => match self.redirect(&cmd, asking) {
+                // a command of another node aborts the transaction at EXEC
+                Some(redirect) => {
+                    self.queue_failed = true;
+                    redirect
+                }
+                None => {
+                    self.queued.as_mut().unwrap().push((cmd, argv));
+                    Frame::Simple("QUEUED".to_string())
+                }
+            },
+            cmd => cmd.execute(&self.db, argv, asking, &self.client).await.unwrap_or_else(error_frame),
+        };
+        vec![reply]
+    }
+
+    /// QUIT was sent: nothing else is read once its reply is written
+    pub(crate) fn is_quitting(&self) -> bool {
+        self.quit
+    }
+
+    /// the PSYNC this connection sent, with the port the replica announced.
+    /// From then on it is a replication link instead of a client
+    pub(crate) fn take_psync(&mut self) -> Option<(Psync, u16)> {
+        self.psync.take().map(|psync| (psync, self.listening_port))
+    }
+
+    /// wait for the next pub/sub push addressed to this connection
+    pub(crate) async fn next_push(&mut self) -> Option<Push> {
+        self.messages.recv().await
+    }
+
+    /// a pushed message of `size` bytes was written to the socket
+    pub(crate) fn written(&self, size: usize) {
+        self.subscriber.written(size);
+    }
+
+    fn is_subscriber(&self) -> bool {
+        !self.channels.is_empty() || !self.patterns.is_empty() || !self.shard_channels.is_empty()
+    }
+
+    /// the count sent back with every (un)subscription. Shard channels are counted apart
+    fn subscription_count(&self, kind: Subscription) -> i64 {
+        match kind {
+            Subscription::Channel | Subscription::Pattern => (self.channels.len() + self.patterns.len()) as i64,
+            Subscription::Shard => self.shard_channels.len() as i64,
+        }
+    }
+
+    fn subscriptions(&mut self, kind: Subscription) -> &mut HashSet<Bytes> {
+        match kind {
+            Subscription::Channel => &mut self.channels,
+            Subscription::Pattern => &mut self.patterns,
+            Subscription::Shard => &mut self.shard_channels,
+        }
+    }
+
+    fn subscribe(&mut self, channels: Vec<Bytes>, kind: Subscription) -> Vec<Frame> {
+        // every shard channel of one SSUBSCRIBE has to live in the same slot
+        if kind == Subscription::Shard {
+            if let Some(first) = channels.first() {
+                let slot = key_hash_slot(first);
+                if channels.iter().any(|channel| key_hash_slot(channel) != slot) {
+                    return vec![Frame::Error("CROSSSLOT Keys in request don't hash to the same slot".to_string())];
+                }
+            }
+        }
+
+        let db = self.db.clone();
+        let hub = db.pub_sub();
+
+        channels.into_iter().map(|channel| {
+            if self.subscriptions(kind).insert(channel.clone()) {
+                match kind {
+                    Subscription::Channel => hub.subscribe(channel.clone(), &self.subscriber),
+                    Subscription::Pattern => hub.psubscribe(channel.clone(), &self.subscriber),
+                    Subscription::Shard => hub.ssubscribe(channel.clone(), &self.subscriber),
+                }
+            }
+            subscription_reply(kind.subscribe_reply(), Some(channel), self.subscription_count(kind))
+        }).collect()
+    }
+
+    fn unsubscribe(&mut self, channels: Vec<Bytes>, kind: Subscription) -> Vec<Frame> {
+        let channels = if channels.is_empty() {
+            self.subscriptions(kind).iter().cloned().collect()
+        } else {
+            channels
+        };
+        if channels.is_empty() {
+            return vec![subscription_reply(kind.unsubscribe_reply(), None, self.subscription_count(kind))];
+        }
+
+        let db = self.db.clone();
+        let hub = db.pub_sub();
+
+        channels.into_iter().map(|channel| {
+            if self.subscriptions(kind).remove(&channel) {
+                match kind {
+                    Subscription::Channel => hub.unsubscribe(&channel, self.id),
+                    Subscription::Pattern => hub.punsubscribe(&channel, self.id),
+                    Subscription::Shard => hub.sunsubscribe(&channel, self.id),
+                }
+            }
+            subscription_reply(kind.unsubscribe_reply(), Some(channel), self.subscription_count(kind))
+        }).collect()
+    }
+
+    /// REPLCONF, sent by replicas before PSYNC. `ACK` gets no reply
+    fn replconf(&mut self, options: Vec<(String, String)>) -> Option<Frame> {
+        for (option, value) in options {
+            match option.as_str() {
+                "listening-port" => match value.parse() {
+                    Ok(port) => self.listening_port = port,
+                    Err(_) => return Some(Frame::Error("ERR value is not an integer or out of range".to_string())),
+                },
+                "ack" => return None,
+                "capa" | "getack" | "ip-address" => {}
+                _ => return Some(Frame::Error(format!("ERR Unrecognized REPLCONF option: {}", option))),
+            }
+        }
+        Some(Frame::ok())
+    }
+
+    /// WAIT: ask the replicas for an acknowledgement of everything written so far
+    /// and poll until enough of them answered
+    async fn wait(&mut self, wait: Wait) -> Frame {
+        let offset = {
+            let mut store = self.db.lock();
+            if store.replication.is_replica() {
+                return Frame::Error("ERR WAIT cannot be used with replica instances.".to_string());
+            }
+            let offset = store.replication.offset();
+            if store.replication.has_replicas() && store.replication.acked(offset) < wait.replicas {
+                let getack = [b"REPLCONF".as_slice(), b"GETACK", b"*"].map(Bytes::from_static);
+                store.replication.feed(encode_command(&getack));
+            }
+            offset
+        };
+
+        let deadline = (wait.timeout > 0).then(|| Instant::now() + Duration::from_millis(wait.timeout));
+        loop {
+            let acked = self.db.lock().replication.acked(offset);
+            if acked >= wait.replicas || deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Frame::Integer(acked as i64);
+            }
+            time::sleep(WAIT_POLL_PERIOD).await;
+        }
+    }
+
+    fn multi(&mut self) -> Frame {
+        if self.queued.is_some() {
+            return Frame::Error("ERR MULTI calls can not be nested".to_string());
+        }
+        self.queued = Some(vec![]);
+        self.queue_failed = false;
+        Frame::ok()
+    }
+
+    /// in cluster mode, where the keys of a request have to be sent instead of this node
+    fn redirect(&self, cmd: &Cmd, asking: bool) -> Option<Frame> {
+        cluster::redirect(&self.db.lock(), &cmd.keys(), asking)
+    }
+
+    fn exec(&mut self, asking: bool) -> Frame {
+        let queued = match self.queued.take() {
+            Some(queued) => queued,
+            None => return Frame::Error("ERR EXEC without MULTI".to_string()),
+        };
+
+        if self.queue_failed {
+            self.unwatch();
+            return Frame::Error("EXECABORT Transaction discarded because of previous errors.".to_string());
+        }
+
+        // everything below runs under one lock, no other client can interleave
+        let mut store = self.db.lock();
+        let dirty = store.is_dirty(self.id, &self.watched);
+        store.unwatch(self.id, &self.watched);
+        self.watched.clear();
+
+        if dirty {
+            return Frame::NullArray;
+        }
+
+        // every key of the transaction has to be served here, and in one slot
+        let keys: Vec<Bytes> = queued.iter().flat_map(|(cmd, _)| cmd.keys()).collect();
+        if let Some(redirect) = cluster::redirect(&store, &keys, asking) {
+            return redirect;
+        }
+
+        store.begin_transaction();
+        let replies = queued.into_iter()
+            .map(|(cmd, argv)| cmd.call_from(&mut store, argv, &self.client).unwrap_or_else(error_frame))
+            .collect();
+        store.end_transaction();
+        Frame::Array(replies)
+    }
+
+    fn discard(&mut self) -> Frame {
+        if self.queued.take().is_none() {
+            return Frame::Error("ERR DISCARD without MULTI".to_string());
+        }
+        self.unwatch();
+        Frame::ok()
+    }
+
+    fn watch(&mut self, keys: Vec<String>, asking: bool) -> Frame {
+        if self.queued.is_some() {
+            return Frame::Error("ERR WATCH inside MULTI is not allowed".to_string());
+        }
+
+        let mut store = self.db.lock();
+        let slot_keys: Vec<Bytes> = keys.iter().map(|key| Bytes::from(key.clone())).collect();
+        if let Some(redirect) = cluster::redirect(&store, &slot_keys, asking) {
+            return redirect;
+        }
+        for key in keys {
+            if !self.watched.iter().any(|(watched, _)| *watched == key) {
+                let alive = store.watch(self.id, &key);
+                self.watched.push((key, alive));
+            }
+        }
+        Frame::ok()
+    }
+
+    fn unwatch(&mut self) {
+        if !self.watched.is_empty() {
+            self.db.lock().unwatch(self.id, &self.watched);
+            self.watched.clear();
+        }
+    }
+
+    /// RESET: drop the transaction, the watched keys, every subscription, ASKING and the name
+    /// of the connection, without the unsubscribe replies
+    fn reset(&mut self) -> Frame {
+        self.queued = None;
+        self.queue_failed = false;
+        self.unwatch();
+        if self.is_subscriber() {
+            self.db.pub_sub().remove_client(self.id);
+            self.channels.clear();
+            self.patterns.clear();
+            self.shard_channels.clear();
+            // messages published before the reset are not delivered anymore
+            while let Ok(push) = self.messages.try_recv() {
+                if let Push::Message(_, size) = push {
+                    self.subscriber.written(size);
+                }
+            }
+        }
+        self.asking = false;
+        self.client.name.clear();
+        Frame::Simple("RESET".to_string())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.unwatch();
+        if self.is_subscriber() {
+            self.db.pub_sub().remove_client(self.id);
+        }
+    }
+}
+
+fn error_frame(e: Box<dyn std::error::Error + Send + Sync>) -> Frame {
+    Frame::Error(format!("ERR {}", e))
+}
+
+/// the lowercased name of the command in a request frame
+fn command_name(frame: &Frame) -> String {
+    match frame {
+        Frame::Array(array) => match array.first() {
+            Some(Frame::Bulk(name)) => String::from_utf8_lossy(name).to_lowercase(),
+            Some(Frame::Simple(name)) => name.to_lowercase(),
+            _ => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
+/// the arguments of a request, as they are logged to the AOF
+fn argv(frame: &Frame) -> Vec<Bytes> {
+    match frame {
+        Frame::Array(array) => array.iter().map(|arg| match arg {
+            Frame::Bulk(data) => data.clone(),
+            Frame::Simple(data) => Bytes::from(data.clone()),
+            Frame::Integer(value) => Bytes::from(value.to_string()),
+            _ => Bytes::new(),
+        }).collect(),
+        _ => vec![],
+    }
+}
+
+/// `[kind, channel, count]`, the confirmation of a (un)subscription
+fn subscription_reply(kind: &'static str, channel: Option<Bytes>, count: i64) -> Frame {
+    Frame::Array(vec![
+        Frame::Bulk(Bytes::from_static(kind.as_bytes())),
+        channel.map(Frame::Bulk).unwrap_or_else(Frame::nil),
+        Frame::Integer(count),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use bytes::Bytes;
+
+    use crate::db::{SharedDb, test_config, test_db};
+    use crate::frame::Frame;
+    use crate::pub_sub::Push;
+    use crate::session::Session;
+
+    fn init_db() -> SharedDb {
+        test_db(test_config())
+    }
+
+    fn command(args: &str) -> Frame {
+        Frame::Array(args.split(' ').map(|arg| Frame::Bulk(Bytes::from(arg.to_string()))).collect())
+    }
+
+    async fn handle(session: &mut Session, frame: Frame) -> Frame {
+        let mut replies = session.handle(frame).await;
+        assert_eq!(replies.len(), 1);
+        replies.remove(0)
+    }
+
+    #[tokio::test]
+    async fn multi_exec_test() {
+        let db = init_db();
+        let mut session = Session::new(db.clone());
+
+        assert!(matches!(handle(&mut session, command("MULTI")).await, Frame::Simple(s) if s == "OK"));
+        assert!(matches!(handle(&mut session, command("SET foo 1")).await, Frame::Simple(s) if s == "QUEUED"));
+        assert!(matches!(handle(&mut session, command("INCR foo")).await, Frame::Simple(s) if s == "QUEUED"));
+        assert_eq!(db.lock().get_bytes("foo"), None);
+
+        match handle(&mut session, command("EXEC")).await {
+            Frame::Array(results) => assert!(matches!(results[1], Frame::Integer(2))),
+            other => panic!("unexpected reply {:?}", other),
+        }
+        assert!(matches!(handle(&mut session, command("EXEC")).await, Frame::Error(_)));
+    }
+
+    #[tokio::test]
+    async fn client_name_test() {
+        let db = init_db();
+        let mut session = Session::new(db.clone());
+        session.set_addr("127.0.0.1:50000".to_string());
+
+        assert!(matches!(handle(&mut session, command("CLIENT GETNAME")).await, Frame::Null));
+        assert!(matches!(handle(&mut session, command("CLIENT SETNAME worker")).await, Frame::Simple(s) if s == "OK"));
+        assert!(matches!(handle(&mut session, command("CLIENT GETNAME")).await, Frame::Bulk(name) if name == "worker"));
+        assert!(matches!(handle(&mut session, Frame::Array(vec![
+            Frame::Bulk(Bytes::from("CLIENT")), Frame::Bulk(Bytes::from("SETNAME")), Frame::Bulk(Bytes::from("a b")),
+        ])).await, Frame::Error(e) if e.contains("cannot contain spaces")));
+        handle(&mut session, command("CLIENT SETNAME worker")).await;
+
+        // commands run by EXEC are logged with the client too
+        db.lock().config.slowlog_log_slower_than = 0;
+        handle(&mut session, command("MULTI")).await;
+        handle(&mut session, command("SET foo 1")).await;
+        handle(&mut session, command("EXEC")).await;
+        let store = db.lock();
+        let entry = store.slowlog.entries(1).next().unwrap();
+        assert_eq!(entry.args, vec![Bytes::from("SET"), Bytes::from("foo"), Bytes::from("1")]);
+        assert_eq!((entry.client.addr.as_str(), entry.client.name.as_str()), ("127.0.0.1:50000", "worker"));
+    }
+
+    #[tokio::test]
+    async fn exec_abort_test() {
+        let db = init_db();
+        let mut session = Session::new(db.clone());
+
+        handle(&mut session, command("MULTI")).await;
+        assert!(matches!(handle(&mut session, command("SET foo")).await, Frame::Error(_)));
+        handle(&mut session, command("SET foo 1")).await;
+
+        assert!(matches!(handle(&mut session, command("EXEC")).await, Frame::Error(e) if e.starts_with("EXECABORT")));
+        assert_eq!(db.lock().get_bytes("foo"), None);
+    }
+
+    #[tokio::test]
+    async fn watch_test() {
+        let db = init_db();
+        let mut session = Session::new(db.clone());
+        let mut other = Session::new(db.clone());
+
+        handle(&mut session, command("WATCH foo")).await;
+        handle(&mut other, command("SET foo 2")).await;
+
+        handle(&mut session, command("MULTI")).await;
+        handle(&mut session, command("SET foo 1")).await;
+        // a null array, not a null bulk string
+        let aborted = handle(&mut session, command("EXEC")).await;
+        assert_eq!(aborted.to_protocol().unwrap(), Bytes::from("*-1\r\n"));
+        assert_eq!(db.lock().get_bytes("foo"), Some(Bytes::from("2")));
+
+        // the watch is gone after EXEC, so the next transaction goes through
+        handle(&mut session, command("MULTI")).await;
+        handle(&mut session, command("SET foo 1")).await;
+        assert!(matches!(handle(&mut session, command("EXEC")).await, Frame::Array(_)));
+        assert_eq!(db.lock().get_bytes("foo"), Some(Bytes::from("1")));
+    }
+
+    #[tokio::test]
+    async fn subscribe_test() {
+        let db = init_db();
+        let mut subscriber = Session::new(db.clone());
+        let mut publisher = Session::new(db.clone());
+
+        let replies = subscriber.handle(command("SUBSCRIBE news sport")).await;
+        assert_eq!(replies.len(), 2);
+        assert!(matches!(&replies[1], Frame::Array(reply) if matches!(reply[2], Frame::Integer(2))));
+
+        // only the subscribe family works in subscriber mode
+        assert!(matches!(handle(&mut subscriber, command("GET foo")).await, Frame::Error(_)));
+        assert!(matches!(handle(&mut subscriber, command("PING")).await, Frame::Array(_)));
+
+        assert!(matches!(handle(&mut publisher, command("PUBLISH news hello")).await, Frame::Integer(1)));
+        match subscriber.next_push().await {
+            Some(Push::Message(Frame::Array(message), _)) => assert!(matches!(&message[2], Frame::Bulk(data) if data == "hello")),
+            other => panic!("unexpected push {:?}", other),
+        }
+
+        assert_eq!(subscriber.handle(command("UNSUBSCRIBE")).await.len(), 2);
+        assert!(matches!(handle(&mut subscriber, command("GET foo")).await, Frame::Null));
+        assert!(matches!(handle(&mut publisher, command("PUBLISH news hello")).await, Frame::Integer(0)));
+    }
+
+    #[tokio::test]
+    async fn quit_reset_test() {
+        let db = init_db();
+        let mut subscriber = Session::new(db.clone());
+        let mut publisher = Session::new(db.clone());
+
+        subscriber.handle(command("SUBSCRIBE news")).await;
+        subscriber.handle(command("PSUBSCRIBE sport.*")).await;
+        handle(&mut publisher, command("PUBLISH news before")).await;
+
+        // RESET leaves every channel and pattern, with a single reply
+        assert_eq!(subscriber.handle(command("RESET")).await.len(), 1);
+        assert!(matches!(handle(&mut subscriber, command("CLIENT GETNAME")).await, Frame::Null));
+        assert!(matches!(handle(&mut publisher, command("PUBLISH news hello")).await, Frame::Integer(0)));
+        assert!(matches!(handle(&mut publisher, command("PUBLISH sport.tennis ace")).await, Frame::Integer(0)));
+        assert!(subscriber.messages.try_recv().is_err());
+
+        // it also drops a transaction and the name of the connection
+        handle(&mut subscriber, command("CLIENT SETNAME worker")).await;
+        handle(&mut subscriber, command("MULTI")).await;
+        assert!(matches!(handle(&mut subscriber, command("RESET")).await, Frame::Simple(reset) if reset == "RESET"));
+        assert!(matches!(handle(&mut subscriber, command("EXEC")).await, Frame::Error(e) if e.contains("without MULTI")));
+        assert!(matches!(handle(&mut subscriber, command("CLIENT GETNAME")).await, Frame::Null));
+
+        // QUIT is allowed in subscriber mode and closes the connection after its reply
+        subscriber.handle(command("SUBSCRIBE news")).await;
+        assert!(!subscriber.is_quitting());
+        assert!(matches!(handle(&mut subscriber, command("QUIT")).await, Frame::Simple(ok) if ok == "OK"));
+        assert!(subscriber.is_quitting());
+    }
+
+    #[tokio::test]
+    async fn ssubscribe_test() {
+        let db = init_db();
+        let mut subscriber = Session::new(db.clone());
+        let mut publisher = Session::new(db.clone());
+
+        assert!(matches!(handle(&mut subscriber, command("SSUBSCRIBE {a}x {b}x")).await, Frame::Error(e) if e.starts_with("CROSSSLOT")));
+        assert_eq!(subscriber.handle(command("SSUBSCRIBE {a}x {a}y")).await.len(), 2);
+
+        assert!(matches!(handle(&mut publisher, command("PUBLISH {a}x hello")).await, Frame::Integer(0)));
+        assert!(matches!(handle(&mut publisher, command("SPUBLISH {a}x hello")).await, Frame::Integer(1)));
+        match subscriber.next_push().await {
+            Some(Push::Message(Frame::Array(message), _)) => assert!(matches!(&message[0], Frame::Bulk(kind) if kind == "smessage")),
+            other => panic!("unexpected push {:?}", other),
+        }
+
+        match handle(&mut publisher, command("PUBSUB SHARDNUMSUB {a}x")).await {
+            Frame::Array(reply) => assert!(matches!(reply[1], Frame::Integer(1))),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+}
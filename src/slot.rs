@@ -0,0 +1,48 @@
+/// Number of hash slots the keyspace is split into, as in Redis Cluster
+pub(crate) const SLOT_COUNT: u16 = 16384;
+
+/// CRC16 with the XMODEM polynomial (0x1021), the variant Redis Cluster uses
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// The hash slot of a key or shard channel.
+/// If the key contains a non empty `{hashtag}`, only the tag is hashed, so that related keys
+/// can be forced into the same slot
+pub(crate) fn key_hash_slot(key: &[u8]) -> u16 {
+    let hashed = match key.iter().position(|&b| b == b'{') {
+        Some(open) => match key[open + 1..].iter().position(|&b| b == b'}') {
+            Some(len) if len > 0 => &key[open + 1..open + 1 + len],
+            _ => key,
+        },
+        None => key,
+    };
+    crc16(hashed) & (SLOT_COUNT - 1)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::slot::{crc16, key_hash_slot};
+
+    #[test]
+    fn crc16_test() {
+        // reference value from the Redis Cluster specification
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn key_hash_slot_test() {
+        assert_eq!(key_hash_slot(b"foo"), 12182);
+        assert_eq!(key_hash_slot(b"{user1000}.following"), key_hash_slot(b"{user1000}.followers"));
+        assert_eq!(key_hash_slot(b"foo{}{bar}"), crc16(b"foo{}{bar}") & 16383);
+        assert_eq!(key_hash_slot(b"foo{{bar}}zap"), key_hash_slot(b"{bar"));
+        assert_eq!(key_hash_slot(b"foo{bar}{zap}"), key_hash_slot(b"bar"));
+    }
+}
@@ -0,0 +1,220 @@
+//! SLOWLOG: the last `slowlog-max-len` commands that ran for `slowlog-log-slower-than`
+//! microseconds or more, with who sent them
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::clock::unix_secs;
+use crate::config::Config;
+
+/// arguments kept per entry, the last one kept says how many more there were
+const MAX_ARGS: usize = 32;
+
+/// bytes kept per argument
+const MAX_ARG_LEN: usize = 128;
+
+/// what a password or a user name is replaced with
+const REDACTED: &[u8] = b"(redacted)";
+
+/// The connection a command came from, as the slow log shows it
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ClientInfo {
+    /// `ip:port` of the peer, empty for commands that did not come over a socket
+    pub(crate) addr: String,
+    /// set by CLIENT SETNAME
+    pub(crate) name: String,
+}
+
+#[derive(Debug)]
+pub(crate) struct SlowLog {
+    /// the newest entry first
+    entries: VecDeque<Entry>,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+pub(crate) struct Entry {
+    /// unique for the whole run, RESET does not start it over
+    pub(crate) id: u64,
+    /// unix time the command ran at, in seconds
+    pub(crate) time: u64,
+    pub(crate) usec: u64,
+    pub(crate) args: Vec<Bytes>,
+    pub(crate) client: ClientInfo,
+}
+
+impl SlowLog {
+    pub(crate) fn new() -> Self {
+        Self { entries: VecDeque::new(), next_id: 0 }
+    }
+
+    /// log `argv` if it ran for `slowlog-log-slower-than` or longer. 0 logs every command,
+    /// a negative threshold none
+    pub(crate) fn record(&mut self, config: &Config, argv: &[Bytes], duration: Duration, client: &ClientInfo) {
+        let usec = duration.as_micros() as u64;
+        if config.slowlog_log_slower_than < 0 || usec < config.slowlog_log_slower_than as u64 {
+            return;
+        }
+        self.entries.push_front(Entry {
+            id: self.next_id,
+            time: unix_secs(),
+            usec,
+            args: redact(truncate(argv)),
+            client: client.clone(),
+        });
+        self.next_id += 1;
+        self.entries.truncate(config.slowlog_max_len);
+    }
+
+    /// the `count` newest entries, newest first
+    pub(crate) fn entries(&self, count: usize) -> impl Iterator<Item = &Entry> {
+        self.entries.iter().take(count)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// drop the oldest entries beyond `max_len`, after CONFIG SET slowlog-max-len
+    pub(crate) fn truncate(&mut self, max_len: usize) {
+        self.entries.truncate(max_len);
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.entries.clear();
+    }
+}
+
+/// what an entry keeps of a request, like Redis: at most `MAX_ARGS` arguments of at most
+/// `MAX_ARG_LEN` bytes, each cut saying how much was left out
+fn truncate(argv: &[Bytes]) -> Vec<Bytes> {
+    let kept = if argv.len() > MAX_ARGS { MAX_ARGS - 1 } else { argv.len() };
+    let mut args: Vec<Bytes> = argv[..kept].iter()
+        .map(|arg| {
+            if arg.len() <= MAX_ARG_LEN {
+                return arg.clone();
+            }
+            let mut cut = BytesMut::from(&arg[..MAX_ARG_LEN]);
+            cut.extend_from_slice(format!("... ({} more bytes)", arg.len() - MAX_ARG_LEN).as_bytes());
+            cut.freeze()
+        })
+        .collect();
+    if kept < argv.len() {
+        args.push(Bytes::from(format!("... ({} more arguments)", argv.len() - kept)));
+    }
+    args
+}
+
+/// hide the credentials of AUTH, HELLO ... AUTH, MIGRATE ... AUTH/AUTH2 and
+/// CONFIG SET masterauth, as Redis does, so that the log does not show them
+fn redact(mut args: Vec<Bytes>) -> Vec<Bytes> {
+    let is = |arg: &Bytes, name: &str| arg.eq_ignore_ascii_case(name.as_bytes());
+    let mut hidden = vec![];
+    match args.first() {
+        Some(command) if is(command, "auth") => hidden.extend(1..args.len()),
+        Some(command) if is(command, "hello") => {
+            if let Some(i) = args.iter().position(|arg| is(arg, "auth")) {
+                hidden.extend([i + 1, i + 2]);
+            }
+        }
+        Some(command) if is(command, "migrate") => {
+            for (i, arg) in args.iter().enumerate() {
+                if is(arg, "auth") {
+                    hidden.push(i + 1);
+                } else if is(arg, "auth2") {
+                    hidden.extend([i + 1, i + 2]);
+                } else if is(arg, "keys") {
+                    break;
+                }
+            }
+        }
+        Some(command) if is(command, "config") && args.get(1).is_some_and(|arg| is(arg, "set")) => {
+            for i in (2..args.len()).step_by(2) {
+                if is(&args[i], "masterauth") {
+                    hidden.push(i + 1);
+                }
+            }
+        }
+        _ => {}
+    }
+    for i in hidden {
+        if let Some(arg) = args.get_mut(i) {
+            *arg = Bytes::from_static(REDACTED);
+        }
+    }
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+
+    use crate::config::Config;
+    use crate::slowlog::{redact, truncate, ClientInfo, SlowLog};
+
+    fn argv(args: &[&str]) -> Vec<Bytes> {
+        args.iter().map(|arg| Bytes::from(arg.to_string())).collect()
+    }
+
+    #[test]
+    fn slowlog_test() {
+        let config = Config { slowlog_log_slower_than: 100, slowlog_max_len: 2, ..Config::default() };
+        let client = ClientInfo { addr: "127.0.0.1:50000".to_string(), name: "worker".to_string() };
+        let mut slowlog = SlowLog::new();
+
+        slowlog.record(&config, &argv(&["GET", "fast"]), Duration::from_micros(99), &client);
+        assert_eq!(slowlog.len(), 0);
+        for key in ["a", "b", "c"] {
+            slowlog.record(&config, &argv(&["GET", key]), Duration::from_micros(100), &client);
+        }
+        let entries: Vec<_> = slowlog.entries(10).collect();
+        assert_eq!(entries.iter().map(|entry| entry.id).collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(entries[0].args, argv(&["GET", "c"]));
+        assert_eq!((entries[0].usec, entries[0].client.name.as_str()), (100, "worker"));
+
+        // ids keep growing after a reset
+        slowlog.reset();
+        slowlog.record(&config, &argv(&["GET", "d"]), Duration::from_millis(1), &client);
+        assert_eq!(slowlog.entries(1).next().unwrap().id, 3);
+
+        let disabled = Config { slowlog_log_slower_than: -1, ..config };
+        slowlog.record(&disabled, &argv(&["GET", "e"]), Duration::from_secs(1), &client);
+        assert_eq!(slowlog.len(), 1);
+    }
+
+    #[test]
+    fn truncate_test() {
+        let long = "x".repeat(200);
+        let args: Vec<Bytes> = (0..40).map(|_| Bytes::from(long.clone())).collect();
+        let kept = truncate(&args);
+        assert_eq!(kept.len(), 32);
+        assert_eq!(kept[0], Bytes::from(format!("{}... (72 more bytes)", "x".repeat(128))));
+        assert_eq!(kept[31], Bytes::from("... (9 more arguments)"));
+        assert_eq!(truncate(&argv(&["GET", "a"])), argv(&["GET", "a"]));
+    }
+
+    #[test]
+    fn redact_test() {
+        assert_eq!(redact(argv(&["AUTH", "secret"])), argv(&["AUTH", "(redacted)"]));
+        assert_eq!(redact(argv(&["auth", "user", "secret"])), argv(&["auth", "(redacted)", "(redacted)"]));
+        assert_eq!(redact(argv(&["HELLO", "3", "AUTH", "user", "secret", "SETNAME", "worker"])),
+            argv(&["HELLO", "3", "AUTH", "(redacted)", "(redacted)", "SETNAME", "worker"]));
+        assert_eq!(redact(argv(&["MIGRATE", "host", "6379", "", "0", "1000", "AUTH", "secret", "KEYS", "auth", "b"])),
+            argv(&["MIGRATE", "host", "6379", "", "0", "1000", "AUTH", "(redacted)", "KEYS", "auth", "b"]));
+        assert_eq!(redact(argv(&["MIGRATE", "host", "6379", "key", "0", "1000", "COPY", "AUTH2", "user", "secret"])),
+            argv(&["MIGRATE", "host", "6379", "key", "0", "1000", "COPY", "AUTH2", "(redacted)", "(redacted)"]));
+        assert_eq!(redact(argv(&["CONFIG", "SET", "maxmemory", "1mb", "masterauth", "secret"])),
+            argv(&["CONFIG", "SET", "maxmemory", "1mb", "masterauth", "(redacted)"]));
+        assert_eq!(redact(argv(&["GET", "auth"])), argv(&["GET", "auth"]));
+
+        // the log itself never holds the password
+        let config = Config { slowlog_log_slower_than: 0, ..Config::default() };
+        let mut slowlog = SlowLog::new();
+        slowlog.record(&config, &argv(&["AUTH", "secret"]), Duration::ZERO, &ClientInfo::default());
+        assert_eq!(slowlog.entries(1).next().unwrap().args, argv(&["AUTH", "(redacted)"]));
+    }
+}
@@ -0,0 +1,283 @@
+//! The counters behind INFO: commands and their latencies, error replies, keyspace lookups,
+//! expired keys and connections. They live in the store and are updated under its lock,
+//! CONFIG RESETSTAT starts them over
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::replication::random_id;
+
+/// how many samples of the command rate `instantaneous_ops_per_sec` averages, like Redis
+const OPS_SAMPLES: usize = 16;
+
+/// each power of two of a latency histogram is split in this many buckets
+const SUB_BUCKETS: u64 = 8;
+
+/// clock ticks per second of the CPU times in `/proc/self/stat`, 100 on every Linux around
+const CLOCK_TICKS: f64 = 100.0;
+
+#[derive(Debug)]
+pub(crate) struct Stats {
+    /// identifies this run of the server, `run_id` in INFO
+    pub(crate) run_id: String,
+    pub(crate) started: Instant,
+    /// client connections open now
+    pub(crate) connected_clients: u64,
+    pub(crate) total_connections_received: u64,
+    /// connections closed right away because `maxclients` were connected
+    pub(crate) rejected_connections: u64,
+    pub(crate) total_commands_processed: u64,
+    /// lookups of a key that existed and of one that did not
+    pub(crate) keyspace_hits: u64,
+    pub(crate) keyspace_misses: u64,
+    /// keys deleted because their TTL was over, lazily or by the background task
+    pub(crate) expired_keys: u64,
+    pub(crate) total_error_replies: u64,
+    /// by lowercase command name
+    pub(crate) commands: BTreeMap<String, CommandStats>,
+    /// error replies by their prefix, `ERR`, `WRONGTYPE`, `OOM` ...
+    pub(crate) errors: BTreeMap<String, u64>,
+    /// commands per second measured by the last `OPS_SAMPLES` calls of `sample_ops`
+    ops_samples: [u64; OPS_SAMPLES],
+    ops_sample_index: usize,
+    /// when `sample_ops` last ran and `total_commands_processed` at that time
+    last_sample: (Instant, u64),
+}
+
+/// what INFO commandstats and latencystats report about one command
+#[derive(Debug, Default)]
+pub(crate) struct CommandStats {
+    pub(crate) calls: u64,
+    /// microseconds spent running the command, over every call
+    pub(crate) usec: u64,
+    /// calls refused before running, by READONLY, OOM or a syntax error
+    pub(crate) rejected_calls: u64,
+    /// calls that ran and replied with an error
+    pub(crate) failed_calls: u64,
+    pub(crate) latency: Histogram,
+}
+
+/// Latencies in microseconds, counted in buckets that double in width every `SUB_BUCKETS`
+/// buckets, so a percentile is off by less than 1/8th of its value
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+}
+
+impl Stats {
+    pub(crate) fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            run_id: random_id(),
+            started: now,
+            connected_clients: 0,
+            total_connections_received: 0,
+            rejected_connections: 0,
+            total_commands_processed: 0,
+            keyspace_hits: 0,
+            keyspace_misses: 0,
+            expired_keys: 0,
+            total_error_replies: 0,
+            commands: BTreeMap::new(),
+            errors: BTreeMap::new(),
+            ops_samples: [0; OPS_SAMPLES],
+            ops_sample_index: 0,
+            last_sample: (now, 0),
+        }
+    }
+
+    /// `name` ran for `duration`, `error` is its reply if it was an error
+    pub(crate) fn command(&mut self, name: &str, duration: Duration, error: Option<&str>) {
+        let usec = duration.as_micros() as u64;
+        let stats = self.command_stats(name);
+        stats.calls += 1;
+        stats.usec += usec;
+        stats.latency.record(usec);
+        if let Some(error) = error {
+            stats.failed_calls += 1;
+            self.error(error);
+        }
+        self.total_commands_processed += 1;
+    }
+
+    /// `name` was refused with `error` before it ran
+    pub(crate) fn rejected(&mut self, name: &str, error: &str) {
+        self.command_stats(name).rejected_calls += 1;
+        self.error(error);
+    }
+
+    /// an error reply was sent
+    pub(crate) fn error(&mut self, error: &str) {
+        let prefix = error.split(' ').next().unwrap_or_default();
+        // 和 Redis 一样，没有大写前缀的错误算作 ERR
+        let prefix = if !prefix.is_empty() && prefix.bytes().all(|c| c.is_ascii_uppercase()) { prefix } else { "ERR" };
+        *self.errors.entry(prefix.to_string()).or_default() += 1;
+        self.total_error_replies += 1;
+    }
+
+    fn command_stats(&mut self, name: &str) -> &mut CommandStats {
+        if !self.commands.contains_key(name) {
+            self.commands.insert(name.to_string(), CommandStats::default());
+        }
+        self.commands.get_mut(name).unwrap()
+    }
+
+    /// take a sample of the command rate, every 100ms
+    pub(crate) fn sample_ops(&mut self) {
+        let now = Instant::now();
+        let (at, commands) = self.last_sample;
+        let elapsed = now.duration_since(at).as_millis() as u64;
+        if let Some(ops) = ((self.total_commands_processed - commands) * 1000).checked_div(elapsed) {
+            self.ops_samples[self.ops_sample_index] = ops;
+            self.ops_sample_index = (self.ops_sample_index + 1) % OPS_SAMPLES;
+        }
+        self.last_sample = (now, self.total_commands_processed);
+    }
+
+    /// `instantaneous_ops_per_sec`
+    pub(crate) fn ops_per_sec(&self) -> u64 {
+        self.ops_samples.iter().sum::<u64>() / OPS_SAMPLES as u64
+    }
+
+    /// CONFIG RESETSTAT. What describes the server rather than counts events stays
+    pub(crate) fn reset(&mut self) {
+        let connected_clients = self.connected_clients;
+        let run_id = std::mem::take(&mut self.run_id);
+        let started = self.started;
+        *self = Self { run_id, started, connected_clients, ..Self::new() };
+    }
+}
+
+impl Histogram {
+    pub(crate) fn record(&mut self, usec: u64) {
+        let index = bucket(usec);
+        if self.buckets.len() <= index {
+            self.buckets.resize(index + 1, 0);
+        }
+        self.buckets[index] += 1;
+        self.count += 1;
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// the latency `percentile` percent of the recorded ones are not above, 0 when empty
+    pub(crate) fn percentile(&self, percentile: f64) -> u64 {
+        let rank = ((percentile / 100.0 * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_max(index);
+            }
+        }
+        0
+    }
+
+    /// how many recorded latencies are not above `usec`, counting whole buckets only, so
+    /// the latencies up to 1/8th below `usec` may be left out
+    pub(crate) fn count_below(&self, usec: u64) -> u64 {
+        self.buckets.iter()
+            .enumerate()
+            .take_while(|(index, _)| bucket_max(*index) <= usec)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+/// values below `SUB_BUCKETS` get a bucket each, then every power of two is split in `SUB_BUCKETS`
+fn bucket(usec: u64) -> usize {
+    if usec < SUB_BUCKETS {
+        return usec as usize;
+    }
+    let exponent = 63 - usec.leading_zeros() as u64;
+    let sub = (usec >> (exponent - 3)) & (SUB_BUCKETS - 1);
+    ((exponent - 2) * SUB_BUCKETS + sub) as usize
+}
+
+/// the largest value that falls in bucket `index`
+fn bucket_max(index: usize) -> u64 {
+    let index = index as u64;
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let shift = index / SUB_BUCKETS - 1;
+    let low = (SUB_BUCKETS + index % SUB_BUCKETS) << shift;
+    low + (1 << shift) - 1
+}
+
+/// seconds of CPU used by the process in user and in system mode, zeros where
+/// `/proc` is not available
+pub(crate) fn cpu_times() -> (f64, f64) {
+    let Ok(stat) = fs::read_to_string("/proc/self/stat") else { return (0.0, 0.0) };
+    // 进程名在括号里，可能带空格，从右括号之后开始数
+    let fields: Vec<&str> = stat.rsplit_once(')').map_or(vec![], |(_, rest)| rest.split_whitespace().collect());
+    let ticks = |index: usize| fields.get(index).and_then(|ticks| ticks.parse::<u64>().ok()).unwrap_or(0);
+    // utime 和 stime 是第 14 和 15 个字段
+    (ticks(11) as f64 / CLOCK_TICKS, ticks(12) as f64 / CLOCK_TICKS)
+}
+
+/// bytes of the process resident in memory, 0 where `/proc` is not available
+pub(crate) fn resident_memory() -> usize {
+    fs::read_to_string("/proc/self/statm").ok()
+        .and_then(|statm| statm.split_whitespace().nth(1).and_then(|pages| pages.parse::<usize>().ok()))
+        .map_or(0, |pages| pages * 4096)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::stats::{bucket, bucket_max, Histogram, Stats};
+
+    #[test]
+    fn histogram_test() {
+        for usec in [0, 1, 7, 8, 9, 15, 16, 17, 100, 1000, 123_456, u64::MAX / 2] {
+            let index = bucket(usec);
+            assert!(bucket_max(index) >= usec, "{}", usec);
+            assert!(index == 0 || bucket_max(index - 1) < usec, "{}", usec);
+            assert!(bucket_max(index) - usec <= usec / 8, "{}", usec);
+        }
+
+        let mut histogram = Histogram::default();
+        assert_eq!(histogram.percentile(50.0), 0);
+        for usec in 1..=1000 {
+            histogram.record(usec);
+        }
+        assert_eq!(histogram.count(), 1000);
+        let p50 = histogram.percentile(50.0);
+        assert!((500..=500 + 500 / 8).contains(&p50), "{}", p50);
+        assert!(histogram.percentile(99.9) >= 999);
+        assert_eq!(histogram.count_below(7), 7);
+        assert_eq!(histogram.count_below(u64::MAX), 1000);
+    }
+
+    #[tokio::test]
+    async fn stats_test() {
+        let mut stats = Stats::new();
+        stats.command("get", Duration::from_micros(10), None);
+        stats.command("get", Duration::from_micros(30), Some("WRONGTYPE Operation against a key"));
+        stats.rejected("set", "OOM command not allowed");
+        stats.error("unknown command");
+
+        let get = &stats.commands["get"];
+        assert_eq!((get.calls, get.usec, get.failed_calls, get.rejected_calls), (2, 40, 1, 0));
+        assert_eq!(stats.commands["set"].rejected_calls, 1);
+        assert_eq!(stats.total_commands_processed, 2);
+        assert_eq!(stats.total_error_replies, 3);
+        assert_eq!(stats.errors.iter().map(|(prefix, count)| (prefix.as_str(), *count)).collect::<Vec<_>>(),
+                   vec![("ERR", 1), ("OOM", 1), ("WRONGTYPE", 1)]);
+
+        let run_id = stats.run_id.clone();
+        stats.connected_clients = 3;
+        stats.reset();
+        assert!(stats.commands.is_empty());
+        assert_eq!(stats.total_error_replies, 0);
+        assert_eq!((stats.run_id.as_str(), stats.connected_clients), (run_id.as_str(), 3));
+    }
+}